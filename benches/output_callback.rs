@@ -0,0 +1,52 @@
+//! Time and allocations of the output queue callback, which runs on the realtime
+//! thread and must not allocate.
+//!
+//! Run with `cargo bench --features bench-internals`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use caudio::queue::output_callback_round;
+
+/// Counts every allocation of the process.
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+const BUFFERS: usize = 64;
+const ROUNDS: usize = 10_000;
+
+fn main() {
+    let mut round = output_callback_round(BUFFERS);
+
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        round();
+    }
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+
+    println!(
+        "output callback: {:?} per buffer, {} allocations in {} callbacks",
+        elapsed / (BUFFERS * ROUNDS) as u32,
+        allocations,
+        BUFFERS * ROUNDS
+    );
+    assert_eq!(allocations, 0, "the output callback allocated");
+}
@@ -0,0 +1,56 @@
+//! Malformed DVI/Intel IMA ADPCM blocks, on their own and in a WAV file.
+//!
+//! Run with `cargo fuzz run ima_adpcm_blocks`.
+
+#![no_main]
+
+use std::io::Cursor;
+
+use caudio::codec::ima_adpcm::{ImaAdpcmDecoder, ImaAdpcmFormat};
+use caudio::file::wav::WavReader;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let [channels, words, data @ ..] = data else {
+        return;
+    };
+    let channels = (*channels % 8 + 1) as usize;
+    let block_align = 4 * channels * (*words as usize % 64 + 2);
+    let Ok(format) = ImaAdpcmFormat::new(channels, block_align) else {
+        return;
+    };
+
+    let decoder = ImaAdpcmDecoder::new(format);
+    let mut out = vec![0_i16; channels * format.frames_per_block()];
+    for block in data.chunks(block_align) {
+        if let Ok(frames) = decoder.decode_block(block, &mut out) {
+            assert_eq!(Some(frames), format.frames_in(block.len()));
+        }
+    }
+
+    // The same blocks as the data of a WAV file.
+    let mut wav = Vec::new();
+    wav.extend_from_slice(b"RIFF\0\0\0\0WAVEfmt \x14\0\0\0");
+    wav.extend_from_slice(&[0x11, 0, channels as u8, 0, 0x44, 0xAC, 0, 0, 0, 0, 0, 0]);
+    wav.extend_from_slice(&(block_align as u16).to_le_bytes());
+    wav.extend_from_slice(&[4, 0, 2, 0]);
+    wav.extend_from_slice(&format.fmt_extension());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    wav.extend_from_slice(data);
+
+    let Ok(mut reader) = WavReader::new(Cursor::new(wav)) else {
+        return;
+    };
+    let frames = reader.frames();
+    let mut out = vec![0.0_f32; channels * 100];
+    let mut read = 0;
+    while let Ok(n @ 1..) = reader.read_samples(&mut out) {
+        read += n as u64;
+    }
+    assert!(read <= frames);
+
+    if frames > 0 && reader.seek(frames / 2).is_ok() {
+        let _ = reader.read_samples(&mut out);
+    }
+});
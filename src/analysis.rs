@@ -0,0 +1,390 @@
+//! Measurements on audio, for making assertions in tests.
+//!
+//! Levels are computed over interleaved samples of any [`SampleConvert`] type,
+//! converted to floats in `[-1.0, 1.0]`. The spectral measurements work on a single
+//! channel, use [`channel`] or [`buffer_list_channel`] to get one.
+
+use std::f64::consts::PI;
+
+use crate::format::SampleConvert;
+use crate::unit::AudioBufferList;
+
+/// Bins on each side of a tone that are counted as part of it.
+///
+/// The main lobe of the Blackman-Harris window is 4 bins wide on each side, the rest
+/// is leakage below -92 dB.
+const TONE_BINS: usize = 5;
+
+/// Highest absolute sample value.
+pub fn peak<S: SampleConvert>(samples: &[S]) -> f32 {
+    samples.iter().map(|s| s.to_f32().abs()).fold(0.0, f32::max)
+}
+
+/// Root mean square.
+pub fn rms<S: SampleConvert>(samples: &[S]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    let sum: f64 = samples.iter().map(|s| (s.to_f32() as f64).powi(2)).sum();
+    (sum / samples.len() as f64).sqrt() as f32
+}
+
+/// Mean sample value.
+pub fn dc_offset<S: SampleConvert>(samples: &[S]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    let sum: f64 = samples.iter().map(|s| s.to_f32() as f64).sum();
+    (sum / samples.len() as f64) as f32
+}
+
+/// Convert a linear level to dB relative to full scale.
+pub fn to_dbfs(level: f32) -> f32 {
+    20.0 * level.log10()
+}
+
+/// One channel of interleaved samples, or `None` if `index` isn't below `channels`.
+pub fn channel<S: SampleConvert>(samples: &[S], channels: usize, index: usize) -> Option<Vec<f32>> {
+    if index >= channels {
+        return None;
+    }
+    let channel = samples
+        .iter()
+        .skip(index)
+        .step_by(channels)
+        .map(|s| s.to_f32())
+        .collect();
+    Some(channel)
+}
+
+/// One channel of a buffer list, counting channels across buffers, or `None` if the
+/// list has no such channel.
+pub fn buffer_list_channel<S: SampleConvert>(
+    list: &AudioBufferList<S>,
+    mut index: usize,
+) -> Option<Vec<f32>> {
+    for buffer in list.iter() {
+        if index < buffer.channels() {
+            return channel(buffer.samples(), buffer.channels(), index);
+        }
+        index -= buffer.channels();
+    }
+    None
+}
+
+/// Magnitude spectrum of a single channel.
+///
+/// The signal is windowed with a 4-term Blackman-Harris window and zero padded to a
+/// power of two.
+#[derive(Debug, Clone)]
+pub struct Spectrum {
+    /// Power of each bin from DC up to and including Nyquist.
+    power: Vec<f64>,
+    sample_rate: f64,
+    size: usize,
+}
+
+impl Spectrum {
+    pub fn new(samples: &[f32], sample_rate: f64) -> Self {
+        let size = samples.len().next_power_of_two().max(2);
+
+        let mut re = vec![0.0; size];
+        let mut im = vec![0.0; size];
+
+        let n = samples.len();
+        for (i, &s) in samples.iter().enumerate() {
+            re[i] = s as f64 * blackman_harris(i, n);
+        }
+
+        fft(&mut re, &mut im);
+
+        let power = re[..=size / 2]
+            .iter()
+            .zip(&im[..=size / 2])
+            .map(|(re, im)| re * re + im * im)
+            .collect();
+
+        Spectrum {
+            power,
+            sample_rate,
+            size,
+        }
+    }
+
+    /// Number of bins, from DC up to and including Nyquist.
+    pub fn len(&self) -> usize {
+        self.power.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.power.is_empty()
+    }
+
+    /// Width of a bin in Hz.
+    pub fn bin_width(&self) -> f64 {
+        self.sample_rate / self.size as f64
+    }
+
+    /// Center frequency of a bin in Hz.
+    pub fn frequency(&self, bin: usize) -> f64 {
+        bin as f64 * self.bin_width()
+    }
+
+    pub(crate) fn power(&self) -> &[f64] {
+        &self.power
+    }
+
+    /// Magnitude of a bin, relative to the strongest bin, in dB.
+    pub fn magnitude_db(&self, bin: usize) -> f64 {
+        let max = self.power.iter().cloned().fold(0.0, f64::max);
+        10.0 * (self.power[bin] / max).log10()
+    }
+
+    /// Frequency of the strongest component, interpolated between bins.
+    pub fn dominant_frequency(&self) -> f64 {
+        // Skip the bins around DC.
+        let bin = (TONE_BINS..self.power.len())
+            .max_by(|&a, &b| self.power[a].total_cmp(&self.power[b]))
+            .unwrap_or(0);
+
+        if bin == 0 || bin + 1 >= self.power.len() {
+            return self.frequency(bin);
+        }
+
+        // Parabolic interpolation of the log magnitudes.
+        let l = self.power[bin - 1].max(f64::MIN_POSITIVE).ln();
+        let c = self.power[bin].max(f64::MIN_POSITIVE).ln();
+        let r = self.power[bin + 1].max(f64::MIN_POSITIVE).ln();
+        let offset = 0.5 * (l - r) / (l - 2.0 * c + r);
+
+        (bin as f64 + offset) * self.bin_width()
+    }
+
+    /// Total harmonic distortion plus noise of a tone, as a ratio to the tone.
+    ///
+    /// Everything except the tone and DC counts as distortion or noise.
+    pub fn thd_n(&self, frequency: f64) -> f64 {
+        let tone = self.band_power(frequency);
+        let rest = self.total_power() - tone;
+        (rest.max(0.0) / tone).sqrt()
+    }
+
+    /// Signal to noise ratio of a tone in dB.
+    ///
+    /// The harmonics of the tone and DC don't count as noise.
+    pub fn snr_db(&self, frequency: f64) -> f64 {
+        let tone = self.band_power(frequency);
+
+        let nyquist = self.sample_rate / 2.0;
+        let harmonics: f64 = (2..)
+            .map(|h| h as f64 * frequency)
+            .take_while(|&f| f < nyquist)
+            .map(|f| self.band_power(f))
+            .sum();
+
+        let noise = self.total_power() - tone - harmonics;
+        10.0 * (tone / noise.max(f64::MIN_POSITIVE)).log10()
+    }
+
+    /// Power of all bins except the ones around DC.
+    fn total_power(&self) -> f64 {
+        self.power.iter().skip(TONE_BINS).sum()
+    }
+
+    /// Power of the bins around a frequency.
+    fn band_power(&self, frequency: f64) -> f64 {
+        let center = (frequency / self.bin_width()).round() as usize;
+        let start = center.saturating_sub(TONE_BINS).max(TONE_BINS);
+        let end = (center + TONE_BINS + 1).min(self.power.len());
+        self.power.get(start..end).map_or(0.0, |b| b.iter().sum())
+    }
+}
+
+/// Delay of `signal` relative to `reference` in frames, found by cross-correlation.
+///
+/// Lags from 0 up to and including `max_lag` are tried. Returns `None` if the signals
+/// don't correlate at all.
+pub fn latency(reference: &[f32], signal: &[f32], max_lag: usize) -> Option<usize> {
+    let mut best = None;
+    let mut best_value = 0.0;
+
+    for lag in 0..=max_lag.min(signal.len()) {
+        let value: f64 = reference
+            .iter()
+            .zip(&signal[lag..])
+            .map(|(&a, &b)| a as f64 * b as f64)
+            .sum();
+
+        if value > best_value {
+            best_value = value;
+            best = Some(lag);
+        }
+    }
+
+    best
+}
+
+fn blackman_harris(i: usize, n: usize) -> f64 {
+    if n < 2 {
+        return 1.0;
+    }
+    let x = 2.0 * PI * i as f64 / (n - 1) as f64;
+    0.35875 - 0.48829 * x.cos() + 0.14128 * (2.0 * x).cos() - 0.01168 * (3.0 * x).cos()
+}
+
+/// In place radix-2 FFT. The length must be a power of two.
+fn fft(re: &mut [f64], im: &mut [f64]) {
+    let n = re.len();
+    debug_assert!(n.is_power_of_two());
+
+    // Bit reversal permutation.
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * PI / len as f64;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (sin, cos) = (angle * k as f64).sin_cos();
+                let a = start + k;
+                let b = a + len / 2;
+
+                let tr = re[b] * cos - im[b] * sin;
+                let ti = re[b] * sin + im[b] * cos;
+
+                re[b] = re[a] - tr;
+                im[b] = im[a] - ti;
+                re[a] += tr;
+                im[a] += ti;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::format::{LinearPcmFlags, SampleFormat, StreamFormat};
+    use crate::generator::{Generator, Waveform};
+
+    use super::*;
+
+    const RATE: f64 = 48_000.0;
+
+    fn tone(frequency: f64, amplitude: f32, frames: usize) -> Vec<f32> {
+        let format = StreamFormat::new(RATE, SampleFormat::F32, LinearPcmFlags::IS_FLOAT, 1);
+        let mut generator = Generator::new(Waveform::Sine(frequency), &format);
+        generator.set_amplitude(amplitude);
+        let mut out = vec![0.0; frames];
+        generator.fill(&mut out);
+        out
+    }
+
+    #[test]
+    fn levels() {
+        let sine = tone(440.0, 0.5, 48_000);
+        assert!((to_dbfs(peak(&sine)) + 6.02).abs() < 0.01);
+        assert!((rms(&sine) - 0.5 / 2_f32.sqrt()).abs() < 1e-4);
+        assert!(dc_offset(&sine).abs() < 1e-4);
+
+        assert_eq!(peak(&[i16::MIN, 0]), 1.0);
+        assert_eq!(dc_offset(&[0.25_f32; 4]), 0.25);
+    }
+
+    #[test]
+    fn fft_matches_dft() {
+        let mut re: Vec<f64> = (0..16).map(|i| ((i * 7) % 5) as f64 - 2.0).collect();
+        let mut im = vec![0.0; 16];
+        let input = re.clone();
+
+        fft(&mut re, &mut im);
+
+        for k in 0..16 {
+            let (mut dr, mut di) = (0.0, 0.0);
+            for (n, x) in input.iter().enumerate() {
+                let angle = -2.0 * PI * (k * n) as f64 / 16.0;
+                dr += x * angle.cos();
+                di += x * angle.sin();
+            }
+            assert!((re[k] - dr).abs() < 1e-9 && (im[k] - di).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn dominant_frequency_and_thd() {
+        let sine = tone(440.0, 0.5, 16_384);
+        let spectrum = Spectrum::new(&sine, RATE);
+        assert!((spectrum.dominant_frequency() - 440.0).abs() < 0.5);
+        assert!(spectrum.thd_n(440.0) < 1e-4);
+
+        // 1% second harmonic.
+        let harmonic = tone(880.0, 0.005, 16_384);
+        let distorted: Vec<f32> = sine.iter().zip(&harmonic).map(|(a, b)| a + b).collect();
+        let spectrum = Spectrum::new(&distorted, RATE);
+        assert!((spectrum.thd_n(440.0) - 0.01).abs() < 1e-3);
+        // Harmonics are not noise.
+        assert!(spectrum.snr_db(440.0) > 80.0);
+    }
+
+    #[test]
+    fn snr_of_noisy_tone() {
+        let format = StreamFormat::new(RATE, SampleFormat::F32, LinearPcmFlags::IS_FLOAT, 1);
+        let mut noise = Generator::new(Waveform::WhiteNoise, &format);
+        noise.set_amplitude(0.01);
+        let mut noisy = vec![0.0_f32; 16_384];
+        noise.fill(&mut noisy);
+
+        for (n, s) in noisy.iter_mut().zip(tone(1000.0, 1.0, 16_384)) {
+            *n += s;
+        }
+
+        // Sine power 1/2, uniform noise power 0.01^2 / 3.
+        let expected = 10.0 * (0.5_f64 / (0.0001 / 3.0)).log10();
+        let snr = Spectrum::new(&noisy, RATE).snr_db(1000.0);
+        assert!((snr - expected).abs() < 1.0, "{snr} vs {expected}");
+    }
+
+    #[test]
+    fn cross_correlation_latency() {
+        let format = StreamFormat::new(RATE, SampleFormat::F32, LinearPcmFlags::IS_FLOAT, 1);
+        let mut noise = Generator::new(Waveform::WhiteNoise, &format);
+        let mut reference = vec![0.0_f32; 1024];
+        noise.fill(&mut reference);
+
+        let mut delayed = vec![0.0_f32; 37];
+        delayed.extend_from_slice(&reference);
+
+        assert_eq!(latency(&reference, &delayed, 100), Some(37));
+        assert_eq!(latency(&reference, &[0.0; 1024], 100), None);
+    }
+
+    #[test]
+    fn channels_of_lists() {
+        let mut list = AudioBufferList::<i16>::new(2, 2, 2);
+        list[1].samples_mut().copy_from_slice(&[1, 2, 3, 4]);
+
+        assert_eq!(
+            buffer_list_channel(&list, 3),
+            Some(vec![2.0 / 32768.0, 4.0 / 32768.0])
+        );
+        assert_eq!(buffer_list_channel(&list, 4), None);
+        assert_eq!(
+            channel(&[1.0_f32, 2.0, 3.0, 4.0], 2, 0),
+            Some(vec![1.0, 3.0])
+        );
+        assert_eq!(channel(&[1.0_f32, 2.0], 0, 0), None);
+        assert_eq!(channel(&[1.0_f32, 2.0], 2, 2), None);
+    }
+}
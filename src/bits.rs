@@ -0,0 +1,182 @@
+//! Reading and writing of bitstreams, most significant bit first.
+
+use crate::error::AudioFileError;
+use crate::CAError;
+
+/// Reader of the bits of a byte slice.
+///
+/// Reading past the end is an [`AudioFileError::InvalidFile`].
+#[derive(Debug, Clone)]
+pub(crate) struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        BitReader { data, position: 0 }
+    }
+
+    /// Bits left to read.
+    pub fn remaining(&self) -> usize {
+        (self.data.len() * 8).saturating_sub(self.position)
+    }
+
+    /// The next 32 bits without reading them, with zeros past the end.
+    pub fn peek32(&self) -> u32 {
+        let byte = self.position / 8;
+        let word = (0..5).fold(0_u64, |word, i| {
+            (word << 8) | *self.data.get(byte + i).unwrap_or(&0) as u64
+        });
+        (word >> (8 - self.position % 8)) as u32
+    }
+
+    /// Read `n` bits, at most 32.
+    pub fn read(&mut self, n: u32) -> Result<u32, CAError> {
+        debug_assert!(n <= 32);
+
+        if n == 0 {
+            return Ok(0);
+        }
+        if n as usize > self.remaining() {
+            return Err(AudioFileError::InvalidFile.into());
+        }
+
+        let value = self.peek32() >> (32 - n);
+        self.position += n as usize;
+        Ok(value)
+    }
+
+    /// Read `n` bits as a two's complement number.
+    pub fn read_signed(&mut self, n: u32) -> Result<i32, CAError> {
+        let value = self.read(n)?;
+        if n == 0 || n == 32 {
+            return Ok(value as i32);
+        }
+        Ok(((value << (32 - n)) as i32) >> (32 - n))
+    }
+
+    pub fn read_bit(&mut self) -> Result<bool, CAError> {
+        Ok(self.read(1)? == 1)
+    }
+
+    pub fn skip(&mut self, n: usize) -> Result<(), CAError> {
+        if n > self.remaining() {
+            return Err(AudioFileError::InvalidFile.into());
+        }
+        self.position += n;
+        Ok(())
+    }
+
+    /// Skip to the next byte boundary.
+    pub fn byte_align(&mut self) {
+        self.position = self.position.next_multiple_of(8);
+    }
+}
+
+/// Writer of bits to a growing byte vector.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct BitWriter {
+    bytes: Vec<u8>,
+    position: usize,
+}
+
+impl BitWriter {
+    pub fn new() -> Self {
+        BitWriter::default()
+    }
+
+    /// Bits written so far.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Write the low `n` bits of `value`, at most 32.
+    pub fn write(&mut self, value: u32, n: u32) {
+        debug_assert!(n <= 32);
+
+        let mut n = n;
+        while n > 0 {
+            let used = (self.position % 8) as u32;
+            if used == 0 {
+                self.bytes.push(0);
+            }
+
+            let take = (8 - used).min(n);
+            let chunk = (value >> (n - take)) & ((1 << take) - 1);
+            *self.bytes.last_mut().unwrap() |= (chunk << (8 - used - take)) as u8;
+
+            self.position += take as usize;
+            n -= take;
+        }
+    }
+
+    /// Pad with zeros to the next byte boundary.
+    pub fn byte_align(&mut self) {
+        self.position = self.bytes.len() * 8;
+    }
+
+    /// Drop everything after the first `position` bits.
+    pub fn truncate(&mut self, position: usize) {
+        if position >= self.position {
+            return;
+        }
+
+        self.bytes.truncate(position.div_ceil(8));
+        if position % 8 != 0 {
+            *self.bytes.last_mut().unwrap() &= 0xFF << (8 - position % 8);
+        }
+        self.position = position;
+    }
+
+    pub fn clear(&mut self) {
+        self.bytes.clear();
+        self.position = 0;
+    }
+
+    /// The bytes written, with the last one padded with zeros.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn write_and_read() {
+        let mut w = BitWriter::new();
+        w.write(0b101, 3);
+        w.write(0xABCD_EF01, 32);
+        w.write(0, 0);
+        w.write(0x7F, 7);
+        assert_eq!(w.position(), 42);
+
+        w.truncate(41);
+        w.write(0, 1);
+        w.byte_align();
+        assert_eq!(w.position(), 48);
+        w.write(1, 1);
+
+        let bytes = w.bytes().to_vec();
+        assert_eq!(bytes.len(), 7);
+        assert_eq!(bytes[6], 0x80);
+
+        let mut r = BitReader::new(&bytes);
+        assert_eq!(r.read(3).unwrap(), 0b101);
+        assert_eq!(r.peek32(), 0xABCD_EF01);
+        assert_eq!(r.read(32).unwrap(), 0xABCD_EF01);
+        assert_eq!(r.read_signed(7).unwrap(), -2);
+        r.byte_align();
+        assert_eq!(r.remaining(), 8);
+        assert!(r.read_bit().unwrap());
+        assert_eq!(r.remaining(), 7);
+        assert_eq!(r.peek32(), 0);
+
+        assert!(r.read(8).is_err());
+        assert!(r.skip(8).is_err());
+        r.skip(7).unwrap();
+        assert_eq!(r.read(0).unwrap(), 0);
+    }
+}
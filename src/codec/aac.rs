@@ -0,0 +1,904 @@
+//! MPEG-4 audio configuration: the AudioSpecificConfig of magic cookies and ADTS headers.
+//!
+//! There is no AAC codec, but streams can be described and split into packets.
+
+use crate::bits::BitReader;
+use crate::error::{AudioFileError, AudioFormatError};
+use crate::format::{AudioFormat, FormatDescription, Mpeg4ObjectId, PacketDescription};
+use crate::CAError;
+
+/// Sample rates by sampling frequency index.
+pub const SAMPLE_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+// Audio object types without an `Mpeg4ObjectId`.
+const AOT_SBR: u8 = 5;
+const AOT_ER_AAC_LD: u8 = 23;
+const AOT_PS: u8 = 29;
+const AOT_ER_AAC_ELD: u8 = 39;
+const AOT_USAC: u8 = 42;
+
+/// Sample rates by USAC sampling frequency index from 15, after those of [`SAMPLE_RATES`].
+const USAC_SAMPLE_RATES: [u32; 13] = [
+    57600, 51200, 40000, 38400, 34150, 28800, 25600, 20000, 19200, 17075, 14400, 12800, 9600,
+];
+
+// Extensions of an AudioSpecificConfig.
+const SYNC_EXTENSION_SBR: u32 = 0x2B7;
+const SYNC_EXTENSION_PS: u32 = 0x548;
+
+// Extensions of an ELDSpecificConfig.
+const ELDEXT_TERM: u32 = 0;
+const ELDEXT_LDSAC: u32 = 1;
+
+// Syntactic element of a program config element in a raw data block.
+const ID_PCE: u32 = 5;
+
+/// An MPEG-4 AudioSpecificConfig, the magic cookie of AAC.
+///
+/// Extensions like SBR only change the output of the decoder, the other fields are those
+/// of the core coder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioSpecificConfig {
+    /// Audio object type of the core coder, 2 for AAC LC.
+    pub object_type: u8,
+    pub sample_rate: u32,
+    /// 0 if the channels are given by a program config element.
+    pub channel_config: u8,
+    pub channels: u32,
+    /// Frames in a packet, 0 if unknown.
+    pub frame_length: u32,
+    /// Output sample rate of spectral band replication, for HE-AAC and ELD with SBR.
+    pub sbr_sample_rate: Option<u32>,
+    /// Parametric stereo, for HE-AAC v2.
+    pub ps: bool,
+    /// Low delay MPEG surround, for ELD v2.
+    pub ld_mps: bool,
+}
+
+impl AudioSpecificConfig {
+    /// Parse an AudioSpecificConfig.
+    ///
+    /// Fails with [`AudioFormatError::UnsupportedDataFormat`] for object types that no
+    /// [`AudioFormat`] describes.
+    pub fn parse(bytes: &[u8]) -> Result<Self, CAError> {
+        let mut bits = BitReader::new(bytes);
+
+        let mut object_type = read_object_type(&mut bits)?;
+        let sample_rate = read_sample_rate(&mut bits)?;
+        let channel_config = bits.read(4)? as u8;
+
+        let mut sbr_sample_rate = None;
+        let mut ps = false;
+        // Explicit hierarchical signaling, the core follows.
+        if object_type == AOT_SBR || object_type == AOT_PS {
+            ps = object_type == AOT_PS;
+            sbr_sample_rate = Some(read_sample_rate(&mut bits)?);
+            object_type = read_object_type(&mut bits)?;
+        }
+
+        let mut config = AudioSpecificConfig {
+            object_type,
+            sample_rate,
+            channel_config,
+            channels: 0,
+            frame_length: 0,
+            sbr_sample_rate,
+            ps,
+            ld_mps: false,
+        };
+
+        if channel_config != 0 {
+            config.channels = channel_count(channel_config).ok_or(AudioFileError::InvalidFile)?;
+        }
+
+        match object_type {
+            1..=4 | 6 | 7 => config.read_ga_specific(&mut bits)?,
+            8 | 9 => (),
+            AOT_ER_AAC_LD => {
+                config.read_ga_specific(&mut bits)?;
+                config.frame_length /= 2;
+                // The error protection config.
+                bits.skip(2)?;
+            }
+            AOT_ER_AAC_ELD => {
+                config.read_eld_specific(&mut bits)?;
+                bits.skip(2)?;
+            }
+            AOT_USAC => config.read_usac_config(&mut bits)?,
+            _ => return Err(AudioFormatError::UnsupportedDataFormat.into()),
+        }
+
+        // Backward compatible signaling of SBR and PS, after the core config.
+        if config.sbr_sample_rate.is_none()
+            && object_type != AOT_USAC
+            && bits.remaining() >= 16
+            && bits.read(11)? == SYNC_EXTENSION_SBR
+            && read_object_type(&mut bits)? == AOT_SBR
+            && bits.read_bit()?
+        {
+            config.sbr_sample_rate = Some(read_sample_rate(&mut bits)?);
+            if bits.remaining() >= 12 && bits.read(11)? == SYNC_EXTENSION_PS {
+                config.ps = bits.read_bit()?;
+            }
+        }
+
+        if config.channels == 0 || config.sample_rate == 0 || config.output_sample_rate() == 0 {
+            return Err(AudioFileError::InvalidFile.into());
+        }
+
+        Ok(config)
+    }
+
+    /// Parse the magic cookie of AAC: an `esds` atom, the ES_Descriptor in it as in CAF
+    /// files, or a bare AudioSpecificConfig.
+    pub fn from_magic_cookie(mut cookie: &[u8]) -> Result<Self, CAError> {
+        if cookie.get(4..8) == Some(b"esds") {
+            // Atom header, version and flags.
+            cookie = cookie.get(12..).ok_or(AudioFileError::InvalidFile)?;
+        }
+        // An AudioSpecificConfig never starts with object type 0.
+        if cookie.first() != Some(&ES_DESCRIPTOR) {
+            return AudioSpecificConfig::parse(cookie);
+        }
+
+        let (_, es) = read_descriptor(cookie, ES_DESCRIPTOR)?;
+        let flags = *es.get(2).ok_or(AudioFileError::InvalidFile)?;
+        let mut at = 3;
+        if flags & 0x80 != 0 {
+            // Stream dependence.
+            at += 2;
+        }
+        if flags & 0x40 != 0 {
+            // URL.
+            at += 1 + *es.get(at).ok_or(AudioFileError::InvalidFile)? as usize;
+        }
+        if flags & 0x20 != 0 {
+            // OCR stream.
+            at += 2;
+        }
+
+        let es = es.get(at..).ok_or(AudioFileError::InvalidFile)?;
+        let (_, decoder_config) = read_descriptor(es, DECODER_CONFIG_DESCRIPTOR)?;
+        // MPEG-4 audio, or MPEG-2 AAC Main, LC or SSR.
+        if !matches!(decoder_config.first(), Some(0x40 | 0x66..=0x68)) {
+            return Err(AudioFormatError::UnsupportedDataFormat.into());
+        }
+
+        let specific = decoder_config
+            .get(13..)
+            .ok_or(AudioFileError::InvalidFile)?;
+        let (_, specific) = read_descriptor(specific, DECODER_SPECIFIC_INFO)?;
+        AudioSpecificConfig::parse(specific)
+    }
+
+    fn read_ga_specific(&mut self, bits: &mut BitReader) -> Result<(), CAError> {
+        let frame_length_flag = bits.read_bit()?;
+        self.frame_length = if frame_length_flag { 960 } else { 1024 };
+
+        if bits.read_bit()? {
+            // Core coder delay.
+            bits.skip(14)?;
+        }
+        let extension_flag = bits.read_bit()?;
+
+        if self.channel_config == 0 {
+            self.channels = read_program_config(bits)?;
+        }
+        if self.object_type == 6 {
+            // Layer number.
+            bits.skip(3)?;
+        }
+        if extension_flag {
+            if self.object_type == AOT_ER_AAC_LD {
+                // Resilience flags.
+                bits.skip(3)?;
+            }
+            // Extension flag 3.
+            bits.skip(1)?;
+        }
+
+        Ok(())
+    }
+
+    /// The start of a UsacConfig. USAC signals its output sample rate and frame length,
+    /// with SBR.
+    fn read_usac_config(&mut self, bits: &mut BitReader) -> Result<(), CAError> {
+        self.sample_rate = match bits.read(5)? as usize {
+            0x1F => bits.read(24)?,
+            index @ 0..=12 => SAMPLE_RATES[index],
+            index @ 15..=27 => USAC_SAMPLE_RATES[index - 15],
+            _ => return Err(AudioFileError::InvalidFile.into()),
+        };
+        self.frame_length = match bits.read(3)? {
+            0 => 768,
+            1 => 1024,
+            2 | 3 => 2048,
+            4 => 4096,
+            _ => return Err(AudioFileError::InvalidFile.into()),
+        };
+
+        self.channel_config = bits.read(5)? as u8;
+        self.channels = match self.channel_config {
+            // A UsacChannelConfig, starting with the number of output channels.
+            0 => {
+                let mut channels = bits.read(5)?;
+                if channels == 31 {
+                    let more = bits.read(8)?;
+                    channels += more;
+                    if more == 255 {
+                        channels += bits.read(16)?;
+                    }
+                }
+                channels
+            }
+            config => channel_count(config).ok_or(AudioFileError::InvalidFile)?,
+        };
+
+        Ok(())
+    }
+
+    fn read_eld_specific(&mut self, bits: &mut BitReader) -> Result<(), CAError> {
+        let frame_length_flag = bits.read_bit()?;
+        self.frame_length = if frame_length_flag { 480 } else { 512 };
+
+        // Resilience flags.
+        bits.skip(3)?;
+
+        if bits.read_bit()? {
+            let dual_rate = bits.read_bit()?;
+            self.sbr_sample_rate = Some(self.sample_rate * (1 + dual_rate as u32));
+            // CRC flag.
+            bits.skip(1)?;
+
+            let headers = match self.channel_config {
+                1 | 2 => 1,
+                3 => 2,
+                4..=6 => 3,
+                7 => 4,
+                _ => 0,
+            };
+            for _ in 0..headers {
+                skip_sbr_header(bits)?;
+            }
+        }
+
+        loop {
+            let extension = bits.read(4)?;
+            if extension == ELDEXT_TERM {
+                break;
+            }
+
+            let mut len = bits.read(4)? as usize;
+            if len == 15 {
+                len += bits.read(8)? as usize;
+                if len == 15 + 255 {
+                    len += bits.read(16)? as usize;
+                }
+            }
+            if extension == ELDEXT_LDSAC {
+                self.ld_mps = true;
+            }
+            bits.skip(len * 8)?;
+        }
+
+        Ok(())
+    }
+
+    /// The format of the stream.
+    pub fn format(&self) -> AudioFormat {
+        match self.object_type {
+            AOT_USAC => AudioFormat::MPEGD_USAC,
+            AOT_ER_AAC_ELD if self.ld_mps => AudioFormat::MPEG4AAC_ELD_V2,
+            AOT_ER_AAC_ELD if self.sbr_sample_rate.is_some() => AudioFormat::MPEG4AAC_ELD_SBR,
+            AOT_ER_AAC_ELD => AudioFormat::MPEG4AAC_ELD,
+            AOT_ER_AAC_LD => AudioFormat::MPEG4AAC_LD,
+            _ if self.ps => AudioFormat::MPEG4AAC_HE_V2,
+            _ if self.sbr_sample_rate.is_some() => AudioFormat::MPEG4AAC_HE,
+            7 => AudioFormat::MPEG4TwinVQ(Mpeg4ObjectId::TwinVQ),
+            8 => AudioFormat::MPEG4CELP(Mpeg4ObjectId::CELP),
+            9 => AudioFormat::MPEG4HVXC(Mpeg4ObjectId::HVXC),
+            _ => AudioFormat::MPEG4AAC(self.object_id().unwrap()),
+        }
+    }
+
+    /// The object type of the core coder.
+    pub fn object_id(&self) -> Option<Mpeg4ObjectId> {
+        Mpeg4ObjectId::from_u32(self.object_type as u32)
+    }
+
+    /// The sample rate of the decoded audio.
+    pub fn output_sample_rate(&self) -> u32 {
+        self.sbr_sample_rate.unwrap_or(self.sample_rate)
+    }
+
+    /// The channels of the decoded audio, parametric stereo makes mono stereo.
+    pub fn output_channels(&self) -> u32 {
+        if self.ps && self.channels == 1 {
+            2
+        } else {
+            self.channels
+        }
+    }
+
+    /// Decoded frames in a packet, 0 if unknown.
+    pub fn frames_per_packet(&self) -> u32 {
+        match self.sbr_sample_rate {
+            Some(rate) if rate == self.sample_rate * 2 => self.frame_length * 2,
+            _ => self.frame_length,
+        }
+    }
+
+    pub fn description(&self) -> FormatDescription {
+        FormatDescription {
+            sample_rate: self.output_sample_rate() as f64,
+            format: self.format(),
+            bytes_per_packet: 0,
+            frames_per_packet: self.frames_per_packet(),
+            channels: self.output_channels(),
+            bits_per_channel: 0,
+        }
+    }
+}
+
+fn read_object_type(bits: &mut BitReader) -> Result<u8, CAError> {
+    let object_type = bits.read(5)? as u8;
+    if object_type == 31 {
+        return Ok(32 + bits.read(6)? as u8);
+    }
+    Ok(object_type)
+}
+
+fn read_sample_rate(bits: &mut BitReader) -> Result<u32, CAError> {
+    match bits.read(4)? {
+        15 => bits.read(24),
+        index => SAMPLE_RATES
+            .get(index as usize)
+            .copied()
+            .ok_or_else(|| AudioFileError::InvalidFile.into()),
+    }
+}
+
+/// Channels of a channel configuration.
+pub fn channel_count(channel_config: u8) -> Option<u32> {
+    match channel_config {
+        1..=6 => Some(channel_config as u32),
+        7 | 12 | 14 => Some(8),
+        11 => Some(7),
+        13 => Some(24),
+        _ => None,
+    }
+}
+
+/// Read a program config element, returning the number of channels.
+fn read_program_config(bits: &mut BitReader) -> Result<u32, CAError> {
+    // Element instance tag, object type and sampling frequency index.
+    bits.skip(10)?;
+    let front = bits.read(4)?;
+    let side = bits.read(4)?;
+    let back = bits.read(4)?;
+    let lfe = bits.read(2)?;
+    let assoc_data = bits.read(3)?;
+    let coupling = bits.read(4)?;
+
+    for _ in 0..2 {
+        // Mono and stereo mixdown.
+        if bits.read_bit()? {
+            bits.skip(4)?;
+        }
+    }
+    if bits.read_bit()? {
+        // Matrix mixdown.
+        bits.skip(3)?;
+    }
+
+    let mut channels = lfe;
+    for _ in 0..front + side + back {
+        channels += 1 + bits.read(1)?;
+        bits.skip(4)?;
+    }
+    bits.skip((lfe + assoc_data) as usize * 4 + coupling as usize * 5)?;
+
+    bits.byte_align();
+    let comment = bits.read(8)?;
+    bits.skip(comment as usize * 8)?;
+
+    Ok(channels)
+}
+
+fn skip_sbr_header(bits: &mut BitReader) -> Result<(), CAError> {
+    // Amplitude resolution, start and stop frequency, crossover band and reserved.
+    bits.skip(14)?;
+    let extra_1 = bits.read_bit()?;
+    let extra_2 = bits.read_bit()?;
+    if extra_1 {
+        bits.skip(5)?;
+    }
+    if extra_2 {
+        bits.skip(6)?;
+    }
+    Ok(())
+}
+
+// Descriptor tags of an ES_Descriptor.
+const ES_DESCRIPTOR: u8 = 3;
+const DECODER_CONFIG_DESCRIPTOR: u8 = 4;
+const DECODER_SPECIFIC_INFO: u8 = 5;
+
+/// Read a descriptor with the tag, returning the bytes after it and its contents.
+fn read_descriptor(bytes: &[u8], tag: u8) -> Result<(&[u8], &[u8]), CAError> {
+    if bytes.first() != Some(&tag) {
+        return Err(AudioFileError::InvalidFile.into());
+    }
+
+    // Up to four bytes of seven bits.
+    let mut len = 0;
+    let mut at = 1;
+    loop {
+        let byte = *bytes.get(at).ok_or(AudioFileError::InvalidFile)?;
+        len = (len << 7) | (byte & 0x7F) as usize;
+        at += 1;
+        if byte & 0x80 == 0 || at == 5 {
+            break;
+        }
+    }
+
+    let contents = bytes.get(at..at + len).ok_or(AudioFileError::InvalidFile)?;
+    Ok((&bytes[at + len..], contents))
+}
+
+/// The header of an ADTS frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdtsHeader {
+    /// MPEG-2 rather than MPEG-4 AAC.
+    pub mpeg2: bool,
+    /// The frame has CRCs.
+    pub protected: bool,
+    /// Audio object type, 1 to 4.
+    pub object_type: u8,
+    pub sample_rate_index: u8,
+    /// 0 if the channels are given by a program config element.
+    pub channel_config: u8,
+    /// Size of the frame, with the header.
+    pub frame_bytes: u16,
+    pub buffer_fullness: u16,
+    /// Raw data blocks in the frame, 1 to 4.
+    pub raw_data_blocks: u8,
+}
+
+impl AdtsHeader {
+    /// Bytes needed to parse a header.
+    pub const SIZE: usize = 7;
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, CAError> {
+        if bytes.len() < AdtsHeader::SIZE {
+            return Err(AudioFileError::InvalidFile.into());
+        }
+        if !is_sync(bytes) {
+            return Err(AudioFileError::InvalidFile.into());
+        }
+
+        let mut bits = BitReader::new(bytes);
+        bits.skip(12)?;
+        let header = AdtsHeader {
+            mpeg2: bits.read_bit()?,
+            protected: {
+                bits.skip(2)?;
+                !bits.read_bit()?
+            },
+            object_type: bits.read(2)? as u8 + 1,
+            sample_rate_index: bits.read(4)? as u8,
+            channel_config: {
+                bits.skip(1)?;
+                bits.read(3)? as u8
+            },
+            frame_bytes: {
+                // Original, home and copyright bits.
+                bits.skip(4)?;
+                bits.read(13)? as u16
+            },
+            buffer_fullness: bits.read(11)? as u16,
+            raw_data_blocks: bits.read(2)? as u8 + 1,
+        };
+
+        if header.sample_rate_index as usize >= SAMPLE_RATES.len()
+            || (header.frame_bytes as usize) < header.header_bytes()
+        {
+            return Err(AudioFileError::InvalidFile.into());
+        }
+
+        Ok(header)
+    }
+
+    /// Size of the header with the CRC and block positions.
+    pub fn header_bytes(&self) -> usize {
+        if !self.protected {
+            return 7;
+        }
+        9 + 2 * (self.raw_data_blocks as usize - 1)
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        SAMPLE_RATES[self.sample_rate_index as usize]
+    }
+
+    /// Frames in the frame.
+    pub fn frames(&self) -> u32 {
+        1024 * self.raw_data_blocks as u32
+    }
+
+    /// The config of the stream, from the header and for channel configuration 0 the
+    /// program config element at the start of the frame.
+    pub fn config(&self, frame: &[u8]) -> Result<AudioSpecificConfig, CAError> {
+        let channels = match self.channel_config {
+            0 => {
+                let mut bits = BitReader::new(frame.get(self.header_bytes()..).unwrap_or(&[]));
+                if bits.read(3)? != ID_PCE {
+                    return Err(AudioFileError::InvalidFile.into());
+                }
+                read_program_config(&mut bits)?
+            }
+            config => channel_count(config).ok_or(AudioFileError::InvalidFile)?,
+        };
+
+        Ok(AudioSpecificConfig {
+            object_type: self.object_type,
+            sample_rate: self.sample_rate(),
+            channel_config: self.channel_config,
+            channels,
+            frame_length: 1024,
+            sbr_sample_rate: None,
+            ps: false,
+            ld_mps: false,
+        })
+    }
+
+    /// Append the raw data blocks of the frame to `data` and their descriptions to
+    /// `descriptions`.
+    ///
+    /// Frames with several blocks can only be split with CRCs.
+    pub fn split(
+        &self,
+        frame: &[u8],
+        data: &mut Vec<u8>,
+        descriptions: &mut Vec<PacketDescription>,
+    ) -> Result<(), CAError> {
+        let frame = frame
+            .get(..self.frame_bytes as usize)
+            .ok_or(AudioFileError::InvalidFile)?;
+        let start = self.header_bytes();
+        let mut push = |block: &[u8]| {
+            descriptions.push(PacketDescription {
+                start_offset: data.len() as u64,
+                variable_frames: 0,
+                data_byte_size: block.len() as u32,
+            });
+            data.extend_from_slice(block);
+        };
+
+        match (self.raw_data_blocks, self.protected) {
+            (1, _) => push(&frame[start..]),
+            (_, false) => return Err(AudioFormatError::UnsupportedDataFormat.into()),
+            (blocks, true) => {
+                // Positions of the blocks after the first, from the end of the header,
+                // each block followed by a CRC.
+                let mut positions = vec![0];
+                for i in 1..blocks as usize {
+                    let at = 7 + 2 * (i - 1);
+                    positions.push(u16::from_be_bytes([frame[at], frame[at + 1]]) as usize);
+                }
+                positions.push(frame.len() - start);
+
+                for pair in positions.windows(2) {
+                    let block = frame
+                        .get(start + pair[0]..(start + pair[1]).saturating_sub(2))
+                        .ok_or(AudioFileError::InvalidFile)?;
+                    push(block);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether the bytes start with the sync word of an ADTS frame.
+pub fn is_sync(bytes: &[u8]) -> bool {
+    bytes.len() >= 2 && bytes[0] == 0xFF && bytes[1] & 0xF6 == 0xF0
+}
+
+/// Split an ADTS stream into raw packets appended to `data`, with descriptions relative to
+/// the start of `data`. Returns the config of the first frame.
+///
+/// Bytes between frames are skipped, the stream has to end with a complete frame.
+pub fn split_adts(
+    stream: &[u8],
+    data: &mut Vec<u8>,
+    descriptions: &mut Vec<PacketDescription>,
+) -> Result<AudioSpecificConfig, CAError> {
+    let mut config = None;
+    let mut at = 0;
+
+    while at < stream.len() {
+        let header = match AdtsHeader::parse(&stream[at..]) {
+            Ok(header) => header,
+            Err(_) if stream.len() - at >= AdtsHeader::SIZE => {
+                at += 1;
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+
+        let frame = &stream[at..];
+        if frame.len() < header.frame_bytes as usize {
+            return Err(AudioFileError::EndOfFile.into());
+        }
+        if config.is_none() {
+            config = Some(header.config(frame)?);
+        }
+        header.split(frame, data, descriptions)?;
+        at += header.frame_bytes as usize;
+    }
+
+    config.ok_or_else(|| AudioFileError::InvalidFile.into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bits::BitWriter;
+
+    #[test]
+    fn audio_specific_config() {
+        // AAC LC, 44.1 kHz, stereo.
+        let config = AudioSpecificConfig::parse(&[0x12, 0x10]).unwrap();
+        assert_eq!(
+            config.format(),
+            AudioFormat::MPEG4AAC(Mpeg4ObjectId::AAC_LC)
+        );
+        assert_eq!(config.object_id(), Some(Mpeg4ObjectId::AAC_LC));
+        assert_eq!((config.sample_rate, config.channels), (44100, 2));
+        assert_eq!(config.frames_per_packet(), 1024);
+
+        // HE-AAC with explicit signaling: 22.05 kHz LC core with SBR to 44.1 kHz.
+        let config = AudioSpecificConfig::parse(&[0x2B, 0x92, 0x08, 0x00]).unwrap();
+        assert_eq!(config.format(), AudioFormat::MPEG4AAC_HE);
+        assert_eq!(config.object_id(), Some(Mpeg4ObjectId::AAC_LC));
+        assert_eq!(config.sample_rate, 22050);
+        assert_eq!(config.output_sample_rate(), 44100);
+        assert_eq!(config.frames_per_packet(), 2048);
+
+        // HE-AAC v2: mono core with PS, signaled after the core config.
+        let mut w = BitWriter::new();
+        w.write(2, 5);
+        w.write(6, 4);
+        w.write(1, 4);
+        w.write(0, 3);
+        w.write(SYNC_EXTENSION_SBR, 11);
+        w.write(AOT_SBR as u32, 5);
+        w.write(1, 1);
+        w.write(3, 4);
+        w.write(SYNC_EXTENSION_PS, 11);
+        w.write(1, 1);
+        let config = AudioSpecificConfig::parse(w.bytes()).unwrap();
+        assert_eq!(config.format(), AudioFormat::MPEG4AAC_HE_V2);
+        assert_eq!(config.output_channels(), 2);
+        let description = config.description();
+        assert_eq!(description.sample_rate, 48000.0);
+        assert_eq!(description.frames_per_packet, 2048);
+        assert_eq!(description.channels, 2);
+
+        // ELD, 480 frames, with dual rate SBR and LD MPEG surround.
+        let mut w = BitWriter::new();
+        w.write(31, 5);
+        w.write((AOT_ER_AAC_ELD - 32) as u32, 6);
+        w.write(3, 4);
+        w.write(1, 4);
+        w.write(0b1_000, 4);
+        w.write(0b1_1_0, 3);
+        w.write(0, 14);
+        w.write(0b10, 2);
+        w.write(0, 5);
+        w.write(ELDEXT_LDSAC, 4);
+        w.write(1, 4);
+        w.write(0xAA, 8);
+        w.write(ELDEXT_TERM, 4);
+        w.write(0, 2);
+        let config = AudioSpecificConfig::parse(w.bytes()).unwrap();
+        assert_eq!(config.format(), AudioFormat::MPEG4AAC_ELD_V2);
+        assert_eq!(config.frame_length, 480);
+        assert_eq!(config.output_sample_rate(), 96000);
+        assert_eq!(config.frames_per_packet(), 960);
+
+        // AAC LD at an explicit sample rate.
+        let mut w = BitWriter::new();
+        w.write(AOT_ER_AAC_LD as u32, 5);
+        w.write(15, 4);
+        w.write(44000, 24);
+        w.write(2, 4);
+        w.write(0, 5);
+        let config = AudioSpecificConfig::parse(w.bytes()).unwrap();
+        assert_eq!(config.format(), AudioFormat::MPEG4AAC_LD);
+        assert_eq!((config.sample_rate, config.frame_length), (44000, 512));
+
+        // Main with a program config element: center, front pair, back pair and LFE.
+        let mut w = BitWriter::new();
+        w.write(1, 5);
+        w.write(3, 4);
+        w.write(0, 4);
+        w.write(0, 3);
+        w.write(0, 10);
+        w.write(2, 4);
+        w.write(0, 4);
+        w.write(1, 4);
+        w.write(1, 2);
+        w.write(0, 3 + 4 + 3);
+        for is_cpe in [0, 1, 1] {
+            w.write(is_cpe, 1);
+            w.write(0, 4);
+        }
+        w.write(0, 4);
+        w.byte_align();
+        w.write(0, 8);
+        let config = AudioSpecificConfig::parse(w.bytes()).unwrap();
+        assert_eq!(
+            config.format(),
+            AudioFormat::MPEG4AAC(Mpeg4ObjectId::AAC_Main)
+        );
+        assert_eq!(config.channels, 6);
+
+        // USAC at 48 kHz with 2:1 SBR, stereo, then a custom layout of 12 channels.
+        let mut w = BitWriter::new();
+        w.write(31, 5);
+        w.write((AOT_USAC - 32) as u32, 6);
+        w.write(3, 4);
+        w.write(2, 4);
+        w.write(3, 5);
+        w.write(3, 3);
+        w.write(2, 5);
+        w.byte_align();
+        let config = AudioSpecificConfig::parse(w.bytes()).unwrap();
+        assert_eq!(config.format(), AudioFormat::MPEGD_USAC);
+        assert_eq!(config.object_id(), None);
+        assert_eq!(config.output_sample_rate(), 48000);
+        assert_eq!((config.frames_per_packet(), config.channels), (2048, 2));
+
+        let bytes = w.bytes().to_vec();
+        w.truncate(bytes.len() * 8 - 13);
+        w.write(0x1F, 5);
+        w.write(57600, 24);
+        w.write(0, 3);
+        w.write(0, 5);
+        w.write(12, 5);
+        w.byte_align();
+        let config = AudioSpecificConfig::parse(w.bytes()).unwrap();
+        assert_eq!((config.sample_rate, config.frame_length), (57600, 768));
+        assert_eq!(config.channels, 12);
+
+        assert!(matches!(
+            AudioSpecificConfig::parse(&[0x88, 0x10]),
+            Err(CAError::AudioFormatError(
+                AudioFormatError::UnsupportedDataFormat
+            ))
+        ));
+        assert!(AudioSpecificConfig::parse(&[0x12]).is_err());
+        assert!(AudioSpecificConfig::parse(&[0x17, 0x10]).is_err());
+    }
+
+    #[test]
+    fn magic_cookie() {
+        // The esds of an MP4 file with AAC LC at 48 kHz, stereo.
+        let esds = [
+            0, 0, 0, 39, b'e', b's', b'd', b's', 0, 0, 0, 0, //
+            0x03, 0x19, 0x00, 0x01, 0x00, //
+            0x04, 0x11, 0x40, 0x15, 0x00, 0x18, 0x00, 0x00, 0x01, 0xF4, 0x00, 0x00, 0x01, 0xF4,
+            0x00, //
+            0x05, 0x02, 0x11, 0x90, //
+            0x06, 0x01, 0x02,
+        ];
+        let config = AudioSpecificConfig::from_magic_cookie(&esds).unwrap();
+        assert_eq!((config.sample_rate, config.channels), (48000, 2));
+
+        // The ES_Descriptor alone, with long lengths as written by Core Audio.
+        let cookie = [
+            0x03, 0x80, 0x80, 0x80, 0x22, 0x00, 0x00, 0x00, //
+            0x04, 0x80, 0x80, 0x80, 0x14, 0x40, 0x15, 0x00, 0x18, 0x00, 0x00, 0x01, 0xF4, 0x00,
+            0x00, 0x01, 0xF4, 0x00, //
+            0x05, 0x80, 0x80, 0x80, 0x02, 0x12, 0x08, //
+            0x06, 0x80, 0x80, 0x80, 0x01, 0x02,
+        ];
+        let config = AudioSpecificConfig::from_magic_cookie(&cookie).unwrap();
+        assert_eq!((config.sample_rate, config.channels), (44100, 1));
+
+        assert_eq!(
+            AudioSpecificConfig::from_magic_cookie(&[0x12, 0x10]).unwrap(),
+            AudioSpecificConfig::parse(&[0x12, 0x10]).unwrap()
+        );
+
+        // MP3 has no AudioSpecificConfig.
+        let mut mp3 = cookie;
+        mp3[13] = 0x6B;
+        assert!(AudioSpecificConfig::from_magic_cookie(&mp3).is_err());
+        assert!(AudioSpecificConfig::from_magic_cookie(&cookie[..20]).is_err());
+    }
+
+    fn adts_frame(payload: &[u8]) -> Vec<u8> {
+        let len = payload.len() + 7;
+        let mut frame = vec![
+            0xFF,
+            0xF1,
+            0x50,
+            0x80 | (len >> 11) as u8,
+            (len >> 3) as u8,
+            ((len & 7) << 5) as u8 | 0x1F,
+            0xFC,
+        ];
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn adts_header() {
+        let header = AdtsHeader::parse(&[0xFF, 0xF1, 0x50, 0x80, 0x2E, 0x7F, 0xFC]).unwrap();
+        assert_eq!(
+            header,
+            AdtsHeader {
+                mpeg2: false,
+                protected: false,
+                object_type: 2,
+                sample_rate_index: 4,
+                channel_config: 2,
+                frame_bytes: 371,
+                buffer_fullness: 0x7FF,
+                raw_data_blocks: 1,
+            }
+        );
+        assert_eq!(header.sample_rate(), 44100);
+        assert_eq!(header.frames(), 1024);
+        let config = header.config(&[]).unwrap();
+        assert_eq!(config, AudioSpecificConfig::parse(&[0x12, 0x10]).unwrap());
+
+        assert!(AdtsHeader::parse(&[0xFF, 0xF1, 0x50, 0x80, 0x2E]).is_err());
+        // Layer must be 0.
+        assert!(AdtsHeader::parse(&[0xFF, 0xF3, 0x50, 0x80, 0x2E, 0x7F, 0xFC]).is_err());
+        // Sampling frequency index 13.
+        assert!(AdtsHeader::parse(&[0xFF, 0xF1, 0x74, 0x80, 0x2E, 0x7F, 0xFC]).is_err());
+    }
+
+    #[test]
+    fn split_stream() {
+        let mut stream = adts_frame(&[1; 10]);
+        stream.extend_from_slice(&[0xFF, 0x00, 0x42]);
+        stream.extend(adts_frame(&[2; 20]));
+
+        // Two blocks with CRCs, the second at 5 bytes.
+        let mut protected = vec![0xFF, 0xF0, 0x50, 0x80, 0x00, 0x00, 0x1D, 0x00, 0x05, 0, 0];
+        protected.extend_from_slice(&[3, 3, 3, 0, 0, 4, 4, 0, 0]);
+        protected[4] = (protected.len() >> 3) as u8;
+        protected[5] = ((protected.len() & 7) << 5) as u8 | 0x1F;
+        stream.extend_from_slice(&protected);
+
+        let mut data = vec![9];
+        let mut descriptions = Vec::new();
+        let config = split_adts(&stream, &mut data, &mut descriptions).unwrap();
+        assert_eq!(config.description().frames_per_packet, 1024);
+
+        let packets: Vec<_> = descriptions
+            .iter()
+            .map(|d| &data[d.start_offset as usize..][..d.data_byte_size as usize])
+            .collect();
+        assert_eq!(
+            packets,
+            [&[1; 10][..], &[2; 20][..], &[3, 3, 3][..], &[4, 4][..]]
+        );
+
+        // Truncated.
+        let mut data = Vec::new();
+        assert!(split_adts(&stream[..stream.len() - 1], &mut data, &mut descriptions).is_err());
+        // Several blocks without CRCs.
+        let mut frame = adts_frame(&[0; 8]);
+        frame[6] |= 1;
+        assert!(split_adts(&frame, &mut data, &mut descriptions).is_err());
+    }
+}
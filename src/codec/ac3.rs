@@ -0,0 +1,452 @@
+//! AC-3 and E-AC-3 sync frames, and their IEC 61937 bursts for passthrough over IEC 60958
+//! links like S/PDIF and HDMI.
+
+use crate::bits::BitReader;
+use crate::error::{AudioCodecError, AudioFileError};
+use crate::format::{LinearPcmFlags, SampleFormat, StreamFormat};
+use crate::unit::AudioBufferList;
+use crate::CAError;
+
+/// Syncword at the start of every sync frame.
+pub const SYNC_WORD: u16 = 0x0B77;
+
+/// Bitrates in kbit/s, by frame size code / 2.
+const BITRATES: [u16; 19] = [
+    32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384, 448, 512, 576, 640,
+];
+
+const SAMPLE_RATES: [u32; 3] = [48000, 44100, 32000];
+
+/// Blocks of 256 frames, by E-AC-3 number of blocks code.
+const BLOCKS: [u8; 4] = [1, 2, 3, 6];
+
+/// Full bandwidth channels by audio coding mode.
+const ACMOD_CHANNELS: [u32; 8] = [2, 1, 2, 3, 3, 4, 4, 5];
+
+/// Dependent substreams of E-AC-3.
+const STREAM_TYPE_DEPENDENT: u8 = 1;
+
+/// Preamble words of an IEC 61937 burst.
+const PA: u16 = 0xF872;
+const PB: u16 = 0x4E1F;
+
+/// IEC 61937 data types.
+const DATA_TYPE_AC3: u16 = 1;
+const DATA_TYPE_EAC3: u16 = 21;
+
+/// The header of an AC-3 or E-AC-3 sync frame, the sync info and the start of the bit
+/// stream info.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ac3Header {
+    /// E-AC-3, with a bit stream id from 11 to 16.
+    pub eac3: bool,
+    pub bsid: u8,
+    /// Bit stream mode, the kind of service. Always 0 for E-AC-3.
+    pub bsmod: u8,
+    /// Audio coding mode, the arrangement of the full bandwidth channels.
+    pub acmod: u8,
+    pub lfe: bool,
+    pub sample_rate: u32,
+    /// Size of the frame, with the header.
+    pub frame_bytes: u16,
+    /// Blocks of 256 frames of audio, always 6 for AC-3.
+    pub blocks: u8,
+    /// E-AC-3 stream type: 0 and 2 are independent, 1 is dependent.
+    pub stream_type: u8,
+    pub substream_id: u8,
+}
+
+impl Ac3Header {
+    /// The sync info and the bit stream info up to the LFE flag.
+    pub const SIZE: usize = 8;
+
+    /// Parse the header of a sync frame.
+    pub fn parse(bytes: &[u8]) -> Result<Self, CAError> {
+        let invalid = || CAError::from(AudioFileError::InvalidFile);
+        let bytes = bytes.get(..Self::SIZE).ok_or_else(invalid)?;
+        if u16::from_be_bytes([bytes[0], bytes[1]]) != SYNC_WORD {
+            return Err(invalid());
+        }
+
+        // At the same position in both syntaxes.
+        match bytes[5] >> 3 {
+            0..=10 => Self::parse_ac3(bytes),
+            11..=16 => Self::parse_eac3(bytes),
+            _ => Err(invalid()),
+        }
+    }
+
+    fn parse_ac3(bytes: &[u8]) -> Result<Self, CAError> {
+        let mut r = BitReader::new(&bytes[4..]);
+        let fscod = r.read(2)? as usize;
+        let frmsizecod = r.read(6)? as usize;
+        if fscod == 3 || frmsizecod >= 2 * BITRATES.len() {
+            return Err(AudioFileError::InvalidFile.into());
+        }
+
+        let bsid = r.read(5)? as u8;
+        let bsmod = r.read(3)? as u8;
+        let acmod = r.read(3)? as u8;
+        // Center, surround and Dolby Surround mix levels.
+        if acmod & 1 != 0 && acmod != 1 {
+            r.skip(2)?;
+        }
+        if acmod & 4 != 0 {
+            r.skip(2)?;
+        }
+        if acmod == 2 {
+            r.skip(2)?;
+        }
+        let lfe = r.read_bit()?;
+
+        let bitrate = BITRATES[frmsizecod / 2] as usize;
+        let words = match fscod {
+            0 => bitrate * 2,
+            1 => bitrate * 1000 * 1536 / 44100 / 16 + (frmsizecod & 1),
+            _ => bitrate * 3,
+        };
+
+        Ok(Ac3Header {
+            eac3: false,
+            bsid,
+            bsmod,
+            acmod,
+            lfe,
+            // Half and quarter rates of bit stream ids 9 and 10.
+            sample_rate: SAMPLE_RATES[fscod] >> bsid.saturating_sub(8),
+            frame_bytes: words as u16 * 2,
+            blocks: 6,
+            stream_type: 0,
+            substream_id: 0,
+        })
+    }
+
+    fn parse_eac3(bytes: &[u8]) -> Result<Self, CAError> {
+        let mut r = BitReader::new(&bytes[2..]);
+        let stream_type = r.read(2)? as u8;
+        let substream_id = r.read(3)? as u8;
+        let words = r.read(11)? + 1;
+        if stream_type == 3 || words < 4 {
+            return Err(AudioFileError::InvalidFile.into());
+        }
+
+        let (sample_rate, blocks) = match r.read(2)? as usize {
+            3 => match r.read(2)? as usize {
+                3 => return Err(AudioFileError::InvalidFile.into()),
+                fscod2 => (SAMPLE_RATES[fscod2] / 2, 6),
+            },
+            fscod => (SAMPLE_RATES[fscod], BLOCKS[r.read(2)? as usize]),
+        };
+
+        let acmod = r.read(3)? as u8;
+        let lfe = r.read_bit()?;
+        let bsid = r.read(5)? as u8;
+
+        Ok(Ac3Header {
+            eac3: true,
+            bsid,
+            bsmod: 0,
+            acmod,
+            lfe,
+            sample_rate,
+            frame_bytes: words as u16 * 2,
+            blocks,
+            stream_type,
+            substream_id,
+        })
+    }
+
+    /// Channels, with the LFE channel.
+    pub fn channels(&self) -> u32 {
+        ACMOD_CHANNELS[self.acmod as usize] + self.lfe as u32
+    }
+
+    /// Frames of audio in the sync frame.
+    pub fn frames(&self) -> u32 {
+        self.blocks as u32 * 256
+    }
+
+    /// In bit/s.
+    pub fn bitrate(&self) -> u32 {
+        (self.frame_bytes as u64 * 8 * self.sample_rate as u64 / self.frames() as u64) as u32
+    }
+
+    /// Whether the frame starts a new access unit, as opposed to a dependent substream
+    /// that extends the frame before it.
+    pub fn is_independent(&self) -> bool {
+        self.stream_type != STREAM_TYPE_DEPENDENT
+    }
+}
+
+/// Packer of AC-3 or E-AC-3 sync frames into IEC 61937 bursts.
+///
+/// Each burst is interleaved 16-bit stereo, to be played as linear PCM at the rate of
+/// [`output_format`](Self::output_format) by a device that passes it through untouched.
+/// An AC-3 burst holds one sync frame, an E-AC-3 burst the sync frames of 1536 frames
+/// of audio.
+#[derive(Debug, Clone)]
+pub struct Iec61937Packer {
+    eac3: bool,
+    sample_rate: u32,
+    /// Bit stream mode of the first frame of the burst.
+    bsmod: u8,
+    /// Frames of the next burst.
+    pending: Vec<u8>,
+    pending_blocks: u32,
+}
+
+impl Iec61937Packer {
+    /// A packer for the stream of the header, the first frame of which can then be
+    /// packed.
+    pub fn new(header: &Ac3Header) -> Self {
+        Iec61937Packer {
+            eac3: header.eac3,
+            sample_rate: header.sample_rate,
+            bsmod: 0,
+            pending: Vec::new(),
+            pending_blocks: 0,
+        }
+    }
+
+    /// Stereo frames of a burst, the repetition period.
+    pub fn burst_frames(&self) -> usize {
+        if self.eac3 {
+            6144
+        } else {
+            1536
+        }
+    }
+
+    /// The format the bursts are played at, four times the sample rate for E-AC-3.
+    pub fn output_format(&self) -> StreamFormat {
+        let rate = if self.eac3 { 4 } else { 1 } * self.sample_rate;
+        StreamFormat::new(
+            rate as f64,
+            SampleFormat::I16,
+            LinearPcmFlags::IS_SIGNED_INTEGER,
+            2,
+        )
+    }
+
+    /// Add a packet of one or more sync frames, like an E-AC-3 frame with its dependent
+    /// substreams, returns a burst when one is complete.
+    ///
+    /// Frames of another format or sample rate are an
+    /// [`AudioCodecError::UnsupportedFormat`].
+    pub fn pack(&mut self, packet: &[u8]) -> Result<Option<AudioBufferList<i16>>, CAError> {
+        let bsmod = if self.pending.is_empty() {
+            Some(Ac3Header::parse(packet)?.bsmod)
+        } else {
+            None
+        };
+        if self.pending.len() + packet.len() > (self.burst_frames() * 2 - 4) * 2 {
+            return Err(AudioCodecError::NotEnoughBufferSpace.into());
+        }
+
+        // Check every frame before taking any, so that errors leave the burst as it was.
+        let mut blocks = 0;
+        let mut rest = packet;
+        while !rest.is_empty() {
+            let header = Ac3Header::parse(rest)?;
+            if (header.eac3, header.sample_rate) != (self.eac3, self.sample_rate) {
+                return Err(AudioCodecError::UnsupportedFormat.into());
+            }
+            if rest.len() < header.frame_bytes as usize {
+                return Err(AudioFileError::EndOfFile.into());
+            }
+
+            if header.is_independent() {
+                blocks += header.blocks as u32;
+            }
+            rest = &rest[header.frame_bytes as usize..];
+        }
+
+        if let Some(bsmod) = bsmod {
+            self.bsmod = bsmod;
+        }
+        self.pending.extend_from_slice(packet);
+        self.pending_blocks += blocks;
+
+        if self.pending_blocks < 6 {
+            return Ok(None);
+        }
+
+        let samples = self.burst();
+        self.pending.clear();
+        self.pending_blocks = 0;
+
+        let mut list = AudioBufferList::new(1, 2, self.burst_frames());
+        list[0].copy_from_slice(&samples);
+        Ok(Some(list))
+    }
+
+    /// Interleaved samples of a burst of the pending frames.
+    fn burst(&self) -> Vec<i16> {
+        let (pc, pd) = if self.eac3 {
+            (DATA_TYPE_EAC3, self.pending.len())
+        } else {
+            // Length in bits.
+            (
+                DATA_TYPE_AC3 | (self.bsmod as u16) << 8,
+                self.pending.len() * 8,
+            )
+        };
+
+        let mut samples = vec![0; self.burst_frames() * 2];
+        samples[..4].copy_from_slice(&[PA as i16, PB as i16, pc as i16, pd as i16]);
+        for (sample, word) in samples[4..].iter_mut().zip(self.pending.chunks(2)) {
+            *sample = i16::from_be_bytes([word[0], *word.get(1).unwrap_or(&0)]);
+        }
+        samples
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A sync frame with the header and zeros.
+    fn frame(header: &[u8]) -> Vec<u8> {
+        let mut frame = header.to_vec();
+        frame.resize(Ac3Header::parse(header).unwrap().frame_bytes as usize, 0);
+        frame
+    }
+
+    #[test]
+    fn ac3_header() {
+        // 3/2 with LFE at 48 kHz, 448 kbit/s.
+        let header = Ac3Header::parse(&[0x0B, 0x77, 0, 0, 0x1E, 0x40, 0xE1, 0]).unwrap();
+        assert!(!header.eac3);
+        assert_eq!((header.bsid, header.bsmod, header.acmod), (8, 0, 7));
+        assert!(header.lfe);
+        assert_eq!(header.channels(), 6);
+        assert_eq!((header.sample_rate, header.frame_bytes), (48000, 1792));
+        assert_eq!((header.frames(), header.bitrate()), (1536, 448000));
+
+        // Stereo at 44.1 kHz, 192 kbit/s, odd frame size code, commentary.
+        let header = Ac3Header::parse(&[0x0B, 0x77, 0, 0, 0x55, 0x45, 0x40, 0]).unwrap();
+        assert_eq!((header.bsmod, header.acmod), (5, 2));
+        assert!(!header.lfe);
+        assert_eq!((header.sample_rate, header.frame_bytes), (44100, 836));
+
+        // Mono at 32 kHz, 640 kbit/s, LFE right after the coding mode.
+        let header = Ac3Header::parse(&[0x0B, 0x77, 0, 0, 0xA4, 0x40, 0x30, 0]).unwrap();
+        assert_eq!(header.channels(), 2);
+        assert_eq!((header.sample_rate, header.frame_bytes), (32000, 3840));
+
+        // Half rate.
+        let header = Ac3Header::parse(&[0x0B, 0x77, 0, 0, 0x00, 0x48, 0x40, 0]).unwrap();
+        assert_eq!((header.sample_rate, header.frame_bytes), (24000, 128));
+
+        // Bad sync word, sample rate, frame size code and bit stream id.
+        for bytes in [
+            [0x0B, 0x78, 0, 0, 0x1E, 0x40, 0xE1, 0],
+            [0x0B, 0x77, 0, 0, 0xDE, 0x40, 0xE1, 0],
+            [0x0B, 0x77, 0, 0, 0x26, 0x40, 0xE1, 0],
+            [0x0B, 0x77, 0, 0, 0x1E, 0x88, 0xE1, 0],
+        ] {
+            assert!(Ac3Header::parse(&bytes).is_err(), "{bytes:x?}");
+        }
+        assert!(Ac3Header::parse(&[0x0B, 0x77, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn eac3_header() {
+        // Independent 3/2 with LFE at 48 kHz, 6 blocks, 1536 bytes.
+        let header = Ac3Header::parse(&[0x0B, 0x77, 0x02, 0xFF, 0x3F, 0x87, 0xC0, 0]).unwrap();
+        assert!(header.eac3);
+        assert_eq!((header.bsid, header.acmod, header.channels()), (16, 7, 6));
+        assert_eq!((header.sample_rate, header.frame_bytes), (48000, 1536));
+        assert_eq!((header.blocks, header.bitrate()), (6, 384000));
+        assert!(header.is_independent());
+
+        // Dependent substream 0, stereo at 44.1 kHz, 2 blocks, 512 bytes.
+        let header = Ac3Header::parse(&[0x0B, 0x77, 0x40, 0xFF, 0x54, 0x80, 0, 0]).unwrap();
+        assert_eq!((header.stream_type, header.substream_id), (1, 0));
+        assert!(!header.is_independent());
+        assert_eq!((header.acmod, header.lfe), (2, false));
+        assert_eq!((header.sample_rate, header.blocks), (44100, 2));
+        assert_eq!(header.frame_bytes, 512);
+
+        // Reduced rate of 24 kHz.
+        let header = Ac3Header::parse(&[0x0B, 0x77, 0x00, 0x7F, 0xC4, 0x80, 0, 0]).unwrap();
+        assert_eq!((header.sample_rate, header.blocks), (24000, 6));
+        assert_eq!(header.channels(), 2);
+    }
+
+    #[test]
+    fn ac3_burst() {
+        // Mono at 48 kHz, 32 kbit/s, bsmod 2.
+        let mut frame = frame(&[0x0B, 0x77, 0xAB, 0xCD, 0x00, 0x42, 0x20, 0]);
+        frame[127] = 0x99;
+        let header = Ac3Header::parse(&frame).unwrap();
+
+        let mut packer = Iec61937Packer::new(&header);
+        let format = packer.output_format();
+        assert_eq!(format.sample_rate(), 48000.0);
+        assert_eq!(format.channels(), 2);
+
+        let burst = packer.pack(&frame).unwrap().unwrap();
+        assert_eq!(burst.len(), 1);
+        assert_eq!(burst[0].frames(), 1536);
+
+        let samples = burst[0].samples();
+        let words: Vec<u16> = samples[..7].iter().map(|&s| s as u16).collect();
+        assert_eq!(
+            words,
+            [0xF872, 0x4E1F, 0x0201, 1024, 0x0B77, 0xABCD, 0x0042]
+        );
+        assert_eq!(samples[4 + 63], 0x0099);
+        assert!(samples[4 + 64..].iter().all(|&s| s == 0));
+
+        // Another rate, or E-AC-3.
+        let other = self::frame(&[0x0B, 0x77, 0, 0, 0x40, 0x40, 0x20, 0]);
+        assert!(packer.pack(&other).is_err());
+        let eac3 = self::frame(&[0x0B, 0x77, 0x02, 0xFF, 0x3F, 0x87, 0xC0, 0]);
+        assert!(packer.pack(&eac3).is_err());
+        assert!(packer.pack(&frame[..100]).is_err());
+
+        // The largest frame, 640 kbit/s at 32 kHz.
+        let frame = self::frame(&[0x0B, 0x77, 0, 0, 0xA4, 0x40, 0x30, 0]);
+        let mut packer = Iec61937Packer::new(&Ac3Header::parse(&frame).unwrap());
+        let burst = packer.pack(&frame).unwrap().unwrap();
+        assert_eq!(burst[0].samples()[3] as u16, 3840 * 8);
+    }
+
+    #[test]
+    fn eac3_burst() {
+        // Independent stereo at 48 kHz, 2 blocks, 256 bytes.
+        let independent = frame(&[0x0B, 0x77, 0x00, 0x7F, 0x14, 0x80, 0, 0]);
+        // Dependent stereo, 2 blocks, 128 bytes.
+        let dependent = frame(&[0x0B, 0x77, 0x40, 0x3F, 0x14, 0x80, 0, 0]);
+
+        let header = Ac3Header::parse(&independent).unwrap();
+        let mut packer = Iec61937Packer::new(&header);
+        assert_eq!(packer.output_format().sample_rate(), 192000.0);
+
+        let packet = [independent.clone(), dependent].concat();
+
+        assert!(packer.pack(&packet).unwrap().is_none());
+        assert!(packer.pack(&packet).unwrap().is_none());
+        let burst = packer.pack(&packet).unwrap().unwrap();
+        assert_eq!(burst[0].frames(), 6144);
+
+        let samples = burst[0].samples();
+        let words: Vec<u16> = samples[..6].iter().map(|&s| s as u16).collect();
+        assert_eq!(words, [0xF872, 0x4E1F, 21, 3 * 384, 0x0B77, 0x007F]);
+        // The dependent substream after the first frame.
+        assert_eq!(samples[4 + 128] as u16, 0x0B77);
+        assert_eq!(samples[4 + 129] as u16, 0x403F);
+        assert!(samples[4 + 3 * 192..].iter().all(|&s| s == 0));
+
+        // The next burst starts empty.
+        assert!(packer.pack(&independent).unwrap().is_none());
+        assert!(packer.pack(&packet[..300]).is_err());
+        assert_eq!(packer.pending.len(), 256);
+
+        // A good frame followed by junk.
+        let junk = [independent.clone(), vec![0xFF; 16]].concat();
+        assert!(packer.pack(&junk).is_err());
+        assert_eq!(packer.pending.len(), 256);
+    }
+}
@@ -0,0 +1,1552 @@
+//! Apple Lossless (ALAC), following Apple's open source reference implementation.
+//!
+//! Both sides are driven by the `ALACSpecificConfig` of the magic cookie. Samples are
+//! the upper bits of [`Sample`], like 24 bit PCM elsewhere in the crate, so 20 bit
+//! ALAC decodes to the upper 20 bits of 24 bit samples.
+
+use crate::bits::{BitReader, BitWriter};
+use crate::error::{AudioCodecError, AudioFileError};
+use crate::file::{deinterleave, interleave, list_frames};
+use crate::format::{
+    AppleLosslessFlags, AudioFormat, FormatDescription, LinearPcmFlags, SampleConvert,
+    SampleFormat, StreamFormat,
+};
+use crate::unit::AudioBufferList;
+use crate::CAError;
+
+/// Frames per packet written by Apple's encoder.
+pub const DEFAULT_FRAME_LENGTH: u32 = 4096;
+
+/// Largest frame length accepted from a magic cookie.
+pub const MAX_FRAME_LENGTH: u32 = 1 << 16;
+
+const MAX_CHANNELS: u8 = 8;
+
+// Element tags.
+const ID_SCE: u32 = 0;
+const ID_CPE: u32 = 1;
+const ID_CCE: u32 = 2;
+const ID_LFE: u32 = 3;
+const ID_DSE: u32 = 4;
+const ID_PCE: u32 = 5;
+const ID_FIL: u32 = 6;
+const ID_END: u32 = 7;
+
+/// Elements of a frame by number of channels.
+const CHANNEL_MAPS: [&[u32]; MAX_CHANNELS as usize] = [
+    &[ID_SCE],
+    &[ID_CPE],
+    &[ID_SCE, ID_CPE],
+    &[ID_SCE, ID_CPE, ID_SCE],
+    &[ID_SCE, ID_CPE, ID_CPE],
+    &[ID_SCE, ID_CPE, ID_CPE, ID_SCE],
+    &[ID_SCE, ID_CPE, ID_CPE, ID_SCE, ID_SCE],
+    &[ID_SCE, ID_CPE, ID_CPE, ID_CPE, ID_SCE],
+];
+
+// Adaptive Golomb coding.
+const QBSHIFT: u32 = 9;
+const QB: u32 = 1 << QBSHIFT;
+const PB0: u32 = 40;
+const MB0: u32 = 10;
+const KB0: u32 = 14;
+const MAX_RUN_DEFAULT: u16 = 255;
+const MMULSHIFT: u32 = 2;
+const MDENSHIFT: u32 = QBSHIFT - MMULSHIFT - 1;
+const MOFF: u32 = 1 << (MDENSHIFT - 2);
+const BITOFF: u32 = 24;
+const MAX_PREFIX_16: u32 = 9;
+const MAX_PREFIX_32: u32 = 9;
+const MAX_DATATYPE_BITS_16: u32 = 16;
+const N_MAX_MEAN_CLAMP: u32 = 0xFFFF;
+const N_MEAN_CLAMP_VAL: u32 = 0xFFFF;
+
+// Prediction.
+const DENSHIFT_DEFAULT: u32 = 9;
+const MIN_ORDER: usize = 4;
+const MAX_ORDER: usize = 8;
+const MAX_COEFS: usize = 32;
+
+// Stereo mixing.
+const DEFAULT_MIX_BITS: u32 = 2;
+const MAX_RES: i32 = 4;
+
+/// The `ALACSpecificConfig` of a magic cookie.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AlacConfig {
+    pub frame_length: u32,
+    pub compatible_version: u8,
+    pub bit_depth: u8,
+    /// Rice parameters, 40, 10 and 14 for Apple's encoder.
+    pub pb: u8,
+    pub mb: u8,
+    pub kb: u8,
+    pub channels: u8,
+    pub max_run: u16,
+    /// 0 if unknown.
+    pub max_frame_bytes: u32,
+    /// 0 if unknown.
+    pub avg_bit_rate: u32,
+    pub sample_rate: u32,
+}
+
+impl AlacConfig {
+    /// Config of the encoder, with the bit depth from the source data flags.
+    pub fn new(sample_rate: u32, channels: u8, flags: AppleLosslessFlags) -> Result<Self, CAError> {
+        let config = AlacConfig {
+            frame_length: DEFAULT_FRAME_LENGTH,
+            compatible_version: 0,
+            bit_depth: bit_depth(flags).ok_or(AudioCodecError::UnsupportedFormat)?,
+            pb: PB0 as u8,
+            mb: MB0 as u8,
+            kb: KB0 as u8,
+            channels,
+            max_run: MAX_RUN_DEFAULT,
+            max_frame_bytes: 0,
+            avg_bit_rate: 0,
+            sample_rate,
+        };
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Parse a magic cookie, which can be wrapped in `frma` and `alac` atoms as in
+    /// QuickTime files.
+    pub fn from_cookie(mut cookie: &[u8]) -> Result<Self, CAError> {
+        if cookie.get(4..8) == Some(b"frma") {
+            cookie = &cookie[12.min(cookie.len())..];
+        }
+        if cookie.get(4..8) == Some(b"alac") {
+            cookie = &cookie[12.min(cookie.len())..];
+        }
+        if cookie.len() < 24 {
+            return Err(AudioFileError::InvalidFile.into());
+        }
+
+        let u16_at = |at: usize| u16::from_be_bytes([cookie[at], cookie[at + 1]]);
+        let u32_at = |at: usize| u32::from_be_bytes(cookie[at..at + 4].try_into().unwrap());
+
+        let config = AlacConfig {
+            frame_length: u32_at(0),
+            compatible_version: cookie[4],
+            bit_depth: cookie[5],
+            pb: cookie[6],
+            mb: cookie[7],
+            kb: cookie[8],
+            channels: cookie[9],
+            max_run: u16_at(10),
+            max_frame_bytes: u32_at(12),
+            avg_bit_rate: u32_at(16),
+            sample_rate: u32_at(20),
+        };
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), CAError> {
+        if self.compatible_version != 0
+            || !matches!(self.bit_depth, 16 | 20 | 24 | 32)
+            || !(1..=MAX_CHANNELS).contains(&self.channels)
+            || !(1..=MAX_FRAME_LENGTH).contains(&self.frame_length)
+            || !(1..=31).contains(&self.kb)
+        {
+            return Err(AudioCodecError::UnsupportedFormat.into());
+        }
+        Ok(())
+    }
+
+    /// The 24 byte magic cookie.
+    pub fn cookie(&self) -> Vec<u8> {
+        let mut cookie = Vec::with_capacity(24);
+        cookie.extend_from_slice(&self.frame_length.to_be_bytes());
+        cookie.extend_from_slice(&[
+            self.compatible_version,
+            self.bit_depth,
+            self.pb,
+            self.mb,
+            self.kb,
+            self.channels,
+        ]);
+        cookie.extend_from_slice(&self.max_run.to_be_bytes());
+        cookie.extend_from_slice(&self.max_frame_bytes.to_be_bytes());
+        cookie.extend_from_slice(&self.avg_bit_rate.to_be_bytes());
+        cookie.extend_from_slice(&self.sample_rate.to_be_bytes());
+        cookie
+    }
+
+    /// The source data flags of the bit depth.
+    pub fn flags(&self) -> AppleLosslessFlags {
+        match self.bit_depth {
+            16 => AppleLosslessFlags::BIT_16_SOURCE_DATA,
+            20 => AppleLosslessFlags::BIT_20_SOURCE_DATA,
+            24 => AppleLosslessFlags::BIT_24_SOURCE_DATA,
+            _ => AppleLosslessFlags::BIT_32_SOURCE_DATA,
+        }
+    }
+
+    pub fn description(&self) -> FormatDescription {
+        FormatDescription {
+            sample_rate: self.sample_rate as f64,
+            format: AudioFormat::AppleLossless(self.flags()),
+            bytes_per_packet: 0,
+            frames_per_packet: self.frame_length,
+            channels: self.channels as u32,
+            bits_per_channel: 0,
+        }
+    }
+
+    /// The linear PCM format decoded to, 24 bit for 20 bit sources.
+    pub fn output_format(&self) -> StreamFormat {
+        let sample_format = match self.bit_depth {
+            16 => SampleFormat::I16,
+            20 | 24 => SampleFormat::I24,
+            _ => SampleFormat::I32,
+        };
+        StreamFormat::new(
+            self.sample_rate as f64,
+            sample_format,
+            LinearPcmFlags::IS_SIGNED_INTEGER,
+            self.channels as usize,
+        )
+    }
+}
+
+/// Bit depth of the source data flags.
+pub fn bit_depth(flags: AppleLosslessFlags) -> Option<u8> {
+    match flags.bits() {
+        1 => Some(16),
+        2 => Some(20),
+        3 => Some(24),
+        4 => Some(32),
+        _ => None,
+    }
+}
+
+/// The bits of a sample that are shifted off before prediction: one byte for 24 bit and
+/// two for 32 bit.
+fn bytes_shifted(bit_depth: u8) -> u32 {
+    match bit_depth {
+        32 => 2,
+        24 => 1,
+        _ => 0,
+    }
+}
+
+fn sign_extend(value: i32, bits: u32) -> i32 {
+    let shift = 32 - bits;
+    value.wrapping_shl(shift).wrapping_shr(shift)
+}
+
+fn lg3a(x: u32) -> u32 {
+    31 - (x + 3).leading_zeros()
+}
+
+/// Parameters of the adaptive Golomb coder.
+struct AgParams {
+    mb: u32,
+    pb: u32,
+    kb: u32,
+    wb: u32,
+}
+
+impl AgParams {
+    fn new(mb: u32, pb: u32, kb: u32) -> Self {
+        AgParams {
+            mb,
+            pb,
+            kb,
+            wb: (1_u32 << kb).wrapping_sub(1),
+        }
+    }
+
+    fn standard() -> Self {
+        AgParams::new(MB0, PB0, KB0)
+    }
+}
+
+/// Read a Golomb code with an escape to `max_bits` raw bits.
+fn dyn_get_32bit(bits: &mut BitReader, m: u32, k: u32, max_bits: u32) -> Result<u32, CAError> {
+    let word = bits.peek32();
+    let prefix = (!word).leading_zeros();
+
+    if prefix >= MAX_PREFIX_32 {
+        bits.skip(MAX_PREFIX_32 as usize)?;
+        return bits.read(max_bits);
+    }
+
+    bits.skip(prefix as usize + 1)?;
+    if k == 1 {
+        return Ok(prefix);
+    }
+
+    let v = bits.peek32() >> (32 - k);
+    let mut result = prefix.wrapping_mul(m);
+    if v >= 2 {
+        result = result.wrapping_add(v - 1);
+        bits.skip(k as usize)?;
+    } else {
+        bits.skip(k as usize - 1)?;
+    }
+    Ok(result)
+}
+
+/// Read the Golomb code of a run of zeros, with an escape to 16 raw bits.
+fn dyn_get(bits: &mut BitReader, m: u32, k: u32) -> Result<u32, CAError> {
+    let word = bits.peek32();
+    let prefix = (!word).leading_zeros();
+
+    if prefix >= MAX_PREFIX_16 {
+        bits.skip(MAX_PREFIX_16 as usize)?;
+        return bits.read(MAX_DATATYPE_BITS_16);
+    }
+
+    bits.skip(prefix as usize + 1)?;
+    let v = bits.peek32() >> (32 - k);
+    let mut result = prefix.wrapping_mul(m);
+    if v >= 2 {
+        result = result.wrapping_add(v - 1);
+        bits.skip(k as usize)?;
+    } else {
+        bits.skip(k as usize - 1)?;
+    }
+    Ok(result)
+}
+
+/// Decode `out.len()` residuals of `chan_bits` bits.
+fn dyn_decomp(
+    params: &AgParams,
+    bits: &mut BitReader,
+    out: &mut [i32],
+    chan_bits: u32,
+) -> Result<(), CAError> {
+    let num_samples = out.len();
+    let mut mb = params.mb;
+    let mut zmode = 0;
+    let mut c = 0;
+
+    while c < num_samples {
+        if bits.remaining() == 0 {
+            return Err(AudioFileError::InvalidFile.into());
+        }
+
+        let k = lg3a(mb >> QBSHIFT).min(params.kb);
+        let m = (1 << k) - 1;
+        let n = dyn_get_32bit(bits, m, k, chan_bits)?;
+
+        // The least significant bit is the sign.
+        let ndecode = n.wrapping_add(zmode);
+        let magnitude = (ndecode.wrapping_add(1) >> 1) as i32;
+        out[c] = if ndecode & 1 == 1 {
+            magnitude.wrapping_neg()
+        } else {
+            magnitude
+        };
+        c += 1;
+
+        mb = params
+            .pb
+            .wrapping_mul(n.wrapping_add(zmode))
+            .wrapping_add(mb)
+            .wrapping_sub(params.pb.wrapping_mul(mb) >> QBSHIFT);
+        if n > N_MAX_MEAN_CLAMP {
+            mb = N_MEAN_CLAMP_VAL;
+        }
+        zmode = 0;
+
+        if (mb << MMULSHIFT) < QB && c < num_samples {
+            zmode = 1;
+            let k = mb.leading_zeros() - BITOFF + ((mb + MOFF) >> MDENSHIFT);
+            let mz = ((1 << k) - 1) & params.wb;
+            let n = dyn_get(bits, mz, k)? as usize;
+
+            if c + n > num_samples {
+                return Err(AudioFileError::InvalidFile.into());
+            }
+            out[c..c + n].fill(0);
+            c += n;
+
+            if n >= 65535 {
+                zmode = 0;
+            }
+            mb = 0;
+        }
+    }
+
+    Ok(())
+}
+
+/// Write a Golomb code with an escape to `max_bits` raw bits.
+fn dyn_code_32bit(bits: &mut BitWriter, m: u32, k: u32, n: u32, max_bits: u32) {
+    let division = n / m;
+
+    if division < MAX_PREFIX_32 {
+        let modulo = n - m * division;
+        let de = (modulo == 0) as u32;
+        let num_bits = division + k + 1 - de;
+        if num_bits <= 25 {
+            let value = (((1 << division) - 1) << (num_bits - division)) + modulo + 1 - de;
+            bits.write(value, num_bits);
+            return;
+        }
+    }
+
+    bits.write((1 << MAX_PREFIX_32) - 1, MAX_PREFIX_32);
+    bits.write(n, max_bits);
+}
+
+/// Write the Golomb code of a run of zeros, with an escape to 16 raw bits.
+fn dyn_code(bits: &mut BitWriter, m: u32, k: u32, n: u32) {
+    let division = n / m;
+
+    if division < MAX_PREFIX_16 {
+        let modulo = n - m * division;
+        let de = (modulo == 0) as u32;
+        let num_bits = division + k + 1 - de;
+        if num_bits <= MAX_PREFIX_16 + MAX_DATATYPE_BITS_16 {
+            let value = (((1 << division) - 1) << (num_bits - division)) + modulo + 1 - de;
+            bits.write(value, num_bits);
+            return;
+        }
+    }
+
+    bits.write(
+        (((1 << MAX_PREFIX_16) - 1) << MAX_DATATYPE_BITS_16) + n,
+        MAX_PREFIX_16 + MAX_DATATYPE_BITS_16,
+    );
+}
+
+/// Encode residuals of `chan_bits` bits.
+fn dyn_comp(params: &AgParams, input: &[i32], bits: &mut BitWriter, chan_bits: u32) {
+    let num_samples = input.len();
+    let mut mb = params.mb;
+    let mut zmode = 0;
+    let mut c = 0;
+
+    while c < num_samples {
+        let k = lg3a(mb >> QBSHIFT).min(params.kb);
+        let m = (1 << k) - 1;
+
+        let del = input[c];
+        let n = (del.unsigned_abs() << 1)
+            .wrapping_sub((del < 0) as u32)
+            .wrapping_sub(zmode);
+        dyn_code_32bit(bits, m, k, n, chan_bits);
+        c += 1;
+
+        mb = params
+            .pb
+            .wrapping_mul(n.wrapping_add(zmode))
+            .wrapping_add(mb)
+            .wrapping_sub(params.pb.wrapping_mul(mb) >> QBSHIFT);
+        if n > N_MAX_MEAN_CLAMP {
+            mb = N_MEAN_CLAMP_VAL;
+        }
+        zmode = 0;
+
+        if (mb << MMULSHIFT) < QB && c < num_samples {
+            zmode = 1;
+            let mut nz = 0;
+            while c < num_samples && input[c] == 0 {
+                nz += 1;
+                c += 1;
+                if nz >= 65535 {
+                    zmode = 0;
+                    break;
+                }
+            }
+
+            let k = mb.leading_zeros() - BITOFF + ((mb + MOFF) >> MDENSHIFT);
+            let mz = ((1 << k) - 1) & params.wb;
+            dyn_code(bits, mz, k, nz);
+            mb = 0;
+        }
+    }
+}
+
+/// Undo the adaptive prediction of `residuals` into `out`.
+///
+/// An order of 31 is first order prediction without coefficients.
+fn unpc_block(
+    residuals: &[i32],
+    out: &mut [i32],
+    coefs: &mut [i16],
+    order: usize,
+    chan_bits: u32,
+    den_shift: u32,
+) {
+    let num = residuals.len();
+    if num == 0 {
+        return;
+    }
+
+    out[0] = residuals[0];
+    if order == 0 {
+        out[1..num].copy_from_slice(&residuals[1..]);
+        return;
+    }
+    if order == 31 {
+        for j in 1..num {
+            out[j] = sign_extend(residuals[j].wrapping_add(out[j - 1]), chan_bits);
+        }
+        return;
+    }
+
+    let warm_up = order.min(num - 1);
+    for j in 1..=warm_up {
+        out[j] = sign_extend(residuals[j].wrapping_add(out[j - 1]), chan_bits);
+    }
+
+    let den_half = (1 << den_shift) >> 1;
+    for j in order + 1..num {
+        let top = out[j - order - 1];
+        let mut sum = 0_i32;
+        for (k, &coef) in coefs[..order].iter().enumerate() {
+            sum = sum.wrapping_add((coef as i32).wrapping_mul(out[j - 1 - k].wrapping_sub(top)));
+        }
+
+        let del = residuals[j];
+        let prediction = sum.wrapping_add(den_half) >> den_shift;
+        out[j] = sign_extend(del.wrapping_add(top).wrapping_add(prediction), chan_bits);
+
+        adapt(coefs, order, &out[..j], top, del, den_shift);
+    }
+}
+
+/// Adaptive prediction of `input` into `residuals`, the inverse of [`unpc_block`].
+fn pc_block(
+    input: &[i32],
+    residuals: &mut [i32],
+    coefs: &mut [i16],
+    order: usize,
+    chan_bits: u32,
+    den_shift: u32,
+) {
+    let num = input.len();
+    if num == 0 {
+        return;
+    }
+
+    residuals[0] = input[0];
+    if order == 0 {
+        residuals[1..num].copy_from_slice(&input[1..]);
+        return;
+    }
+    if order == 31 {
+        for j in 1..num {
+            residuals[j] = sign_extend(input[j].wrapping_sub(input[j - 1]), chan_bits);
+        }
+        return;
+    }
+
+    let warm_up = order.min(num - 1);
+    for j in 1..=warm_up {
+        residuals[j] = sign_extend(input[j].wrapping_sub(input[j - 1]), chan_bits);
+    }
+
+    let den_half = (1 << den_shift) >> 1;
+    for j in order + 1..num {
+        let top = input[j - order - 1];
+        let mut sum = 0_i32;
+        for (k, &coef) in coefs[..order].iter().enumerate() {
+            sum = sum.wrapping_add((coef as i32).wrapping_mul(input[j - 1 - k].wrapping_sub(top)));
+        }
+
+        let prediction = sum.wrapping_add(den_half) >> den_shift;
+        let del = sign_extend(
+            input[j].wrapping_sub(top).wrapping_sub(prediction),
+            chan_bits,
+        );
+        residuals[j] = del;
+
+        adapt(coefs, order, &input[..j], top, del, den_shift);
+    }
+}
+
+/// Move the coefficients in the direction that would have reduced the residual `del`,
+/// with `past` the samples before the current one.
+fn adapt(coefs: &mut [i16], order: usize, past: &[i32], top: i32, del: i32, den_shift: u32) {
+    let last = past.len() - 1;
+    let mut del0 = del;
+
+    if del > 0 {
+        for k in (0..order).rev() {
+            let dd = top.wrapping_sub(past[last - k]);
+            let sgn = dd.signum();
+            coefs[k] = coefs[k].wrapping_sub(sgn as i16);
+            del0 = del0
+                .wrapping_sub(((order - k) as i32).wrapping_mul(sgn.wrapping_mul(dd) >> den_shift));
+            if del0 <= 0 {
+                break;
+            }
+        }
+    } else if del < 0 {
+        for k in (0..order).rev() {
+            let dd = top.wrapping_sub(past[last - k]);
+            let sgn = dd.signum();
+            coefs[k] = coefs[k].wrapping_add(sgn as i16);
+            del0 = del0.wrapping_sub(
+                ((order - k) as i32).wrapping_mul((-sgn).wrapping_mul(dd) >> den_shift),
+            );
+            if del0 >= 0 {
+                break;
+            }
+        }
+    }
+}
+
+/// The initial coefficients of Apple's encoder.
+fn init_coefs(coefs: &mut [i16]) {
+    let den = 1 << DENSHIFT_DEFAULT;
+    coefs.fill(0);
+    coefs[0] = ((38 * den) >> 4) as i16;
+    coefs[1] = ((-29 * den) >> 4) as i16;
+    coefs[2] = ((-2 * den) >> 4) as i16;
+}
+
+/// Prediction parameters of a channel in a compressed element.
+struct Predictor {
+    mode: u32,
+    den_shift: u32,
+    pb_factor: u32,
+    coefs: [i16; MAX_COEFS],
+    order: usize,
+}
+
+impl Predictor {
+    fn read(bits: &mut BitReader) -> Result<Self, CAError> {
+        let header = bits.read(8)?;
+        let mode = header >> 4;
+        let den_shift = header & 0x0F;
+
+        let header = bits.read(8)?;
+        let pb_factor = header >> 5;
+        let order = (header & 0x1F) as usize;
+
+        let mut coefs = [0; MAX_COEFS];
+        for coef in &mut coefs[..order] {
+            *coef = bits.read_signed(16)? as i16;
+        }
+
+        Ok(Predictor {
+            mode,
+            den_shift,
+            pb_factor,
+            coefs,
+            order,
+        })
+    }
+
+    /// Decode the residuals of the channel and undo the prediction into `out`.
+    fn decode(
+        &mut self,
+        config: &AlacConfig,
+        bits: &mut BitReader,
+        scratch: &mut [i32],
+        out: &mut [i32],
+        chan_bits: u32,
+    ) -> Result<(), CAError> {
+        let params = AgParams::new(
+            config.mb as u32,
+            (config.pb as u32 * self.pb_factor) / 4,
+            config.kb as u32,
+        );
+        dyn_decomp(&params, bits, scratch, chan_bits)?;
+
+        if self.mode != 0 {
+            // First order prediction in place.
+            for j in 1..scratch.len() {
+                scratch[j] = sign_extend(scratch[j].wrapping_add(scratch[j - 1]), chan_bits);
+            }
+        }
+        unpc_block(
+            scratch,
+            out,
+            &mut self.coefs,
+            self.order,
+            chan_bits,
+            self.den_shift,
+        );
+
+        Ok(())
+    }
+}
+
+/// Decoder of ALAC packets.
+pub struct AlacDecoder {
+    config: AlacConfig,
+    // Right aligned, interleaved.
+    samples: Vec<i32>,
+    predictor: Vec<i32>,
+    mix_u: Vec<i32>,
+    mix_v: Vec<i32>,
+    shift: Vec<u16>,
+}
+
+impl AlacDecoder {
+    /// Decoder for the magic cookie.
+    pub fn new(cookie: &[u8]) -> Result<Self, CAError> {
+        Ok(AlacDecoder::with_config(AlacConfig::from_cookie(cookie)?))
+    }
+
+    pub fn with_config(config: AlacConfig) -> Self {
+        let frames = config.frame_length as usize;
+        AlacDecoder {
+            config,
+            samples: vec![0; frames * config.channels as usize],
+            predictor: vec![0; frames],
+            mix_u: vec![0; frames],
+            mix_v: vec![0; frames],
+            shift: vec![0; frames * 2],
+        }
+    }
+
+    pub fn config(&self) -> &AlacConfig {
+        &self.config
+    }
+
+    /// Decode a packet to interleaved samples.
+    ///
+    /// Returns the number of frames, which is less than the frame length for the last
+    /// packet of a stream. Fails if the packet is malformed or `out` is too small.
+    pub fn decode<S: SampleConvert>(
+        &mut self,
+        packet: &[u8],
+        out: &mut [S],
+    ) -> Result<usize, CAError> {
+        let frames = self.decode_packet(packet)?;
+        let channels = self.config.channels as usize;
+        if out.len() < frames * channels {
+            return Err(AudioCodecError::NotEnoughBufferSpace.into());
+        }
+
+        let shift = 32 - self.config.bit_depth as u32;
+        for (out, &sample) in out.iter_mut().zip(&self.samples[..frames * channels]) {
+            *out = S::from_i32(sample.wrapping_shl(shift));
+        }
+
+        Ok(frames)
+    }
+
+    /// Decode a packet into the buffers. Returns the number of frames.
+    pub fn decode_buffer_list<S: SampleConvert>(
+        &mut self,
+        packet: &[u8],
+        list: &mut AudioBufferList<S>,
+    ) -> Result<usize, CAError> {
+        let channels = self.config.channels as usize;
+        let mut samples = vec![S::default(); list_frames(list) * channels];
+        let frames = self.decode(packet, &mut samples)?;
+        deinterleave(&samples[..frames * channels], list);
+        Ok(frames)
+    }
+
+    fn decode_packet(&mut self, packet: &[u8]) -> Result<usize, CAError> {
+        let invalid = || CAError::from(AudioFileError::InvalidFile);
+
+        let mut bits = BitReader::new(packet);
+        let channels = self.config.channels as usize;
+        let mut channel = 0;
+        let mut frames = None;
+
+        loop {
+            match bits.read(3)? {
+                tag @ (ID_SCE | ID_LFE | ID_CPE) => {
+                    let pair = tag == ID_CPE;
+                    if channel + 1 + pair as usize > channels {
+                        return Err(invalid());
+                    }
+
+                    let element_frames = self.decode_element(&mut bits, pair, channel)?;
+                    if frames.is_some_and(|frames| frames != element_frames) {
+                        return Err(invalid());
+                    }
+                    frames = Some(element_frames);
+                    channel += 1 + pair as usize;
+                }
+                ID_DSE => {
+                    // Data stream element, skipped.
+                    bits.skip(4)?;
+                    let align = bits.read_bit()?;
+                    let mut count = bits.read(8)? as usize;
+                    if count == 255 {
+                        count += bits.read(8)? as usize;
+                    }
+                    if align {
+                        bits.byte_align();
+                    }
+                    bits.skip(count * 8)?;
+                }
+                ID_FIL => {
+                    let mut count = bits.read(4)? as usize;
+                    if count == 15 {
+                        // 15 plus the escape byte, minus one.
+                        count = 14 + bits.read(8)? as usize;
+                    }
+                    bits.skip(count * 8)?;
+                }
+                ID_END => break,
+                tag => {
+                    // Coupling channel and program config elements are not used.
+                    debug_assert!(tag == ID_CCE || tag == ID_PCE);
+                    return Err(AudioCodecError::UnsupportedFormat.into());
+                }
+            }
+        }
+
+        if channel != channels {
+            return Err(invalid());
+        }
+        frames.ok_or_else(invalid)
+    }
+
+    /// Decode a single channel or channel pair element starting at `channel`.
+    fn decode_element(
+        &mut self,
+        bits: &mut BitReader,
+        pair: bool,
+        channel: usize,
+    ) -> Result<usize, CAError> {
+        let invalid = || CAError::from(AudioFileError::InvalidFile);
+        let config = self.config;
+        let bit_depth = config.bit_depth as u32;
+        let count = 1 + pair as usize;
+
+        // Element instance tag.
+        bits.skip(4)?;
+        if bits.read(12)? != 0 {
+            return Err(invalid());
+        }
+
+        let header = bits.read(4)?;
+        let partial_frame = header >> 3 == 1;
+        let bytes_shifted = (header >> 1) & 3;
+        let escape = header & 1 == 1;
+        if bytes_shifted == 3 || bytes_shifted * 8 >= bit_depth {
+            return Err(invalid());
+        }
+
+        let mut frames = config.frame_length as usize;
+        if partial_frame {
+            frames = bits.read(32)? as usize;
+            if frames == 0 || frames > config.frame_length as usize {
+                return Err(invalid());
+            }
+        }
+
+        let (mut mix_bits, mut mix_res) = (0, 0);
+        let mut bytes_shifted = bytes_shifted;
+
+        if !escape {
+            mix_bits = bits.read(8)?;
+            mix_res = bits.read_signed(8)?;
+            if pair && mix_bits > 31 {
+                return Err(invalid());
+            }
+
+            let mut u = Predictor::read(bits)?;
+            let mut v = if pair {
+                Some(Predictor::read(bits)?)
+            } else {
+                None
+            };
+
+            // The shifted off bits come before the residuals.
+            let shift_bits = bits.clone();
+            bits.skip(bytes_shifted as usize * 8 * count * frames)?;
+
+            let chan_bits = bit_depth - bytes_shifted * 8 + pair as u32;
+            if chan_bits > 32 {
+                return Err(invalid());
+            }
+            let scratch = &mut self.predictor[..frames];
+            u.decode(&config, bits, scratch, &mut self.mix_u[..frames], chan_bits)?;
+            if let Some(v) = &mut v {
+                v.decode(&config, bits, scratch, &mut self.mix_v[..frames], chan_bits)?;
+            }
+
+            if bytes_shifted != 0 {
+                let mut shift_bits = shift_bits;
+                for shift in &mut self.shift[..frames * count] {
+                    *shift = shift_bits.read(bytes_shifted * 8)? as u16;
+                }
+            }
+        } else {
+            // Uncompressed.
+            for i in 0..frames {
+                self.mix_u[i] = bits.read_signed(bit_depth)?;
+                if pair {
+                    self.mix_v[i] = bits.read_signed(bit_depth)?;
+                }
+            }
+            bytes_shifted = 0;
+        }
+
+        // Unmix and interleave.
+        let shift = bytes_shifted * 8;
+        let channels = config.channels as usize;
+        for i in 0..frames {
+            let (mut l, mut r) = (self.mix_u[i], self.mix_v[i]);
+            if pair && mix_res != 0 {
+                let v = r;
+                l = l
+                    .wrapping_add(v)
+                    .wrapping_sub(mix_res.wrapping_mul(v) >> mix_bits);
+                r = l.wrapping_sub(v);
+            }
+
+            let at = i * channels + channel;
+            if shift != 0 {
+                l = (l << shift) | self.shift[i * count] as i32;
+                if pair {
+                    r = (r << shift) | self.shift[i * count + 1] as i32;
+                }
+            }
+            self.samples[at] = l;
+            if pair {
+                self.samples[at + 1] = r;
+            }
+        }
+
+        Ok(frames)
+    }
+}
+
+/// Encoder of ALAC packets.
+pub struct AlacEncoder {
+    config: AlacConfig,
+    // Coefficients for each order, kept from packet to packet.
+    coefs_u: Vec<[[i16; MAX_COEFS]; MAX_ORDER]>,
+    coefs_v: Vec<[[i16; MAX_COEFS]; MAX_ORDER]>,
+    last_mix_res: Vec<i32>,
+    mix_u: Vec<i32>,
+    mix_v: Vec<i32>,
+    predictor_u: Vec<i32>,
+    predictor_v: Vec<i32>,
+    shift: Vec<u16>,
+    work: BitWriter,
+    bits: BitWriter,
+    total_bytes: u64,
+    total_frames: u64,
+}
+
+impl AlacEncoder {
+    pub fn new(config: AlacConfig) -> Result<Self, CAError> {
+        config.validate()?;
+        if (config.pb as u32, config.mb as u32, config.kb as u32) != (PB0, MB0, KB0) {
+            return Err(AudioCodecError::UnsupportedFormat.into());
+        }
+
+        let mut coefs = [[0; MAX_COEFS]; MAX_ORDER];
+        for coefs in &mut coefs {
+            init_coefs(coefs);
+        }
+
+        let frames = config.frame_length as usize;
+        let channels = config.channels as usize;
+        Ok(AlacEncoder {
+            config,
+            coefs_u: vec![coefs; channels],
+            coefs_v: vec![coefs; channels],
+            last_mix_res: vec![0; channels],
+            mix_u: vec![0; frames],
+            mix_v: vec![0; frames],
+            predictor_u: vec![0; frames],
+            predictor_v: vec![0; frames],
+            shift: vec![0; frames * 2],
+            work: BitWriter::new(),
+            bits: BitWriter::new(),
+            total_bytes: 0,
+            total_frames: 0,
+        })
+    }
+
+    /// The config with the largest packet and average bit rate so far.
+    pub fn config(&self) -> AlacConfig {
+        let mut config = self.config;
+        if let Some(rate) =
+            (self.total_bytes * 8 * config.sample_rate as u64).checked_div(self.total_frames)
+        {
+            config.avg_bit_rate = rate as u32;
+        }
+        config
+    }
+
+    /// The magic cookie for the packets encoded so far.
+    pub fn cookie(&self) -> Vec<u8> {
+        self.config().cookie()
+    }
+
+    /// Encode interleaved samples as a packet appended to `out`.
+    ///
+    /// There can be at most `frame_length` frames. Only the last packet of a stream can
+    /// have fewer. Returns the size of the packet.
+    pub fn encode<S: SampleConvert>(
+        &mut self,
+        samples: &[S],
+        out: &mut Vec<u8>,
+    ) -> Result<usize, CAError> {
+        let channels = self.config.channels as usize;
+        let frames = samples.len() / channels;
+        if frames == 0 || frames > self.config.frame_length as usize {
+            return Err(AudioCodecError::IllegalOperation.into());
+        }
+
+        let shift = 32 - self.config.bit_depth as u32;
+        let input: Vec<i32> = samples[..frames * channels]
+            .iter()
+            .map(|s| s.to_i32() >> shift)
+            .collect();
+
+        self.bits.clear();
+        let elements = CHANNEL_MAPS[channels - 1];
+        let mut channel = 0;
+        for (instance, &tag) in elements.iter().enumerate() {
+            self.bits.write(tag, 3);
+            // Instance tags count separately for each kind of element.
+            let same = elements[..instance].iter().filter(|&&t| t == tag).count();
+            self.bits.write(same as u32, 4);
+
+            if tag == ID_CPE {
+                self.encode_stereo(&input, channel, frames);
+                channel += 2;
+            } else {
+                self.encode_mono(&input, channel, frames);
+                channel += 1;
+            }
+        }
+        self.bits.write(ID_END, 3);
+        self.bits.byte_align();
+
+        let packet = self.bits.bytes();
+        out.extend_from_slice(packet);
+        self.config.max_frame_bytes = self.config.max_frame_bytes.max(packet.len() as u32);
+        self.total_bytes += packet.len() as u64;
+        self.total_frames += frames as u64;
+
+        Ok(packet.len())
+    }
+
+    /// Encode all frames of the buffers as a packet appended to `out`.
+    pub fn encode_buffer_list<S: SampleConvert>(
+        &mut self,
+        list: &AudioBufferList<S>,
+        out: &mut Vec<u8>,
+    ) -> Result<usize, CAError> {
+        self.encode(&interleave(list, list_frames(list)), out)
+    }
+
+    /// Write the 12 bit unused header, flags and frame count of an element.
+    fn write_header(&mut self, frames: usize, bytes_shifted: u32, escape: bool) {
+        let partial_frame = frames != self.config.frame_length as usize;
+        self.bits.write(0, 12);
+        self.bits.write(
+            ((partial_frame as u32) << 3) | (bytes_shifted << 1) | escape as u32,
+            4,
+        );
+        if partial_frame {
+            self.bits.write(frames as u32, 32);
+        }
+    }
+
+    /// Write the uncompressed samples of the channels of an element.
+    fn write_escape(&mut self, input: &[i32], channel: usize, count: usize, frames: usize) {
+        let channels = self.config.channels as usize;
+        let bit_depth = self.config.bit_depth as u32;
+
+        self.write_header(frames, 0, true);
+        for frame in input.chunks_exact(channels).take(frames) {
+            for &sample in &frame[channel..channel + count] {
+                self.bits.write(sample as u32 & mask(bit_depth), bit_depth);
+            }
+        }
+    }
+
+    fn escape_bits(&self, frames: usize, count: usize) -> usize {
+        let partial_frame = frames != self.config.frame_length as usize;
+        frames * self.config.bit_depth as usize * count + 32 * partial_frame as usize + 16
+    }
+
+    fn encode_mono(&mut self, input: &[i32], channel: usize, frames: usize) {
+        let channels = self.config.channels as usize;
+        let bytes_shifted = bytes_shifted(self.config.bit_depth);
+        let shift = bytes_shifted * 8;
+        let chan_bits = self.config.bit_depth as u32 - shift;
+        let partial_frame = frames != self.config.frame_length as usize;
+        let start = self.bits.position();
+
+        for (i, frame) in input.chunks_exact(channels).take(frames).enumerate() {
+            let sample = frame[channel];
+            self.shift[i] = (sample as u32 & mask(shift)) as u16;
+            self.mix_u[i] = sample >> shift;
+        }
+
+        // Pick the order that compresses a part of the packet best.
+        let mut min_bits = usize::MAX;
+        let mut best_order = MIN_ORDER;
+        for order in (MIN_ORDER..=MAX_ORDER).step_by(4) {
+            let coefs = &mut self.coefs_u[channel][order - 1];
+            for (dilate, passes) in [(32, 7), (8, 1)] {
+                let num = frames / dilate;
+                for _ in 0..passes {
+                    pc_block(
+                        &self.mix_u[..num],
+                        &mut self.predictor_u[..num],
+                        coefs,
+                        order,
+                        chan_bits,
+                        DENSHIFT_DEFAULT,
+                    );
+                }
+            }
+
+            self.work.clear();
+            let num = frames / 8;
+            dyn_comp(
+                &AgParams::standard(),
+                &self.predictor_u[..num],
+                &mut self.work,
+                chan_bits,
+            );
+            let bits = self.work.position() * 8 + 16 * order;
+            if bits < min_bits {
+                min_bits = bits;
+                best_order = order;
+            }
+        }
+
+        let estimate = min_bits + 4 * 8 + 32 * partial_frame as usize + frames * shift as usize;
+        let escape_bits = self.escape_bits(frames, 1);
+
+        if estimate < escape_bits {
+            self.write_header(frames, bytes_shifted, false);
+            // No mixing.
+            self.bits.write(0, 16);
+
+            let order = best_order;
+            self.bits.write(DENSHIFT_DEFAULT, 8);
+            self.bits.write((4 << 5) | order as u32, 8);
+            let coefs = &mut self.coefs_u[channel][order - 1];
+            for &coef in &coefs[..order] {
+                self.bits.write(coef as u16 as u32, 16);
+            }
+
+            if shift != 0 {
+                for &bits in &self.shift[..frames] {
+                    self.bits.write(bits as u32, shift);
+                }
+            }
+
+            pc_block(
+                &self.mix_u[..frames],
+                &mut self.predictor_u[..frames],
+                coefs,
+                order,
+                chan_bits,
+                DENSHIFT_DEFAULT,
+            );
+            dyn_comp(
+                &AgParams::standard(),
+                &self.predictor_u[..frames],
+                &mut self.bits,
+                chan_bits,
+            );
+
+            if self.bits.position() - start < escape_bits {
+                return;
+            }
+            self.bits.truncate(start);
+        }
+
+        self.write_escape(input, channel, 1, frames);
+    }
+
+    fn encode_stereo(&mut self, input: &[i32], channel: usize, frames: usize) {
+        let bytes_shifted = bytes_shifted(self.config.bit_depth);
+        let shift = bytes_shifted * 8;
+        let chan_bits = self.config.bit_depth as u32 - shift + 1;
+        let partial_frame = frames != self.config.frame_length as usize;
+        let start = self.bits.position();
+        let mix_bits = DEFAULT_MIX_BITS;
+
+        // Pick the mix that compresses a part of the packet best.
+        let num = frames / 8;
+        let mut min_bits = usize::MAX;
+        let mut best_res = self.last_mix_res[channel];
+        for mix_res in 0..=MAX_RES {
+            self.mix(input, channel, num, mix_bits, mix_res);
+
+            let mut coefs_u = [0; MAX_COEFS];
+            let mut coefs_v = [0; MAX_COEFS];
+            init_coefs(&mut coefs_u[..MIN_ORDER]);
+            init_coefs(&mut coefs_v[..MIN_ORDER]);
+            pc_block(
+                &self.mix_u[..num],
+                &mut self.predictor_u[..num],
+                &mut coefs_u,
+                MIN_ORDER,
+                chan_bits,
+                DENSHIFT_DEFAULT,
+            );
+            pc_block(
+                &self.mix_v[..num],
+                &mut self.predictor_v[..num],
+                &mut coefs_v,
+                MIN_ORDER,
+                chan_bits,
+                DENSHIFT_DEFAULT,
+            );
+
+            self.work.clear();
+            let params = AgParams::standard();
+            dyn_comp(&params, &self.predictor_u[..num], &mut self.work, chan_bits);
+            dyn_comp(&params, &self.predictor_v[..num], &mut self.work, chan_bits);
+            if self.work.position() < min_bits {
+                min_bits = self.work.position();
+                best_res = mix_res;
+            }
+        }
+        self.last_mix_res[channel] = best_res;
+        let mix_res = best_res;
+        self.mix(input, channel, frames, mix_bits, mix_res);
+
+        // Then the order for each channel.
+        let mut min_bits = [usize::MAX; 2];
+        let mut best_order = [MIN_ORDER; 2];
+        for order in (MIN_ORDER..=MAX_ORDER).step_by(4) {
+            for (dilate, passes) in [(32, 7), (8, 1)] {
+                let num = frames / dilate;
+                for _ in 0..passes {
+                    pc_block(
+                        &self.mix_u[..num],
+                        &mut self.predictor_u[..num],
+                        &mut self.coefs_u[channel][order - 1],
+                        order,
+                        chan_bits,
+                        DENSHIFT_DEFAULT,
+                    );
+                    pc_block(
+                        &self.mix_v[..num],
+                        &mut self.predictor_v[..num],
+                        &mut self.coefs_v[channel][order - 1],
+                        order,
+                        chan_bits,
+                        DENSHIFT_DEFAULT,
+                    );
+                }
+            }
+
+            for (i, predictor) in [&self.predictor_u, &self.predictor_v]
+                .into_iter()
+                .enumerate()
+            {
+                self.work.clear();
+                dyn_comp(
+                    &AgParams::standard(),
+                    &predictor[..num],
+                    &mut self.work,
+                    chan_bits,
+                );
+                let bits = self.work.position() * 8 + 16 * order;
+                if bits < min_bits[i] {
+                    min_bits[i] = bits;
+                    best_order[i] = order;
+                }
+            }
+        }
+
+        let estimate = min_bits[0]
+            + min_bits[1]
+            + 8 * 8
+            + 32 * partial_frame as usize
+            + frames * shift as usize * 2;
+        let escape_bits = self.escape_bits(frames, 2);
+
+        if estimate < escape_bits {
+            self.write_header(frames, bytes_shifted, false);
+            self.bits.write(mix_bits, 8);
+            self.bits.write(mix_res as u32, 8);
+
+            for (coefs, order) in [
+                (&self.coefs_u[channel], best_order[0]),
+                (&self.coefs_v[channel], best_order[1]),
+            ] {
+                self.bits.write(DENSHIFT_DEFAULT, 8);
+                self.bits.write((4 << 5) | order as u32, 8);
+                for &coef in &coefs[order - 1][..order] {
+                    self.bits.write(coef as u16 as u32, 16);
+                }
+            }
+
+            if shift != 0 {
+                for &bits in &self.shift[..frames * 2] {
+                    self.bits.write(bits as u32, shift);
+                }
+            }
+
+            for (i, order) in best_order.into_iter().enumerate() {
+                let (mix, predictor, coefs) = if i == 0 {
+                    (&self.mix_u, &mut self.predictor_u, &mut self.coefs_u)
+                } else {
+                    (&self.mix_v, &mut self.predictor_v, &mut self.coefs_v)
+                };
+                pc_block(
+                    &mix[..frames],
+                    &mut predictor[..frames],
+                    &mut coefs[channel][order - 1],
+                    order,
+                    chan_bits,
+                    DENSHIFT_DEFAULT,
+                );
+                dyn_comp(
+                    &AgParams::standard(),
+                    &predictor[..frames],
+                    &mut self.bits,
+                    chan_bits,
+                );
+            }
+
+            if self.bits.position() - start < escape_bits {
+                return;
+            }
+            self.bits.truncate(start);
+        }
+
+        self.write_escape(input, channel, 2, frames);
+    }
+
+    /// Mix the first `frames` frames of a channel pair into the U and V buffers,
+    /// keeping the shifted off bits.
+    fn mix(&mut self, input: &[i32], channel: usize, frames: usize, mix_bits: u32, mix_res: i32) {
+        let channels = self.config.channels as usize;
+        let shift = bytes_shifted(self.config.bit_depth) * 8;
+
+        for (i, frame) in input.chunks_exact(channels).take(frames).enumerate() {
+            let (mut l, mut r) = (frame[channel], frame[channel + 1]);
+            self.shift[2 * i] = (l as u32 & mask(shift)) as u16;
+            self.shift[2 * i + 1] = (r as u32 & mask(shift)) as u16;
+            l >>= shift;
+            r >>= shift;
+
+            if mix_res != 0 {
+                let m2 = 1 << mix_bits;
+                self.mix_u[i] = (mix_res * l + (m2 - mix_res) * r) >> mix_bits;
+                self.mix_v[i] = l - r;
+            } else {
+                self.mix_u[i] = l;
+                self.mix_v[i] = r;
+            }
+        }
+    }
+}
+
+fn mask(bits: u32) -> u32 {
+    if bits >= 32 {
+        u32::MAX
+    } else {
+        (1 << bits) - 1
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rng::Rng;
+
+    /// Sine waves with a bit of noise for each channel, in the upper `bit_depth` bits.
+    fn signal(frames: usize, channels: usize, bit_depth: u32, seed: u64) -> Vec<i32> {
+        let mut rng = Rng::new(seed);
+        let max = ((1_i64 << (bit_depth - 1)) - 1) as f64;
+        (0..frames * channels)
+            .map(|i| {
+                let (frame, channel) = (i / channels, i % channels);
+                let phase = frame as f64 * (channel + 1) as f64 * 440.0 / 44100.0;
+                let noise = (rng.next_f64() - 0.5) * max / 1000.0;
+                let v = (phase * std::f64::consts::TAU).sin() * max * 0.8 + noise;
+                (v as i32) << (32 - bit_depth)
+            })
+            .collect()
+    }
+
+    fn config(channels: u8, bit_depth: u8, frame_length: u32) -> AlacConfig {
+        let flags = AppleLosslessFlags::from_bits_truncate(match bit_depth {
+            16 => 1,
+            20 => 2,
+            24 => 3,
+            _ => 4,
+        });
+        AlacConfig {
+            frame_length,
+            ..AlacConfig::new(44100, channels, flags).unwrap()
+        }
+    }
+
+    #[test]
+    fn reference_packet() {
+        // 64 frames of mono 16 bit from Apple's encoder.
+        let cookie = [
+            0, 0, 16, 0, 0, 16, 40, 10, 14, 1, 0, 255, 0, 0, 0, 70, 0, 0, 0, 0, 0, 0, 172, 68,
+        ];
+        let packet = [
+            0, 0, 16, 0, 0, 0, 128, 0, 0, 19, 8, 9, 135, 248, 199, 255, 134, 0, 6, 15, 248, 0, 255,
+            252, 0, 139, 252, 247, 233, 249, 248, 163, 124, 91, 104, 126, 150, 231, 122, 114, 143,
+            4, 142, 193, 84, 2, 144, 143, 4, 82, 44, 217, 83, 73, 101, 144, 208, 239, 212, 250,
+            141, 185, 142, 226, 211, 184, 252, 133, 45, 60,
+        ];
+        let expected: [i16; 64] = [
+            0, 16, 33, 45, 62, 74, 90, 106, 117, 133, 143, 158, 173, 182, 196, 204, 217, 229, 235,
+            247, 252, 262, 271, 274, 282, 284, 291, 296, 296, 300, 299, 301, 303, 300, 300, 295,
+            294, 292, 284, 281, 272, 268, 262, 252, 245, 233, 225, 217, 203, 194, 179, 169, 158,
+            142, 131, 114, 102, 90, 72, 60, 42, 29, 16, -1,
+        ];
+
+        let mut decoder = AlacDecoder::new(&cookie).unwrap();
+        let mut out = [0_i16; 64];
+        assert_eq!(decoder.decode(&packet, &mut out).unwrap(), 64);
+        assert_eq!(out, expected);
+
+        // The encoder makes the same choices.
+        let mut encoder = AlacEncoder::new(*decoder.config()).unwrap();
+        let mut encoded = Vec::new();
+        assert_eq!(encoder.encode(&expected, &mut encoded).unwrap(), 70);
+        assert_eq!(encoded, packet);
+        assert_eq!(encoder.config().max_frame_bytes, 70);
+    }
+
+    #[test]
+    fn round_trip() {
+        for bit_depth in [16, 20, 24, 32] {
+            for channels in [1, 2, 3, 6, 8] {
+                let config = config(channels, bit_depth, 256);
+                let channels = channels as usize;
+                let samples = signal(700, channels, bit_depth as u32, bit_depth as u64);
+
+                let mut encoder = AlacEncoder::new(config).unwrap();
+                let mut packets = Vec::new();
+                let mut sizes = Vec::new();
+                for chunk in samples.chunks(256 * channels) {
+                    sizes.push(encoder.encode(chunk, &mut packets).unwrap());
+                }
+                // Compressed, not escaped.
+                assert!(packets.len() < samples.len() * bit_depth as usize / 8);
+
+                let mut decoder = AlacDecoder::new(&encoder.cookie()).unwrap();
+                let mut decoded = Vec::new();
+                let mut out = vec![0_i32; 256 * channels];
+                let mut start = 0;
+                for size in sizes {
+                    let frames = decoder
+                        .decode(&packets[start..start + size], &mut out)
+                        .unwrap();
+                    decoded.extend_from_slice(&out[..frames * channels]);
+                    start += size;
+                }
+                assert_eq!(decoded, samples, "{bit_depth} bit, {channels} channels");
+            }
+        }
+    }
+
+    #[test]
+    fn escape_and_partial_frames() {
+        // Noise at full scale doesn't compress.
+        let mut rng = Rng::new(3);
+        let samples: Vec<i16> = (0..2 * 100).map(|_| rng.next_u64() as i16).collect();
+
+        let mut encoder = AlacEncoder::new(config(2, 16, 4096)).unwrap();
+        let mut packet = Vec::new();
+        let size = encoder.encode(&samples, &mut packet).unwrap();
+        // Element and escape header, frame count, samples and end tag.
+        assert_eq!(size, (3 + 4 + 16 + 32 + 100 * 2 * 16 + 3_usize).div_ceil(8));
+
+        let mut decoder = AlacDecoder::new(&encoder.cookie()).unwrap();
+        let mut out = vec![0_i16; 4096 * 2];
+        assert_eq!(decoder.decode(&packet, &mut out).unwrap(), 100);
+        assert_eq!(out[..200], samples);
+
+        // Into buffers, with silence in a single frame.
+        let mut list = AudioBufferList::<i16>::new(2, 1, 4096);
+        let mut packet = Vec::new();
+        encoder.encode(&[0_i16, 0], &mut packet).unwrap();
+        assert_eq!(decoder.decode_buffer_list(&packet, &mut list).unwrap(), 1);
+
+        assert!(matches!(
+            decoder.decode(&packet, &mut [0_i16; 1]),
+            Err(CAError::AudioCodecError(
+                AudioCodecError::NotEnoughBufferSpace
+            ))
+        ));
+        assert!(encoder.encode(&[0_i16; 4097 * 2], &mut packet).is_err());
+    }
+
+    #[test]
+    fn malformed_packets() {
+        let config_24 = config(2, 24, 256);
+        let mut encoder = AlacEncoder::new(config_24).unwrap();
+        let mut packet = Vec::new();
+        encoder.encode(&signal(256, 2, 24, 1), &mut packet).unwrap();
+
+        let mut decoder = AlacDecoder::with_config(config_24);
+        let mut out = vec![0_i32; 256 * 2];
+        for len in 0..packet.len() {
+            assert!(decoder.decode(&packet[..len], &mut out).is_err(), "{len}");
+        }
+
+        let mut rng = Rng::new(5);
+        for _ in 0..1000 {
+            let len = rng.next_u64() as usize % 64;
+            let garbage: Vec<u8> = (0..len).map(|_| rng.next_u64() as u8).collect();
+            let _ = decoder.decode(&garbage, &mut out);
+        }
+
+        // Fill element with an escape byte of 0, then the end.
+        let mut packet = vec![0xDE, 0x00, 0x00];
+        packet.extend_from_slice(&[0; 14]);
+        packet.push(0xE0);
+        assert!(decoder.decode(&packet, &mut out).is_err());
+
+        // A compressed 32 bit channel pair without shifted bytes would need 33 bits. The
+        // header and predictors take 71 bits, the escaped residuals that follow are read
+        // with the channel bits.
+        let mut decoder = AlacDecoder::with_config(config(2, 32, 256));
+        let mut packet = vec![0xFF; 64];
+        packet[..9].copy_from_slice(&[(ID_CPE << 5) as u8, 0, 0, 0, 0, 0, 0, 0, 0x01]);
+        assert!(matches!(
+            decoder.decode(&packet, &mut out),
+            Err(CAError::AudioFileError(AudioFileError::InvalidFile))
+        ));
+    }
+
+    #[test]
+    fn cookie() {
+        let config = config(6, 20, 4096);
+        let cookie = config.cookie();
+        assert_eq!(cookie.len(), 24);
+        assert_eq!(AlacConfig::from_cookie(&cookie).unwrap(), config);
+
+        // Wrapped in atoms as in QuickTime files.
+        let mut wrapped = Vec::new();
+        wrapped.extend_from_slice(&[0, 0, 0, 12]);
+        wrapped.extend_from_slice(b"frmaalac");
+        wrapped.extend_from_slice(&[0, 0, 0, 36]);
+        wrapped.extend_from_slice(b"alac");
+        wrapped.extend_from_slice(&[0; 4]);
+        wrapped.extend_from_slice(&cookie);
+        assert_eq!(AlacConfig::from_cookie(&wrapped).unwrap(), config);
+
+        assert!(AlacConfig::from_cookie(&cookie[..23]).is_err());
+        let mut bad = cookie.clone();
+        bad[5] = 8;
+        assert!(AlacConfig::from_cookie(&bad).is_err());
+        bad[5] = 20;
+        bad[9] = 9;
+        assert!(AlacConfig::from_cookie(&bad).is_err());
+
+        assert_eq!(config.flags(), AppleLosslessFlags::BIT_20_SOURCE_DATA);
+        let description = config.description();
+        assert!(description.has_variable_packets());
+        assert_eq!(description.frames_per_packet, 4096);
+        assert_eq!(config.output_format().sample_format(), SampleFormat::I24);
+        assert_eq!(bit_depth(AppleLosslessFlags::empty()), None);
+    }
+}
@@ -0,0 +1,306 @@
+//! ITU-T G.711 µ-law and A-law, as in the G.191 reference implementation.
+//!
+//! Encoding and decoding are table lookups. The tables are built at compile time.
+
+use crate::error::AudioFormatError;
+use crate::file::{deinterleave, interleave, list_frames};
+use crate::format::{AudioFormat, SampleConvert};
+use crate::unit::AudioBufferList;
+use crate::CAError;
+
+const fn ulaw_expand(code: u8) -> i16 {
+    let mantissa = !code;
+    let exponent = ((mantissa >> 4) & 0x07) as i32;
+    let mantissa = (mantissa & 0x0F) as i32;
+    let step = 4 << (exponent + 1);
+
+    let magnitude = (0x80 << exponent) + step * mantissa + step / 2 - 4 * 33;
+    if code < 0x80 {
+        -magnitude as i16
+    } else {
+        magnitude as i16
+    }
+}
+
+const fn alaw_expand(code: u8) -> i16 {
+    let ix = (code ^ 0x55) & 0x7F;
+    let exponent = (ix >> 4) as i32;
+    let mut mantissa = (ix & 0x0F) as i32;
+
+    if exponent > 0 {
+        mantissa += 16;
+    }
+    mantissa = (mantissa << 4) + 0x08;
+    if exponent > 1 {
+        mantissa <<= exponent - 1;
+    }
+
+    if code > 127 {
+        mantissa as i16
+    } else {
+        -mantissa as i16
+    }
+}
+
+/// µ-law of the 14 bit value `x >> 2`.
+const fn ulaw_compress(x: i16) -> u8 {
+    let magnitude = if x < 0 { (!x) >> 2 } else { x >> 2 } as i32 + 33;
+    let magnitude = if magnitude > 0x1FFF {
+        0x1FFF
+    } else {
+        magnitude
+    };
+
+    let mut segment = 1;
+    let mut i = magnitude >> 6;
+    while i != 0 {
+        segment += 1;
+        i >>= 1;
+    }
+
+    let high = 0x08 - segment;
+    let low = 0x0F - ((magnitude >> segment) & 0x0F);
+    let code = ((high << 4) | low) as u8;
+
+    if x >= 0 {
+        code | 0x80
+    } else {
+        code
+    }
+}
+
+/// A-law of the 12 bit value `x >> 4`.
+const fn alaw_compress(x: i16) -> u8 {
+    let mut ix = if x < 0 { (!x) >> 4 } else { x >> 4 } as i32;
+
+    if ix > 15 {
+        let mut exponent = 1;
+        while ix > 16 + 15 {
+            ix >>= 1;
+            exponent += 1;
+        }
+        ix -= 16;
+        ix += exponent << 4;
+    }
+
+    if x >= 0 {
+        ix |= 0x80;
+    }
+
+    (ix ^ 0x55) as u8
+}
+
+const ULAW_DECODE: [i16; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        table[i] = ulaw_expand(i as u8);
+        i += 1;
+    }
+    table
+};
+
+const ALAW_DECODE: [i16; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        table[i] = alaw_expand(i as u8);
+        i += 1;
+    }
+    table
+};
+
+/// Indexed by the top 14 bits of the linear value.
+const ULAW_ENCODE: [u8; 1 << 14] = {
+    let mut table = [0; 1 << 14];
+    let mut i = 0;
+    while i < 1 << 14 {
+        table[i] = ulaw_compress(((i as u16) << 2) as i16);
+        i += 1;
+    }
+    table
+};
+
+/// Indexed by the top 12 bits of the linear value.
+const ALAW_ENCODE: [u8; 1 << 12] = {
+    let mut table = [0; 1 << 12];
+    let mut i = 0;
+    while i < 1 << 12 {
+        table[i] = alaw_compress(((i as u16) << 4) as i16);
+        i += 1;
+    }
+    table
+};
+
+/// Encode a 16 bit linear sample as µ-law.
+pub fn ulaw_encode(sample: i16) -> u8 {
+    ULAW_ENCODE[sample as u16 as usize >> 2]
+}
+
+/// Decode a µ-law code to a 16 bit linear sample.
+pub fn ulaw_decode(code: u8) -> i16 {
+    ULAW_DECODE[code as usize]
+}
+
+/// Encode a 16 bit linear sample as A-law.
+pub fn alaw_encode(sample: i16) -> u8 {
+    ALAW_ENCODE[sample as u16 as usize >> 4]
+}
+
+/// Decode an A-law code to a 16 bit linear sample.
+pub fn alaw_decode(code: u8) -> i16 {
+    ALAW_DECODE[code as usize]
+}
+
+/// Converter between samples and µ-law or A-law bytes, one byte per sample.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct G711Codec {
+    ulaw: bool,
+}
+
+impl G711Codec {
+    /// Codec for [`AudioFormat::ULaw`] or [`AudioFormat::ALaw`].
+    pub fn new(format: AudioFormat) -> Result<Self, CAError> {
+        match format {
+            AudioFormat::ULaw => Ok(G711Codec { ulaw: true }),
+            AudioFormat::ALaw => Ok(G711Codec { ulaw: false }),
+            _ => Err(AudioFormatError::UnsupportedDataFormat.into()),
+        }
+    }
+
+    pub fn format(&self) -> AudioFormat {
+        if self.ulaw {
+            AudioFormat::ULaw
+        } else {
+            AudioFormat::ALaw
+        }
+    }
+
+    /// Append the codes of the samples to `out`. Samples are rounded down to 16 bits.
+    pub fn encode<S: SampleConvert>(&self, samples: &[S], out: &mut Vec<u8>) {
+        let encode = if self.ulaw { ulaw_encode } else { alaw_encode };
+        out.extend(samples.iter().map(|s| encode((s.to_i32() >> 16) as i16)));
+    }
+
+    /// Decode as many codes as fit in `out`. Returns the number of samples decoded.
+    pub fn decode<S: SampleConvert>(&self, codes: &[u8], out: &mut [S]) -> usize {
+        let table = if self.ulaw {
+            &ULAW_DECODE
+        } else {
+            &ALAW_DECODE
+        };
+        for (sample, &code) in out.iter_mut().zip(codes) {
+            *sample = S::from_i32((table[code as usize] as i32) << 16);
+        }
+        codes.len().min(out.len())
+    }
+
+    /// Append the codes of all frames of the buffers to `out`, interleaved.
+    pub fn encode_buffer_list<S: SampleConvert>(
+        &self,
+        list: &AudioBufferList<S>,
+        out: &mut Vec<u8>,
+    ) {
+        self.encode(&interleave(list, list_frames(list)), out);
+    }
+
+    /// Decode interleaved codes into the buffers. Returns the number of frames decoded,
+    /// the rest of the buffers is left untouched.
+    pub fn decode_buffer_list<S: SampleConvert>(
+        &self,
+        codes: &[u8],
+        list: &mut AudioBufferList<S>,
+    ) -> usize {
+        let channels: usize = list.iter().map(|b| b.channels()).sum();
+        let frames = (codes.len() / channels).min(list_frames(list));
+
+        let mut samples = vec![S::default(); frames * channels];
+        self.decode(&codes[..frames * channels], &mut samples);
+        deinterleave(&samples, list);
+
+        frames
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ulaw_conformance() {
+        // Reference values of G.191.
+        assert_eq!(ulaw_encode(0), 0xFF);
+        assert_eq!(ulaw_encode(-1), 0x7F);
+        assert_eq!(ulaw_encode(i16::MAX), 0x80);
+        assert_eq!(ulaw_encode(i16::MIN), 0x00);
+        assert_eq!(ulaw_decode(0xFF), 0);
+        assert_eq!(ulaw_decode(0x7F), 0);
+        assert_eq!(ulaw_decode(0x80), 32124);
+        assert_eq!(ulaw_decode(0x00), -32124);
+        assert_eq!(ulaw_decode(0xEF), 132);
+        assert_eq!(ulaw_encode(1000), 0xCE);
+        assert_eq!(ulaw_decode(0xCE), 988);
+
+        for x in i16::MIN..=i16::MAX {
+            assert_eq!(ulaw_encode(x), ulaw_compress(x));
+        }
+    }
+
+    #[test]
+    fn alaw_conformance() {
+        assert_eq!(alaw_encode(0), 0xD5);
+        assert_eq!(alaw_encode(-1), 0x55);
+        assert_eq!(alaw_encode(i16::MAX), 0xAA);
+        assert_eq!(alaw_encode(i16::MIN), 0x2A);
+        assert_eq!(alaw_decode(0xD5), 8);
+        assert_eq!(alaw_decode(0x55), -8);
+        assert_eq!(alaw_decode(0xAA), 32256);
+        assert_eq!(alaw_decode(0x2A), -32256);
+        assert_eq!(alaw_encode(1000), 0xFA);
+        assert_eq!(alaw_decode(0xFA), 1008);
+
+        for x in i16::MIN..=i16::MAX {
+            assert_eq!(alaw_encode(x), alaw_compress(x));
+        }
+    }
+
+    #[test]
+    fn codes_round_trip() {
+        for code in 0..=255 {
+            // Except negative zero.
+            if code != 0x7F {
+                assert_eq!(ulaw_encode(ulaw_decode(code)), code);
+            }
+            assert_eq!(alaw_encode(alaw_decode(code)), code);
+        }
+
+        // Decoding is monotonic in the magnitude.
+        for code in 0x81..=0xFF_u8 {
+            assert!(ulaw_decode(code) < ulaw_decode(code - 1));
+        }
+    }
+
+    #[test]
+    fn codec_buffers() {
+        assert!(G711Codec::new(AudioFormat::AC3).is_err());
+
+        let codec = G711Codec::new(AudioFormat::ALaw).unwrap();
+        assert_eq!(codec.format(), AudioFormat::ALaw);
+
+        let mut list = AudioBufferList::<f32>::new(2, 1, 2);
+        deinterleave(&[0.0, 0.5, -0.5, 1.0], &mut list);
+
+        let mut codes = Vec::new();
+        codec.encode_buffer_list(&list, &mut codes);
+        assert_eq!(codes.len(), 4);
+        assert_eq!(codes[0], 0xD5);
+
+        let mut decoded = AudioBufferList::<i16>::new(1, 2, 3);
+        assert_eq!(codec.decode_buffer_list(&codes, &mut decoded), 2);
+        let samples = decoded[0].samples();
+        assert_eq!(samples[0], 8);
+        assert!((samples[1] - 16384).abs() <= 512);
+        assert!((samples[2] + 16384).abs() <= 512);
+        assert_eq!(samples[3], 32256);
+    }
+}
@@ -0,0 +1,442 @@
+//! Apple IMA4 ADPCM, as found in CAF and AIFF-C files.
+//!
+//! A packet holds 64 frames. Each channel has its own 34 byte block in the packet: a
+//! 2 byte big endian header with the top 9 bits of the predictor and the step index,
+//! then 32 bytes of 4 bit codes, low nibble first.
+
+use crate::error::{AudioCodecError, AudioFileError};
+use crate::file::{deinterleave, interleave, list_frames};
+use crate::format::{AudioFormat, FormatDescription};
+use crate::unit::AudioBufferList;
+use crate::CAError;
+
+/// Frames in a packet.
+pub const FRAMES_PER_PACKET: usize = 64;
+
+/// Bytes per channel in a packet.
+pub const BYTES_PER_BLOCK: usize = 34;
+
+pub(crate) const STEP_TABLE: [i32; 89] = [
+    7, 8, 9, 10, 11, 12, 13, 14, 16, 17, 19, 21, 23, 25, 28, 31, 34, 37, 41, 45, 50, 55, 60, 66,
+    73, 80, 88, 97, 107, 118, 130, 143, 157, 173, 190, 209, 230, 253, 279, 307, 337, 371, 408, 449,
+    494, 544, 598, 658, 724, 796, 876, 963, 1060, 1166, 1282, 1411, 1552, 1707, 1878, 2066, 2272,
+    2499, 2749, 3024, 3327, 3660, 4026, 4428, 4871, 5358, 5894, 6484, 7132, 7845, 8630, 9493,
+    10442, 11487, 12635, 13899, 15289, 16818, 18500, 20350, 22385, 24623, 27086, 29794, 32767,
+];
+
+const INDEX_TABLE: [i32; 16] = [-1, -1, -1, -1, 2, 4, 6, 8, -1, -1, -1, -1, 2, 4, 6, 8];
+
+/// Predictor and step index of one channel of an IMA ADPCM stream.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct ImaState {
+    pub(crate) predictor: i32,
+    pub(crate) index: i32,
+}
+
+impl ImaState {
+    pub(crate) fn new(predictor: i16, index: u8) -> Self {
+        ImaState {
+            predictor: predictor as i32,
+            index: (index as i32).min(88),
+        }
+    }
+
+    /// Decode a 4 bit code and advance the state.
+    pub(crate) fn decode(&mut self, code: u8) -> i16 {
+        let step = STEP_TABLE[self.index as usize];
+
+        let mut diff = step >> 3;
+        if code & 4 != 0 {
+            diff += step;
+        }
+        if code & 2 != 0 {
+            diff += step >> 1;
+        }
+        if code & 1 != 0 {
+            diff += step >> 2;
+        }
+        if code & 8 != 0 {
+            diff = -diff;
+        }
+
+        self.predictor = (self.predictor + diff).clamp(i16::MIN as i32, i16::MAX as i32);
+        self.index = (self.index + INDEX_TABLE[code as usize & 0x0F]).clamp(0, 88);
+
+        self.predictor as i16
+    }
+
+    /// Encode a sample as the 4 bit code closest to it and advance the state.
+    pub(crate) fn encode(&mut self, sample: i16) -> u8 {
+        let mut step = STEP_TABLE[self.index as usize];
+        let mut diff = sample as i32 - self.predictor;
+
+        let mut code = 0;
+        if diff < 0 {
+            code = 8;
+            diff = -diff;
+        }
+        for bit in [4, 2, 1] {
+            if diff >= step {
+                code |= bit;
+                diff -= step;
+            }
+            step >>= 1;
+        }
+
+        self.decode(code);
+        code
+    }
+}
+
+/// Description of IMA4 at the sample rate.
+pub fn description(sample_rate: f64, channels: u32) -> FormatDescription {
+    FormatDescription {
+        sample_rate,
+        format: AudioFormat::AppleIMA4,
+        bytes_per_packet: BYTES_PER_BLOCK as u32 * channels,
+        frames_per_packet: FRAMES_PER_PACKET as u32,
+        channels,
+        bits_per_channel: 0,
+    }
+}
+
+/// Decode a packet with a block for each channel to 64 interleaved frames.
+///
+/// Fails if the packet isn't made of whole blocks or `out` is too small.
+pub fn decode_packet(packet: &[u8], out: &mut [i16]) -> Result<(), CAError> {
+    let channels = packet.len() / BYTES_PER_BLOCK;
+    if channels == 0 || packet.len() % BYTES_PER_BLOCK != 0 {
+        return Err(AudioFileError::InvalidFile.into());
+    }
+    if out.len() < channels * FRAMES_PER_PACKET {
+        return Err(AudioCodecError::NotEnoughBufferSpace.into());
+    }
+
+    decode_blocks(packet, channels, out);
+    Ok(())
+}
+
+/// Decode a packet of `channels` blocks, `out` holds at least 64 frames.
+fn decode_blocks(packet: &[u8], channels: usize, out: &mut [i16]) {
+    for (channel, block) in packet.chunks_exact(BYTES_PER_BLOCK).enumerate() {
+        let header = u16::from_be_bytes([block[0], block[1]]);
+        let mut state = ImaState::new((header & 0xFF80) as i16, (header & 0x7F) as u8);
+
+        for (i, byte) in block[2..].iter().enumerate() {
+            let frame = i * 2;
+            out[frame * channels + channel] = state.decode(byte & 0x0F);
+            out[(frame + 1) * channels + channel] = state.decode(byte >> 4);
+        }
+    }
+}
+
+/// Streaming encoder from i16 buffers to IMA4 packets.
+#[derive(Debug, Clone)]
+pub struct Ima4Encoder {
+    states: Vec<ImaState>,
+    // Interleaved frames that don't fill a packet yet.
+    pending: Vec<i16>,
+}
+
+impl Ima4Encoder {
+    pub fn new(channels: usize) -> Self {
+        assert!(channels >= 1);
+
+        Ima4Encoder {
+            states: vec![ImaState::default(); channels],
+            pending: Vec::with_capacity(channels * FRAMES_PER_PACKET),
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn bytes_per_packet(&self) -> usize {
+        self.channels() * BYTES_PER_BLOCK
+    }
+
+    /// Append a packet for every 64 frames of the buffers to `out`. Frames that don't
+    /// fill a packet are kept for the next call.
+    ///
+    /// Returns the number of packets written.
+    pub fn encode(
+        &mut self,
+        list: &AudioBufferList<i16>,
+        out: &mut Vec<u8>,
+    ) -> Result<usize, CAError> {
+        let channels: usize = list.iter().map(|b| b.channels()).sum();
+        if channels != self.channels() {
+            return Err(AudioCodecError::UnsupportedFormat.into());
+        }
+
+        Ok(self.encode_interleaved(&interleave(list, list_frames(list)), out))
+    }
+
+    /// Like [`encode`](Self::encode) but for interleaved samples.
+    pub fn encode_interleaved(&mut self, mut samples: &[i16], out: &mut Vec<u8>) -> usize {
+        let packet_len = self.channels() * FRAMES_PER_PACKET;
+        let mut packets = 0;
+
+        if !self.pending.is_empty() {
+            let n = (packet_len - self.pending.len()).min(samples.len());
+            self.pending.extend_from_slice(&samples[..n]);
+            samples = &samples[n..];
+
+            if self.pending.len() < packet_len {
+                return 0;
+            }
+            let pending = std::mem::take(&mut self.pending);
+            self.encode_packet(&pending, out);
+            self.pending = pending;
+            self.pending.clear();
+            packets += 1;
+        }
+
+        let mut chunks = samples.chunks_exact(packet_len);
+        for packet in &mut chunks {
+            self.encode_packet(packet, out);
+            packets += 1;
+        }
+        self.pending.extend_from_slice(chunks.remainder());
+
+        packets
+    }
+
+    /// Write the frames that are left as a last packet, padded by repeating the last
+    /// frame.
+    ///
+    /// Returns the number of padding frames, which is 0 if no packet was written.
+    pub fn flush(&mut self, out: &mut Vec<u8>) -> usize {
+        if self.pending.is_empty() {
+            return 0;
+        }
+
+        let channels = self.channels();
+        let padding = FRAMES_PER_PACKET - self.pending.len() / channels;
+        let last = self.pending[self.pending.len() - channels..].to_vec();
+        for _ in 0..padding {
+            self.pending.extend_from_slice(&last);
+        }
+
+        let pending = std::mem::take(&mut self.pending);
+        self.encode_packet(&pending, out);
+        self.pending = pending;
+        self.pending.clear();
+
+        padding
+    }
+
+    fn encode_packet(&mut self, samples: &[i16], out: &mut Vec<u8>) {
+        let channels = self.channels();
+
+        for (channel, state) in self.states.iter_mut().enumerate() {
+            // The decoder only gets the top 9 bits of the predictor.
+            let header = (state.predictor as i16 as u16 & 0xFF80) | state.index as u16;
+            *state = ImaState::new((header & 0xFF80) as i16, state.index as u8);
+            out.extend_from_slice(&header.to_be_bytes());
+
+            let mut frames = samples.iter().skip(channel).step_by(channels);
+            while let (Some(&low), Some(&high)) = (frames.next(), frames.next()) {
+                out.push(state.encode(low) | (state.encode(high) << 4));
+            }
+        }
+    }
+}
+
+/// Streaming decoder from IMA4 packets to i16 buffers.
+#[derive(Debug, Clone)]
+pub struct Ima4Decoder {
+    channels: usize,
+    scratch: Vec<i16>,
+}
+
+impl Ima4Decoder {
+    pub fn new(channels: usize) -> Self {
+        assert!(channels >= 1);
+
+        Ima4Decoder {
+            channels,
+            scratch: vec![0; channels * FRAMES_PER_PACKET],
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn bytes_per_packet(&self) -> usize {
+        self.channels * BYTES_PER_BLOCK
+    }
+
+    /// Decode as many whole packets as fit in the buffers, starting at the first
+    /// frame. The rest of the buffers is left untouched.
+    ///
+    /// Returns the number of frames decoded, the bytes used are
+    /// `frames / 64 * bytes_per_packet()`.
+    pub fn decode(
+        &mut self,
+        packets: &[u8],
+        list: &mut AudioBufferList<i16>,
+    ) -> Result<usize, CAError> {
+        let channels: usize = list.iter().map(|b| b.channels()).sum();
+        if channels != self.channels {
+            return Err(AudioCodecError::UnsupportedFormat.into());
+        }
+
+        let count =
+            (packets.len() / self.bytes_per_packet()).min(list_frames(list) / FRAMES_PER_PACKET);
+        let mut samples = vec![0; count * FRAMES_PER_PACKET * channels];
+
+        self.decode_interleaved(packets, &mut samples);
+        deinterleave(&samples, list);
+
+        Ok(count * FRAMES_PER_PACKET)
+    }
+
+    /// Like [`decode`](Self::decode) but to interleaved samples.
+    pub fn decode_interleaved(&mut self, packets: &[u8], out: &mut [i16]) -> usize {
+        let packet_len = self.channels * FRAMES_PER_PACKET;
+        let mut frames = 0;
+
+        for (packet, out) in packets
+            .chunks_exact(self.bytes_per_packet())
+            .zip(out.chunks_exact_mut(packet_len))
+        {
+            decode_blocks(packet, self.channels, &mut self.scratch);
+            out.copy_from_slice(&self.scratch);
+            frames += FRAMES_PER_PACKET;
+        }
+
+        frames
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sine(frames: usize, channels: usize) -> Vec<i16> {
+        (0..frames * channels)
+            .map(|i| {
+                let (frame, channel) = (i / channels, i % channels);
+                let phase = frame as f64 * (channel + 1) as f64 * 440.0 / 44100.0;
+                ((phase * std::f64::consts::TAU).sin() * 20000.0) as i16
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reference_packet() {
+        // Predictor 0, index 0, all codes 7.
+        let mut packet = [0x77; BYTES_PER_BLOCK];
+        packet[..2].copy_from_slice(&[0x00, 0x00]);
+        let mut out = [0; FRAMES_PER_PACKET];
+        decode_packet(&packet, &mut out).unwrap();
+        assert_eq!(out[..4], [11, 41, 104, 240]);
+        assert_eq!(out[63], i16::MAX);
+
+        // Predictor 0x1200 and index 52 (step 1060), codes 8 then 0.
+        let mut packet = [0x08; BYTES_PER_BLOCK];
+        packet[..2].copy_from_slice(&[0x12, 0x34]);
+        decode_packet(&packet, &mut out).unwrap();
+        assert_eq!(out[..2], [4608 - 132, 4608 - 132 + 120]);
+
+        // The low 7 bits of the predictor are dropped, indices are clamped.
+        packet[..2].copy_from_slice(&[0xFF, 0xFF]);
+        decode_packet(&packet, &mut out).unwrap();
+        assert_eq!(out[0], -128 - 4095);
+
+        assert!(decode_packet(&packet[..33], &mut out).is_err());
+        assert!(decode_packet(&[], &mut out).is_err());
+        assert!(matches!(
+            decode_packet(&packet, &mut out[..63]),
+            Err(CAError::AudioCodecError(
+                AudioCodecError::NotEnoughBufferSpace
+            ))
+        ));
+    }
+
+    #[test]
+    fn state_round_trip() {
+        let mut encoder = ImaState::default();
+        let mut decoder = ImaState::default();
+
+        for sample in sine(1000, 1) {
+            let code = encoder.encode(sample);
+            assert_eq!(decoder.decode(code) as i32, encoder.predictor);
+        }
+        assert_eq!(encoder, decoder);
+    }
+
+    #[test]
+    fn encode_decode() {
+        let samples = sine(1000, 2);
+
+        let mut encoder = Ima4Encoder::new(2);
+        let mut packets = Vec::new();
+        assert_eq!(encoder.encode_interleaved(&samples, &mut packets), 15);
+        assert_eq!(encoder.flush(&mut packets), 24);
+        assert_eq!(encoder.flush(&mut packets), 0);
+        assert_eq!(packets.len(), 16 * 68);
+
+        let mut decoder = Ima4Decoder::new(2);
+        let mut decoded = vec![0; 16 * 64 * 2];
+        assert_eq!(decoder.decode_interleaved(&packets, &mut decoded), 1024);
+
+        let error: f64 = samples
+            .iter()
+            .zip(&decoded)
+            .map(|(&a, &b)| ((a as f64) - (b as f64)).powi(2))
+            .sum();
+        let signal: f64 = samples.iter().map(|&a| (a as f64).powi(2)).sum();
+        let snr = 10.0 * (signal / error).log10();
+        assert!(snr > 25.0, "snr {snr}");
+
+        // Padding repeats the last frame.
+        assert!((decoded[2047] - samples[1999]).abs() < 300);
+    }
+
+    #[test]
+    fn streaming_buffers() {
+        let samples = sine(300, 2);
+
+        let mut whole = Vec::new();
+        Ima4Encoder::new(2).encode_interleaved(&samples, &mut whole);
+
+        // Non interleaved buffers in uneven chunks give the same packets.
+        let mut encoder = Ima4Encoder::new(2);
+        let mut packets = Vec::new();
+        let mut written = 0;
+        for chunk in samples.chunks(2 * 50) {
+            let mut list = AudioBufferList::<i16>::new(2, 1, chunk.len() / 2);
+            deinterleave(chunk, &mut list);
+            written += encoder.encode(&list, &mut packets).unwrap();
+        }
+        assert_eq!(written, 4);
+        assert_eq!(packets, whole);
+
+        let mut list = AudioBufferList::<i16>::new(1, 1, 64);
+        assert!(encoder.encode(&list, &mut packets).is_err());
+
+        // Only whole packets that fit are decoded.
+        let mut decoder = Ima4Decoder::new(2);
+        assert!(decoder.decode(&packets, &mut list).is_err());
+        let mut list = AudioBufferList::<i16>::new(2, 1, 150);
+        assert_eq!(decoder.decode(&packets, &mut list).unwrap(), 128);
+        assert_eq!(decoder.decode(&packets[..67], &mut list).unwrap(), 0);
+
+        let mut decoded = vec![0; 128 * 2];
+        decoder.decode_interleaved(&packets, &mut decoded);
+        assert_eq!(interleave(&list, 128), decoded);
+    }
+
+    #[test]
+    fn description_of_packets() {
+        let desc = description(22050.0, 2);
+        assert_eq!(desc.bytes_per_packet, 68);
+        assert_eq!(desc.frames_per_packet, 64);
+        assert!(!desc.has_variable_packets());
+        assert!(desc.stream_format().is_none());
+    }
+}
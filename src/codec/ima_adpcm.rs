@@ -0,0 +1,447 @@
+//! DVI/Intel IMA ADPCM, the WAVE_FORMAT_DVI_ADPCM (0x11) format of WAV files.
+//!
+//! A block starts with a 4 byte header for each channel: the first sample as little
+//! endian i16, the step index and a reserved byte. The other samples follow as 4 bit
+//! codes, low nibble first, interleaved in words of 4 bytes (8 samples) per channel.
+
+use crate::error::{AudioCodecError, AudioFileError};
+use crate::file::{deinterleave, interleave, list_frames};
+use crate::format::{AudioFormat, FormatDescription};
+use crate::unit::AudioBufferList;
+use crate::CAError;
+
+use super::ima4::ImaState;
+
+/// Channels and block size of a stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImaAdpcmFormat {
+    channels: usize,
+    block_align: usize,
+}
+
+impl ImaAdpcmFormat {
+    /// Blocks of `block_align` bytes, which is a multiple of 4 bytes per channel and
+    /// fits in the 16 bit field of WAV.
+    pub fn new(channels: usize, block_align: usize) -> Result<Self, CAError> {
+        if channels == 0
+            || block_align < 8 * channels
+            || block_align % (4 * channels) != 0
+            || block_align > u16::MAX as usize
+        {
+            return Err(AudioCodecError::UnsupportedFormat.into());
+        }
+
+        Ok(ImaAdpcmFormat {
+            channels,
+            block_align,
+        })
+    }
+
+    /// The usual block size for the sample rate: 256 bytes per channel up to 11025 Hz,
+    /// doubled for every doubling of the rate.
+    pub fn for_sample_rate(sample_rate: f64, channels: usize) -> Result<Self, CAError> {
+        let per_channel = match sample_rate {
+            r if r <= 11025.0 => 256,
+            r if r <= 22050.0 => 512,
+            _ => 1024,
+        };
+        ImaAdpcmFormat::new(channels, per_channel * channels)
+    }
+
+    /// Format from the fields of a `fmt ` chunk, with `extension` the bytes after
+    /// `cbSize`.
+    pub fn from_fmt(
+        channels: u16,
+        block_align: u16,
+        bits_per_sample: u16,
+        extension: &[u8],
+    ) -> Result<Self, CAError> {
+        let invalid = || CAError::from(AudioFileError::InvalidFile);
+
+        if bits_per_sample != 4 {
+            return Err(invalid());
+        }
+        let format =
+            ImaAdpcmFormat::new(channels as usize, block_align as usize).map_err(|_| invalid())?;
+
+        // wSamplesPerBlock, which some writers leave out.
+        if extension.len() >= 2 {
+            let samples_per_block = u16::from_le_bytes([extension[0], extension[1]]);
+            if samples_per_block as usize != format.frames_per_block() {
+                return Err(invalid());
+            }
+        }
+
+        Ok(format)
+    }
+
+    /// The bytes after `cbSize` in a `fmt ` chunk.
+    pub fn fmt_extension(&self) -> [u8; 2] {
+        (self.frames_per_block() as u16).to_le_bytes()
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn block_align(&self) -> usize {
+        self.block_align
+    }
+
+    pub fn frames_per_block(&self) -> usize {
+        self.frames_in(self.block_align).unwrap()
+    }
+
+    /// Frames in a block of `len` bytes, which can be short at the end of a stream.
+    pub fn frames_in(&self, len: usize) -> Option<usize> {
+        let header = 4 * self.channels;
+        if len < header || len > self.block_align {
+            return None;
+        }
+        Some(1 + (len - header) / header * 8)
+    }
+
+    pub fn description(&self, sample_rate: f64) -> FormatDescription {
+        FormatDescription {
+            sample_rate,
+            format: AudioFormat::DVIIntelIMA,
+            bytes_per_packet: self.block_align as u32,
+            frames_per_packet: self.frames_per_block() as u32,
+            channels: self.channels as u32,
+            bits_per_channel: 4,
+        }
+    }
+}
+
+/// Streaming encoder from i16 buffers to blocks.
+#[derive(Debug, Clone)]
+pub struct ImaAdpcmEncoder {
+    format: ImaAdpcmFormat,
+    states: Vec<ImaState>,
+    // Interleaved frames that don't fill a block yet.
+    pending: Vec<i16>,
+}
+
+impl ImaAdpcmEncoder {
+    pub fn new(format: ImaAdpcmFormat) -> Self {
+        ImaAdpcmEncoder {
+            format,
+            states: vec![ImaState::default(); format.channels],
+            pending: Vec::with_capacity(format.channels * format.frames_per_block()),
+        }
+    }
+
+    pub fn format(&self) -> &ImaAdpcmFormat {
+        &self.format
+    }
+
+    /// Append a block for every `frames_per_block()` frames of the buffers to `out`.
+    /// Frames that don't fill a block are kept for the next call.
+    ///
+    /// Returns the number of blocks written.
+    pub fn encode(
+        &mut self,
+        list: &AudioBufferList<i16>,
+        out: &mut Vec<u8>,
+    ) -> Result<usize, CAError> {
+        let channels: usize = list.iter().map(|b| b.channels()).sum();
+        if channels != self.format.channels {
+            return Err(AudioCodecError::UnsupportedFormat.into());
+        }
+
+        Ok(self.encode_interleaved(&interleave(list, list_frames(list)), out))
+    }
+
+    /// Like [`encode`](Self::encode) but for interleaved samples.
+    pub fn encode_interleaved(&mut self, mut samples: &[i16], out: &mut Vec<u8>) -> usize {
+        let block_len = self.format.channels * self.format.frames_per_block();
+        let mut blocks = 0;
+
+        if !self.pending.is_empty() {
+            let n = (block_len - self.pending.len()).min(samples.len());
+            self.pending.extend_from_slice(&samples[..n]);
+            samples = &samples[n..];
+
+            if self.pending.len() < block_len {
+                return 0;
+            }
+            let pending = std::mem::take(&mut self.pending);
+            self.encode_block(&pending, out);
+            self.pending = pending;
+            self.pending.clear();
+            blocks += 1;
+        }
+
+        let mut chunks = samples.chunks_exact(block_len);
+        for block in &mut chunks {
+            self.encode_block(block, out);
+            blocks += 1;
+        }
+        self.pending.extend_from_slice(chunks.remainder());
+
+        blocks
+    }
+
+    /// Write the frames that are left as a last block, padded by repeating the last
+    /// frame.
+    ///
+    /// Returns the number of padding frames, which is 0 if no block was written.
+    pub fn flush(&mut self, out: &mut Vec<u8>) -> usize {
+        if self.pending.is_empty() {
+            return 0;
+        }
+
+        let channels = self.format.channels;
+        let padding = self.format.frames_per_block() - self.pending.len() / channels;
+        let last = self.pending[self.pending.len() - channels..].to_vec();
+        for _ in 0..padding {
+            self.pending.extend_from_slice(&last);
+        }
+
+        let pending = std::mem::take(&mut self.pending);
+        self.encode_block(&pending, out);
+        self.pending = pending;
+        self.pending.clear();
+
+        padding
+    }
+
+    fn encode_block(&mut self, samples: &[i16], out: &mut Vec<u8>) {
+        let channels = self.format.channels;
+
+        for (channel, state) in self.states.iter_mut().enumerate() {
+            // The first sample is stored as is.
+            state.predictor = samples[channel] as i32;
+            out.extend_from_slice(&samples[channel].to_le_bytes());
+            out.extend_from_slice(&[state.index as u8, 0]);
+        }
+
+        let words = (self.format.frames_per_block() - 1) / 8;
+        for word in 0..words {
+            for (channel, state) in self.states.iter_mut().enumerate() {
+                for pair in 0..4 {
+                    let frame = 1 + word * 8 + pair * 2;
+                    let low = state.encode(samples[frame * channels + channel]);
+                    let high = state.encode(samples[(frame + 1) * channels + channel]);
+                    out.push(low | (high << 4));
+                }
+            }
+        }
+    }
+}
+
+/// Decoder from blocks to i16 buffers.
+#[derive(Debug, Clone)]
+pub struct ImaAdpcmDecoder {
+    format: ImaAdpcmFormat,
+}
+
+impl ImaAdpcmDecoder {
+    pub fn new(format: ImaAdpcmFormat) -> Self {
+        ImaAdpcmDecoder { format }
+    }
+
+    pub fn format(&self) -> &ImaAdpcmFormat {
+        &self.format
+    }
+
+    /// Decode a block to interleaved samples. The block can be shorter than
+    /// `block_align()`, as the last block of a stream might be.
+    ///
+    /// Returns the number of frames decoded. Fails if the block is malformed or `out`
+    /// is too small.
+    pub fn decode_block(&self, block: &[u8], out: &mut [i16]) -> Result<usize, CAError> {
+        let channels = self.format.channels;
+        let frames = self
+            .format
+            .frames_in(block.len())
+            .ok_or(AudioFileError::InvalidFile)?;
+        if out.len() < frames * channels {
+            return Err(AudioCodecError::NotEnoughBufferSpace.into());
+        }
+
+        let mut states = Vec::with_capacity(channels);
+        for (channel, header) in block.chunks_exact(4).take(channels).enumerate() {
+            let sample = i16::from_le_bytes([header[0], header[1]]);
+            if header[2] > 88 {
+                return Err(AudioFileError::InvalidFile.into());
+            }
+            states.push(ImaState::new(sample, header[2]));
+            out[channel] = sample;
+        }
+
+        let words = block[4 * channels..].chunks_exact(4 * channels);
+        for (word, bytes) in words.take((frames - 1) / 8).enumerate() {
+            for (channel, state) in states.iter_mut().enumerate() {
+                for (pair, byte) in bytes[channel * 4..channel * 4 + 4].iter().enumerate() {
+                    let frame = 1 + word * 8 + pair * 2;
+                    out[frame * channels + channel] = state.decode(byte & 0x0F);
+                    out[(frame + 1) * channels + channel] = state.decode(byte >> 4);
+                }
+            }
+        }
+
+        Ok(frames)
+    }
+
+    /// Decode as many whole blocks as fit in the buffers, starting at the first frame.
+    /// The rest of the buffers is left untouched.
+    ///
+    /// Returns the number of frames decoded, the bytes used are
+    /// `frames / frames_per_block() * block_align()`.
+    pub fn decode(&self, blocks: &[u8], list: &mut AudioBufferList<i16>) -> Result<usize, CAError> {
+        let channels: usize = list.iter().map(|b| b.channels()).sum();
+        if channels != self.format.channels {
+            return Err(AudioCodecError::UnsupportedFormat.into());
+        }
+
+        let frames_per_block = self.format.frames_per_block();
+        let count =
+            (blocks.len() / self.format.block_align).min(list_frames(list) / frames_per_block);
+
+        let mut samples = vec![0; count * frames_per_block * channels];
+        for (block, out) in blocks
+            .chunks_exact(self.format.block_align)
+            .zip(samples.chunks_exact_mut(frames_per_block * channels))
+        {
+            self.decode_block(block, out)?;
+        }
+        deinterleave(&samples, list);
+
+        Ok(count * frames_per_block)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rng::Rng;
+
+    use super::*;
+
+    fn sine(frames: usize, channels: usize) -> Vec<i16> {
+        (0..frames * channels)
+            .map(|i| {
+                let (frame, channel) = (i / channels, i % channels);
+                let phase = frame as f64 * (channel + 1) as f64 * 440.0 / 44100.0;
+                ((phase * std::f64::consts::TAU).sin() * 20000.0) as i16
+            })
+            .collect()
+    }
+
+    #[test]
+    fn block_formats() {
+        let format = ImaAdpcmFormat::new(2, 2048).unwrap();
+        assert_eq!(format.frames_per_block(), 2041);
+        assert_eq!(format.fmt_extension(), [0xF9, 0x07]);
+        assert_eq!(format.frames_in(8), Some(1));
+        assert_eq!(format.frames_in(20), Some(9));
+        assert_eq!(format.frames_in(7), None);
+        assert_eq!(format.frames_in(2052), None);
+        assert_eq!(
+            ImaAdpcmFormat::for_sample_rate(22050.0, 1).unwrap(),
+            ImaAdpcmFormat::new(1, 512).unwrap()
+        );
+
+        assert!(ImaAdpcmFormat::new(2, 2044).is_err());
+        assert!(ImaAdpcmFormat::new(2, 4).is_err());
+        assert!(ImaAdpcmFormat::new(1, 1 << 16).is_err());
+
+        assert_eq!(
+            ImaAdpcmFormat::from_fmt(2, 2048, 4, &[0xF9, 0x07]),
+            Ok(format)
+        );
+        assert_eq!(ImaAdpcmFormat::from_fmt(2, 2048, 4, &[]), Ok(format));
+        assert!(ImaAdpcmFormat::from_fmt(2, 2048, 4, &[0xF8, 0x07]).is_err());
+        assert!(ImaAdpcmFormat::from_fmt(2, 2048, 3, &[0xF9, 0x07]).is_err());
+
+        let desc = format.description(44100.0);
+        assert_eq!(desc.format, AudioFormat::DVIIntelIMA);
+        assert_eq!(desc.bytes_per_packet, 2048);
+        assert_eq!(desc.frames_per_packet, 2041);
+    }
+
+    #[test]
+    fn reference_block() {
+        let decoder = ImaAdpcmDecoder::new(ImaAdpcmFormat::new(1, 8).unwrap());
+
+        // First sample 1000, index 0, all codes 7.
+        let mut out = [0; 9];
+        let block = [0xE8, 0x03, 0x00, 0x00, 0x77, 0x77, 0x77, 0x77];
+        assert_eq!(decoder.decode_block(&block, &mut out).unwrap(), 9);
+        assert_eq!(out[..5], [1000, 1011, 1041, 1104, 1240]);
+
+        // The header alone is one frame.
+        assert_eq!(decoder.decode_block(&block[..4], &mut out).unwrap(), 1);
+    }
+
+    #[test]
+    fn encode_decode() {
+        let format = ImaAdpcmFormat::new(2, 256).unwrap();
+        assert_eq!(format.frames_per_block(), 249);
+        let samples = sine(1000, 2);
+
+        let mut encoder = ImaAdpcmEncoder::new(format);
+        let mut blocks = Vec::new();
+        assert_eq!(encoder.encode_interleaved(&samples, &mut blocks), 4);
+        assert_eq!(encoder.flush(&mut blocks), 245);
+        assert_eq!(blocks.len(), 5 * 256);
+
+        let decoder = ImaAdpcmDecoder::new(format);
+        let mut list = AudioBufferList::<i16>::new(2, 1, 1245);
+        assert_eq!(decoder.decode(&blocks, &mut list).unwrap(), 1245);
+        let decoded = interleave(&list, 1000);
+
+        // First samples of blocks are exact.
+        for block in 0..4 {
+            let at = block * 249 * 2;
+            assert_eq!(decoded[at..at + 2], samples[at..at + 2]);
+        }
+
+        let error: f64 = samples
+            .iter()
+            .zip(&decoded)
+            .map(|(&a, &b)| ((a as f64) - (b as f64)).powi(2))
+            .sum();
+        let signal: f64 = samples.iter().map(|&a| (a as f64).powi(2)).sum();
+        let snr = 10.0 * (signal / error).log10();
+        assert!(snr > 25.0, "snr {snr}");
+
+        // Buffers and chunked input give the same blocks.
+        let mut encoder = ImaAdpcmEncoder::new(format);
+        let mut chunked = Vec::new();
+        for chunk in samples.chunks(2 * 100) {
+            let mut list = AudioBufferList::<i16>::new(1, 2, chunk.len() / 2);
+            deinterleave(chunk, &mut list);
+            encoder.encode(&list, &mut chunked).unwrap();
+        }
+        encoder.flush(&mut chunked);
+        assert_eq!(chunked, blocks);
+    }
+
+    #[test]
+    fn malformed_blocks() {
+        let format = ImaAdpcmFormat::new(2, 64).unwrap();
+        let decoder = ImaAdpcmDecoder::new(format);
+        let mut out = [0; 2 * 57];
+
+        let mut block = [0_u8; 64];
+        assert!(decoder.decode_block(&block, &mut out).is_ok());
+        assert!(decoder.decode_block(&block, &mut out[..100]).is_err());
+        assert!(decoder.decode_block(&[0; 65], &mut out).is_err());
+        assert!(decoder.decode_block(&block[..7], &mut out).is_err());
+
+        // Step index out of range.
+        block[6] = 89;
+        assert!(decoder.decode_block(&block, &mut out).is_err());
+
+        // Random blocks of random lengths never panic.
+        let mut rng = Rng::new(11);
+        for _ in 0..2000 {
+            let len = (rng.next_u64() % 72) as usize;
+            let block: Vec<u8> = (0..len).map(|_| rng.next_u64() as u8).collect();
+            if let Ok(frames) = decoder.decode_block(&block, &mut out) {
+                assert_eq!(Some(frames), format.frames_in(len));
+            }
+        }
+    }
+}
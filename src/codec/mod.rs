@@ -0,0 +1,9 @@
+//! Pure Rust codecs and bitstream parsers for some of the formats of [`AudioFormat`](crate::format::AudioFormat).
+
+pub mod aac;
+pub mod ac3;
+pub mod alac;
+pub mod g711;
+pub mod ima4;
+pub mod ima_adpcm;
+pub mod mpeg;
@@ -0,0 +1,434 @@
+//! MPEG-1, MPEG-2 and MPEG-2.5 audio frame headers, Layer I to III, with the Xing, LAME
+//! and VBRI headers that encoders put in the first frame.
+
+use crate::error::AudioFileError;
+use crate::format::{AudioFormat, FormatDescription};
+use crate::CAError;
+
+/// Frames of the decoder delay of Layer III, added to the encoder delay for gapless
+/// playback.
+pub const DECODER_DELAY: u32 = 529;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MpegVersion {
+    Mpeg1,
+    Mpeg2,
+    Mpeg25,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelMode {
+    Stereo,
+    JointStereo,
+    DualChannel,
+    Mono,
+}
+
+/// Bitrates in kbit/s by bitrate index, 0 is free format.
+const BITRATES: [[u16; 15]; 5] = [
+    // MPEG-1 Layer I.
+    [
+        0, 32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448,
+    ],
+    // MPEG-1 Layer II.
+    [
+        0, 32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384,
+    ],
+    // MPEG-1 Layer III.
+    [
+        0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+    ],
+    // MPEG-2 and 2.5 Layer I.
+    [
+        0, 32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256,
+    ],
+    // MPEG-2 and 2.5 Layer II and III.
+    [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+];
+
+const SAMPLE_RATES: [u32; 3] = [44100, 48000, 32000];
+
+/// The 4 byte header of an MPEG audio frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHeader {
+    pub version: MpegVersion,
+    /// 1 to 3.
+    pub layer: u8,
+    /// A CRC follows the header.
+    pub protected: bool,
+    /// In bit/s.
+    pub bitrate: u32,
+    pub sample_rate: u32,
+    pub padding: bool,
+    pub channel_mode: ChannelMode,
+}
+
+impl FrameHeader {
+    pub const SIZE: usize = 4;
+
+    /// Parse a frame header. Free format bitrates are not supported.
+    pub fn parse(bytes: &[u8]) -> Result<Self, CAError> {
+        let invalid = || CAError::from(AudioFileError::InvalidFile);
+        let header = u32::from_be_bytes(bytes.get(..4).ok_or_else(invalid)?.try_into().unwrap());
+
+        if header >> 21 != 0x7FF {
+            return Err(invalid());
+        }
+        let version = match (header >> 19) & 3 {
+            0 => MpegVersion::Mpeg25,
+            2 => MpegVersion::Mpeg2,
+            3 => MpegVersion::Mpeg1,
+            _ => return Err(invalid()),
+        };
+        let layer = match (header >> 17) & 3 {
+            0 => return Err(invalid()),
+            bits => 4 - bits as u8,
+        };
+
+        let table = match (version, layer) {
+            (MpegVersion::Mpeg1, layer) => layer as usize - 1,
+            (_, 1) => 3,
+            _ => 4,
+        };
+        let bitrate = match (header >> 12) & 0xF {
+            0 | 15 => return Err(invalid()),
+            index => BITRATES[table][index as usize] as u32 * 1000,
+        };
+
+        let sample_rate = match (header >> 10) & 3 {
+            3 => return Err(invalid()),
+            index => {
+                let rate = SAMPLE_RATES[index as usize];
+                match version {
+                    MpegVersion::Mpeg1 => rate,
+                    MpegVersion::Mpeg2 => rate / 2,
+                    MpegVersion::Mpeg25 => rate / 4,
+                }
+            }
+        };
+
+        let channel_mode = match (header >> 6) & 3 {
+            0 => ChannelMode::Stereo,
+            1 => ChannelMode::JointStereo,
+            2 => ChannelMode::DualChannel,
+            _ => ChannelMode::Mono,
+        };
+        // Reserved emphasis.
+        if header & 3 == 2 {
+            return Err(invalid());
+        }
+
+        Ok(FrameHeader {
+            version,
+            layer,
+            protected: (header >> 16) & 1 == 0,
+            bitrate,
+            sample_rate,
+            padding: (header >> 9) & 1 == 1,
+            channel_mode,
+        })
+    }
+
+    pub fn channels(&self) -> u32 {
+        if self.channel_mode == ChannelMode::Mono {
+            1
+        } else {
+            2
+        }
+    }
+
+    /// Frames of audio in the frame.
+    pub fn frames(&self) -> u32 {
+        match (self.layer, self.version) {
+            (1, _) => 384,
+            (3, MpegVersion::Mpeg2 | MpegVersion::Mpeg25) => 576,
+            _ => 1152,
+        }
+    }
+
+    /// Size of the frame, with the header.
+    pub fn frame_bytes(&self) -> usize {
+        let (bitrate, rate) = (self.bitrate as usize, self.sample_rate as usize);
+        if self.layer == 1 {
+            return (12 * bitrate / rate + self.padding as usize) * 4;
+        }
+        self.frames() as usize / 8 * bitrate / rate + self.padding as usize
+    }
+
+    /// Whether another frame header belongs to the same stream.
+    pub fn matches(&self, other: &FrameHeader) -> bool {
+        (self.version, self.layer, self.sample_rate)
+            == (other.version, other.layer, other.sample_rate)
+            && self.channels() == other.channels()
+    }
+
+    pub fn format(&self) -> AudioFormat {
+        match self.layer {
+            1 => AudioFormat::MPEGLayer1,
+            2 => AudioFormat::MPEGLayer2,
+            _ => AudioFormat::MPEGLayer3,
+        }
+    }
+
+    pub fn description(&self) -> FormatDescription {
+        FormatDescription {
+            sample_rate: self.sample_rate as f64,
+            format: self.format(),
+            bytes_per_packet: 0,
+            frames_per_packet: self.frames(),
+            channels: self.channels(),
+            bits_per_channel: 0,
+        }
+    }
+
+    /// Offset of the Xing header in a Layer III frame, after the side information.
+    fn xing_offset(&self) -> usize {
+        let side_info = match (self.version, self.channels()) {
+            (MpegVersion::Mpeg1, 1) => 17,
+            (MpegVersion::Mpeg1, _) => 32,
+            (_, 1) => 9,
+            _ => 17,
+        };
+        4 + 2 * self.protected as usize + side_info
+    }
+}
+
+/// The Xing header of a VBR stream, or the Info header of a CBR stream, in a Layer III
+/// frame without audio.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XingHeader {
+    /// An Info header.
+    pub cbr: bool,
+    /// Frames of the stream, without the frame of the header.
+    pub frames: Option<u32>,
+    /// Bytes of the stream.
+    pub bytes: Option<u32>,
+    /// Byte positions at each percent of the duration, in 256ths of the bytes.
+    pub toc: Option<[u8; 100]>,
+    pub quality: Option<u32>,
+    pub lame: Option<LameHeader>,
+}
+
+/// The extension of the Xing header written by LAME and FFmpeg.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LameHeader {
+    /// Like `LAME3.100`.
+    pub encoder: String,
+    /// Frames added at the start by the encoder.
+    pub encoder_delay: u16,
+    /// Frames added at the end to fill the last frame.
+    pub encoder_padding: u16,
+}
+
+impl XingHeader {
+    /// Parse the Xing header of a frame, if it has one.
+    pub fn parse(header: &FrameHeader, frame: &[u8]) -> Option<XingHeader> {
+        if header.layer != 3 {
+            return None;
+        }
+
+        let mut at = header.xing_offset();
+        let cbr = match frame.get(at..at + 4)? {
+            b"Xing" => false,
+            b"Info" => true,
+            _ => return None,
+        };
+        let flags = read_u32(frame, at + 4)?;
+        at += 8;
+
+        let mut field = |flag: u32, len: usize| {
+            if flags & flag == 0 {
+                return Some(None);
+            }
+            let bytes = frame.get(at..at + len)?;
+            at += len;
+            Some(Some(bytes))
+        };
+        let frames = field(1, 4)?.map(|b| read_u32(b, 0).unwrap());
+        let bytes = field(2, 4)?.map(|b| read_u32(b, 0).unwrap());
+        let toc = field(4, 100)?.map(|b| b.try_into().unwrap());
+        let quality = field(8, 4)?.map(|b| read_u32(b, 0).unwrap());
+
+        Some(XingHeader {
+            cbr,
+            frames,
+            bytes,
+            toc,
+            quality,
+            lame: frame.get(at..).and_then(LameHeader::parse),
+        })
+    }
+}
+
+impl LameHeader {
+    fn parse(bytes: &[u8]) -> Option<LameHeader> {
+        let bytes = bytes.get(..24)?;
+        if !matches!(&bytes[..4], b"LAME" | b"Lavc" | b"Lavf") {
+            return None;
+        }
+
+        let encoder = String::from_utf8_lossy(&bytes[..9])
+            .trim_end_matches(['\0', ' '])
+            .to_string();
+        let delay_padding = read_u32(bytes, 20)? & 0xFF_FFFF;
+
+        Some(LameHeader {
+            encoder,
+            encoder_delay: (delay_padding >> 12) as u16,
+            encoder_padding: (delay_padding & 0xFFF) as u16,
+        })
+    }
+}
+
+/// The VBRI header of the Fraunhofer encoder, in a Layer III frame without audio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VbriHeader {
+    pub version: u16,
+    pub delay: u16,
+    pub quality: u16,
+    /// Bytes of the stream.
+    pub bytes: u32,
+    /// Frames of the stream, without the frame of the header.
+    pub frames: u32,
+}
+
+impl VbriHeader {
+    /// Parse the VBRI header of a frame, if it has one.
+    pub fn parse(header: &FrameHeader, frame: &[u8]) -> Option<VbriHeader> {
+        if header.layer != 3 {
+            return None;
+        }
+
+        // Always after 32 bytes of side information.
+        let vbri = frame.get(36..54)?;
+        if &vbri[..4] != b"VBRI" {
+            return None;
+        }
+
+        let u16_at = |at: usize| u16::from_be_bytes([vbri[at], vbri[at + 1]]);
+        Some(VbriHeader {
+            version: u16_at(4),
+            delay: u16_at(6),
+            quality: u16_at(8),
+            bytes: read_u32(vbri, 10)?,
+            frames: read_u32(vbri, 14)?,
+        })
+    }
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        bytes.get(at..at + 4)?.try_into().unwrap(),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn frame_header() {
+        // MPEG-1 Layer III, 128 kbit/s, 44.1 kHz, joint stereo.
+        let header = FrameHeader::parse(&[0xFF, 0xFB, 0x90, 0x40]).unwrap();
+        assert_eq!(header.version, MpegVersion::Mpeg1);
+        assert_eq!(header.layer, 3);
+        assert!(!header.protected);
+        assert_eq!((header.bitrate, header.sample_rate), (128000, 44100));
+        assert_eq!(header.channel_mode, ChannelMode::JointStereo);
+        assert_eq!(header.frame_bytes(), 417);
+        assert_eq!(header.format(), AudioFormat::MPEGLayer3);
+        assert_eq!(header.description().frames_per_packet, 1152);
+
+        // With padding.
+        let header = FrameHeader::parse(&[0xFF, 0xFB, 0x92, 0x40]).unwrap();
+        assert_eq!(header.frame_bytes(), 418);
+
+        // MPEG-2 Layer III, 64 kbit/s, 22.05 kHz, mono, with CRC.
+        let header = FrameHeader::parse(&[0xFF, 0xF2, 0x80, 0xC0]).unwrap();
+        assert_eq!(header.version, MpegVersion::Mpeg2);
+        assert!(header.protected);
+        assert_eq!((header.bitrate, header.sample_rate), (64000, 22050));
+        assert_eq!((header.channels(), header.frames()), (1, 576));
+        assert_eq!(header.frame_bytes(), 208);
+
+        // MPEG-2.5 Layer III at 8 kHz.
+        let header = FrameHeader::parse(&[0xFF, 0xE3, 0x18, 0xC0]).unwrap();
+        assert_eq!(header.version, MpegVersion::Mpeg25);
+        assert_eq!((header.bitrate, header.sample_rate), (8000, 8000));
+
+        // MPEG-1 Layer II, 192 kbit/s, 48 kHz.
+        let header = FrameHeader::parse(&[0xFF, 0xFD, 0xA4, 0x00]).unwrap();
+        assert_eq!(header.format(), AudioFormat::MPEGLayer2);
+        assert_eq!(header.frame_bytes(), 576);
+
+        // MPEG-1 Layer I, 384 kbit/s, 32 kHz, padded.
+        let header = FrameHeader::parse(&[0xFF, 0xFF, 0xCA, 0x00]).unwrap();
+        assert_eq!(header.format(), AudioFormat::MPEGLayer1);
+        assert_eq!(header.frames(), 384);
+        assert_eq!(header.frame_bytes(), 580);
+
+        // Reserved version, layer, bitrate, sample rate and emphasis, free format.
+        for bytes in [
+            [0xFF, 0xEB, 0x90, 0x40],
+            [0xFF, 0xF9, 0x90, 0x40],
+            [0xFF, 0xFB, 0xF0, 0x40],
+            [0xFF, 0xFB, 0x9C, 0x40],
+            [0xFF, 0xFB, 0x90, 0x42],
+            [0xFF, 0xFB, 0x00, 0x40],
+            [0xFE, 0xFB, 0x90, 0x40],
+        ] {
+            assert!(FrameHeader::parse(&bytes).is_err(), "{bytes:x?}");
+        }
+    }
+
+    #[test]
+    fn xing_and_lame() {
+        let header = FrameHeader::parse(&[0xFF, 0xFB, 0x90, 0x40]).unwrap();
+        let mut frame = vec![0; header.frame_bytes()];
+        frame[..4].copy_from_slice(&[0xFF, 0xFB, 0x90, 0x40]);
+        assert_eq!(XingHeader::parse(&header, &frame), None);
+
+        frame[36..40].copy_from_slice(b"Xing");
+        frame[40..44].copy_from_slice(&7_u32.to_be_bytes());
+        frame[44..48].copy_from_slice(&1000_u32.to_be_bytes());
+        frame[48..52].copy_from_slice(&417000_u32.to_be_bytes());
+        frame[52] = 1;
+        frame[152..161].copy_from_slice(b"LAME3.100");
+        // Delay 576, padding 1234.
+        frame[173..176].copy_from_slice(&[0x24, 0x04, 0xD2]);
+
+        let xing = XingHeader::parse(&header, &frame).unwrap();
+        assert!(!xing.cbr);
+        assert_eq!((xing.frames, xing.bytes), (Some(1000), Some(417000)));
+        assert_eq!(xing.toc.unwrap()[0], 1);
+        assert_eq!(xing.quality, None);
+        let lame = xing.lame.unwrap();
+        assert_eq!(lame.encoder, "LAME3.100");
+        assert_eq!((lame.encoder_delay, lame.encoder_padding), (576, 1234));
+
+        // Info, without the LAME header.
+        frame[36..40].copy_from_slice(b"Info");
+        frame[152] = b'X';
+        let xing = XingHeader::parse(&header, &frame).unwrap();
+        assert!(xing.cbr);
+        assert_eq!(xing.lame, None);
+
+        // Truncated.
+        assert_eq!(XingHeader::parse(&header, &frame[..100]), None);
+    }
+
+    #[test]
+    fn vbri() {
+        let header = FrameHeader::parse(&[0xFF, 0xFB, 0x90, 0x40]).unwrap();
+        let mut frame = vec![0; header.frame_bytes()];
+        frame[36..40].copy_from_slice(b"VBRI");
+        frame[40..42].copy_from_slice(&1_u16.to_be_bytes());
+        frame[42..44].copy_from_slice(&1104_u16.to_be_bytes());
+        frame[46..50].copy_from_slice(&50000_u32.to_be_bytes());
+        frame[50..54].copy_from_slice(&120_u32.to_be_bytes());
+
+        let vbri = VbriHeader::parse(&header, &frame).unwrap();
+        assert_eq!((vbri.version, vbri.delay), (1, 1104));
+        assert_eq!((vbri.bytes, vbri.frames), (50000, 120));
+    }
+}
@@ -0,0 +1,427 @@
+//! Software audio device for tests.
+//!
+//! A [`VirtualDevice`] drives [`RenderCallback`] and [`InputCallback`] implementations
+//! the way CoreAudio would, from a virtual clock instead of hardware. Everything,
+//! including jitter and dropouts, is derived from a seed, which means the audio produced
+//! is the same on every run and machine.
+
+use std::ffi::c_void;
+use std::mem;
+use std::ptr;
+
+use crate::format::{LinearPcmFlags, Sample, StreamFormat};
+use crate::queue::{InputBuffer, InputCallback, InputShared};
+use crate::rng::Rng;
+use crate::unit::{ActionFlags, AudioBufferList, RenderCallback, TimeStampFlags};
+use crate::CAError;
+
+/// Host ticks per second of the time stamps. Matches the host clock on Intel Macs.
+pub const HOST_TICKS_PER_SECOND: f64 = 1_000_000_000.0;
+
+/// Configuration of a [`VirtualDevice`].
+#[derive(Debug, Clone)]
+pub struct VirtualDeviceConfig {
+    /// Frames per cycle.
+    pub buffer_frames: usize,
+    /// Each cycle is up to this many frames shorter or longer than `buffer_frames`.
+    pub jitter_frames: usize,
+    /// Chance between 0 and 1 for a cycle to be dropped. The callback isn't called for
+    /// dropped cycles, but the clock still advances.
+    pub dropout_rate: f64,
+    /// Fields of the time stamps that are marked valid and filled in.
+    pub timestamp_flags: TimeStampFlags,
+    /// Seed for the jitter and dropouts.
+    pub seed: u64,
+}
+
+impl VirtualDeviceConfig {
+    /// A steady clock with fixed size cycles.
+    pub fn new(buffer_frames: usize) -> Self {
+        VirtualDeviceConfig {
+            buffer_frames,
+            jitter_frames: 0,
+            dropout_rate: 0.0,
+            timestamp_flags: TimeStampFlags::SAMPLE_TIME_VALID | TimeStampFlags::HOST_TIME_VALID,
+            seed: 0,
+        }
+    }
+}
+
+/// A cycle of the virtual clock.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Cycle {
+    frames: usize,
+    dropped: bool,
+}
+
+/// Deterministic software device.
+pub struct VirtualDevice<S: Sample> {
+    format: StreamFormat,
+    config: VirtualDeviceConfig,
+    rng: Rng,
+    sample_time: u64,
+    cycles: u64,
+    dropouts: u64,
+    render_buffers: AudioBufferList<S>,
+    /// Rendered frames not yet returned, interleaved.
+    pending: Vec<S>,
+}
+
+impl<S: Sample> VirtualDevice<S> {
+    pub fn new(format: &StreamFormat, config: VirtualDeviceConfig) -> Self {
+        assert_eq!(S::sample_format(), format.sample_format());
+        assert!(config.buffer_frames > 0);
+
+        let max_frames = config.buffer_frames + config.jitter_frames;
+        let channels = format.channels();
+
+        let render_buffers = if format.flags().contains(LinearPcmFlags::IS_NON_INTERLEAVED) {
+            AudioBufferList::new(channels, 1, max_frames)
+        } else {
+            AudioBufferList::new(1, channels, max_frames)
+        };
+
+        VirtualDevice {
+            format: format.clone(),
+            rng: Rng::new(config.seed),
+            config,
+            sample_time: 0,
+            cycles: 0,
+            dropouts: 0,
+            render_buffers,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Current position of the clock in frames.
+    pub fn sample_time(&self) -> u64 {
+        self.sample_time
+    }
+
+    /// Number of cycles run so far, including dropped ones.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Number of dropped cycles so far.
+    pub fn dropouts(&self) -> u64 {
+        self.dropouts
+    }
+
+    /// Pull audio from a render callback like an output unit does.
+    ///
+    /// Runs as many cycles as needed for `frames` frames and returns them interleaved.
+    /// Dropped cycles are silent. Frames rendered past `frames` are returned by the next
+    /// call.
+    pub fn render(
+        &mut self,
+        callback: &mut impl RenderCallback<S>,
+        frames: usize,
+    ) -> Result<Vec<S>, CAError> {
+        let channels = self.format.channels();
+
+        while self.pending.len() < frames * channels {
+            let cycle = self.next_cycle();
+            let time = self.time_stamp();
+
+            if cycle.dropped {
+                let len = self.pending.len();
+                self.pending
+                    .resize(len + cycle.frames * channels, S::default());
+            } else {
+                self.render_cycle(callback, cycle.frames, time)?;
+            }
+
+            self.sample_time += cycle.frames as u64;
+        }
+
+        let rest = self.pending.split_off(frames * channels);
+        Ok(mem::replace(&mut self.pending, rest))
+    }
+
+    fn render_cycle(
+        &mut self,
+        callback: &mut impl RenderCallback<S>,
+        frames: usize,
+        time: sys::AudioTimeStamp,
+    ) -> Result<(), CAError> {
+        for buffer in self.render_buffers.iter_mut() {
+            // The list was allocated for the longest cycle.
+            unsafe { buffer.set_frames(frames) };
+            buffer.samples_mut().fill(S::default());
+        }
+
+        let mut flags = ActionFlags::empty();
+        callback.render(&mut flags, time, 0, frames, &mut self.render_buffers)?;
+
+        let buffers = self.render_buffers.buffers();
+        for frame in 0..frames {
+            for buffer in buffers {
+                let channels = buffer.channels();
+                let samples = buffer.samples();
+                self.pending
+                    .extend_from_slice(&samples[frame * channels..(frame + 1) * channels]);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Push recorded audio to an input callback like an input queue does.
+    ///
+    /// The interleaved `input` is cut into cycles, the last one might be shorter. The
+    /// audio of dropped cycles is lost.
+    ///
+    /// Buffers kept with [`InputBuffer::hold`] are freed once the held buffer is
+    /// dropped.
+    pub fn capture(&mut self, callback: &mut impl InputCallback<S>, input: &[S]) {
+        let channels = self.format.channels();
+        let mut input = &input[..input.len() / channels * channels];
+
+        while !input.is_empty() {
+            let mut cycle = self.next_cycle();
+            cycle.frames = cycle.frames.min(input.len() / channels);
+
+            let (chunk, rest) = input.split_at(cycle.frames * channels);
+            input = rest;
+
+            if !cycle.dropped {
+                let time = self.time_stamp();
+                capture_cycle(callback, chunk, time);
+            }
+
+            self.sample_time += cycle.frames as u64;
+        }
+    }
+
+    fn next_cycle(&mut self) -> Cycle {
+        let jitter = self.rng.jitter(self.config.jitter_frames);
+        let frames = (self.config.buffer_frames as isize + jitter).max(1) as usize;
+
+        // Always draw, so that the jitter doesn't depend on the dropout rate.
+        let dropped = self.rng.next_f64() < self.config.dropout_rate;
+
+        self.cycles += 1;
+        if dropped {
+            self.dropouts += 1;
+        }
+
+        Cycle { frames, dropped }
+    }
+
+    fn time_stamp(&self) -> sys::AudioTimeStamp {
+        let flags = self.config.timestamp_flags;
+        let mut time = sys::AudioTimeStamp::default();
+
+        if flags.contains(TimeStampFlags::SAMPLE_TIME_VALID) {
+            time.mSampleTime = self.sample_time as f64;
+        }
+        if flags.contains(TimeStampFlags::HOST_TIME_VALID) {
+            let seconds = self.sample_time as f64 / self.format.sample_rate();
+            time.mHostTime = (seconds * HOST_TICKS_PER_SECOND).round() as u64;
+        }
+        if flags.contains(TimeStampFlags::RATE_SCALAR_VALID) {
+            time.mRateScalar = 1.0;
+        }
+        if flags.contains(TimeStampFlags::WORD_CLOCK_TIME_VALID) {
+            time.mWordClockTime = self.sample_time;
+        }
+
+        time.mFlags = flags.bits();
+        time
+    }
+}
+
+/// Memory of a captured buffer.
+struct CaptureMemory<S> {
+    raw: *mut sys::AudioQueueBuffer,
+    data: *mut [S],
+}
+
+// Only freed, by whichever thread drops the last user of the buffer.
+unsafe impl<S> Send for CaptureMemory<S> {}
+unsafe impl<S> Sync for CaptureMemory<S> {}
+
+impl<S> Drop for CaptureMemory<S> {
+    fn drop(&mut self) {
+        unsafe {
+            drop(Box::from_raw(self.raw));
+            drop(Box::from_raw(self.data));
+        }
+    }
+}
+
+fn capture_cycle<S: Sample>(
+    callback: &mut impl InputCallback<S>,
+    chunk: &[S],
+    time: sys::AudioTimeStamp,
+) {
+    let data = chunk.to_vec().into_boxed_slice();
+    let bytes = mem::size_of_val(&*data) as u32;
+    let data = Box::into_raw(data);
+
+    let raw = Box::into_raw(Box::new(sys::AudioQueueBuffer {
+        mAudioDataBytesCapacity: bytes,
+        mAudioData: data as *mut S as *mut c_void,
+        mAudioDataByteSize: bytes,
+        mUserData: ptr::null_mut(),
+        mPacketDescriptionCapacity: 0,
+        mPacketDescriptions: ptr::null_mut(),
+        mPacketDescriptionCount: 0,
+    }));
+
+    // Freed with the buffer, or with the last held buffer.
+    let shared = InputShared::detached(Some(Box::new(CaptureMemory { raw, data })));
+    let buffer = InputBuffer::borrowed(ptr::null_mut(), raw, shared);
+    callback.audio_input(time, &buffer);
+}
+
+#[cfg(test)]
+mod test {
+    use crate::format::SampleFormat;
+
+    use super::*;
+
+    fn stereo() -> StreamFormat {
+        StreamFormat::new(
+            48_000.0,
+            SampleFormat::I16,
+            LinearPcmFlags::IS_SIGNED_INTEGER,
+            2,
+        )
+    }
+
+    /// Renders a ramp continuing across cycles, and records the cycles it saw.
+    fn ramp(
+        log: &mut Vec<(f64, usize)>,
+    ) -> impl FnMut(
+        &mut ActionFlags,
+        sys::AudioTimeStamp,
+        u32,
+        usize,
+        &mut AudioBufferList<i16>,
+    ) -> Result<(), CAError>
+           + '_ {
+        move |_flags, time, _bus, frames, buffers| {
+            for buffer in buffers.iter_mut() {
+                for (i, frame) in buffer.samples_mut().chunks_mut(2).enumerate() {
+                    let v = (time.mSampleTime as usize + i) as i16;
+                    frame[0] = v;
+                    frame[1] = -v;
+                }
+            }
+            log.push((time.mSampleTime, frames));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn render_is_bit_exact() {
+        let mut log = Vec::new();
+        let mut device = VirtualDevice::new(&stereo(), VirtualDeviceConfig::new(64));
+
+        let first = device.render(&mut ramp(&mut log), 100).unwrap();
+        let second = device.render(&mut ramp(&mut log), 28).unwrap();
+
+        let expected: Vec<i16> = (0..128).flat_map(|i| [i, -i]).collect();
+        assert_eq!([first, second].concat(), expected);
+        assert_eq!(log, vec![(0.0, 64), (64.0, 64)]);
+        assert_eq!(device.sample_time(), 128);
+    }
+
+    #[test]
+    fn jitter_and_dropouts_are_deterministic() {
+        let config = VirtualDeviceConfig {
+            jitter_frames: 16,
+            dropout_rate: 0.25,
+            seed: 42,
+            ..VirtualDeviceConfig::new(64)
+        };
+
+        let run = || {
+            let mut log = Vec::new();
+            let mut device = VirtualDevice::new(&stereo(), config.clone());
+            let out = device.render(&mut ramp(&mut log), 4096).unwrap();
+            (out, log, device.dropouts())
+        };
+
+        let (out, log, dropouts) = run();
+        assert_eq!(run(), (out.clone(), log.clone(), dropouts));
+
+        assert!(dropouts > 0);
+        assert!(log.iter().any(|&(_, frames)| frames != 64));
+        assert!(log.iter().all(|&(_, frames)| (48..=80).contains(&frames)));
+
+        // Rendered cycles are where the clock says, dropped ones are silent.
+        for &(start, frames) in &log {
+            let start = start as usize;
+            if start + frames <= 4096 {
+                assert_eq!(out[start * 2], start as i16);
+            }
+        }
+        assert_eq!(out.len(), 4096 * 2);
+        assert!(out.chunks(2).any(|f| f == [0, 0]));
+    }
+
+    #[test]
+    fn time_stamp_flags() {
+        let config = VirtualDeviceConfig {
+            timestamp_flags: TimeStampFlags::HOST_TIME_VALID,
+            ..VirtualDeviceConfig::new(48)
+        };
+
+        let mut times = Vec::new();
+        let mut device = VirtualDevice::new(&stereo(), config);
+        device
+            .render(
+                &mut |_: &mut ActionFlags,
+                      time: sys::AudioTimeStamp,
+                      _: u32,
+                      _: usize,
+                      _: &mut AudioBufferList<i16>| {
+                    times.push(time);
+                    Ok(())
+                },
+                96,
+            )
+            .unwrap();
+
+        assert_eq!(times.len(), 2);
+        assert_eq!(times[1].mFlags, TimeStampFlags::HOST_TIME_VALID.bits());
+        assert_eq!(times[1].mSampleTime, 0.0);
+        assert_eq!(times[1].mHostTime, 1_000_000);
+    }
+
+    #[test]
+    fn capture_cuts_cycles() {
+        let mut device = VirtualDevice::new(&stereo(), VirtualDeviceConfig::new(3));
+
+        let mut received = Vec::new();
+        let mut held = Vec::new();
+
+        let input: Vec<i16> = (0..8).flat_map(|i| [i, -i]).collect();
+        device.capture(
+            &mut |time: sys::AudioTimeStamp, buffer: &InputBuffer<i16>| {
+                received.push((time.mSampleTime, buffer.to_vec()));
+                if time.mSampleTime == 3.0 {
+                    held.push(buffer.hold());
+                }
+            },
+            &input,
+        );
+
+        assert_eq!(
+            received,
+            vec![
+                (0.0, input[0..6].to_vec()),
+                (3.0, input[6..12].to_vec()),
+                (6.0, input[12..16].to_vec()),
+            ]
+        );
+        assert_eq!(&*held[0], &input[6..12]);
+
+        // Freed by whichever thread drops it last.
+        let held = held.pop().unwrap();
+        std::thread::spawn(move || drop(held)).join().unwrap();
+    }
+}
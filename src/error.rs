@@ -1,3 +1,5 @@
+use std::io;
+
 use sys::OSStatus;
 use thiserror::Error;
 
@@ -13,12 +15,20 @@ pub enum CAError {
     AudioFormatError(#[from] AudioFormatError),
     #[error("audio unit error: {0}")]
     AudioUnitError(#[from] AudioUnitError),
+    #[error("audio file error: {0}")]
+    AudioFileError(#[from] AudioFileError),
     #[error("no description found for type: {0}")]
     NoDescriptionFound(Type),
     #[error("no component for description: {0:?}")]
     NoComponentFound(Description),
     #[error("unknown OSStatus: {0}")]
     UnknownOSStatus(OSStatus),
+    #[error("timed out")]
+    Timeout,
+    #[error("queue is not running")]
+    QueueNotRunning,
+    #[error("io error ({0:?}): {1}")]
+    Io(io::ErrorKind, String),
     #[error("other: {0}")]
     Other(String),
 }
@@ -41,9 +51,32 @@ impl CAError {
         if let Some(e) = AudioUnitError::from_os_status(status) {
             return Err(e.into());
         };
+        if let Some(e) = AudioFileError::from_os_status(status) {
+            return Err(e.into());
+        };
 
         return Err(CAError::UnknownOSStatus(status));
     }
+
+    pub fn from_io_error(e: io::Error) -> Self {
+        CAError::Io(e.kind(), e.to_string())
+    }
+
+    /// The OSStatus to report back to CoreAudio for this error.
+    ///
+    /// Errors that don't originate from an OSStatus are reported as
+    /// [`AudioError::Param`].
+    pub fn to_os_status(&self) -> OSStatus {
+        match self {
+            CAError::AudioError(e) => *e as OSStatus,
+            CAError::AudioCodecError(e) => *e as OSStatus,
+            CAError::AudioFormatError(e) => *e as OSStatus,
+            CAError::AudioUnitError(e) => *e as OSStatus,
+            CAError::AudioFileError(e) => *e as OSStatus,
+            CAError::UnknownOSStatus(status) => *status,
+            _ => AudioError::Param as OSStatus,
+        }
+    }
 }
 
 impl<S: Into<String>> From<S> for CAError {
@@ -123,24 +156,31 @@ impl AudioCodecError {
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Error)]
 pub enum AudioFormatError {
     #[error("unspecified")]
-    Unspecified, // 'what'
+    Unspecified = 2003329396, // 'what'
     #[error("unsupported property")]
-    UnsupportedProperty, // 'prop'
+    UnsupportedProperty = 1886547824, // 'prop'
     #[error("bad property size")]
-    BadPropertySize, // '!siz'
+    BadPropertySize = 561211770, // '!siz'
     #[error("bad specifier size")]
-    BadSpecifierSize, // '!spc'
+    BadSpecifierSize = 561213539, // '!spc'
     #[error("unsupported data format")]
     UnsupportedDataFormat = 1718449215, // 'fmt?'
     #[error("unknown format")]
-    UnknownFormat, // '!fmt'
+    UnknownFormat = 560360820, // '!fmt'
 }
 
 impl AudioFormatError {
     pub fn from_os_status(status: OSStatus) -> Option<Self> {
         use AudioFormatError::*;
+        // 'what' and '!siz' are the same codes as for AudioCodecError, which is
+        // checked first.
         match status {
+            2003329396 => Some(Unspecified),
+            1886547824 => Some(UnsupportedProperty),
+            561211770 => Some(BadPropertySize),
+            561213539 => Some(BadSpecifierSize),
             1718449215 => Some(UnsupportedDataFormat),
+            560360820 => Some(UnknownFormat),
             _ => None,
         }
     }
@@ -209,3 +249,81 @@ impl AudioUnitError {
         }
     }
 }
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Error)]
+pub enum AudioFileError {
+    #[error("unspecified")]
+    Unspecified = 2003334207, // 'wht?'
+    #[error("unsupported file type")]
+    UnsupportedFileType = 1954115647, // 'typ?'
+    #[error("unsupported property")]
+    UnsupportedProperty = 1886681407, // 'pty?'
+    #[error("permissions")]
+    Permissions = 1886547263, // 'prm?'
+    #[error("not optimized")]
+    NotOptimized = 1869640813, // 'optm'
+    #[error("invalid chunk")]
+    InvalidChunk = 1667787583, // 'chk?'
+    #[error("does not allow 64 bit data size")]
+    DoesNotAllow64BitDataSize = 1868981823, // 'off?'
+    #[error("invalid packet offset")]
+    InvalidPacketOffset = 1885563711, // 'pck?'
+    #[error("invalid packet dependency")]
+    InvalidPacketDependency = 1684369471, // 'dep?'
+    #[error("invalid file")]
+    InvalidFile = 1685348671, // 'dta?'
+    #[error("operation not supported")]
+    OperationNotSupported = 1869627199, // 'op??'
+    #[error("not open")]
+    NotOpen = -38,
+    #[error("end of file")]
+    EndOfFile = -39,
+    #[error("position")]
+    Position = -40,
+}
+
+impl AudioFileError {
+    pub fn from_os_status(status: OSStatus) -> Option<Self> {
+        use AudioFileError::*;
+        // The file errors 'fmt?', '!siz' and -43 have no variants here, they decode as
+        // AudioFormatError and AudioError which share those codes.
+        match status {
+            2003334207 => Some(Unspecified),
+            1954115647 => Some(UnsupportedFileType),
+            1886681407 => Some(UnsupportedProperty),
+            1886547263 => Some(Permissions),
+            1869640813 => Some(NotOptimized),
+            1667787583 => Some(InvalidChunk),
+            1868981823 => Some(DoesNotAllow64BitDataSize),
+            1885563711 => Some(InvalidPacketOffset),
+            1684369471 => Some(InvalidPacketDependency),
+            1685348671 => Some(InvalidFile),
+            1869627199 => Some(OperationNotSupported),
+            -38 => Some(NotOpen),
+            -39 => Some(EndOfFile),
+            -40 => Some(Position),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn os_status_round_trip() {
+        for status in [
+            -50, -10874, 1718449215, 560360820, 1886547824, 1685348671, -39, 12345,
+        ] {
+            let err = CAError::from_os_status(status).unwrap_err();
+            assert_eq!(err.to_os_status(), status);
+        }
+    }
+
+    #[test]
+    fn other_errors_map_to_param() {
+        assert_eq!(CAError::Timeout.to_os_status(), -50);
+        assert_eq!(CAError::Other("nope".into()).to_os_status(), -50);
+    }
+}
@@ -0,0 +1,306 @@
+//! Raw AAC in ADTS frames, as in `.aac` files.
+
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+
+use super::{id3v2_size, read_full, PacketReader};
+use crate::codec::aac::{AdtsHeader, AudioSpecificConfig};
+use crate::error::AudioFileError;
+use crate::format::{FormatDescription, PacketDescription};
+use crate::CAError;
+
+/// Streaming reader of the packets of an ADTS stream.
+///
+/// A leading ID3v2 tag and bytes between frames are skipped.
+pub struct AdtsReader<R: Read> {
+    inner: R,
+    /// The header of the first frame and the config from it.
+    config: Option<(AdtsHeader, AudioSpecificConfig)>,
+    /// Bytes read ahead, starting at the next frame.
+    buffer: Vec<u8>,
+    /// Raw data blocks of the last frame that didn't fit in the last read, with
+    /// descriptions relative to the start of `pending`.
+    pending: Vec<u8>,
+    pending_descriptions: Vec<PacketDescription>,
+    frames_read: u64,
+}
+
+impl AdtsReader<BufReader<File>> {
+    /// Open the ADTS file at the path.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, CAError> {
+        let file = File::open(path).map_err(CAError::from_io_error)?;
+        AdtsReader::new(BufReader::new(file))
+    }
+}
+
+impl<R: Read> AdtsReader<R> {
+    /// Read up to the first frame, which gives the config of the stream.
+    pub fn new(mut inner: R) -> Result<Self, CAError> {
+        let mut buffer = vec![0; 10];
+        let len = read_full(&mut inner, &mut buffer)?;
+        buffer.truncate(len);
+
+        if let Some(size) = id3v2_size(&buffer) {
+            let skip = (size - buffer.len()) as u64;
+            io::copy(&mut (&mut inner).take(skip), &mut io::sink())
+                .map_err(CAError::from_io_error)?;
+            buffer.clear();
+        }
+
+        let mut reader = AdtsReader {
+            inner,
+            config: None,
+            buffer,
+            pending: Vec::new(),
+            pending_descriptions: Vec::new(),
+            frames_read: 0,
+        };
+
+        let first = reader
+            .next_frame()?
+            .ok_or(AudioFileError::UnsupportedFileType)?;
+        reader.config = Some((first, first.config(&reader.buffer)?));
+
+        Ok(reader)
+    }
+
+    /// The config of the stream, from the first frame.
+    pub fn config(&self) -> &AudioSpecificConfig {
+        &self.config.as_ref().unwrap().1
+    }
+
+    pub fn description(&self) -> FormatDescription {
+        self.config().description()
+    }
+
+    /// ADTS frames read so far.
+    pub fn frames_read(&self) -> u64 {
+        self.frames_read
+    }
+
+    /// Append up to `max_packets` raw data blocks to `data` and their descriptions,
+    /// relative to the start of `data`, to `descriptions`.
+    ///
+    /// The blocks of a frame that don't fit are read next time. Returns the number of
+    /// packets read, which is 0 at the end of the stream.
+    pub fn read_packets(
+        &mut self,
+        max_packets: usize,
+        data: &mut Vec<u8>,
+        descriptions: &mut Vec<PacketDescription>,
+    ) -> Result<usize, CAError> {
+        let before = descriptions.len();
+
+        while descriptions.len() - before < max_packets {
+            if self.pending_descriptions.is_empty() {
+                let Some(header) = self.next_frame()? else {
+                    break;
+                };
+                self.pending.clear();
+                if let Err(e) = header.split(
+                    &self.buffer,
+                    &mut self.pending,
+                    &mut self.pending_descriptions,
+                ) {
+                    self.pending_descriptions.clear();
+                    return Err(e);
+                }
+                self.buffer.drain(..header.frame_bytes as usize);
+                self.frames_read += 1;
+            }
+
+            let count =
+                (max_packets - (descriptions.len() - before)).min(self.pending_descriptions.len());
+            for description in self.pending_descriptions.drain(..count) {
+                let start = description.start_offset as usize;
+                let block = &self.pending[start..start + description.data_byte_size as usize];
+                descriptions.push(PacketDescription {
+                    start_offset: data.len() as u64,
+                    ..description
+                });
+                data.extend_from_slice(block);
+            }
+        }
+
+        Ok(descriptions.len() - before)
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Find the next frame and read all of it into the buffer.
+    ///
+    /// Headers that don't match the first frame are taken for bytes between frames.
+    fn next_frame(&mut self) -> Result<Option<AdtsHeader>, CAError> {
+        loop {
+            if !self.fill(AdtsHeader::SIZE)? {
+                return Ok(None);
+            }
+
+            let header = match AdtsHeader::parse(&self.buffer) {
+                Ok(header) if self.matches(&header) => header,
+                _ => {
+                    let skip = self.buffer[1..]
+                        .iter()
+                        .position(|&b| b == 0xFF)
+                        .map_or(self.buffer.len(), |at| at + 1);
+                    self.buffer.drain(..skip);
+                    continue;
+                }
+            };
+
+            if !self.fill(header.frame_bytes as usize)? {
+                return Err(AudioFileError::EndOfFile.into());
+            }
+            return Ok(Some(header));
+        }
+    }
+
+    fn matches(&self, header: &AdtsHeader) -> bool {
+        self.config.as_ref().map_or(true, |(first, _)| {
+            (header.mpeg2, header.object_type, header.sample_rate_index)
+                == (first.mpeg2, first.object_type, first.sample_rate_index)
+                && header.channel_config == first.channel_config
+        })
+    }
+
+    /// Read until the buffer holds `len` bytes, false at the end of the stream.
+    fn fill(&mut self, len: usize) -> Result<bool, CAError> {
+        if self.buffer.len() < len {
+            let start = self.buffer.len();
+            self.buffer.resize(len, 0);
+            let read = read_full(&mut self.inner, &mut self.buffer[start..])?;
+            self.buffer.truncate(start + read);
+        }
+        Ok(self.buffer.len() >= len)
+    }
+}
+
+impl<R: Read> PacketReader for AdtsReader<R> {
+    fn read_packets(
+        &mut self,
+        max_packets: usize,
+        data: &mut Vec<u8>,
+        descriptions: &mut Vec<PacketDescription>,
+    ) -> Result<usize, CAError> {
+        AdtsReader::read_packets(self, max_packets, data, descriptions)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::format::{AudioFormat, PacketBuffer};
+    use std::io::Cursor;
+
+    /// An AAC LC frame at 44.1 kHz, stereo.
+    fn frame(payload: &[u8]) -> Vec<u8> {
+        let len = payload.len() + 7;
+        let mut frame = vec![
+            0xFF,
+            0xF1,
+            0x50,
+            0x80 | (len >> 11) as u8,
+            (len >> 3) as u8,
+            ((len & 7) << 5) as u8 | 0x1F,
+            0xFC,
+        ];
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn read_packets() {
+        let mut file = b"ID3\x04\x00\x00\x00\x00\x00\x03tag".to_vec();
+        file.extend(frame(&[1; 100]));
+        file.extend(frame(&[2; 50]));
+        // Junk that looks like a header of another stream.
+        file.extend_from_slice(&[0xFF, 0xF1, 0x98, 0x80, 0x00]);
+        file.extend(frame(&[3; 10]));
+
+        let mut reader = AdtsReader::new(Cursor::new(file)).unwrap();
+        let description = reader.description();
+        assert_eq!(
+            description.format,
+            AudioFormat::MPEG4AAC(crate::format::Mpeg4ObjectId::AAC_LC)
+        );
+        assert_eq!(description.sample_rate, 44100.0);
+        assert_eq!(description.frames_per_packet, 1024);
+        assert_eq!(description.channels, 2);
+
+        let mut data = Vec::new();
+        let mut descriptions = Vec::new();
+        let mut read = || {
+            reader
+                .read_packets(2, &mut data, &mut descriptions)
+                .unwrap()
+        };
+        assert_eq!(read(), 2);
+        assert_eq!(read(), 1);
+        assert_eq!(read(), 0);
+        assert_eq!(reader.frames_read(), 3);
+
+        let sizes: Vec<_> = descriptions.iter().map(|d| d.data_byte_size).collect();
+        assert_eq!(sizes, [100, 50, 10]);
+        assert_eq!(descriptions[2].start_offset, 150);
+        assert_eq!(data[150..], [3; 10]);
+    }
+
+    #[test]
+    fn read_packet_buffer() {
+        let mut file = frame(&[1; 20]);
+        file.extend(frame(&[2; 30]));
+
+        let mut reader = AdtsReader::new(Cursor::new(file)).unwrap();
+        let mut buffer = PacketBuffer::new();
+        assert_eq!(reader.read_packet_buffer(1, &mut buffer).unwrap(), 1);
+        assert_eq!(reader.read_packet_buffer(5, &mut buffer).unwrap(), 1);
+
+        let packets: Vec<_> = buffer.iter().map(|(_, data)| data).collect();
+        assert_eq!(packets, [&[1; 20][..], &[2; 30]]);
+    }
+
+    #[test]
+    fn several_blocks() {
+        // Three blocks in a frame with CRCs, the positions after the first and the CRC
+        // of the header before them.
+        let blocks: [&[u8]; 3] = [&[1; 10], &[2; 20], &[3; 30]];
+        let mut payload = [12_u16.to_be_bytes(), 34_u16.to_be_bytes(), [0; 2]].concat();
+        for block in blocks {
+            payload.extend_from_slice(block);
+            payload.extend_from_slice(&[0; 2]);
+        }
+        let mut file = frame(&payload);
+        file[1] = 0xF0;
+        file[6] |= 2;
+        file.extend(frame(&[4; 40]));
+
+        let mut reader = AdtsReader::new(Cursor::new(file)).unwrap();
+        let mut buffer = PacketBuffer::new();
+        assert_eq!(reader.read_packet_buffer(2, &mut buffer).unwrap(), 2);
+        assert_eq!(reader.read_packet_buffer(2, &mut buffer).unwrap(), 2);
+        assert_eq!(reader.read_packet_buffer(2, &mut buffer).unwrap(), 0);
+        assert_eq!(reader.frames_read(), 2);
+
+        let packets: Vec<_> = buffer.iter().map(|(_, data)| data).collect();
+        assert_eq!(packets, [&[1; 10][..], &[2; 20], &[3; 30], &[4; 40]]);
+    }
+
+    #[test]
+    fn truncated() {
+        let mut file = frame(&[1; 100]);
+        file.extend_from_slice(&frame(&[2; 50])[..30]);
+
+        let mut reader = AdtsReader::new(Cursor::new(file)).unwrap();
+        let mut data = Vec::new();
+        let mut descriptions = Vec::new();
+        assert!(reader
+            .read_packets(2, &mut data, &mut descriptions)
+            .is_err());
+        assert_eq!(descriptions.len(), 1);
+
+        assert!(AdtsReader::new(Cursor::new(vec![0; 100])).is_err());
+    }
+}
@@ -0,0 +1,941 @@
+//! AIFF and AIFF-C files.
+//!
+//! Big endian PCM is written as AIFF, little endian PCM (`sowt`), 32 bit float (`fl32`)
+//! and G.711 (`ulaw`, `alaw`) as AIFF-C. Markers and the loops of the instrument chunk
+//! are read and written.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use crate::codec::g711::G711Codec;
+use crate::error::{AudioFileError, AudioFormatError};
+use crate::format::{
+    AudioFormat, FormatDescription, LinearPcmFlags, SampleConvert, SampleFormat, StreamFormat,
+};
+use crate::unit::AudioBufferList;
+use crate::CAError;
+
+use super::{
+    decode_samples, deinterleave, encode_samples, interleave, list_frames, read_full, write_u32_be,
+    ChunkReader,
+};
+
+/// Timestamp of the AIFF-C version in the `FVER` chunk.
+const AIFC_VERSION_1: u32 = 0xA280_5140;
+
+/// A marker of the `MARK` chunk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Marker {
+    /// Positive ID, referred to by the loops of the instrument chunk.
+    pub id: u16,
+    /// Frame the marker is at, 0 is before the first frame.
+    pub position: u32,
+    pub name: String,
+}
+
+/// How a loop of the instrument chunk is played.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlayMode {
+    NoLooping,
+    Forward,
+    ForwardBackward,
+}
+
+/// A loop between two markers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Loop {
+    pub play_mode: PlayMode,
+    pub begin_marker: u16,
+    pub end_marker: u16,
+}
+
+impl Default for Loop {
+    fn default() -> Self {
+        Loop {
+            play_mode: PlayMode::NoLooping,
+            begin_marker: 0,
+            end_marker: 0,
+        }
+    }
+}
+
+/// The `INST` chunk, which describes how a sampler plays the sound.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instrument {
+    /// MIDI note of the recorded pitch.
+    pub base_note: u8,
+    /// Pitch offset in cents, -50 to 50.
+    pub detune: i8,
+    pub low_note: u8,
+    pub high_note: u8,
+    pub low_velocity: u8,
+    pub high_velocity: u8,
+    /// Gain in dB.
+    pub gain: i16,
+    pub sustain_loop: Loop,
+    pub release_loop: Loop,
+}
+
+impl Default for Instrument {
+    fn default() -> Self {
+        Instrument {
+            base_note: 60,
+            detune: 0,
+            low_note: 0,
+            high_note: 127,
+            low_velocity: 1,
+            high_velocity: 127,
+            gain: 0,
+            sustain_loop: Loop::default(),
+            release_loop: Loop::default(),
+        }
+    }
+}
+
+/// Convert to an 80 bit IEEE 754 extended float, the format of the sample rate.
+fn to_extended(value: f64) -> [u8; 10] {
+    let mut out = [0_u8; 10];
+    if value == 0.0 || !value.is_finite() {
+        return out;
+    }
+
+    let bits = value.to_bits();
+    let sign = (bits >> 63) as u16;
+    let exponent = ((bits >> 52) & 0x7FF) as i32 - 1023 + 16383;
+    // With the explicit integer bit.
+    let mantissa = ((bits & ((1 << 52) - 1)) | (1 << 52)) << 11;
+
+    out[0..2].copy_from_slice(&((sign << 15) | exponent as u16).to_be_bytes());
+    out[2..10].copy_from_slice(&mantissa.to_be_bytes());
+    out
+}
+
+fn from_extended(bytes: [u8; 10]) -> f64 {
+    let sign_exponent = u16::from_be_bytes([bytes[0], bytes[1]]);
+    let mantissa = u64::from_be_bytes(bytes[2..10].try_into().unwrap());
+
+    if mantissa == 0 {
+        return 0.0;
+    }
+
+    let exponent = (sign_exponent & 0x7FFF) as i32 - 16383 - 63;
+    let value = mantissa as f64 * 2_f64.powi(exponent);
+
+    if sign_exponent & 0x8000 != 0 {
+        -value
+    } else {
+        value
+    }
+}
+
+/// Length of a Pascal string including the count and padding to an even length.
+fn pstring_len(s: &str) -> usize {
+    (1 + s.len().min(255) + 1) & !1
+}
+
+fn write_pstring(out: &mut Vec<u8>, s: &str) {
+    let bytes = &s.as_bytes()[..s.len().min(255)];
+    out.push(bytes.len() as u8);
+    out.extend_from_slice(bytes);
+    if bytes.len() % 2 == 0 {
+        out.push(0);
+    }
+}
+
+fn read_pstring(body: &mut ChunkReader) -> Result<String, CAError> {
+    let len = body.u8()? as usize;
+    let s = String::from_utf8_lossy(body.bytes(len)?).into_owned();
+    if len % 2 == 0 {
+        body.u8()?;
+    }
+    Ok(s)
+}
+
+/// The compression type and name of AIFF-C, `None` for plain AIFF.
+fn compression(
+    description: &FormatDescription,
+) -> Result<Option<(&'static [u8; 4], &'static str)>, CAError> {
+    Ok(match description.format {
+        AudioFormat::LinearPCM(flags) if flags.contains(LinearPcmFlags::IS_FLOAT) => {
+            Some((b"fl32", "32-bit floating point"))
+        }
+        AudioFormat::LinearPCM(flags) if flags.contains(LinearPcmFlags::IS_BIG_ENDIAN) => None,
+        AudioFormat::LinearPCM(_) => Some((b"sowt", "")),
+        AudioFormat::ULaw => Some((b"ulaw", "\u{b5}Law 2:1")),
+        AudioFormat::ALaw => Some((b"alaw", "ALaw 2:1")),
+        _ => return Err(AudioFormatError::UnsupportedDataFormat.into()),
+    })
+}
+
+fn g711_description(format: AudioFormat, sample_rate: f64, channels: u32) -> FormatDescription {
+    FormatDescription {
+        sample_rate,
+        format,
+        bytes_per_packet: channels,
+        frames_per_packet: 1,
+        channels,
+        bits_per_channel: 8,
+    }
+}
+
+/// Streaming writer of an AIFF or AIFF-C file.
+///
+/// The chunk sizes and the number of frames are filled in by [`AiffWriter::finalize`],
+/// which also writes the markers and instrument chunk after the sound data.
+pub struct AiffWriter<W: Write + Seek> {
+    inner: W,
+    description: FormatDescription,
+    /// Sample format and endianness for linear PCM.
+    pcm: Option<(SampleFormat, bool)>,
+    codec: Option<G711Codec>,
+    header_size: u64,
+    frames_offset: u64,
+    data_bytes: u64,
+    markers: Vec<Marker>,
+    instrument: Option<Instrument>,
+    scratch: Vec<u8>,
+}
+
+impl AiffWriter<BufWriter<File>> {
+    /// Create an AIFF file of linear PCM at the path.
+    pub fn create(path: impl AsRef<Path>, format: &StreamFormat) -> Result<Self, CAError> {
+        let file = File::create(path).map_err(CAError::from_io_error)?;
+        AiffWriter::new(BufWriter::new(file), format)
+    }
+}
+
+impl<W: Write + Seek> AiffWriter<W> {
+    /// Start writing linear PCM by writing the header. Little endian and float formats
+    /// are written as AIFF-C.
+    pub fn new(inner: W, format: &StreamFormat) -> Result<Self, CAError> {
+        AiffWriter::with_description(inner, &FormatDescription::from(format))
+    }
+
+    /// Start writing linear PCM, µ-law or A-law by writing the header.
+    pub fn with_description(
+        mut inner: W,
+        description: &FormatDescription,
+    ) -> Result<Self, CAError> {
+        let pcm = match description.format {
+            AudioFormat::LinearPCM(flags) => {
+                let format = description
+                    .stream_format()
+                    .ok_or(AudioFormatError::UnsupportedDataFormat)?;
+                // Floats are always big endian.
+                let big_endian = flags.contains(LinearPcmFlags::IS_BIG_ENDIAN)
+                    || flags.contains(LinearPcmFlags::IS_FLOAT);
+                Some((format.sample_format(), big_endian))
+            }
+            _ => None,
+        };
+
+        let description = match description.format {
+            AudioFormat::LinearPCM(flags) if flags.contains(LinearPcmFlags::IS_FLOAT) => {
+                FormatDescription {
+                    format: AudioFormat::LinearPCM(flags | LinearPcmFlags::IS_BIG_ENDIAN),
+                    ..*description
+                }
+            }
+            AudioFormat::ULaw | AudioFormat::ALaw => g711_description(
+                description.format,
+                description.sample_rate,
+                description.channels,
+            ),
+            _ => *description,
+        };
+        let compression = compression(&description)?;
+
+        let mut header = Vec::new();
+        header.extend_from_slice(b"FORM");
+        header.extend_from_slice(&0_u32.to_be_bytes());
+        header.extend_from_slice(if compression.is_some() {
+            b"AIFC"
+        } else {
+            b"AIFF"
+        });
+
+        if compression.is_some() {
+            header.extend_from_slice(b"FVER");
+            header.extend_from_slice(&4_u32.to_be_bytes());
+            header.extend_from_slice(&AIFC_VERSION_1.to_be_bytes());
+        }
+
+        let comm_size = match compression {
+            Some((_, name)) => 18 + 4 + pstring_len(name),
+            None => 18,
+        };
+        header.extend_from_slice(b"COMM");
+        header.extend_from_slice(&(comm_size as u32).to_be_bytes());
+        header.extend_from_slice(&(description.channels as u16).to_be_bytes());
+        let frames_offset = header.len() as u64;
+        header.extend_from_slice(&0_u32.to_be_bytes());
+        // G.711 is described as 16 bit.
+        let sample_size = match pcm {
+            Some(_) => description.bits_per_channel as u16,
+            None => 16,
+        };
+        header.extend_from_slice(&sample_size.to_be_bytes());
+        header.extend_from_slice(&to_extended(description.sample_rate));
+        if let Some((id, name)) = compression {
+            header.extend_from_slice(id);
+            write_pstring(&mut header, name);
+        }
+
+        header.extend_from_slice(b"SSND");
+        header.extend_from_slice(&0_u32.to_be_bytes());
+        // Offset and block size.
+        header.extend_from_slice(&[0; 8]);
+
+        inner.write_all(&header).map_err(CAError::from_io_error)?;
+
+        Ok(AiffWriter {
+            inner,
+            description,
+            pcm,
+            codec: G711Codec::new(description.format).ok(),
+            header_size: header.len() as u64,
+            frames_offset,
+            data_bytes: 0,
+            markers: Vec::new(),
+            instrument: None,
+            scratch: Vec::new(),
+        })
+    }
+
+    /// Number of whole frames written so far.
+    pub fn frames_written(&self) -> u64 {
+        self.data_bytes / self.description.bytes_per_packet as u64
+    }
+
+    /// Total file size so far, including the header.
+    pub fn bytes_written(&self) -> u64 {
+        self.header_size + self.data_bytes
+    }
+
+    pub fn add_marker(&mut self, marker: Marker) {
+        self.markers.push(marker);
+    }
+
+    pub fn set_instrument(&mut self, instrument: Instrument) {
+        self.instrument = Some(instrument);
+    }
+
+    /// Write interleaved samples, converting them to the sample format of the file or
+    /// encoding them as µ-law or A-law.
+    pub fn write_samples<S: SampleConvert>(&mut self, samples: &[S]) -> Result<(), CAError> {
+        self.scratch.clear();
+
+        match (self.pcm, self.codec) {
+            (Some((sample_format, big_endian)), _) => {
+                encode_samples(samples, sample_format, big_endian, &mut self.scratch)
+            }
+            (None, Some(codec)) => codec.encode(samples, &mut self.scratch),
+            (None, None) => return Err(AudioFormatError::UnsupportedDataFormat.into()),
+        }
+
+        self.inner
+            .write_all(&self.scratch)
+            .map_err(CAError::from_io_error)?;
+        self.data_bytes += self.scratch.len() as u64;
+
+        Ok(())
+    }
+
+    /// Write all frames of the buffers, which can be interleaved or not.
+    pub fn write_buffer_list<S: SampleConvert>(
+        &mut self,
+        list: &AudioBufferList<S>,
+    ) -> Result<(), CAError> {
+        self.write_samples(&interleave(list, list_frames(list)))
+    }
+
+    /// Write sound data in the format of the file as is.
+    pub fn write_bytes(&mut self, data: &[u8]) -> Result<(), CAError> {
+        self.inner.write_all(data).map_err(CAError::from_io_error)?;
+        self.data_bytes += data.len() as u64;
+        Ok(())
+    }
+
+    /// Fill in the sizes, write the markers and instrument and return the inner writer.
+    ///
+    /// Fails if the file is larger than 4 GB, which AIFF can't describe.
+    pub fn finalize(mut self) -> Result<W, CAError> {
+        let mut trailer = Vec::new();
+        // Chunks are word aligned.
+        if self.data_bytes % 2 == 1 {
+            trailer.push(0);
+        }
+        self.write_trailer(&mut trailer);
+
+        let form_size = self.header_size + self.data_bytes + trailer.len() as u64 - 8;
+        if form_size > u32::MAX as u64 {
+            return Err(AudioFileError::DoesNotAllow64BitDataSize.into());
+        }
+
+        let frames = self.frames_written() as u32;
+        let ssnd_size = self.data_bytes as u32 + 8;
+        let frames_offset = self.frames_offset;
+        let ssnd_size_offset = self.header_size - 12;
+
+        let patch = |w: &mut W| -> std::io::Result<()> {
+            w.write_all(&trailer)?;
+            w.seek(SeekFrom::Start(4))?;
+            write_u32_be(w, form_size as u32)?;
+            w.seek(SeekFrom::Start(frames_offset))?;
+            write_u32_be(w, frames)?;
+            w.seek(SeekFrom::Start(ssnd_size_offset))?;
+            write_u32_be(w, ssnd_size)?;
+            w.seek(SeekFrom::End(0))?;
+            w.flush()
+        };
+
+        patch(&mut self.inner).map_err(CAError::from_io_error)?;
+
+        Ok(self.inner)
+    }
+
+    fn write_trailer(&self, out: &mut Vec<u8>) {
+        if !self.markers.is_empty() {
+            let mut body = Vec::new();
+            body.extend_from_slice(&(self.markers.len() as u16).to_be_bytes());
+            for marker in &self.markers {
+                body.extend_from_slice(&marker.id.to_be_bytes());
+                body.extend_from_slice(&marker.position.to_be_bytes());
+                write_pstring(&mut body, &marker.name);
+            }
+            write_chunk(out, b"MARK", &body);
+        }
+
+        if let Some(instrument) = &self.instrument {
+            let mut body = vec![
+                instrument.base_note,
+                instrument.detune as u8,
+                instrument.low_note,
+                instrument.high_note,
+                instrument.low_velocity,
+                instrument.high_velocity,
+            ];
+            body.extend_from_slice(&instrument.gain.to_be_bytes());
+            for l in [&instrument.sustain_loop, &instrument.release_loop] {
+                let play_mode: u16 = match l.play_mode {
+                    PlayMode::NoLooping => 0,
+                    PlayMode::Forward => 1,
+                    PlayMode::ForwardBackward => 2,
+                };
+                body.extend_from_slice(&play_mode.to_be_bytes());
+                body.extend_from_slice(&l.begin_marker.to_be_bytes());
+                body.extend_from_slice(&l.end_marker.to_be_bytes());
+            }
+            write_chunk(out, b"INST", &body);
+        }
+    }
+}
+
+fn write_chunk(out: &mut Vec<u8>, id: &[u8; 4], body: &[u8]) {
+    out.extend_from_slice(id);
+    out.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    out.extend_from_slice(body);
+    if body.len() % 2 == 1 {
+        out.push(0);
+    }
+}
+
+/// Streaming reader of an AIFF or AIFF-C file.
+pub struct AiffReader<R: Read + Seek> {
+    inner: R,
+    description: FormatDescription,
+    pcm: Option<StreamFormat>,
+    codec: Option<G711Codec>,
+    markers: Vec<Marker>,
+    instrument: Option<Instrument>,
+    data_start: u64,
+    frames: u64,
+    position: u64,
+    scratch: Vec<u8>,
+}
+
+impl AiffReader<BufReader<File>> {
+    /// Open the AIFF or AIFF-C file at the path.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, CAError> {
+        let file = File::open(path).map_err(CAError::from_io_error)?;
+        AiffReader::new(BufReader::new(file))
+    }
+}
+
+impl<R: Read + Seek> AiffReader<R> {
+    /// Read the chunks, leaving the reader at the first frame.
+    pub fn new(mut inner: R) -> Result<Self, CAError> {
+        let mut form = [0_u8; 12];
+        if read_full(&mut inner, &mut form)? < 12 || &form[0..4] != b"FORM" {
+            return Err(AudioFileError::UnsupportedFileType.into());
+        }
+        let aifc = match &form[8..12] {
+            b"AIFF" => false,
+            b"AIFC" => true,
+            _ => return Err(AudioFileError::UnsupportedFileType.into()),
+        };
+
+        let end = inner
+            .seek(SeekFrom::End(0))
+            .map_err(CAError::from_io_error)?;
+        inner
+            .seek(SeekFrom::Start(12))
+            .map_err(CAError::from_io_error)?;
+
+        let mut comm = None;
+        let mut sound = None;
+        let mut markers = Vec::new();
+        let mut instrument = None;
+
+        loop {
+            let mut chunk = [0_u8; 8];
+            let read = read_full(&mut inner, &mut chunk)?;
+            if read < 8 {
+                break;
+            }
+
+            let id = &chunk[0..4];
+            let size = u32::from_be_bytes(chunk[4..8].try_into().unwrap()) as u64;
+            let start = inner.stream_position().map_err(CAError::from_io_error)?;
+
+            if id == b"SSND" {
+                let mut header = [0_u8; 8];
+                if read_full(&mut inner, &mut header)? < 8 {
+                    return Err(AudioFileError::InvalidChunk.into());
+                }
+                let offset = u32::from_be_bytes(header[0..4].try_into().unwrap()) as u64;
+                let data_start = start + 8 + offset;
+
+                // Files that were never finalized have a size of 0.
+                let data_end = match size {
+                    0 => end,
+                    size => (start + size).min(end),
+                };
+                sound = Some((data_start, data_end.saturating_sub(data_start)));
+
+                if size == 0 {
+                    break;
+                }
+            } else if matches!(id, b"COMM" | b"MARK" | b"INST") {
+                if size > end - start {
+                    return Err(AudioFileError::InvalidChunk.into());
+                }
+                let mut body = vec![0_u8; size as usize];
+                read_full(&mut inner, &mut body)?;
+                let mut body = ChunkReader::new(&body);
+
+                match id {
+                    b"COMM" => comm = Some(parse_comm(&mut body, aifc)?),
+                    b"MARK" => markers = parse_mark(&mut body)?,
+                    _ => instrument = Some(parse_inst(&mut body)?),
+                }
+            }
+
+            inner
+                .seek(SeekFrom::Start(start + size + (size & 1)))
+                .map_err(CAError::from_io_error)?;
+        }
+
+        let (description, comm_frames) = comm.ok_or(AudioFileError::InvalidFile)?;
+        let (data_start, data_size) = sound.ok_or(AudioFileError::InvalidFile)?;
+
+        let frames = match comm_frames {
+            0 => data_size / description.bytes_per_packet as u64,
+            frames => (frames as u64).min(data_size / description.bytes_per_packet as u64),
+        };
+
+        inner
+            .seek(SeekFrom::Start(data_start))
+            .map_err(CAError::from_io_error)?;
+
+        Ok(AiffReader {
+            inner,
+            pcm: description.stream_format(),
+            codec: G711Codec::new(description.format).ok(),
+            description,
+            markers,
+            instrument,
+            data_start,
+            frames,
+            position: 0,
+            scratch: Vec::new(),
+        })
+    }
+
+    pub fn description(&self) -> &FormatDescription {
+        &self.description
+    }
+
+    /// The format as a [`StreamFormat`] if the file is linear PCM.
+    pub fn stream_format(&self) -> Option<&StreamFormat> {
+        self.pcm.as_ref()
+    }
+
+    pub fn markers(&self) -> &[Marker] {
+        &self.markers
+    }
+
+    pub fn instrument(&self) -> Option<&Instrument> {
+        self.instrument.as_ref()
+    }
+
+    /// Frames between the markers of a loop, if it loops and the markers exist.
+    pub fn loop_range(&self, l: &Loop) -> Option<Range<u32>> {
+        if l.play_mode == PlayMode::NoLooping {
+            return None;
+        }
+
+        let position = |id| self.markers.iter().find(|m| m.id == id).map(|m| m.position);
+
+        Some(position(l.begin_marker)?..position(l.end_marker)?)
+    }
+
+    /// Total number of frames.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// The next frame to be read.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Move to a frame. Seeking past the end is an error.
+    pub fn seek(&mut self, frame: u64) -> Result<(), CAError> {
+        if frame > self.frames {
+            return Err(AudioFileError::Position.into());
+        }
+
+        let offset = frame * self.description.bytes_per_packet as u64;
+        self.inner
+            .seek(SeekFrom::Start(self.data_start + offset))
+            .map_err(CAError::from_io_error)?;
+        self.position = frame;
+
+        Ok(())
+    }
+
+    /// Read whole frames of sound data as is.
+    ///
+    /// Returns the number of frames read, which is 0 at the end of the file.
+    pub fn read_bytes(&mut self, out: &mut [u8]) -> Result<usize, CAError> {
+        let block_align = self.description.bytes_per_packet as usize;
+        let frames = ((out.len() / block_align) as u64).min(self.frames - self.position);
+
+        let read = read_full(&mut self.inner, &mut out[..frames as usize * block_align])?;
+        let frames = read / block_align;
+        self.position += frames as u64;
+
+        Ok(frames)
+    }
+
+    /// Read interleaved frames, converting them to the sample type or decoding µ-law or
+    /// A-law.
+    ///
+    /// Returns the number of frames read, which is 0 at the end of the file.
+    pub fn read_samples<S: SampleConvert>(&mut self, out: &mut [S]) -> Result<usize, CAError> {
+        if self.pcm.is_none() && self.codec.is_none() {
+            return Err(AudioFormatError::UnsupportedDataFormat.into());
+        }
+
+        let channels = self.description.channels as usize;
+        let block_align = self.description.bytes_per_packet as usize;
+
+        let mut scratch = std::mem::take(&mut self.scratch);
+        scratch.resize((out.len() / channels) * block_align, 0);
+        let frames = self.read_bytes(&mut scratch)?;
+
+        let bytes = &scratch[..frames * block_align];
+        let out = &mut out[..frames * channels];
+        match (&self.pcm, self.codec) {
+            (Some(format), _) => decode_samples(
+                bytes,
+                format.sample_format(),
+                format.flags().contains(LinearPcmFlags::IS_BIG_ENDIAN),
+                out,
+            ),
+            (None, Some(codec)) => {
+                codec.decode(bytes, out);
+            }
+            (None, None) => unreachable!(),
+        }
+        self.scratch = scratch;
+
+        Ok(frames)
+    }
+
+    /// Read frames into the buffers, which can be interleaved or not.
+    ///
+    /// Returns the number of frames read, the rest of the buffers is left untouched.
+    pub fn read_buffer_list<S: SampleConvert>(
+        &mut self,
+        list: &mut AudioBufferList<S>,
+    ) -> Result<usize, CAError> {
+        let channels = self.description.channels as usize;
+        let mut samples = vec![S::default(); list_frames(list) * channels];
+        let frames = self.read_samples(&mut samples)?;
+        deinterleave(&samples[..frames * channels], list);
+        Ok(frames)
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+/// The description and number of frames of the `COMM` chunk.
+fn parse_comm(body: &mut ChunkReader, aifc: bool) -> Result<(FormatDescription, u32), CAError> {
+    let channels = body.u16()? as u32;
+    let frames = body.u32()?;
+    let sample_size = body.u16()? as u32;
+    let sample_rate = from_extended(body.bytes(10)?.try_into().unwrap());
+
+    let compression = if aifc { body.bytes(4)? } else { b"NONE" };
+
+    if channels == 0 {
+        return Err(AudioFileError::InvalidFile.into());
+    }
+
+    let (flags, bits) = match compression {
+        b"NONE" | b"twos" | b"in24" | b"in32" => (
+            LinearPcmFlags::IS_SIGNED_INTEGER | LinearPcmFlags::IS_BIG_ENDIAN,
+            sample_size,
+        ),
+        b"sowt" => (LinearPcmFlags::IS_SIGNED_INTEGER, sample_size),
+        b"fl32" | b"FL32" => (LinearPcmFlags::IS_FLOAT | LinearPcmFlags::IS_BIG_ENDIAN, 32),
+        b"ulaw" | b"ULAW" => {
+            let description = g711_description(AudioFormat::ULaw, sample_rate, channels);
+            return Ok((description, frames));
+        }
+        b"alaw" | b"ALAW" => {
+            let description = g711_description(AudioFormat::ALaw, sample_rate, channels);
+            return Ok((description, frames));
+        }
+        _ => return Err(AudioFormatError::UnsupportedDataFormat.into()),
+    };
+
+    // Sizes that are not a multiple of 8 are left justified in whole bytes.
+    let bits = match bits {
+        1..=8 => 8,
+        9..=16 => 16,
+        17..=24 => 24,
+        25..=32 => 32,
+        _ => return Err(AudioFormatError::UnsupportedDataFormat.into()),
+    };
+    let sample_format =
+        SampleFormat::from_flags_and_bits_per_sample(flags | LinearPcmFlags::IS_PACKED, bits)
+            .ok_or(AudioFormatError::UnsupportedDataFormat)?;
+
+    let format = StreamFormat::new(sample_rate, sample_format, flags, channels as usize);
+
+    Ok((FormatDescription::from(&format), frames))
+}
+
+fn parse_mark(body: &mut ChunkReader) -> Result<Vec<Marker>, CAError> {
+    let count = body.u16()?;
+
+    let mut markers = Vec::new();
+    for _ in 0..count {
+        let id = body.u16()?;
+        let position = body.u32()?;
+        let name = read_pstring(body)?;
+        markers.push(Marker { id, position, name });
+    }
+
+    Ok(markers)
+}
+
+fn parse_inst(body: &mut ChunkReader) -> Result<Instrument, CAError> {
+    let read_loop = |body: &mut ChunkReader| -> Result<Loop, CAError> {
+        let play_mode = match body.u16()? {
+            1 => PlayMode::Forward,
+            2 => PlayMode::ForwardBackward,
+            _ => PlayMode::NoLooping,
+        };
+        Ok(Loop {
+            play_mode,
+            begin_marker: body.u16()?,
+            end_marker: body.u16()?,
+        })
+    };
+
+    Ok(Instrument {
+        base_note: body.u8()?,
+        detune: body.u8()? as i8,
+        low_note: body.u8()?,
+        high_note: body.u8()?,
+        low_velocity: body.u8()?,
+        high_velocity: body.u8()?,
+        gain: body.i16()?,
+        sustain_loop: read_loop(body)?,
+        release_loop: read_loop(body)?,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn extended_floats() {
+        let rate = to_extended(44_100.0);
+        assert_eq!(rate, [0x40, 0x0E, 0xAC, 0x44, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(from_extended(rate), 44_100.0);
+
+        for value in [8_000.0, 48_000.0, 96_000.0, 11_025.5, -1.0, 0.0] {
+            assert_eq!(from_extended(to_extended(value)), value);
+        }
+    }
+
+    #[test]
+    fn big_endian_aiff() {
+        let format = StreamFormat::new(
+            44_100.0,
+            SampleFormat::I16,
+            LinearPcmFlags::IS_SIGNED_INTEGER | LinearPcmFlags::IS_BIG_ENDIAN,
+            2,
+        );
+
+        let mut w = AiffWriter::new(Cursor::new(Vec::new()), &format).unwrap();
+        w.write_samples(&[0x0102_i16, -2, 3, 4]).unwrap();
+        let bytes = w.finalize().unwrap().into_inner();
+
+        assert_eq!(&bytes[0..4], b"FORM");
+        assert_eq!(&bytes[4..8], &(bytes.len() as u32 - 8).to_be_bytes());
+        assert_eq!(&bytes[8..12], b"AIFF");
+        assert_eq!(&bytes[12..16], b"COMM");
+        // Frames.
+        assert_eq!(&bytes[22..26], &2_u32.to_be_bytes());
+        assert_eq!(&bytes[38..42], b"SSND");
+        assert_eq!(&bytes[54..58], [0x01, 0x02, 0xff, 0xfe]);
+
+        let mut r = AiffReader::new(Cursor::new(bytes)).unwrap();
+        let read_format = r.stream_format().unwrap();
+        assert_eq!(read_format.sample_format(), SampleFormat::I16);
+        assert!(read_format.flags().contains(LinearPcmFlags::IS_BIG_ENDIAN));
+        assert_eq!(read_format.sample_rate(), 44_100.0);
+        assert_eq!(r.frames(), 2);
+
+        let mut out = [0_i16; 4];
+        assert_eq!(r.read_samples(&mut out).unwrap(), 2);
+        assert_eq!(out, [0x0102, -2, 3, 4]);
+    }
+
+    #[test]
+    fn aifc_compression_types() {
+        let sowt = StreamFormat::new(
+            48_000.0,
+            SampleFormat::I24,
+            LinearPcmFlags::IS_SIGNED_INTEGER,
+            1,
+        );
+        let mut w = AiffWriter::new(Cursor::new(Vec::new()), &sowt).unwrap();
+        w.write_samples(&[0x0012_3456 << 8]).unwrap();
+        let bytes = w.finalize().unwrap().into_inner();
+        assert_eq!(&bytes[8..12], b"AIFC");
+        assert_eq!(&bytes[12..16], b"FVER");
+        assert_eq!(&bytes[50..54], b"sowt");
+
+        let mut r = AiffReader::new(Cursor::new(bytes)).unwrap();
+        let format = r.stream_format().unwrap();
+        assert_eq!(format.sample_format(), SampleFormat::I24);
+        assert!(!format.flags().contains(LinearPcmFlags::IS_BIG_ENDIAN));
+        let mut out = [0_i32; 1];
+        r.read_samples(&mut out).unwrap();
+        assert_eq!(out, [0x0012_3456 << 8]);
+
+        let float = StreamFormat::new(48_000.0, SampleFormat::F32, LinearPcmFlags::IS_FLOAT, 1);
+        let mut w = AiffWriter::new(Cursor::new(Vec::new()), &float).unwrap();
+        w.write_samples(&[0.5_f32]).unwrap();
+        let bytes = w.finalize().unwrap().into_inner();
+        assert_eq!(&bytes[50..54], b"fl32");
+        assert!(bytes.ends_with(&0.5_f32.to_be_bytes()));
+
+        let r = AiffReader::new(Cursor::new(bytes)).unwrap();
+        assert!(r
+            .stream_format()
+            .unwrap()
+            .flags()
+            .contains(LinearPcmFlags::IS_FLOAT | LinearPcmFlags::IS_BIG_ENDIAN));
+
+        let ulaw = g711_description(AudioFormat::ULaw, 8_000.0, 1);
+        let mut w = AiffWriter::with_description(Cursor::new(Vec::new()), &ulaw).unwrap();
+        w.write_bytes(&[0xff, 0x7f, 0x00]).unwrap();
+        w.write_samples(&[i16::MAX]).unwrap();
+        let bytes = w.finalize().unwrap().into_inner();
+        assert_eq!(&bytes[50..54], b"ulaw");
+
+        let mut r = AiffReader::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(r.description(), &ulaw);
+        assert!(r.stream_format().is_none());
+        let mut out = [0_u8; 3];
+        assert_eq!(r.read_bytes(&mut out).unwrap(), 3);
+        assert_eq!(out, [0xff, 0x7f, 0x00]);
+        let mut out = [0_i16; 2];
+        assert_eq!(r.read_samples(&mut out).unwrap(), 1);
+        assert_eq!(out[0], 32124);
+    }
+
+    #[test]
+    fn markers_and_loops() {
+        let format = StreamFormat::new(
+            22_050.0,
+            SampleFormat::I8,
+            LinearPcmFlags::IS_SIGNED_INTEGER | LinearPcmFlags::IS_BIG_ENDIAN,
+            1,
+        );
+
+        let mut w = AiffWriter::new(Cursor::new(Vec::new()), &format).unwrap();
+        // Odd number of bytes, the SSND chunk is padded.
+        w.write_samples(&[1_i8, -1, 2]).unwrap();
+        w.add_marker(Marker {
+            id: 1,
+            position: 1,
+            name: "loop start".into(),
+        });
+        w.add_marker(Marker {
+            id: 2,
+            position: 3,
+            name: "end".into(),
+        });
+        let instrument = Instrument {
+            base_note: 69,
+            detune: -10,
+            gain: -3,
+            sustain_loop: Loop {
+                play_mode: PlayMode::Forward,
+                begin_marker: 1,
+                end_marker: 2,
+            },
+            ..Instrument::default()
+        };
+        w.set_instrument(instrument);
+        let bytes = w.finalize().unwrap().into_inner();
+        assert_eq!(bytes.len() % 2, 0);
+
+        let mut r = AiffReader::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(r.frames(), 3);
+        assert_eq!(r.markers().len(), 2);
+        assert_eq!(r.markers()[0].name, "loop start");
+        assert_eq!(r.instrument(), Some(&instrument));
+        assert_eq!(r.loop_range(&instrument.sustain_loop), Some(1..3));
+        assert_eq!(r.loop_range(&instrument.release_loop), None);
+
+        r.seek(2).unwrap();
+        let mut out = [0_i8; 2];
+        assert_eq!(r.read_samples(&mut out).unwrap(), 1);
+        assert_eq!(out[0], 2);
+    }
+
+    #[test]
+    fn not_aiff() {
+        assert!(matches!(
+            AiffReader::new(Cursor::new(b"RIFF\0\0\0\0WAVE".to_vec())),
+            Err(CAError::AudioFileError(AudioFileError::UnsupportedFileType))
+        ));
+    }
+}
@@ -0,0 +1,1252 @@
+//! Core Audio Format files.
+//!
+//! Reads and writes the `desc`, `data`, `pakt`, `kuki`, `chan`, `info`, `strg` and `mark`
+//! chunks. Linear PCM is streamed as samples, other formats as packets.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::error::{AudioFileError, AudioFormatError};
+use crate::format::{
+    AudioFormat, ChannelLabel, ChannelLayout, FormatDescription, LinearPcmFlags, PacketDescription,
+    PacketTableInfo, SampleConvert, SampleFormat, StreamFormat,
+};
+use crate::unit::AudioBufferList;
+use crate::CAError;
+
+use super::{
+    decode_samples, deinterleave, encode_samples, interleave, list_frames, read_full, write_u32_be,
+    write_u64_be, ChunkReader, PacketReader,
+};
+
+const FLAG_IS_FLOAT: u32 = 1 << 0;
+const FLAG_IS_LITTLE_ENDIAN: u32 = 1 << 1;
+
+const LPCM: u32 = u32::from_be_bytes(*b"lpcm");
+
+const CHANNEL_LAYOUT_USE_DESCRIPTIONS: u32 = 0;
+const CHANNEL_LAYOUT_USE_BITMAP: u32 = 1 << 16;
+const CHANNEL_LAYOUT_MONO: u32 = (100 << 16) | 1;
+const CHANNEL_LAYOUT_STEREO: u32 = (101 << 16) | 2;
+const CHANNEL_LAYOUT_DISCRETE_IN_ORDER: u32 = 147 << 16;
+
+/// Size of a channel description in the `chan` chunk.
+const CHANNEL_DESCRIPTION_SIZE: usize = 20;
+
+/// Size of a marker in the `mark` chunk.
+const MARKER_SIZE: usize = 28;
+
+/// Size of the header written by [`CafWriter::new`]: the file header, the desc chunk
+/// and the data chunk header including the edit count.
+pub(crate) const HEADER_SIZE: u64 = 8 + 12 + 32 + 12 + 4;
+
+/// A marker of the `mark` chunk.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Marker {
+    /// Marker type, a four char code. 0 is a generic marker.
+    pub kind: u32,
+    pub frame_position: f64,
+    pub name: Option<String>,
+    /// Channel the marker applies to, 0 for all channels.
+    pub channel: u32,
+}
+
+/// Streaming reader of a CAF file.
+///
+/// Linear PCM can be read as samples, every format can be read as packets.
+pub struct CafReader<R: Read + Seek> {
+    inner: R,
+    description: FormatDescription,
+    pcm: Option<StreamFormat>,
+    magic_cookie: Option<Vec<u8>>,
+    layout: Option<ChannelLayout>,
+    info: Vec<(String, String)>,
+    markers: Vec<Marker>,
+    packet_table_info: Option<PacketTableInfo>,
+    /// Packets in the data, only for formats with variable packets.
+    packets: Vec<PacketDescription>,
+    packet_count: u64,
+    data_start: u64,
+    /// Next packet to read.
+    position: u64,
+    scratch: Vec<u8>,
+}
+
+impl CafReader<BufReader<File>> {
+    /// Open the CAF file at the path.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, CAError> {
+        let file = File::open(path).map_err(CAError::from_io_error)?;
+        CafReader::new(BufReader::new(file))
+    }
+}
+
+impl<R: Read + Seek> CafReader<R> {
+    /// Read the chunks before the audio data, leaving the reader at the first packet.
+    pub fn new(mut inner: R) -> Result<Self, CAError> {
+        let mut header = [0_u8; 8];
+        if read_full(&mut inner, &mut header)? < 8 || &header[0..4] != b"caff" {
+            return Err(AudioFileError::UnsupportedFileType.into());
+        }
+        if header[4..6] != 1_u16.to_be_bytes() {
+            return Err(AudioFileError::InvalidFile.into());
+        }
+
+        let end = inner
+            .seek(SeekFrom::End(0))
+            .map_err(CAError::from_io_error)?;
+        inner
+            .seek(SeekFrom::Start(8))
+            .map_err(CAError::from_io_error)?;
+
+        let mut description = None;
+        let mut magic_cookie = None;
+        let mut layout = None;
+        let mut info = Vec::new();
+        let mut strings = HashMap::new();
+        let mut markers = Vec::new();
+        let mut packet_table = None;
+        let mut data = None;
+
+        loop {
+            let mut chunk = [0_u8; 12];
+            let read = read_full(&mut inner, &mut chunk)?;
+            if read == 0 {
+                break;
+            }
+            if read < 12 {
+                return Err(AudioFileError::InvalidChunk.into());
+            }
+
+            let id = &chunk[0..4];
+            let size = i64::from_be_bytes(chunk[4..12].try_into().unwrap());
+            let start = inner.stream_position().map_err(CAError::from_io_error)?;
+
+            if description.is_none() && id != b"desc" {
+                // desc has to be the first chunk.
+                return Err(AudioFileError::InvalidFile.into());
+            }
+
+            if id == b"data" {
+                if size < -1 {
+                    return Err(AudioFileError::InvalidChunk.into());
+                }
+                // Skip the edit count.
+                let data_start = start + 4;
+                if size == -1 || size as u64 >= end.saturating_sub(start) {
+                    // Unknown size or cut short, the data goes to the end of the file.
+                    data = Some((data_start, end.saturating_sub(data_start)));
+                    break;
+                }
+                if size < 4 {
+                    return Err(AudioFileError::InvalidChunk.into());
+                }
+                data = Some((data_start, size as u64 - 4));
+                inner
+                    .seek(SeekFrom::Start(start + size as u64))
+                    .map_err(CAError::from_io_error)?;
+                continue;
+            }
+
+            if size < 0 || size as u64 > end - start {
+                return Err(AudioFileError::InvalidChunk.into());
+            }
+            let mut body = vec![0_u8; size as usize];
+            read_full(&mut inner, &mut body)?;
+            let mut body = ChunkReader::new(&body);
+
+            match id {
+                b"desc" => description = Some(parse_desc(&mut body)?),
+                b"kuki" => magic_cookie = Some(body.remaining().to_vec()),
+                b"chan" => layout = Some(parse_chan(&mut body)?),
+                b"info" => info = parse_info(&mut body)?,
+                b"strg" => strings = parse_strg(&mut body)?,
+                b"mark" => markers = parse_mark(&mut body)?,
+                b"pakt" => packet_table = Some(body.remaining().to_vec()),
+                _ => {}
+            }
+        }
+
+        let description = description.ok_or(AudioFileError::InvalidFile)?;
+        let (data_start, data_size) = data.ok_or(AudioFileError::InvalidFile)?;
+
+        let mut packet_table_info = None;
+        let mut packets = Vec::new();
+
+        if let Some(pakt) = packet_table {
+            let (info, table) = parse_pakt(&mut ChunkReader::new(&pakt), &description)?;
+            packet_table_info = Some(info);
+            packets = table;
+        } else if description.has_variable_packets() {
+            return Err(AudioFileError::InvalidFile.into());
+        }
+
+        let packet_count = if description.has_variable_packets() {
+            // The file might be cut short.
+            let complete = packets
+                .iter()
+                .take_while(|p| p.start_offset + p.data_byte_size as u64 <= data_size)
+                .count();
+            packets.truncate(complete);
+            complete as u64
+        } else {
+            packets.clear();
+            data_size / description.bytes_per_packet as u64
+        };
+
+        // Names are looked up by marker ID in the strg chunk.
+        let markers = markers
+            .into_iter()
+            .map(|(marker, id): (Marker, u32)| Marker {
+                name: strings.get(&id).cloned(),
+                ..marker
+            })
+            .collect();
+
+        inner
+            .seek(SeekFrom::Start(data_start))
+            .map_err(CAError::from_io_error)?;
+
+        Ok(CafReader {
+            inner,
+            pcm: description.stream_format(),
+            description,
+            magic_cookie,
+            layout,
+            info,
+            markers,
+            packet_table_info,
+            packets,
+            packet_count,
+            data_start,
+            position: 0,
+            scratch: Vec::new(),
+        })
+    }
+
+    pub fn description(&self) -> &FormatDescription {
+        &self.description
+    }
+
+    /// The format as a [`StreamFormat`] if the file is linear PCM that can be read as
+    /// samples.
+    pub fn stream_format(&self) -> Option<&StreamFormat> {
+        self.pcm.as_ref()
+    }
+
+    pub fn magic_cookie(&self) -> Option<&[u8]> {
+        self.magic_cookie.as_deref()
+    }
+
+    pub fn channel_layout(&self) -> Option<&ChannelLayout> {
+        self.layout.as_ref()
+    }
+
+    /// Key and value pairs of the `info` chunk, in file order.
+    pub fn info(&self) -> &[(String, String)] {
+        &self.info
+    }
+
+    pub fn markers(&self) -> &[Marker] {
+        &self.markers
+    }
+
+    pub fn packet_table_info(&self) -> Option<&PacketTableInfo> {
+        self.packet_table_info.as_ref()
+    }
+
+    pub fn packet_count(&self) -> u64 {
+        self.packet_count
+    }
+
+    /// Total number of frames in the packets, including priming and remainder frames.
+    pub fn frames(&self) -> u64 {
+        match self.description.frames_per_packet {
+            0 => self.packets.iter().map(|p| p.variable_frames as u64).sum(),
+            frames => self.packet_count * frames as u64,
+        }
+    }
+
+    /// The next packet to be read, which is also the next frame for linear PCM.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Where a packet is in the audio data.
+    pub fn packet(&self, index: u64) -> Option<PacketDescription> {
+        if index >= self.packet_count {
+            return None;
+        }
+
+        if self.description.has_variable_packets() {
+            return Some(self.packets[index as usize]);
+        }
+
+        let size = self.description.bytes_per_packet;
+        Some(PacketDescription {
+            start_offset: index * size as u64,
+            variable_frames: 0,
+            data_byte_size: size,
+        })
+    }
+
+    /// Move to a packet. Seeking past the end is an error.
+    pub fn seek_packet(&mut self, index: u64) -> Result<(), CAError> {
+        let offset = match self.packet(index) {
+            Some(packet) => packet.start_offset,
+            None if index == self.packet_count => self.data_end(),
+            None => return Err(AudioFileError::Position.into()),
+        };
+
+        self.inner
+            .seek(SeekFrom::Start(self.data_start + offset))
+            .map_err(CAError::from_io_error)?;
+        self.position = index;
+
+        Ok(())
+    }
+
+    fn data_end(&self) -> u64 {
+        match self.packet_count {
+            0 => 0,
+            n => {
+                let last = self.packet(n - 1).unwrap();
+                last.start_offset + last.data_byte_size as u64
+            }
+        }
+    }
+
+    /// Append up to `max_packets` packets to `data` and their descriptions, relative to
+    /// the start of `data`, to `descriptions`.
+    ///
+    /// Returns the number of packets read, which is 0 at the end of the file.
+    pub fn read_packets(
+        &mut self,
+        max_packets: usize,
+        data: &mut Vec<u8>,
+        descriptions: &mut Vec<PacketDescription>,
+    ) -> Result<usize, CAError> {
+        let count = (max_packets as u64).min(self.packet_count - self.position);
+
+        for index in self.position..self.position + count {
+            let packet = self.packet(index).unwrap();
+            let start = data.len();
+
+            data.resize(start + packet.data_byte_size as usize, 0);
+            if read_full(&mut self.inner, &mut data[start..])? < packet.data_byte_size as usize {
+                return Err(AudioFileError::EndOfFile.into());
+            }
+
+            descriptions.push(PacketDescription {
+                start_offset: start as u64,
+                ..packet
+            });
+        }
+
+        self.position += count;
+
+        Ok(count as usize)
+    }
+
+    /// Move to a frame of linear PCM.
+    pub fn seek(&mut self, frame: u64) -> Result<(), CAError> {
+        if self.pcm.is_none() {
+            return Err(AudioFormatError::UnsupportedDataFormat.into());
+        }
+        self.seek_packet(frame)
+    }
+
+    /// Read interleaved frames of linear PCM, converting them to the sample type.
+    ///
+    /// Returns the number of frames read, which is 0 at the end of the file.
+    pub fn read_samples<S: SampleConvert>(&mut self, out: &mut [S]) -> Result<usize, CAError> {
+        let format = self
+            .pcm
+            .as_ref()
+            .ok_or(AudioFormatError::UnsupportedDataFormat)?;
+        let channels = format.channels();
+        let sample_format = format.sample_format();
+        let big_endian = format.flags().contains(LinearPcmFlags::IS_BIG_ENDIAN);
+        let block_align = self.description.bytes_per_packet as usize;
+
+        let frames =
+            ((out.len() / channels) as u64).min(self.packet_count - self.position) as usize;
+
+        self.scratch.resize(frames * block_align, 0);
+        let frames = read_full(&mut self.inner, &mut self.scratch)? / block_align;
+
+        decode_samples(
+            &self.scratch[..frames * block_align],
+            sample_format,
+            big_endian,
+            &mut out[..frames * channels],
+        );
+        self.position += frames as u64;
+
+        Ok(frames)
+    }
+
+    /// Read frames of linear PCM into the buffers, which can be interleaved or not.
+    ///
+    /// Returns the number of frames read, the rest of the buffers is left untouched.
+    pub fn read_buffer_list<S: SampleConvert>(
+        &mut self,
+        list: &mut AudioBufferList<S>,
+    ) -> Result<usize, CAError> {
+        let channels = self.description.channels as usize;
+        let mut samples = vec![S::default(); list_frames(list) * channels];
+        let frames = self.read_samples(&mut samples)?;
+        deinterleave(&samples[..frames * channels], list);
+        Ok(frames)
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read + Seek> PacketReader for CafReader<R> {
+    fn read_packets(
+        &mut self,
+        max_packets: usize,
+        data: &mut Vec<u8>,
+        descriptions: &mut Vec<PacketDescription>,
+    ) -> Result<usize, CAError> {
+        CafReader::read_packets(self, max_packets, data, descriptions)
+    }
+}
+
+fn parse_desc(body: &mut ChunkReader) -> Result<FormatDescription, CAError> {
+    let sample_rate = body.f64()?;
+    let format_id = body.u32()?;
+    let format_flags = body.u32()?;
+    let bytes_per_packet = body.u32()?;
+    let frames_per_packet = body.u32()?;
+    let channels = body.u32()?;
+    let bits_per_channel = body.u32()?;
+
+    let format = if format_id == LPCM {
+        // CAF has its own flags for linear PCM.
+        let mut flags = LinearPcmFlags::IS_PACKED;
+        if format_flags & FLAG_IS_FLOAT != 0 {
+            flags |= LinearPcmFlags::IS_FLOAT;
+        } else {
+            flags |= LinearPcmFlags::IS_SIGNED_INTEGER;
+        }
+        if format_flags & FLAG_IS_LITTLE_ENDIAN == 0 {
+            flags |= LinearPcmFlags::IS_BIG_ENDIAN;
+        }
+        AudioFormat::LinearPCM(flags)
+    } else {
+        AudioFormat::from_id_and_flags(format_id, format_flags)
+    };
+
+    // Linear PCM packets are single frames of interleaved channels.
+    if channels == 0
+        || (format_id == LPCM
+            && (bytes_per_packet == 0
+                || frames_per_packet != 1
+                || bytes_per_packet % channels != 0))
+    {
+        return Err(AudioFileError::InvalidFile.into());
+    }
+
+    Ok(FormatDescription {
+        sample_rate,
+        format,
+        bytes_per_packet,
+        frames_per_packet,
+        channels,
+        bits_per_channel,
+    })
+}
+
+fn parse_chan(body: &mut ChunkReader) -> Result<ChannelLayout, CAError> {
+    let tag = body.u32()?;
+    let bitmap = body.u32()?;
+    let descriptions = body.u32()?;
+
+    let labels = match tag {
+        CHANNEL_LAYOUT_USE_DESCRIPTIONS => {
+            let mut labels = Vec::new();
+            for _ in 0..descriptions {
+                let description = body.bytes(CHANNEL_DESCRIPTION_SIZE)?;
+                let label = u32::from_be_bytes(description[0..4].try_into().unwrap());
+                labels.push(ChannelLabel::from_u32(label));
+            }
+            labels
+        }
+        CHANNEL_LAYOUT_USE_BITMAP => {
+            let channels = bitmap.count_ones() as usize;
+            return Ok(ChannelLayout::from_bitmap(bitmap, channels));
+        }
+        CHANNEL_LAYOUT_MONO => vec![ChannelLabel::Mono],
+        CHANNEL_LAYOUT_STEREO => vec![ChannelLabel::Left, ChannelLabel::Right],
+        tag if tag & 0xFFFF_0000 == CHANNEL_LAYOUT_DISCRETE_IN_ORDER => {
+            (0..tag as u16).map(ChannelLabel::Discrete).collect()
+        }
+        // Other layout tags only keep the number of channels.
+        tag => vec![ChannelLabel::Unknown; (tag & 0xFFFF) as usize],
+    };
+
+    Ok(ChannelLayout::new(labels))
+}
+
+fn parse_strings(bytes: &[u8]) -> Vec<String> {
+    let mut strings: Vec<String> = bytes
+        .split(|&b| b == 0)
+        .map(|s| String::from_utf8_lossy(s).into_owned())
+        .collect();
+    // After the last NUL.
+    strings.pop();
+    strings
+}
+
+fn parse_info(body: &mut ChunkReader) -> Result<Vec<(String, String)>, CAError> {
+    let entries = body.u32()? as usize;
+    let strings = parse_strings(body.remaining());
+
+    if strings.len() / 2 < entries {
+        return Err(AudioFileError::InvalidChunk.into());
+    }
+
+    Ok(strings[..entries * 2]
+        .chunks_exact(2)
+        .map(|pair| (pair[0].clone(), pair[1].clone()))
+        .collect())
+}
+
+fn parse_strg(body: &mut ChunkReader) -> Result<HashMap<u32, String>, CAError> {
+    let entries = body.u32()?;
+
+    let mut ids = Vec::new();
+    for _ in 0..entries {
+        let id = body.u32()?;
+        let offset = body.i64()?;
+        ids.push((id, offset));
+    }
+
+    let data = body.remaining();
+    ids.into_iter()
+        .map(|(id, offset)| {
+            let bytes = usize::try_from(offset)
+                .ok()
+                .and_then(|offset| data.get(offset..))
+                .ok_or(AudioFileError::InvalidChunk)?;
+            let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+            Ok((id, String::from_utf8_lossy(&bytes[..end]).into_owned()))
+        })
+        .collect()
+}
+
+/// Markers with the ID of their name.
+fn parse_mark(body: &mut ChunkReader) -> Result<Vec<(Marker, u32)>, CAError> {
+    // SMPTE time type.
+    body.u32()?;
+    let count = body.u32()?;
+
+    let mut markers = Vec::new();
+    for _ in 0..count {
+        let mut marker = ChunkReader::new(body.bytes(MARKER_SIZE)?);
+        let kind = marker.u32()?;
+        let frame_position = marker.f64()?;
+        let id = marker.u32()?;
+        // SMPTE time.
+        marker.bytes(8)?;
+        let channel = marker.u32()?;
+
+        markers.push((
+            Marker {
+                kind,
+                frame_position,
+                name: None,
+                channel,
+            },
+            id,
+        ));
+    }
+
+    Ok(markers)
+}
+
+fn parse_pakt(
+    body: &mut ChunkReader,
+    description: &FormatDescription,
+) -> Result<(PacketTableInfo, Vec<PacketDescription>), CAError> {
+    let packets = body.i64()?;
+    let info = PacketTableInfo {
+        valid_frames: body.i64()?,
+        priming_frames: body.i32()?,
+        remainder_frames: body.i32()?,
+    };
+
+    if !description.has_variable_packets() {
+        return Ok((info, Vec::new()));
+    }
+
+    let mut table = Vec::with_capacity((packets.max(0) as usize).min(body.remaining().len()));
+    let mut offset = 0;
+
+    for _ in 0..packets {
+        let data_byte_size = match description.bytes_per_packet {
+            0 => read_varint(body)?,
+            size => size,
+        };
+        let variable_frames = match description.frames_per_packet {
+            0 => read_varint(body)?,
+            _ => 0,
+        };
+
+        table.push(PacketDescription {
+            start_offset: offset,
+            variable_frames,
+            data_byte_size,
+        });
+        offset += data_byte_size as u64;
+    }
+
+    Ok((info, table))
+}
+
+/// Read a variable length integer of the packet table: 7 bits per byte, most significant
+/// first, with the high bit set on all but the last byte.
+fn read_varint(body: &mut ChunkReader) -> Result<u32, CAError> {
+    let mut value: u32 = 0;
+    for _ in 0..5 {
+        let byte = body.u8()?;
+        value = (value << 7) | (byte & 0x7F) as u32;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(AudioFileError::InvalidChunk.into())
+}
+
+fn write_varint(out: &mut Vec<u8>, value: u32) {
+    let mut groups = 1;
+    while groups < 5 && value >> (7 * groups) != 0 {
+        groups += 1;
+    }
+    for i in (0..groups).rev() {
+        let byte = ((value >> (7 * i)) & 0x7F) as u8;
+        out.push(if i == 0 { byte } else { byte | 0x80 });
+    }
+}
+
+/// Streaming writer of a CAF file.
+///
+/// The data chunk size is written as unknown (-1) until [`CafWriter::finalize`], so a
+/// file that is never finalized is still readable. The packet table, channel layout,
+/// info and markers are written after the data by `finalize`.
+pub struct CafWriter<W: Write + Seek> {
+    inner: W,
+    description: FormatDescription,
+    /// Sample format and endianness for linear PCM.
+    pcm: Option<(SampleFormat, bool)>,
+    header_size: u64,
+    data_bytes: u64,
+    /// Sizes and frames of the packets, only for formats with variable packets.
+    packets: Vec<(u32, u32)>,
+    priming_frames: i32,
+    remainder_frames: i32,
+    layout: Option<ChannelLayout>,
+    info: Vec<(String, String)>,
+    markers: Vec<Marker>,
+    scratch: Vec<u8>,
+}
+
+impl CafWriter<BufWriter<File>> {
+    /// Create a CAF file of linear PCM at the path.
+    pub fn create(path: impl AsRef<Path>, format: &StreamFormat) -> Result<Self, CAError> {
+        let file = File::create(path).map_err(CAError::from_io_error)?;
+        CafWriter::new(BufWriter::new(file), format)
+    }
+}
+
+impl<W: Write + Seek> CafWriter<W> {
+    /// Start writing a CAF file of little endian linear PCM by writing the header.
+    pub fn new(inner: W, format: &StreamFormat) -> Result<Self, CAError> {
+        let mut description = FormatDescription::from(format);
+        if let AudioFormat::LinearPCM(flags) = &mut description.format {
+            flags.remove(LinearPcmFlags::IS_BIG_ENDIAN);
+        }
+        CafWriter::with_description(inner, &description, None)
+    }
+
+    /// Start writing a CAF file in any format by writing the header.
+    ///
+    /// Formats that need one must be given their magic cookie.
+    pub fn with_description(
+        mut inner: W,
+        description: &FormatDescription,
+        magic_cookie: Option<&[u8]>,
+    ) -> Result<Self, CAError> {
+        let pcm = match description.format {
+            AudioFormat::LinearPCM(flags) => {
+                let format = description
+                    .stream_format()
+                    .ok_or(AudioFormatError::UnsupportedDataFormat)?;
+                Some((
+                    format.sample_format(),
+                    flags.contains(LinearPcmFlags::IS_BIG_ENDIAN),
+                ))
+            }
+            _ => None,
+        };
+
+        if description.bytes_per_packet == 0 && description.frames_per_packet == 0 {
+            // Not representable.
+            return Err(AudioFormatError::UnsupportedDataFormat.into());
+        }
+
+        write_header(&mut inner, description, magic_cookie).map_err(CAError::from_io_error)?;
+
+        Ok(CafWriter {
+            inner,
+            description: *description,
+            pcm,
+            header_size: HEADER_SIZE + magic_cookie.map_or(0, |c| 12 + c.len() as u64),
+            data_bytes: 0,
+            packets: Vec::new(),
+            priming_frames: 0,
+            remainder_frames: 0,
+            layout: None,
+            info: Vec::new(),
+            markers: Vec::new(),
+            scratch: Vec::new(),
+        })
+    }
+
+    /// Number of frames written so far, for linear PCM only whole frames.
+    pub fn frames_written(&self) -> u64 {
+        match (
+            self.description.bytes_per_packet,
+            self.description.frames_per_packet,
+        ) {
+            (_, 0) => self.packets.iter().map(|&(_, frames)| frames as u64).sum(),
+            (0, frames) => self.packets.len() as u64 * frames as u64,
+            (size, frames) => self.data_bytes / size as u64 * frames as u64,
+        }
+    }
+
+    /// Total file size so far, including the header.
+    pub fn bytes_written(&self) -> u64 {
+        self.header_size + self.data_bytes
+    }
+
+    /// Set the encoder delay and padding of the packet table.
+    pub fn set_priming(&mut self, priming_frames: i32, remainder_frames: i32) {
+        self.priming_frames = priming_frames;
+        self.remainder_frames = remainder_frames;
+    }
+
+    pub fn set_channel_layout(&mut self, layout: &ChannelLayout) {
+        self.layout = Some(layout.clone());
+    }
+
+    /// Add an entry to the `info` chunk, e.g. `("title", "...")`.
+    pub fn add_info(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        self.info.push((key.into(), value.into()));
+    }
+
+    pub fn add_marker(&mut self, marker: Marker) {
+        self.markers.push(marker);
+    }
+
+    /// Write interleaved samples of linear PCM, converting them to the sample format of
+    /// the file.
+    pub fn write_samples<S: SampleConvert>(&mut self, samples: &[S]) -> Result<(), CAError> {
+        let (sample_format, big_endian) =
+            self.pcm.ok_or(AudioFormatError::UnsupportedDataFormat)?;
+
+        self.scratch.clear();
+        encode_samples(samples, sample_format, big_endian, &mut self.scratch);
+
+        self.inner
+            .write_all(&self.scratch)
+            .map_err(CAError::from_io_error)?;
+        self.data_bytes += self.scratch.len() as u64;
+
+        Ok(())
+    }
+
+    /// Write all frames of linear PCM buffers, which can be interleaved or not.
+    pub fn write_buffer_list<S: SampleConvert>(
+        &mut self,
+        list: &AudioBufferList<S>,
+    ) -> Result<(), CAError> {
+        self.write_samples(&interleave(list, list_frames(list)))
+    }
+
+    /// Write packets. Formats with variable packets need a description of every packet
+    /// in `data`, for other formats `data` has to hold whole packets.
+    pub fn write_packets(
+        &mut self,
+        data: &[u8],
+        descriptions: &[PacketDescription],
+    ) -> Result<(), CAError> {
+        if !self.description.has_variable_packets() {
+            if data.len() % self.description.bytes_per_packet as usize != 0 {
+                return Err(AudioFileError::InvalidPacketOffset.into());
+            }
+            self.inner.write_all(data).map_err(CAError::from_io_error)?;
+            self.data_bytes += data.len() as u64;
+            return Ok(());
+        }
+
+        for description in descriptions {
+            let start = description.start_offset as usize;
+            let packet = data
+                .get(start..start + description.data_byte_size as usize)
+                .ok_or(AudioFileError::InvalidPacketOffset)?;
+
+            self.inner
+                .write_all(packet)
+                .map_err(CAError::from_io_error)?;
+            self.data_bytes += packet.len() as u64;
+
+            let frames = match self.description.frames_per_packet {
+                0 => description.variable_frames,
+                frames => frames,
+            };
+            self.packets.push((description.data_byte_size, frames));
+        }
+
+        Ok(())
+    }
+
+    /// Fill in the data chunk size, write the chunks that follow the data and return the
+    /// inner writer.
+    pub fn finalize(mut self) -> Result<W, CAError> {
+        // The chunk size includes the edit count.
+        let data_size = self.data_bytes + 4;
+        let data_size_offset = self.header_size - 12;
+
+        let mut trailer = Vec::new();
+        self.write_trailer(&mut trailer);
+
+        let patch = |w: &mut W| -> std::io::Result<()> {
+            w.seek(SeekFrom::Start(data_size_offset))?;
+            write_u64_be(w, data_size)?;
+            w.seek(SeekFrom::End(0))?;
+            w.write_all(&trailer)?;
+            w.flush()
+        };
+
+        patch(&mut self.inner).map_err(CAError::from_io_error)?;
+
+        Ok(self.inner)
+    }
+
+    fn write_trailer(&self, out: &mut Vec<u8>) {
+        let variable = self.description.has_variable_packets();
+
+        if variable || self.priming_frames != 0 || self.remainder_frames != 0 {
+            let packets = match self.description.bytes_per_packet {
+                _ if variable => self.packets.len() as u64,
+                size => self.data_bytes / size as u64,
+            };
+            let valid_frames = self.frames_written() as i64
+                - self.priming_frames as i64
+                - self.remainder_frames as i64;
+
+            let mut body = Vec::new();
+            body.extend_from_slice(&(packets as i64).to_be_bytes());
+            body.extend_from_slice(&valid_frames.to_be_bytes());
+            body.extend_from_slice(&self.priming_frames.to_be_bytes());
+            body.extend_from_slice(&self.remainder_frames.to_be_bytes());
+            if variable {
+                for &(size, frames) in &self.packets {
+                    if self.description.bytes_per_packet == 0 {
+                        write_varint(&mut body, size);
+                    }
+                    if self.description.frames_per_packet == 0 {
+                        write_varint(&mut body, frames);
+                    }
+                }
+            }
+            write_chunk(out, b"pakt", &body);
+        }
+
+        if let Some(layout) = &self.layout {
+            let mut body = Vec::new();
+            body.extend_from_slice(&CHANNEL_LAYOUT_USE_DESCRIPTIONS.to_be_bytes());
+            body.extend_from_slice(&0_u32.to_be_bytes());
+            body.extend_from_slice(&(layout.channels() as u32).to_be_bytes());
+            for label in layout.labels() {
+                body.extend_from_slice(&label.as_u32().to_be_bytes());
+                // Flags and coordinates.
+                body.extend_from_slice(&[0; CHANNEL_DESCRIPTION_SIZE - 4]);
+            }
+            write_chunk(out, b"chan", &body);
+        }
+
+        if !self.info.is_empty() {
+            let mut body = Vec::new();
+            body.extend_from_slice(&(self.info.len() as u32).to_be_bytes());
+            for (key, value) in &self.info {
+                for s in [key, value] {
+                    body.extend_from_slice(s.as_bytes());
+                    body.push(0);
+                }
+            }
+            write_chunk(out, b"info", &body);
+        }
+
+        if !self.markers.is_empty() {
+            // Names go in the strg chunk, with the marker number as ID.
+            let named: Vec<(u32, &str)> = self
+                .markers
+                .iter()
+                .enumerate()
+                .filter_map(|(i, m)| Some((i as u32 + 1, m.name.as_deref()?)))
+                .collect();
+
+            if !named.is_empty() {
+                let mut body = Vec::new();
+                let mut strings = Vec::new();
+                body.extend_from_slice(&(named.len() as u32).to_be_bytes());
+                for (id, name) in &named {
+                    body.extend_from_slice(&id.to_be_bytes());
+                    body.extend_from_slice(&(strings.len() as i64).to_be_bytes());
+                    strings.extend_from_slice(name.as_bytes());
+                    strings.push(0);
+                }
+                body.extend_from_slice(&strings);
+                write_chunk(out, b"strg", &body);
+            }
+
+            let mut body = Vec::new();
+            // No SMPTE time.
+            body.extend_from_slice(&0_u32.to_be_bytes());
+            body.extend_from_slice(&(self.markers.len() as u32).to_be_bytes());
+            for (i, marker) in self.markers.iter().enumerate() {
+                let id = if marker.name.is_some() {
+                    i as u32 + 1
+                } else {
+                    0
+                };
+                body.extend_from_slice(&marker.kind.to_be_bytes());
+                body.extend_from_slice(&marker.frame_position.to_be_bytes());
+                body.extend_from_slice(&id.to_be_bytes());
+                body.extend_from_slice(&[0; 8]);
+                body.extend_from_slice(&marker.channel.to_be_bytes());
+            }
+            write_chunk(out, b"mark", &body);
+        }
+    }
+}
+
+fn write_chunk(out: &mut Vec<u8>, id: &[u8; 4], body: &[u8]) {
+    out.extend_from_slice(id);
+    out.extend_from_slice(&(body.len() as i64).to_be_bytes());
+    out.extend_from_slice(body);
+}
+
+fn write_header(
+    w: &mut impl Write,
+    description: &FormatDescription,
+    magic_cookie: Option<&[u8]>,
+) -> std::io::Result<()> {
+    let (format_id, format_flags) = match description.format {
+        AudioFormat::LinearPCM(flags) => {
+            let mut caf_flags = 0;
+            if flags.contains(LinearPcmFlags::IS_FLOAT) {
+                caf_flags |= FLAG_IS_FLOAT;
+            }
+            if !flags.contains(LinearPcmFlags::IS_BIG_ENDIAN) {
+                caf_flags |= FLAG_IS_LITTLE_ENDIAN;
+            }
+            (LPCM, caf_flags)
+        }
+        format => {
+            let (id, flags) = format.as_format_and_flag();
+            (id, flags.unwrap_or(0))
+        }
+    };
+
+    w.write_all(b"caff")?;
+    w.write_all(&1_u16.to_be_bytes())?;
+    w.write_all(&0_u16.to_be_bytes())?;
+
+    w.write_all(b"desc")?;
+    write_u64_be(w, 32)?;
+    w.write_all(&description.sample_rate.to_be_bytes())?;
+    write_u32_be(w, format_id)?;
+    write_u32_be(w, format_flags)?;
+    write_u32_be(w, description.bytes_per_packet)?;
+    write_u32_be(w, description.frames_per_packet)?;
+    write_u32_be(w, description.channels)?;
+    write_u32_be(w, description.bits_per_channel)?;
+
+    if let Some(cookie) = magic_cookie {
+        w.write_all(b"kuki")?;
+        write_u64_be(w, cookie.len() as u64)?;
+        w.write_all(cookie)?;
+    }
+
+    w.write_all(b"data")?;
+    write_u64_be(w, u64::MAX)?;
+    // Edit count.
+    write_u32_be(w, 0)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use crate::format::Mpeg4ObjectId;
+
+    use super::*;
+
+    #[test]
+    fn write_f32_mono() {
+        let format = StreamFormat::new(44_100.0, SampleFormat::F32, LinearPcmFlags::IS_FLOAT, 1);
+
+        let mut w = CafWriter::new(Cursor::new(Vec::new()), &format).unwrap();
+        w.write_samples(&[0.5_f32, -0.5]).unwrap();
+        assert_eq!(w.frames_written(), 2);
+
+        let bytes = w.finalize().unwrap().into_inner();
+
+        assert_eq!(bytes.len(), 68 + 8);
+        assert_eq!(&bytes[0..4], b"caff");
+        assert_eq!(&bytes[8..12], b"desc");
+        assert_eq!(&bytes[20..28], &44_100.0_f64.to_be_bytes());
+        assert_eq!(&bytes[28..32], b"lpcm");
+        assert_eq!(&bytes[32..36], &3_u32.to_be_bytes());
+        assert_eq!(&bytes[52..56], b"data");
+        assert_eq!(&bytes[56..64], &12_u64.to_be_bytes());
+        assert_eq!(&bytes[68..72], &0.5_f32.to_le_bytes());
+    }
+
+    #[test]
+    fn pcm_round_trip_with_metadata() {
+        let format = StreamFormat::new(
+            48_000.0,
+            SampleFormat::I24,
+            LinearPcmFlags::IS_SIGNED_INTEGER | LinearPcmFlags::IS_BIG_ENDIAN,
+            2,
+        );
+        let description = FormatDescription::from(&format);
+
+        let mut w =
+            CafWriter::with_description(Cursor::new(Vec::new()), &description, None).unwrap();
+        w.write_samples(&[1_i16, -1, 2, -2, 3, -3]).unwrap();
+        w.set_channel_layout(&ChannelLayout::default_for(2));
+        w.add_info("title", "Test");
+        w.add_info("artist", "Nobody");
+        w.add_marker(Marker {
+            kind: 0,
+            frame_position: 1.0,
+            name: Some("start".into()),
+            channel: 0,
+        });
+        w.add_marker(Marker {
+            kind: 0,
+            frame_position: 2.5,
+            name: None,
+            channel: 1,
+        });
+        let bytes = w.finalize().unwrap().into_inner();
+
+        // Big endian 24 bit.
+        assert_eq!(&bytes[68..71], [0x00, 0x01, 0x00]);
+
+        let mut r = CafReader::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(r.description(), &description);
+        assert_eq!(r.frames(), 3);
+        assert_eq!(r.channel_layout(), Some(&ChannelLayout::default_for(2)));
+        assert_eq!(
+            r.info(),
+            [
+                ("title".to_string(), "Test".to_string()),
+                ("artist".to_string(), "Nobody".to_string())
+            ]
+        );
+        assert_eq!(r.markers().len(), 2);
+        assert_eq!(r.markers()[0].name.as_deref(), Some("start"));
+        assert_eq!(r.markers()[1].name, None);
+        assert_eq!(r.markers()[1].frame_position, 2.5);
+        assert_eq!(r.packet_table_info(), None);
+
+        r.seek(1).unwrap();
+        let mut list = AudioBufferList::<i16>::new(2, 1, 4);
+        assert_eq!(r.read_buffer_list(&mut list).unwrap(), 2);
+        assert_eq!(list[0].samples(), [2, 3, 0, 0]);
+        assert_eq!(list[1].samples(), [-2, -3, 0, 0]);
+    }
+
+    #[test]
+    fn unfinalized_file_is_readable() {
+        let format = StreamFormat::new(
+            8_000.0,
+            SampleFormat::I16,
+            LinearPcmFlags::IS_SIGNED_INTEGER,
+            1,
+        );
+        let mut w = CafWriter::new(Cursor::new(Vec::new()), &format).unwrap();
+        w.write_samples(&[5_i16, 6, 7]).unwrap();
+        // Without finalize.
+        let bytes = w.inner.into_inner();
+
+        let mut r = CafReader::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(r.frames(), 3);
+        let mut out = [0_i16; 4];
+        assert_eq!(r.read_samples(&mut out).unwrap(), 3);
+        assert_eq!(out, [5, 6, 7, 0]);
+    }
+
+    #[test]
+    fn variable_packets() {
+        let description = FormatDescription {
+            sample_rate: 44_100.0,
+            format: AudioFormat::MPEG4AAC(Mpeg4ObjectId::AAC_LC),
+            bytes_per_packet: 0,
+            frames_per_packet: 1024,
+            channels: 2,
+            bits_per_channel: 0,
+        };
+        let cookie = [0x12, 0x10];
+
+        let sizes = [3_u32, 200, 1, 70_000];
+        let data: Vec<u8> = (0..sizes.iter().sum::<u32>()).map(|i| i as u8).collect();
+        let mut descriptions = Vec::new();
+        let mut offset = 0;
+        for &size in &sizes {
+            descriptions.push(PacketDescription {
+                start_offset: offset,
+                variable_frames: 0,
+                data_byte_size: size,
+            });
+            offset += size as u64;
+        }
+
+        let mut w =
+            CafWriter::with_description(Cursor::new(Vec::new()), &description, Some(&cookie))
+                .unwrap();
+        w.set_priming(2112, 100);
+        w.write_packets(&data[..203], &descriptions[..2]).unwrap();
+        let rest: Vec<_> = descriptions[2..]
+            .iter()
+            .map(|d| PacketDescription {
+                start_offset: d.start_offset - 203,
+                ..*d
+            })
+            .collect();
+        w.write_packets(&data[203..], &rest).unwrap();
+        assert_eq!(w.frames_written(), 4096);
+        let bytes = w.finalize().unwrap().into_inner();
+
+        let mut r = CafReader::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(r.description(), &description);
+        assert_eq!(r.magic_cookie(), Some(&cookie[..]));
+        assert!(r.stream_format().is_none());
+        assert_eq!(r.packet_count(), 4);
+        assert_eq!(r.frames(), 4096);
+        assert_eq!(
+            r.packet_table_info(),
+            Some(&PacketTableInfo {
+                valid_frames: 4096 - 2112 - 100,
+                priming_frames: 2112,
+                remainder_frames: 100,
+            })
+        );
+        assert_eq!(r.packet(3), Some(descriptions[3]));
+
+        let mut read = Vec::new();
+        let mut read_descriptions = Vec::new();
+        assert_eq!(
+            r.read_packets(3, &mut read, &mut read_descriptions)
+                .unwrap(),
+            3
+        );
+        assert_eq!(read_descriptions, descriptions[..3]);
+        assert_eq!(read, data[..204]);
+
+        r.seek_packet(1).unwrap();
+        read.clear();
+        read_descriptions.clear();
+        assert_eq!(
+            r.read_packets(10, &mut read, &mut read_descriptions)
+                .unwrap(),
+            3
+        );
+        assert_eq!(read, data[3..]);
+        assert_eq!(read_descriptions[2].start_offset, 201);
+        assert_eq!(
+            r.read_packets(10, &mut read, &mut read_descriptions)
+                .unwrap(),
+            0
+        );
+
+        assert!(r.read_samples(&mut [0_i16; 2]).is_err());
+    }
+
+    #[test]
+    fn varints() {
+        for value in [0, 1, 127, 128, 16_383, 16_384, u32::MAX] {
+            let mut out = Vec::new();
+            write_varint(&mut out, value);
+            assert_eq!(read_varint(&mut ChunkReader::new(&out)).unwrap(), value);
+        }
+
+        let mut out = Vec::new();
+        write_varint(&mut out, 300);
+        assert_eq!(out, [0x82, 0x2C]);
+    }
+
+    #[test]
+    fn malformed() {
+        assert!(CafReader::new(Cursor::new(b"RIFF".to_vec())).is_err());
+
+        // Missing data chunk.
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"caff\x00\x01\x00\x00desc");
+        bytes.extend_from_slice(&32_i64.to_be_bytes());
+        bytes.extend_from_slice(&8_000.0_f64.to_be_bytes());
+        bytes.extend_from_slice(b"lpcm");
+        for value in [FLAG_IS_LITTLE_ENDIAN, 2, 1, 1, 16] {
+            bytes.extend_from_slice(&value.to_be_bytes());
+        }
+        assert!(matches!(
+            CafReader::new(Cursor::new(bytes.clone())),
+            Err(CAError::AudioFileError(AudioFileError::InvalidFile))
+        ));
+
+        // More channels than bytes in a packet.
+        let mut desc = bytes[..28].to_vec();
+        desc.extend_from_slice(b"lpcm");
+        for value in [FLAG_IS_LITTLE_ENDIAN, 2, 1, u32::MAX, 16] {
+            desc.extend_from_slice(&value.to_be_bytes());
+        }
+        desc.extend_from_slice(b"data");
+        desc.extend_from_slice(&(-1_i64).to_be_bytes());
+        desc.extend_from_slice(&[0; 8]);
+        assert!(matches!(
+            CafReader::new(Cursor::new(desc)),
+            Err(CAError::AudioFileError(AudioFileError::InvalidFile))
+        ));
+
+        // Negative data size other than unknown.
+        let mut data = bytes.clone();
+        data.extend_from_slice(b"data");
+        data.extend_from_slice(&(-2_i64).to_be_bytes());
+        data.extend_from_slice(&[0; 8]);
+        assert!(matches!(
+            CafReader::new(Cursor::new(data)),
+            Err(CAError::AudioFileError(AudioFileError::InvalidChunk))
+        ));
+
+        // Chunk larger than the file.
+        bytes.extend_from_slice(b"info");
+        bytes.extend_from_slice(&1000_i64.to_be_bytes());
+        assert!(matches!(
+            CafReader::new(Cursor::new(bytes)),
+            Err(CAError::AudioFileError(AudioFileError::InvalidChunk))
+        ));
+    }
+}
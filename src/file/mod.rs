@@ -0,0 +1,298 @@
+//! Audio file containers.
+
+use std::io::{self, Read, Write};
+
+use crate::error::AudioFileError;
+use crate::format::{PacketBuffer, PacketDescription, Sample, SampleConvert, SampleFormat};
+use crate::unit::AudioBufferList;
+use crate::CAError;
+
+pub mod adts;
+pub mod aiff;
+pub mod caf;
+pub mod mpeg;
+pub mod wav;
+
+/// Readers of the packets of a compressed stream.
+pub trait PacketReader {
+    /// Append up to `max_packets` packets to `data` and their descriptions, relative to
+    /// the start of `data`, to `descriptions`.
+    ///
+    /// Returns the number of packets read, which is 0 at the end of the stream.
+    fn read_packets(
+        &mut self,
+        max_packets: usize,
+        data: &mut Vec<u8>,
+        descriptions: &mut Vec<PacketDescription>,
+    ) -> Result<usize, CAError>;
+
+    /// Append up to `max_packets` packets to `buffer`.
+    fn read_packet_buffer(
+        &mut self,
+        max_packets: usize,
+        buffer: &mut PacketBuffer,
+    ) -> Result<usize, CAError> {
+        let (data, descriptions) = buffer.parts_mut();
+        self.read_packets(max_packets, data, descriptions)
+    }
+}
+
+/// Append samples to `out` in the sample format of a file.
+///
+/// Integers are converted through full scale 32 bit, so conversions between integer
+/// formats are exact or truncate.
+pub(crate) fn encode_samples<S: SampleConvert>(
+    samples: &[S],
+    format: SampleFormat,
+    big_endian: bool,
+    out: &mut Vec<u8>,
+) {
+    out.reserve(samples.len() * format.size_in_bytes());
+
+    for sample in samples {
+        if format == SampleFormat::F32 {
+            let bits = sample.to_f32().to_bits();
+            out.extend_from_slice(&if big_endian {
+                bits.to_be_bytes()
+            } else {
+                bits.to_le_bytes()
+            });
+            continue;
+        }
+
+        let bytes = sample.to_i32().to_be_bytes();
+        let width = format.size_in_bytes();
+
+        // Most significant bytes first.
+        let msb = &bytes[..width];
+        if big_endian {
+            out.extend_from_slice(msb);
+        } else {
+            out.extend(msb.iter().rev());
+        }
+    }
+}
+
+/// Decode samples in the sample format of a file. `bytes` must hold exactly as many
+/// samples as `out`.
+pub(crate) fn decode_samples<S: SampleConvert>(
+    bytes: &[u8],
+    format: SampleFormat,
+    big_endian: bool,
+    out: &mut [S],
+) {
+    let width = format.size_in_bytes();
+    debug_assert_eq!(bytes.len(), out.len() * width);
+
+    for (chunk, sample) in bytes.chunks_exact(width).zip(out.iter_mut()) {
+        let mut be = [0_u8; 4];
+        if big_endian {
+            be[..width].copy_from_slice(chunk);
+        } else {
+            for (b, c) in be[..width].iter_mut().zip(chunk.iter().rev()) {
+                *b = *c;
+            }
+        }
+
+        *sample = if format == SampleFormat::F32 {
+            S::from_f32(f32::from_bits(u32::from_be_bytes(be)))
+        } else {
+            S::from_i32(i32::from_be_bytes(be))
+        };
+    }
+}
+
+/// Number of frames in the buffers of a list.
+pub(crate) fn list_frames<S: Sample>(list: &AudioBufferList<S>) -> usize {
+    list.first().map_or(0, |b| b.frames())
+}
+
+/// Copy the first `frames` frames of a list to interleaved samples.
+pub(crate) fn interleave<S: Sample>(list: &AudioBufferList<S>, frames: usize) -> Vec<S> {
+    let channels: usize = list.iter().map(|b| b.channels()).sum();
+    let mut out = Vec::with_capacity(frames * channels);
+
+    for frame in 0..frames {
+        for buffer in list.iter() {
+            let c = buffer.channels();
+            out.extend_from_slice(&buffer.samples()[frame * c..(frame + 1) * c]);
+        }
+    }
+
+    out
+}
+
+/// Copy interleaved samples to the first frames of a list.
+pub(crate) fn deinterleave<S: Sample>(samples: &[S], list: &mut AudioBufferList<S>) {
+    let channels: usize = list.iter().map(|b| b.channels()).sum();
+
+    for (frame, samples) in samples.chunks_exact(channels).enumerate() {
+        let mut offset = 0;
+        for buffer in list.iter_mut() {
+            let c = buffer.channels();
+            buffer.samples_mut()[frame * c..(frame + 1) * c]
+                .clone_from_slice(&samples[offset..offset + c]);
+            offset += c;
+        }
+    }
+}
+
+/// Read as many bytes as possible, returns the number read.
+pub(crate) fn read_full(r: &mut impl Read, buf: &mut [u8]) -> Result<usize, CAError> {
+    let mut read = 0;
+    while read < buf.len() {
+        match r.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(CAError::from_io_error(e)),
+        }
+    }
+    Ok(read)
+}
+
+/// Size of the ID3v2 tag at the start of a file, from its first 10 bytes.
+pub(crate) fn id3v2_size(header: &[u8]) -> Option<usize> {
+    let header = header.get(..10)?;
+    if &header[..3] != b"ID3" || header[6..].iter().any(|b| b & 0x80 != 0) {
+        return None;
+    }
+
+    // Syncsafe, seven bits per byte.
+    let size = header[6..]
+        .iter()
+        .fold(0, |size, &b| (size << 7) | b as usize);
+    let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+    Some(10 + size + footer)
+}
+
+/// Big endian reader of the body of a chunk. Reading past the end is an invalid chunk.
+pub(crate) struct ChunkReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ChunkReader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        ChunkReader { bytes, pos: 0 }
+    }
+
+    pub(crate) fn bytes(&mut self, len: usize) -> Result<&'a [u8], CAError> {
+        let bytes = self
+            .bytes
+            .get(self.pos..self.pos.saturating_add(len))
+            .ok_or(AudioFileError::InvalidChunk)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    pub(crate) fn remaining(&self) -> &'a [u8] {
+        &self.bytes[self.pos..]
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], CAError> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, CAError> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, CAError> {
+        Ok(u16::from_be_bytes(self.array()?))
+    }
+
+    pub(crate) fn i16(&mut self) -> Result<i16, CAError> {
+        Ok(i16::from_be_bytes(self.array()?))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, CAError> {
+        Ok(u32::from_be_bytes(self.array()?))
+    }
+
+    pub(crate) fn i32(&mut self) -> Result<i32, CAError> {
+        Ok(i32::from_be_bytes(self.array()?))
+    }
+
+    pub(crate) fn i64(&mut self) -> Result<i64, CAError> {
+        Ok(i64::from_be_bytes(self.array()?))
+    }
+
+    pub(crate) fn f64(&mut self) -> Result<f64, CAError> {
+        Ok(f64::from_be_bytes(self.array()?))
+    }
+}
+
+pub(crate) fn write_u16_le(w: &mut impl Write, v: u16) -> io::Result<()> {
+    w.write_all(&v.to_le_bytes())
+}
+
+pub(crate) fn write_u32_le(w: &mut impl Write, v: u32) -> io::Result<()> {
+    w.write_all(&v.to_le_bytes())
+}
+
+pub(crate) fn write_u64_le(w: &mut impl Write, v: u64) -> io::Result<()> {
+    w.write_all(&v.to_le_bytes())
+}
+
+pub(crate) fn write_u32_be(w: &mut impl Write, v: u32) -> io::Result<()> {
+    w.write_all(&v.to_be_bytes())
+}
+
+pub(crate) fn write_u64_be(w: &mut impl Write, v: u64) -> io::Result<()> {
+    w.write_all(&v.to_be_bytes())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encode_decode() {
+        let mut out = Vec::new();
+        encode_samples(&[0x1234_i16, -2], SampleFormat::I16, false, &mut out);
+        assert_eq!(out, [0x34, 0x12, 0xfe, 0xff]);
+
+        out.clear();
+        encode_samples(&[0x0012_3456 << 8], SampleFormat::I24, true, &mut out);
+        assert_eq!(out, [0x12, 0x34, 0x56]);
+
+        let mut decoded = [0_i32; 1];
+        decode_samples(&out, SampleFormat::I24, true, &mut decoded);
+        assert_eq!(decoded, [0x0012_3456 << 8]);
+
+        out.clear();
+        encode_samples(&[0.5_f32], SampleFormat::F32, true, &mut out);
+        assert_eq!(out, 0.5_f32.to_be_bytes());
+
+        // Converting while decoding.
+        let mut as_float = [0.0_f32; 2];
+        decode_samples(
+            &[0x00, 0x40, 0x00, 0xc0],
+            SampleFormat::I16,
+            false,
+            &mut as_float,
+        );
+        assert_eq!(as_float, [0.5, -0.5]);
+    }
+
+    #[test]
+    fn interleaving() {
+        let mut list = AudioBufferList::<i16>::new(2, 1, 3);
+        deinterleave(&[1, 2, 3, 4, 5, 6], &mut list);
+        assert_eq!(list[0].samples(), [1, 3, 5]);
+        assert_eq!(list[1].samples(), [2, 4, 6]);
+        assert_eq!(interleave(&list, 2), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn id3v2() {
+        let mut header = *b"ID3\x04\x00\x00\x00\x00\x02\x01";
+        assert_eq!(id3v2_size(&header), Some(10 + 257));
+        header[5] = 0x10;
+        assert_eq!(id3v2_size(&header), Some(10 + 257 + 10));
+        header[8] = 0x80;
+        assert_eq!(id3v2_size(&header), None);
+        assert_eq!(id3v2_size(b"ID3"), None);
+    }
+}
@@ -0,0 +1,453 @@
+//! MPEG audio streams, as in `.mp3`, `.mp2` and `.mp1` files.
+
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use super::{id3v2_size, read_full, PacketReader};
+use crate::codec::mpeg::{FrameHeader, VbriHeader, XingHeader, DECODER_DELAY};
+use crate::error::AudioFileError;
+use crate::format::{FormatDescription, PacketDescription, PacketTableInfo};
+use crate::CAError;
+
+/// Reader of the packets of an MPEG audio stream.
+///
+/// The whole stream is scanned when opened, giving the offset of every frame. A leading
+/// ID3v2 tag and bytes between frames, like a trailing ID3v1 tag, are skipped. The frame
+/// with a Xing, Info or VBRI header is not a packet.
+pub struct MpegReader<R: Read + Seek> {
+    inner: R,
+    /// The header of the first packet.
+    header: FrameHeader,
+    xing: Option<XingHeader>,
+    vbri: Option<VbriHeader>,
+    /// Packets with offsets in the stream.
+    packets: Vec<PacketDescription>,
+    /// Sum of the bitrates of the packets.
+    bitrate_sum: u64,
+    /// Next packet to read.
+    position: u64,
+}
+
+impl MpegReader<BufReader<File>> {
+    /// Open the MPEG audio file at the path.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, CAError> {
+        let file = File::open(path).map_err(CAError::from_io_error)?;
+        MpegReader::new(BufReader::new(file))
+    }
+}
+
+impl<R: Read + Seek> MpegReader<R> {
+    /// Scan the frames of the stream, from the current position of `inner`.
+    pub fn new(mut inner: R) -> Result<Self, CAError> {
+        let mut offset = inner.stream_position().map_err(CAError::from_io_error)?;
+        let mut buffer = Vec::new();
+        fill(&mut inner, &mut buffer, 10)?;
+
+        if let Some(size) = id3v2_size(&buffer) {
+            offset = inner
+                .seek(SeekFrom::Start(offset + size as u64))
+                .map_err(CAError::from_io_error)?;
+            buffer.clear();
+        }
+
+        let mut first: Option<FrameHeader> = None;
+        let mut xing = None;
+        let mut vbri = None;
+        let mut packets = Vec::new();
+        let mut bitrate_sum = 0;
+
+        while fill(&mut inner, &mut buffer, FrameHeader::SIZE)? {
+            let header = match FrameHeader::parse(&buffer) {
+                Ok(header) if first.map_or(true, |first| first.matches(&header)) => header,
+                _ => {
+                    let skip = buffer[1..]
+                        .iter()
+                        .position(|&b| b == 0xFF)
+                        .map_or(buffer.len(), |at| at + 1);
+                    buffer.drain(..skip);
+                    offset += skip as u64;
+                    continue;
+                }
+            };
+
+            let size = header.frame_bytes();
+            if !fill(&mut inner, &mut buffer, size)? {
+                // A truncated last frame.
+                break;
+            }
+
+            let mut audio = true;
+            if first.is_none() {
+                // Only trust a first header followed by another one or the end.
+                fill(&mut inner, &mut buffer, size + FrameHeader::SIZE)?;
+                if let Some(next) = buffer.get(size..size + FrameHeader::SIZE) {
+                    if !FrameHeader::parse(next).is_ok_and(|next| header.matches(&next)) {
+                        buffer.drain(..1);
+                        offset += 1;
+                        continue;
+                    }
+                }
+
+                first = Some(header);
+                xing = XingHeader::parse(&header, &buffer[..size]);
+                vbri = VbriHeader::parse(&header, &buffer[..size]);
+                audio = xing.is_none() && vbri.is_none();
+            }
+
+            if audio {
+                packets.push(PacketDescription {
+                    start_offset: offset,
+                    variable_frames: 0,
+                    data_byte_size: size as u32,
+                });
+                bitrate_sum += header.bitrate as u64;
+            }
+
+            buffer.drain(..size);
+            offset += size as u64;
+        }
+
+        let Some(header) = first else {
+            return Err(AudioFileError::UnsupportedFileType.into());
+        };
+        let mut reader = MpegReader {
+            inner,
+            header,
+            xing,
+            vbri,
+            packets,
+            bitrate_sum,
+            position: 0,
+        };
+        reader.seek_packet(0)?;
+
+        Ok(reader)
+    }
+
+    pub fn description(&self) -> FormatDescription {
+        self.header.description()
+    }
+
+    /// The header of the first frame.
+    pub fn header(&self) -> &FrameHeader {
+        &self.header
+    }
+
+    /// The Xing or Info header of the first frame.
+    pub fn xing(&self) -> Option<&XingHeader> {
+        self.xing.as_ref()
+    }
+
+    /// The VBRI header of the first frame.
+    pub fn vbri(&self) -> Option<&VbriHeader> {
+        self.vbri.as_ref()
+    }
+
+    /// Encoder delay and padding from the LAME header, or the encoder delay of the VBRI
+    /// header.
+    ///
+    /// The priming frames include the delay of the decoder, as for gapless playback of
+    /// decoded Layer III. The valid frames are the rest of the frames.
+    pub fn packet_table_info(&self) -> Option<PacketTableInfo> {
+        let (delay, padding) = match (self.xing.as_ref().and_then(|x| x.lame.as_ref()), &self.vbri)
+        {
+            (Some(lame), _) => (lame.encoder_delay as u32, lame.encoder_padding as u32),
+            (None, Some(vbri)) => (vbri.delay as u32, 0),
+            (None, None) => return None,
+        };
+        let priming = delay + DECODER_DELAY;
+        let remainder = padding.saturating_sub(DECODER_DELAY);
+
+        Some(PacketTableInfo {
+            valid_frames: self.frames() as i64 - priming as i64 - remainder as i64,
+            priming_frames: priming as i32,
+            remainder_frames: remainder as i32,
+        })
+    }
+
+    pub fn packet_count(&self) -> u64 {
+        self.packets.len() as u64
+    }
+
+    /// Total number of frames in the packets, including encoder delay and padding.
+    pub fn frames(&self) -> u64 {
+        self.packet_count() * self.header.frames() as u64
+    }
+
+    /// Duration of the audio without encoder delay and padding, in seconds.
+    pub fn duration(&self) -> f64 {
+        let frames = match self.packet_table_info() {
+            Some(info) => info.valid_frames.max(0) as u64,
+            None => self.frames(),
+        };
+        frames as f64 / self.header.sample_rate as f64
+    }
+
+    /// Average bitrate of the packets, in bit/s.
+    pub fn bitrate(&self) -> u32 {
+        match self.packets.len() {
+            0 => self.header.bitrate,
+            n => (self.bitrate_sum / n as u64) as u32,
+        }
+    }
+
+    /// The next packet to be read.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Where a packet is in the stream.
+    pub fn packet(&self, index: u64) -> Option<PacketDescription> {
+        self.packets.get(index as usize).copied()
+    }
+
+    /// Every packet, with offsets in the stream.
+    pub fn packets(&self) -> &[PacketDescription] {
+        &self.packets
+    }
+
+    /// Move to a packet. Seeking past the end is an error.
+    pub fn seek_packet(&mut self, index: u64) -> Result<(), CAError> {
+        let offset = match self.packet(index) {
+            Some(packet) => packet.start_offset,
+            None if index == self.packet_count() => match self.packets.last() {
+                Some(last) => last.start_offset + last.data_byte_size as u64,
+                None => return Ok(()),
+            },
+            None => return Err(AudioFileError::Position.into()),
+        };
+
+        self.inner
+            .seek(SeekFrom::Start(offset))
+            .map_err(CAError::from_io_error)?;
+        self.position = index;
+
+        Ok(())
+    }
+
+    /// Append up to `max_packets` packets to `data` and their descriptions, relative to
+    /// the start of `data`, to `descriptions`.
+    ///
+    /// Returns the number of packets read, which is 0 at the end of the stream.
+    pub fn read_packets(
+        &mut self,
+        max_packets: usize,
+        data: &mut Vec<u8>,
+        descriptions: &mut Vec<PacketDescription>,
+    ) -> Result<usize, CAError> {
+        let start = self.position as usize;
+        let end = (start + max_packets).min(self.packets.len());
+
+        for packet in &self.packets[start..end] {
+            let at = data.len();
+            // Packets can be apart, with junk in between.
+            self.inner
+                .seek(SeekFrom::Start(packet.start_offset))
+                .map_err(CAError::from_io_error)?;
+
+            data.resize(at + packet.data_byte_size as usize, 0);
+            if read_full(&mut self.inner, &mut data[at..])? < packet.data_byte_size as usize {
+                return Err(AudioFileError::EndOfFile.into());
+            }
+
+            descriptions.push(PacketDescription {
+                start_offset: at as u64,
+                ..*packet
+            });
+        }
+
+        self.position = end as u64;
+
+        Ok(end - start)
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+/// Read until the buffer holds `len` bytes, false at the end of the stream.
+fn fill(inner: &mut impl Read, buffer: &mut Vec<u8>, len: usize) -> Result<bool, CAError> {
+    if buffer.len() < len {
+        let start = buffer.len();
+        buffer.resize(len, 0);
+        let read = read_full(inner, &mut buffer[start..])?;
+        buffer.truncate(start + read);
+    }
+    Ok(buffer.len() >= len)
+}
+
+impl<R: Read + Seek> PacketReader for MpegReader<R> {
+    fn read_packets(
+        &mut self,
+        max_packets: usize,
+        data: &mut Vec<u8>,
+        descriptions: &mut Vec<PacketDescription>,
+    ) -> Result<usize, CAError> {
+        MpegReader::read_packets(self, max_packets, data, descriptions)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::format::AudioFormat;
+    use std::io::Cursor;
+
+    /// An MPEG-1 Layer III frame at 44.1 kHz, joint stereo.
+    fn frame(bitrate_index: u8, fill: u8) -> Vec<u8> {
+        let header = [0xFF, 0xFB, bitrate_index << 4, 0x40];
+        let mut frame = vec![fill; FrameHeader::parse(&header).unwrap().frame_bytes()];
+        frame[..4].copy_from_slice(&header);
+        frame
+    }
+
+    #[test]
+    fn scan() {
+        let mut file = b"ID3\x04\x00\x00\x00\x00\x00\x03tag".to_vec();
+        file.extend_from_slice(&[0xFF, 0x00, 0x12]);
+
+        let mut xing = frame(9, 0);
+        xing[36..40].copy_from_slice(b"Xing");
+        xing[40..44].copy_from_slice(&1_u32.to_be_bytes());
+        xing[44..48].copy_from_slice(&5_u32.to_be_bytes());
+        xing[48..57].copy_from_slice(b"LAME3.100");
+        // Delay 576, padding 1000.
+        xing[69..72].copy_from_slice(&[0x24, 0x03, 0xE8]);
+        file.extend(xing);
+
+        let first = file.len() as u64;
+        for (i, bitrate_index) in [9, 10, 9, 10, 9].into_iter().enumerate() {
+            file.extend(frame(bitrate_index, i as u8 + 1));
+        }
+        file.extend_from_slice(b"TAG");
+        file.extend_from_slice(&[0; 125]);
+
+        let mut reader = MpegReader::new(Cursor::new(file)).unwrap();
+        let description = reader.description();
+        assert_eq!(description.format, AudioFormat::MPEGLayer3);
+        assert_eq!(description.sample_rate, 44100.0);
+        assert_eq!(description.frames_per_packet, 1152);
+        assert_eq!(description.channels, 2);
+
+        assert_eq!(reader.xing().unwrap().frames, Some(5));
+        assert_eq!(reader.vbri(), None);
+        assert_eq!(reader.packet_count(), 5);
+        assert_eq!(reader.frames(), 5760);
+        assert_eq!(reader.bitrate(), 140800);
+        assert_eq!(
+            reader.packet_table_info(),
+            Some(PacketTableInfo {
+                valid_frames: 4184,
+                priming_frames: 1105,
+                remainder_frames: 471,
+            })
+        );
+        assert_eq!(reader.duration(), 4184.0 / 44100.0);
+
+        let offsets: Vec<_> = reader.packets().iter().map(|p| p.start_offset).collect();
+        assert_eq!(
+            offsets,
+            [first, first + 417, first + 939, first + 1356, first + 1878]
+        );
+
+        let mut data = Vec::new();
+        let mut descriptions = Vec::new();
+        assert_eq!(
+            reader
+                .read_packets(2, &mut data, &mut descriptions)
+                .unwrap(),
+            2
+        );
+        assert_eq!(descriptions[1].start_offset, 417);
+        assert_eq!(descriptions[1].data_byte_size, 522);
+        assert_eq!(data[417..421], [0xFF, 0xFB, 0xA0, 0x40]);
+        assert_eq!(data[421], 2);
+
+        reader.seek_packet(4).unwrap();
+        assert_eq!(
+            reader
+                .read_packets(2, &mut data, &mut descriptions)
+                .unwrap(),
+            1
+        );
+        assert_eq!(data[939 + 4], 5);
+        assert_eq!(
+            reader
+                .read_packets(2, &mut data, &mut descriptions)
+                .unwrap(),
+            0
+        );
+        assert!(reader.seek_packet(6).is_err());
+    }
+
+    #[test]
+    fn cbr_without_tags() {
+        // MPEG-1 Layer II, 192 kbit/s, 48 kHz, stereo.
+        let mut frame = vec![0; 576];
+        frame[..4].copy_from_slice(&[0xFF, 0xFD, 0xA4, 0x00]);
+        let mut file = frame.repeat(3);
+        // Truncated last frame.
+        file.extend_from_slice(&frame[..100]);
+
+        let reader = MpegReader::new(Cursor::new(file)).unwrap();
+        assert_eq!(reader.description().format, AudioFormat::MPEGLayer2);
+        assert_eq!(reader.packet_count(), 3);
+        assert_eq!(reader.packet_table_info(), None);
+        assert_eq!(reader.bitrate(), 192000);
+        assert_eq!(reader.duration(), 3456.0 / 48000.0);
+
+        // A header without a following one is junk.
+        let mut file = vec![0; 100];
+        file.extend_from_slice(&frame[..4]);
+        file.extend_from_slice(&[0xFF; 600]);
+        assert!(MpegReader::new(Cursor::new(file)).is_err());
+        assert!(MpegReader::new(Cursor::new(vec![0; 1000])).is_err());
+    }
+
+    #[test]
+    fn gapless_info() {
+        let frames = |first: Vec<u8>| {
+            let mut file = first;
+            for _ in 0..3 {
+                file.extend(frame(9, 1));
+            }
+            MpegReader::new(Cursor::new(file)).unwrap()
+        };
+
+        // Padding shorter than the decoder delay.
+        let mut xing = frame(9, 0);
+        xing[36..40].copy_from_slice(b"Xing");
+        xing[40..44].copy_from_slice(&1_u32.to_be_bytes());
+        xing[44..48].copy_from_slice(&3_u32.to_be_bytes());
+        xing[48..57].copy_from_slice(b"LAME3.100");
+        // Delay 576, padding 100.
+        xing[69..72].copy_from_slice(&[0x24, 0x00, 0x64]);
+        let info = frames(xing).packet_table_info().unwrap();
+        assert_eq!(
+            info,
+            PacketTableInfo {
+                valid_frames: 2351,
+                priming_frames: 1105,
+                remainder_frames: 0,
+            }
+        );
+
+        let mut vbri = frame(9, 0);
+        vbri[36..40].copy_from_slice(b"VBRI");
+        vbri[40..42].copy_from_slice(&1_u16.to_be_bytes());
+        vbri[42..44].copy_from_slice(&576_u16.to_be_bytes());
+        vbri[50..54].copy_from_slice(&3_u32.to_be_bytes());
+        let reader = frames(vbri);
+        assert_eq!(reader.vbri().unwrap().delay, 576);
+        assert_eq!(
+            reader.packet_table_info(),
+            Some(PacketTableInfo {
+                valid_frames: 2351,
+                priming_frames: 1105,
+                remainder_frames: 0,
+            })
+        );
+    }
+}
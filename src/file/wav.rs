@@ -0,0 +1,1064 @@
+//! WAV (RIFF/WAVE) files.
+//!
+//! Supports PCM in every [`SampleFormat`], 32 bit float, DVI/Intel IMA ADPCM,
+//! WAVE_FORMAT_EXTENSIBLE channel masks and RF64 for files over 4 GB.
+//!
+//! The writer reserves room for the RF64 `ds64` chunk with a `JUNK` chunk, which is
+//! turned into `ds64` when the file grows too large for RIFF.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::codec::ima_adpcm::{ImaAdpcmDecoder, ImaAdpcmEncoder, ImaAdpcmFormat};
+use crate::error::{AudioFileError, AudioFormatError};
+use crate::format::{
+    ChannelLayout, FormatDescription, LinearPcmFlags, SampleConvert, SampleFormat, StreamFormat,
+};
+use crate::unit::AudioBufferList;
+use crate::CAError;
+
+use super::{
+    decode_samples, deinterleave, encode_samples, interleave, list_frames, read_full, write_u16_le,
+    write_u32_le, write_u64_le,
+};
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_DVI_ADPCM: u16 = 0x11;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// Largest `fmt ` chunk: a WAVEFORMATEX with the largest extra size.
+const MAX_FMT_SIZE: u32 = 18 + u16::MAX as u32;
+
+/// The sub format GUID of WAVE_FORMAT_EXTENSIBLE, after the format tag.
+const KSDATAFORMAT_SUFFIX: [u8; 14] = [
+    0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71,
+];
+
+/// Size of the `ds64` chunk body.
+const DS64_SIZE: u32 = 28;
+
+/// Offset of the `JUNK`/`ds64` chunk.
+const DS64_OFFSET: u64 = 12;
+
+/// Offset of the `fmt ` chunk.
+const FMT_OFFSET: u64 = DS64_OFFSET + 8 + DS64_SIZE as u64;
+
+/// Size written in place of a 32 bit size that is found in the `ds64` chunk.
+const RF64_SIZE: u32 = u32::MAX;
+
+fn use_extensible(format: &StreamFormat, layout: Option<&ChannelLayout>) -> bool {
+    layout.is_some() || format.channels() > 2
+}
+
+fn fmt_size(extensible: bool) -> u64 {
+    if extensible {
+        40
+    } else {
+        16
+    }
+}
+
+/// Size of the header written by [`WavWriter::new`] for the format.
+pub(crate) fn header_size(format: &StreamFormat) -> u64 {
+    FMT_OFFSET + 8 + fmt_size(use_extensible(format, None)) + 8
+}
+
+/// Size of the `fmt ` chunk body for IMA ADPCM.
+const ADPCM_FMT_SIZE: u64 = 20;
+
+/// Offset of the sample count in the `fact` chunk for IMA ADPCM.
+const ADPCM_FACT_OFFSET: u64 = FMT_OFFSET + 8 + ADPCM_FMT_SIZE + 8;
+
+/// Converts samples to i16 for the ADPCM encoder.
+fn to_i16<S: SampleConvert>(samples: &[S]) -> Vec<i16> {
+    samples.iter().map(|s| (s.to_i32() >> 16) as i16).collect()
+}
+
+/// Streaming writer of PCM or IMA ADPCM to a WAV file.
+///
+/// The chunk sizes are filled in by [`WavWriter::finalize`].
+pub struct WavWriter<W: Write + Seek> {
+    inner: W,
+    sample_format: SampleFormat,
+    channels: usize,
+    header_size: u64,
+    data_bytes: u64,
+    // With the number of frames given to it.
+    adpcm: Option<(ImaAdpcmEncoder, u64)>,
+    scratch: Vec<u8>,
+}
+
+impl WavWriter<BufWriter<File>> {
+    /// Create a WAV file at the path.
+    pub fn create(path: impl AsRef<Path>, format: &StreamFormat) -> Result<Self, CAError> {
+        let file = File::create(path).map_err(CAError::from_io_error)?;
+        WavWriter::new(BufWriter::new(file), format)
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    /// Start writing a WAV file by writing the header.
+    ///
+    /// Files with more than 2 channels are written as WAVE_FORMAT_EXTENSIBLE without
+    /// channel mask.
+    pub fn new(inner: W, format: &StreamFormat) -> Result<Self, CAError> {
+        WavWriter::start(inner, format, None)
+    }
+
+    /// Start writing a WAVE_FORMAT_EXTENSIBLE file with the channel mask of the layout.
+    ///
+    /// Fails if the layout can't be expressed as a channel mask.
+    pub fn with_channel_layout(
+        inner: W,
+        format: &StreamFormat,
+        layout: &ChannelLayout,
+    ) -> Result<Self, CAError> {
+        WavWriter::start(inner, format, Some(layout))
+    }
+
+    fn start(
+        mut inner: W,
+        format: &StreamFormat,
+        layout: Option<&ChannelLayout>,
+    ) -> Result<Self, CAError> {
+        let mask = match layout {
+            Some(layout) if layout.channels() == format.channels() => layout
+                .to_bitmap()
+                .ok_or(AudioFormatError::UnsupportedDataFormat)?,
+            Some(_) => return Err(AudioFormatError::UnsupportedDataFormat.into()),
+            None => 0,
+        };
+
+        let extensible = use_extensible(format, layout);
+
+        write_header(&mut inner, format, extensible, mask).map_err(CAError::from_io_error)?;
+
+        Ok(WavWriter {
+            inner,
+            sample_format: format.sample_format(),
+            channels: format.channels(),
+            header_size: FMT_OFFSET + 8 + fmt_size(extensible) + 8,
+            data_bytes: 0,
+            adpcm: None,
+            scratch: Vec::new(),
+        })
+    }
+
+    /// Start writing DVI/Intel IMA ADPCM in blocks of the format.
+    ///
+    /// The last block is padded by [`WavWriter::finalize`], the `fact` chunk holds the
+    /// number of frames without padding.
+    pub fn with_ima_adpcm(
+        mut inner: W,
+        sample_rate: f64,
+        format: ImaAdpcmFormat,
+    ) -> Result<Self, CAError> {
+        write_adpcm_header(&mut inner, sample_rate, &format).map_err(CAError::from_io_error)?;
+
+        Ok(WavWriter {
+            inner,
+            sample_format: SampleFormat::I16,
+            channels: format.channels(),
+            header_size: ADPCM_FACT_OFFSET + 4 + 8,
+            data_bytes: 0,
+            adpcm: Some((ImaAdpcmEncoder::new(format), 0)),
+            scratch: Vec::new(),
+        })
+    }
+
+    /// Number of whole frames written so far.
+    pub fn frames_written(&self) -> u64 {
+        match &self.adpcm {
+            Some((_, frames)) => *frames,
+            None => self.data_bytes / self.block_align(),
+        }
+    }
+
+    /// Total file size so far, including the header.
+    pub fn bytes_written(&self) -> u64 {
+        self.header_size + self.data_bytes
+    }
+
+    fn block_align(&self) -> u64 {
+        (self.sample_format.size_in_bytes() * self.channels) as u64
+    }
+
+    /// Write interleaved samples, converting them to the sample format of the file.
+    ///
+    /// IMA ADPCM is written in whole blocks, frames that don't fill a block are kept
+    /// until the next call.
+    pub fn write_samples<S: SampleConvert>(&mut self, samples: &[S]) -> Result<(), CAError> {
+        self.scratch.clear();
+
+        if let Some((encoder, frames)) = &mut self.adpcm {
+            encoder.encode_interleaved(&to_i16(samples), &mut self.scratch);
+            *frames += (samples.len() / self.channels) as u64;
+        } else {
+            encode_samples(samples, self.sample_format, false, &mut self.scratch);
+        }
+
+        if self.sample_format == SampleFormat::I8 {
+            // 8 bit WAV is unsigned.
+            for b in &mut self.scratch {
+                *b ^= 0x80;
+            }
+        }
+
+        self.inner
+            .write_all(&self.scratch)
+            .map_err(CAError::from_io_error)?;
+        self.data_bytes += self.scratch.len() as u64;
+
+        Ok(())
+    }
+
+    /// Write all frames of the buffers, which can be interleaved or not.
+    pub fn write_buffer_list<S: SampleConvert>(
+        &mut self,
+        list: &AudioBufferList<S>,
+    ) -> Result<(), CAError> {
+        self.write_samples(&interleave(list, list_frames(list)))
+    }
+
+    /// Fill in the chunk sizes and return the inner writer.
+    ///
+    /// The file is turned into RF64 if it is larger than 4 GB.
+    pub fn finalize(mut self) -> Result<W, CAError> {
+        if let Some((encoder, _)) = &mut self.adpcm {
+            self.scratch.clear();
+            encoder.flush(&mut self.scratch);
+            self.inner
+                .write_all(&self.scratch)
+                .map_err(CAError::from_io_error)?;
+            self.data_bytes += self.scratch.len() as u64;
+        }
+
+        let data_bytes = self.data_bytes;
+        let pad = data_bytes % 2;
+        let riff_size = self.header_size + data_bytes + pad - 8;
+        let frames = self.frames_written();
+        let data_size_offset = self.header_size - 4;
+        let adpcm = self.adpcm.is_some();
+
+        let patch = |w: &mut W| -> std::io::Result<()> {
+            // Chunks are word aligned.
+            if pad == 1 {
+                w.write_all(&[0])?;
+            }
+
+            if riff_size > u32::MAX as u64 {
+                w.seek(SeekFrom::Start(0))?;
+                w.write_all(b"RF64")?;
+                write_u32_le(w, RF64_SIZE)?;
+
+                w.seek(SeekFrom::Start(DS64_OFFSET))?;
+                w.write_all(b"ds64")?;
+                write_u32_le(w, DS64_SIZE)?;
+                write_u64_le(w, riff_size)?;
+                write_u64_le(w, data_bytes)?;
+                write_u64_le(w, frames)?;
+                // No table.
+                write_u32_le(w, 0)?;
+
+                w.seek(SeekFrom::Start(data_size_offset))?;
+                write_u32_le(w, RF64_SIZE)?;
+            } else {
+                w.seek(SeekFrom::Start(4))?;
+                write_u32_le(w, riff_size as u32)?;
+                w.seek(SeekFrom::Start(data_size_offset))?;
+                write_u32_le(w, data_bytes as u32)?;
+            }
+
+            if adpcm {
+                w.seek(SeekFrom::Start(ADPCM_FACT_OFFSET))?;
+                write_u32_le(w, frames.min(u32::MAX as u64) as u32)?;
+            }
+
+            w.seek(SeekFrom::End(0))?;
+            w.flush()
+        };
+
+        patch(&mut self.inner).map_err(CAError::from_io_error)?;
+
+        Ok(self.inner)
+    }
+}
+
+fn write_header(
+    w: &mut impl Write,
+    format: &StreamFormat,
+    extensible: bool,
+    mask: u32,
+) -> std::io::Result<()> {
+    let sample_format = format.sample_format();
+    let channels = format.channels() as u16;
+    let sample_rate = format.sample_rate().round() as u32;
+    let bits = sample_format.size_in_bits() as u16;
+    let block_align = channels * sample_format.size_in_bytes() as u16;
+
+    let tag = match sample_format {
+        SampleFormat::F32 => WAVE_FORMAT_IEEE_FLOAT,
+        _ => WAVE_FORMAT_PCM,
+    };
+
+    w.write_all(b"RIFF")?;
+    write_u32_le(w, 0)?;
+    w.write_all(b"WAVE")?;
+
+    // Room for ds64.
+    w.write_all(b"JUNK")?;
+    write_u32_le(w, DS64_SIZE)?;
+    w.write_all(&[0; DS64_SIZE as usize])?;
+
+    w.write_all(b"fmt ")?;
+    write_u32_le(w, fmt_size(extensible) as u32)?;
+    write_u16_le(
+        w,
+        if extensible {
+            WAVE_FORMAT_EXTENSIBLE
+        } else {
+            tag
+        },
+    )?;
+    write_u16_le(w, channels)?;
+    write_u32_le(w, sample_rate)?;
+    write_u32_le(w, sample_rate * block_align as u32)?;
+    write_u16_le(w, block_align)?;
+    write_u16_le(w, bits)?;
+
+    if extensible {
+        // Size of the extension.
+        write_u16_le(w, 22)?;
+        // Valid bits.
+        write_u16_le(w, bits)?;
+        write_u32_le(w, mask)?;
+        write_u16_le(w, tag)?;
+        w.write_all(&KSDATAFORMAT_SUFFIX)?;
+    }
+
+    w.write_all(b"data")?;
+    write_u32_le(w, 0)?;
+
+    Ok(())
+}
+
+fn write_adpcm_header(
+    w: &mut impl Write,
+    sample_rate: f64,
+    format: &ImaAdpcmFormat,
+) -> std::io::Result<()> {
+    let sample_rate = sample_rate.round() as u32;
+    let block_align = format.block_align() as u32;
+    let bytes_per_second =
+        (sample_rate as u64 * block_align as u64 / format.frames_per_block() as u64) as u32;
+
+    w.write_all(b"RIFF")?;
+    write_u32_le(w, 0)?;
+    w.write_all(b"WAVE")?;
+
+    // Room for ds64.
+    w.write_all(b"JUNK")?;
+    write_u32_le(w, DS64_SIZE)?;
+    w.write_all(&[0; DS64_SIZE as usize])?;
+
+    w.write_all(b"fmt ")?;
+    write_u32_le(w, ADPCM_FMT_SIZE as u32)?;
+    write_u16_le(w, WAVE_FORMAT_DVI_ADPCM)?;
+    write_u16_le(w, format.channels() as u16)?;
+    write_u32_le(w, sample_rate)?;
+    write_u32_le(w, bytes_per_second)?;
+    write_u16_le(w, block_align as u16)?;
+    write_u16_le(w, 4)?;
+    let extension = format.fmt_extension();
+    write_u16_le(w, extension.len() as u16)?;
+    w.write_all(&extension)?;
+
+    w.write_all(b"fact")?;
+    write_u32_le(w, 4)?;
+    write_u32_le(w, 0)?;
+
+    w.write_all(b"data")?;
+    write_u32_le(w, 0)?;
+
+    Ok(())
+}
+
+/// Streaming reader of PCM or IMA ADPCM from a WAV or RF64 file.
+pub struct WavReader<R: Read + Seek> {
+    inner: R,
+    format: StreamFormat,
+    layout: Option<ChannelLayout>,
+    adpcm: Option<AdpcmBlocks>,
+    data_start: u64,
+    frames: u64,
+    position: u64,
+    scratch: Vec<u8>,
+}
+
+/// The decoded block of an IMA ADPCM file.
+struct AdpcmBlocks {
+    decoder: ImaAdpcmDecoder,
+    // Interleaved.
+    samples: Vec<i16>,
+    frames: usize,
+    // Frames of the block that were read.
+    offset: usize,
+    // Frames to skip in the next block after seeking.
+    skip: usize,
+}
+
+impl WavReader<BufReader<File>> {
+    /// Open the WAV file at the path.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, CAError> {
+        let file = File::open(path).map_err(CAError::from_io_error)?;
+        WavReader::new(BufReader::new(file))
+    }
+}
+
+impl<R: Read + Seek> WavReader<R> {
+    /// Read the header, leaving the reader at the first frame.
+    pub fn new(mut inner: R) -> Result<Self, CAError> {
+        let header = read_header(&mut inner)?;
+
+        Ok(WavReader {
+            inner,
+            format: header.format,
+            layout: header.layout,
+            adpcm: header.adpcm.map(|format| AdpcmBlocks {
+                decoder: ImaAdpcmDecoder::new(format),
+                samples: vec![0; format.channels() * format.frames_per_block()],
+                frames: 0,
+                offset: 0,
+                skip: 0,
+            }),
+            data_start: header.data_start,
+            frames: header.frames,
+            position: 0,
+            scratch: Vec::new(),
+        })
+    }
+
+    /// The format of the samples, which is 16 bit PCM for IMA ADPCM.
+    pub fn format(&self) -> &StreamFormat {
+        &self.format
+    }
+
+    /// The format of the data in the file.
+    pub fn description(&self) -> FormatDescription {
+        match &self.adpcm {
+            Some(adpcm) => adpcm
+                .decoder
+                .format()
+                .description(self.format.sample_rate()),
+            None => FormatDescription::from(&self.format),
+        }
+    }
+
+    /// The channel layout from the WAVE_FORMAT_EXTENSIBLE channel mask, if there is one.
+    pub fn channel_layout(&self) -> Option<&ChannelLayout> {
+        self.layout.as_ref()
+    }
+
+    /// Total number of frames.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// The next frame to be read.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    fn block_align(&self) -> u64 {
+        (self.format.sample_format().size_in_bytes() * self.format.channels()) as u64
+    }
+
+    /// Move to a frame. Seeking past the end is an error.
+    pub fn seek(&mut self, frame: u64) -> Result<(), CAError> {
+        if frame > self.frames {
+            return Err(AudioFileError::Position.into());
+        }
+
+        let offset = match &mut self.adpcm {
+            Some(adpcm) => {
+                let format = adpcm.decoder.format();
+                let frames_per_block = format.frames_per_block() as u64;
+                adpcm.frames = 0;
+                adpcm.offset = 0;
+                adpcm.skip = (frame % frames_per_block) as usize;
+                frame / frames_per_block * format.block_align() as u64
+            }
+            None => frame * self.block_align(),
+        };
+
+        self.inner
+            .seek(SeekFrom::Start(self.data_start + offset))
+            .map_err(CAError::from_io_error)?;
+        self.position = frame;
+
+        Ok(())
+    }
+
+    /// Read interleaved frames, converting them to the sample type.
+    ///
+    /// Returns the number of frames read, which is 0 at the end of the file.
+    pub fn read_samples<S: SampleConvert>(&mut self, out: &mut [S]) -> Result<usize, CAError> {
+        let channels = self.format.channels();
+        let block_align = self.block_align() as usize;
+
+        let frames = ((out.len() / channels) as u64).min(self.frames - self.position) as usize;
+
+        if self.adpcm.is_some() {
+            return self.read_adpcm(&mut out[..frames * channels]);
+        }
+
+        self.scratch.resize(frames * block_align, 0);
+        let read = read_full(&mut self.inner, &mut self.scratch)?;
+
+        // The file might be shorter than its header claims.
+        let frames = read / block_align;
+        let bytes = &mut self.scratch[..frames * block_align];
+
+        let sample_format = self.format.sample_format();
+        if sample_format == SampleFormat::I8 {
+            for b in bytes.iter_mut() {
+                *b ^= 0x80;
+            }
+        }
+
+        decode_samples(bytes, sample_format, false, &mut out[..frames * channels]);
+        self.position += frames as u64;
+
+        Ok(frames)
+    }
+
+    /// Read frames into the buffers, which can be interleaved or not.
+    ///
+    /// Returns the number of frames read, the rest of the buffers is left untouched.
+    pub fn read_buffer_list<S: SampleConvert>(
+        &mut self,
+        list: &mut AudioBufferList<S>,
+    ) -> Result<usize, CAError> {
+        let mut samples = vec![S::default(); list_frames(list) * self.format.channels()];
+        let frames = self.read_samples(&mut samples)?;
+        deinterleave(&samples[..frames * self.format.channels()], list);
+        Ok(frames)
+    }
+
+    fn read_adpcm<S: SampleConvert>(&mut self, out: &mut [S]) -> Result<usize, CAError> {
+        let channels = self.format.channels();
+        let adpcm = self.adpcm.as_mut().unwrap();
+        let mut read = 0;
+
+        while read * channels < out.len() {
+            if adpcm.offset == adpcm.frames {
+                let format = adpcm.decoder.format();
+                self.scratch.resize(format.block_align(), 0);
+                let len = read_full(&mut self.inner, &mut self.scratch)?;
+                if len == 0 {
+                    // The file might be shorter than its header claims.
+                    break;
+                }
+
+                adpcm.frames = adpcm
+                    .decoder
+                    .decode_block(&self.scratch[..len], &mut adpcm.samples)?;
+                adpcm.offset = std::mem::take(&mut adpcm.skip).min(adpcm.frames);
+                continue;
+            }
+
+            let frames = (adpcm.frames - adpcm.offset).min(out.len() / channels - read);
+            let samples = &adpcm.samples[adpcm.offset * channels..];
+            for (out, &sample) in out[read * channels..(read + frames) * channels]
+                .iter_mut()
+                .zip(samples)
+            {
+                *out = S::from_i32((sample as i32) << 16);
+            }
+
+            adpcm.offset += frames;
+            read += frames;
+        }
+
+        self.position += read as u64;
+        Ok(read)
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+struct Header {
+    format: StreamFormat,
+    layout: Option<ChannelLayout>,
+    adpcm: Option<ImaAdpcmFormat>,
+    data_start: u64,
+    frames: u64,
+}
+
+fn read_header(r: &mut (impl Read + Seek)) -> Result<Header, CAError> {
+    let invalid = || CAError::from(AudioFileError::InvalidFile);
+
+    let mut riff = [0_u8; 12];
+    if read_full(r, &mut riff)? < 12 {
+        return Err(invalid());
+    }
+
+    let rf64 = match &riff[0..4] {
+        b"RIFF" => false,
+        b"RF64" => true,
+        _ => return Err(AudioFileError::UnsupportedFileType.into()),
+    };
+    if &riff[8..12] != b"WAVE" {
+        return Err(AudioFileError::UnsupportedFileType.into());
+    }
+
+    let mut ds64_data_size = None;
+    let mut fmt = None;
+    let mut fact = None;
+
+    loop {
+        let mut chunk = [0_u8; 8];
+        if read_full(r, &mut chunk)? < 8 {
+            // No data chunk.
+            return Err(invalid());
+        }
+
+        let id = &chunk[0..4];
+        let size = u32::from_le_bytes(chunk[4..8].try_into().unwrap());
+
+        match id {
+            b"ds64" if rf64 => {
+                let mut body = [0_u8; 16];
+                if size < 16 || read_full(r, &mut body)? < 16 {
+                    return Err(invalid());
+                }
+                ds64_data_size = Some(u64::from_le_bytes(body[8..16].try_into().unwrap()));
+                skip(r, size as u64 - 16, size)?;
+            }
+            b"fmt " => {
+                if size > MAX_FMT_SIZE {
+                    return Err(invalid());
+                }
+                let mut body = vec![0_u8; size as usize];
+                if read_full(r, &mut body)? < body.len() {
+                    return Err(invalid());
+                }
+                fmt = Some(parse_fmt(&body)?);
+                skip(r, 0, size)?;
+            }
+            b"fact" => {
+                let mut body = [0_u8; 4];
+                if size < 4 || read_full(r, &mut body)? < 4 {
+                    return Err(invalid());
+                }
+                fact = Some(u32::from_le_bytes(body) as u64);
+                skip(r, size as u64 - 4, size)?;
+            }
+            b"data" => {
+                let (format, layout, adpcm) = fmt.ok_or_else(invalid)?;
+
+                let data_start = r.stream_position().map_err(CAError::from_io_error)?;
+                let end = r.seek(SeekFrom::End(0)).map_err(CAError::from_io_error)?;
+                r.seek(SeekFrom::Start(data_start))
+                    .map_err(CAError::from_io_error)?;
+
+                let size = match (size, ds64_data_size) {
+                    (RF64_SIZE, Some(size)) => size,
+                    (size, _) => size as u64,
+                };
+                // Files that were never finalized have a size of 0.
+                let size = if size == 0 { end - data_start } else { size };
+                let size = size.min(end - data_start);
+
+                let frames = match adpcm {
+                    Some(adpcm) => {
+                        let block_align = adpcm.block_align() as u64;
+                        let last = adpcm.frames_in((size % block_align) as usize);
+                        let frames = size / block_align * adpcm.frames_per_block() as u64
+                            + last.unwrap_or(0) as u64;
+                        // The last block is padded.
+                        fact.map_or(frames, |fact| frames.min(fact))
+                    }
+                    None => {
+                        size / (format.sample_format().size_in_bytes() * format.channels()) as u64
+                    }
+                };
+
+                return Ok(Header {
+                    format,
+                    layout,
+                    adpcm,
+                    data_start,
+                    frames,
+                });
+            }
+            _ => skip(r, size as u64, size)?,
+        }
+    }
+}
+
+/// Skip the rest of a chunk and its padding byte.
+fn skip(r: &mut impl Seek, rest: u64, size: u32) -> Result<(), CAError> {
+    r.seek(SeekFrom::Current((rest + (size as u64 & 1)) as i64))
+        .map_err(CAError::from_io_error)?;
+    Ok(())
+}
+
+type Fmt = (StreamFormat, Option<ChannelLayout>, Option<ImaAdpcmFormat>);
+
+fn parse_fmt(body: &[u8]) -> Result<Fmt, CAError> {
+    let u16_at = |at: usize| u16::from_le_bytes([body[at], body[at + 1]]);
+    let u32_at = |at: usize| u32::from_le_bytes(body[at..at + 4].try_into().unwrap());
+
+    if body.len() < 16 {
+        return Err(AudioFileError::InvalidFile.into());
+    }
+
+    let mut tag = u16_at(0);
+    let channels = u16_at(2) as usize;
+    let sample_rate = u32_at(4) as f64;
+    let block_align = u16_at(12) as usize;
+    let bits = u16_at(14) as u32;
+
+    let mut layout = None;
+
+    if tag == WAVE_FORMAT_DVI_ADPCM {
+        let extension = match body.get(16..18) {
+            Some(size) => {
+                let size = u16::from_le_bytes([size[0], size[1]]) as usize;
+                &body[18..(18 + size).min(body.len())]
+            }
+            None => &[],
+        };
+        let adpcm =
+            ImaAdpcmFormat::from_fmt(channels as u16, block_align as u16, bits as u16, extension)?;
+        let format = StreamFormat::new(
+            sample_rate,
+            SampleFormat::I16,
+            LinearPcmFlags::IS_SIGNED_INTEGER,
+            channels,
+        );
+
+        return Ok((format, None, Some(adpcm)));
+    }
+
+    if tag == WAVE_FORMAT_EXTENSIBLE {
+        if body.len() < 40 || body[26..40] != KSDATAFORMAT_SUFFIX {
+            return Err(AudioFormatError::UnsupportedDataFormat.into());
+        }
+
+        let mask = u32_at(20);
+        if mask != 0 {
+            layout = Some(ChannelLayout::from_bitmap(mask, channels));
+        }
+
+        tag = u16_at(24);
+    }
+
+    let (sample_format, flags) = match (tag, bits) {
+        (WAVE_FORMAT_PCM, 8) => (SampleFormat::I8, LinearPcmFlags::IS_SIGNED_INTEGER),
+        (WAVE_FORMAT_PCM, 16) => (SampleFormat::I16, LinearPcmFlags::IS_SIGNED_INTEGER),
+        (WAVE_FORMAT_PCM, 24) => (SampleFormat::I24, LinearPcmFlags::IS_SIGNED_INTEGER),
+        (WAVE_FORMAT_PCM, 32) => (SampleFormat::I32, LinearPcmFlags::IS_SIGNED_INTEGER),
+        (WAVE_FORMAT_IEEE_FLOAT, 32) => (SampleFormat::F32, LinearPcmFlags::IS_FLOAT),
+        _ => return Err(AudioFormatError::UnsupportedDataFormat.into()),
+    };
+
+    if channels == 0 || block_align != channels * sample_format.size_in_bytes() {
+        return Err(AudioFileError::InvalidFile.into());
+    }
+
+    let format = StreamFormat::new(sample_rate, sample_format, flags, channels);
+
+    Ok((format, layout, None))
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use crate::format::ChannelLabel;
+
+    use super::*;
+
+    fn stereo(sample_format: SampleFormat) -> StreamFormat {
+        let flags = match sample_format {
+            SampleFormat::F32 => LinearPcmFlags::IS_FLOAT,
+            _ => LinearPcmFlags::IS_SIGNED_INTEGER,
+        };
+        StreamFormat::new(48_000.0, sample_format, flags, 2)
+    }
+
+    fn round_trip<S: SampleConvert + PartialEq>(
+        sample_format: SampleFormat,
+        samples: &[S],
+    ) -> Vec<u8> {
+        let format = stereo(sample_format);
+        let mut w = WavWriter::new(Cursor::new(Vec::new()), &format).unwrap();
+        w.write_samples(samples).unwrap();
+        let bytes = w.finalize().unwrap().into_inner();
+
+        let mut r = WavReader::new(Cursor::new(bytes.clone())).unwrap();
+        assert_eq!(r.format().sample_format(), sample_format);
+        assert_eq!(r.format().channels(), 2);
+        assert_eq!(r.format().sample_rate(), 48_000.0);
+        assert_eq!(r.frames(), samples.len() as u64 / 2);
+
+        let mut read = vec![S::default(); samples.len() + 2];
+        assert_eq!(r.read_samples(&mut read).unwrap(), samples.len() / 2);
+        assert_eq!(&read[..samples.len()], samples);
+        assert_eq!(r.read_samples(&mut read).unwrap(), 0);
+
+        bytes
+    }
+
+    #[test]
+    fn all_sample_formats() {
+        let header = header_size(&stereo(SampleFormat::I16)) as usize;
+        assert_eq!(header, 80);
+
+        let bytes = round_trip(SampleFormat::I8, &[i8::MIN, -1, 0, i8::MAX]);
+        assert_eq!(&bytes[header..], [0x00, 0x7f, 0x80, 0xff]);
+
+        let bytes = round_trip(SampleFormat::I16, &[1_i16, -1, i16::MIN, i16::MAX]);
+        assert_eq!(&bytes[header..], [1, 0, 0xff, 0xff, 0, 0x80, 0xff, 0x7f]);
+
+        // 24 bit is read and written as the upper bits of i32.
+        let bytes = round_trip(SampleFormat::I24, &[0x1234_5600_i32, -256]);
+        assert_eq!(&bytes[header..], [0x56, 0x34, 0x12, 0xff, 0xff, 0xff]);
+
+        round_trip(SampleFormat::I32, &[i32::MIN, i32::MAX, 0, 7]);
+        round_trip(SampleFormat::F32, &[0.25_f32, -0.5, 1.0, 0.0]);
+
+        // Header fields.
+        let bytes = round_trip(SampleFormat::I16, &[0_i16; 6]);
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(&bytes[4..8], &(bytes.len() as u32 - 8).to_le_bytes());
+        assert_eq!(&bytes[12..16], b"JUNK");
+        assert_eq!(&bytes[48..52], b"fmt ");
+        assert_eq!(&bytes[56..58], &WAVE_FORMAT_PCM.to_le_bytes());
+        assert_eq!(&bytes[72..76], b"data");
+        assert_eq!(&bytes[76..80], &12_u32.to_le_bytes());
+    }
+
+    #[test]
+    fn converts_and_pads() {
+        let format = StreamFormat::new(
+            8_000.0,
+            SampleFormat::I24,
+            LinearPcmFlags::IS_SIGNED_INTEGER,
+            1,
+        );
+        let mut w = WavWriter::new(Cursor::new(Vec::new()), &format).unwrap();
+        w.write_samples(&[0.5_f32]).unwrap();
+        let bytes = w.finalize().unwrap().into_inner();
+
+        // 3 bytes of data and a padding byte.
+        assert_eq!(bytes.len(), 80 + 4);
+
+        let mut r = WavReader::new(Cursor::new(bytes)).unwrap();
+        let mut out = [0_i16; 1];
+        r.read_samples(&mut out).unwrap();
+        assert_eq!(out, [0x4000]);
+    }
+
+    #[test]
+    fn buffer_lists_and_seeking() {
+        let format = stereo(SampleFormat::I16);
+
+        let mut list = AudioBufferList::<i16>::new(2, 1, 4);
+        deinterleave(&[0, 0, 1, -1, 2, -2, 3, -3], &mut list);
+
+        let mut w = WavWriter::new(Cursor::new(Vec::new()), &format).unwrap();
+        w.write_buffer_list(&list).unwrap();
+        w.write_buffer_list(&list).unwrap();
+        let bytes = w.finalize().unwrap().into_inner();
+
+        let mut r = WavReader::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(r.frames(), 8);
+
+        r.seek(6).unwrap();
+        let mut out = AudioBufferList::<i16>::new(1, 2, 4);
+        assert_eq!(r.read_buffer_list(&mut out).unwrap(), 2);
+        assert_eq!(out[0].samples(), [2, -2, 3, -3, 0, 0, 0, 0]);
+        assert_eq!(r.position(), 8);
+
+        r.seek(1).unwrap();
+        let mut out = AudioBufferList::<i16>::new(2, 1, 1);
+        r.read_buffer_list(&mut out).unwrap();
+        assert_eq!((out[0].samples(), out[1].samples()), (&[1][..], &[-1][..]));
+
+        assert!(r.seek(9).is_err());
+    }
+
+    #[test]
+    fn extensible_channel_mask() {
+        let format = StreamFormat::new(48_000.0, SampleFormat::F32, LinearPcmFlags::IS_FLOAT, 6);
+        let layout = ChannelLayout::default_for(6);
+
+        let mut w =
+            WavWriter::with_channel_layout(Cursor::new(Vec::new()), &format, &layout).unwrap();
+        w.write_samples(&[0.0_f32; 12]).unwrap();
+        let bytes = w.finalize().unwrap().into_inner();
+
+        assert_eq!(&bytes[56..58], &WAVE_FORMAT_EXTENSIBLE.to_le_bytes());
+        assert_eq!(&bytes[76..80], &0x3F_u32.to_le_bytes());
+        assert_eq!(&bytes[80..82], &WAVE_FORMAT_IEEE_FLOAT.to_le_bytes());
+
+        let r = WavReader::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(r.format().sample_format(), SampleFormat::F32);
+        assert_eq!(r.channel_layout(), Some(&layout));
+        assert_eq!(r.frames(), 2);
+
+        let swapped = ChannelLayout::new(vec![ChannelLabel::Right, ChannelLabel::Left]);
+        assert!(WavWriter::with_channel_layout(
+            Cursor::new(Vec::new()),
+            &stereo(SampleFormat::I16),
+            &swapped
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn rf64() {
+        let format = stereo(SampleFormat::I16);
+        let mut w = WavWriter::new(Cursor::new(Vec::new()), &format).unwrap();
+        w.write_samples(&[1_i16, 2, 3, 4]).unwrap();
+
+        // Pretend 5 GB were written.
+        w.data_bytes = 5 << 30;
+        let bytes = w.finalize().unwrap().into_inner();
+
+        assert_eq!(&bytes[0..4], b"RF64");
+        assert_eq!(&bytes[4..8], &u32::MAX.to_le_bytes());
+        assert_eq!(&bytes[12..16], b"ds64");
+        assert_eq!(&bytes[20..28], &((5 << 30) + 80 - 8_u64).to_le_bytes());
+        assert_eq!(&bytes[28..36], &(5_u64 << 30).to_le_bytes());
+        assert_eq!(&bytes[36..44], &((5_u64 << 30) / 4).to_le_bytes());
+        assert_eq!(&bytes[76..80], &u32::MAX.to_le_bytes());
+
+        // The data is cut short, only what is there can be read.
+        let mut r = WavReader::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(r.frames(), 2);
+        let mut out = [0_i16; 4];
+        r.read_samples(&mut out).unwrap();
+        assert_eq!(out, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn plain_riff_with_other_chunks() {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF\0\0\0\0WAVE");
+        // Odd sized chunk with padding.
+        bytes.extend_from_slice(b"LIST\x03\0\0\0abc\0");
+        bytes.extend_from_slice(b"fmt \x10\0\0\0");
+        bytes.extend_from_slice(&[1, 0, 1, 0, 0x44, 0xac, 0, 0, 0x88, 0x58, 1, 0, 2, 0, 16, 0]);
+        bytes.extend_from_slice(b"data\x04\0\0\0");
+        bytes.extend_from_slice(&[0xff, 0x7f, 0x00, 0x80]);
+
+        let mut r = WavReader::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(r.format().sample_rate(), 44_100.0);
+        assert_eq!(r.format().channels(), 1);
+        assert_eq!(r.channel_layout(), None);
+
+        let mut out = [0.0_f32; 2];
+        assert_eq!(r.read_samples(&mut out).unwrap(), 2);
+        assert_eq!(out, [i16::MAX.to_f32(), -1.0]);
+
+        let not_wav = WavReader::new(Cursor::new(b"FORM\0\0\0\0AIFF".to_vec()));
+        assert!(matches!(
+            not_wav,
+            Err(CAError::AudioFileError(AudioFileError::UnsupportedFileType))
+        ));
+    }
+
+    #[test]
+    fn ima_adpcm() {
+        let format = ImaAdpcmFormat::new(2, 64).unwrap();
+        assert_eq!(format.frames_per_block(), 57);
+
+        let samples: Vec<i16> = (0..300)
+            .flat_map(|i| {
+                let s = (i as f64 * 0.05).sin();
+                [(s * 8000.0) as i16, (s * -4000.0) as i16]
+            })
+            .collect();
+        let mut w = WavWriter::with_ima_adpcm(Cursor::new(Vec::new()), 22_050.0, format).unwrap();
+        w.write_samples(&samples[..100]).unwrap();
+        w.write_samples(&samples[100..]).unwrap();
+        assert_eq!(w.frames_written(), 300);
+        let bytes = w.finalize().unwrap().into_inner();
+
+        // 6 blocks, the last one padded.
+        let header = (ADPCM_FACT_OFFSET + 12) as usize;
+        assert_eq!(bytes.len(), header + 6 * 64);
+        assert_eq!(&bytes[56..58], &WAVE_FORMAT_DVI_ADPCM.to_le_bytes());
+        assert_eq!(&bytes[74..76], &57_u16.to_le_bytes());
+        assert_eq!(&bytes[76..80], b"fact");
+        assert_eq!(&bytes[84..88], &300_u32.to_le_bytes());
+
+        let mut r = WavReader::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(r.format().sample_format(), SampleFormat::I16);
+        assert_eq!(r.description(), format.description(22_050.0));
+        assert_eq!(r.frames(), 300);
+
+        let mut decoded = vec![0_i16; 700];
+        assert_eq!(r.read_samples(&mut decoded).unwrap(), 300);
+        assert_eq!(r.read_samples(&mut decoded).unwrap(), 0);
+        for (&a, &b) in samples.iter().zip(&decoded) {
+            assert!((a - b).abs() < 1500, "{a} {b}");
+        }
+
+        // Seeking into the middle of a block.
+        r.seek(60).unwrap();
+        let mut out = AudioBufferList::<i16>::new(2, 1, 3);
+        assert_eq!(r.read_buffer_list(&mut out).unwrap(), 3);
+        assert_eq!(out[0].samples(), [decoded[120], decoded[122], decoded[124]]);
+        assert_eq!(out[1].samples(), [decoded[121], decoded[123], decoded[125]]);
+        assert_eq!(r.position(), 63);
+    }
+
+    #[test]
+    fn ima_adpcm_short_last_block() {
+        // Mono, 8 bytes per block, without wSamplesPerBlock and fact.
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF\0\0\0\0WAVE");
+        bytes.extend_from_slice(b"fmt \x12\0\0\0");
+        bytes.extend_from_slice(&[
+            0x11, 0, 1, 0, 0x40, 0x1f, 0, 0, 0, 0, 0, 0, 8, 0, 4, 0, 0, 0,
+        ]);
+        bytes.extend_from_slice(b"data\x0c\0\0\0");
+        bytes.extend_from_slice(&[0xE8, 0x03, 0x00, 0x00, 0x77, 0x77, 0x77, 0x77]);
+        bytes.extend_from_slice(&[0x10, 0x00, 0x00, 0x00]);
+
+        let mut r = WavReader::new(Cursor::new(bytes.clone())).unwrap();
+        assert_eq!(r.frames(), 10);
+        let mut out = [0_i16; 12];
+        assert_eq!(r.read_samples(&mut out).unwrap(), 10);
+        assert_eq!(out[..4], [1000, 1011, 1041, 1104]);
+        assert_eq!(out[9], 16);
+
+        // Step index out of range.
+        bytes[48] = 89;
+        let mut r = WavReader::new(Cursor::new(bytes.clone())).unwrap();
+        assert!(r.read_samples(&mut out).is_err());
+
+        // Block align that doesn't fit the channels.
+        bytes[32] = 6;
+        assert!(WavReader::new(Cursor::new(bytes)).is_err());
+    }
+
+    #[test]
+    fn oversized_fmt() {
+        let mut bytes = b"RIFF\0\0\0\0WAVEfmt \xF0\xFF\xFF\xFF".to_vec();
+        bytes.extend_from_slice(&[0; 16]);
+        assert!(matches!(
+            WavReader::new(Cursor::new(bytes)),
+            Err(CAError::AudioFileError(AudioFileError::InvalidFile))
+        ));
+    }
+}
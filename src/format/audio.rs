@@ -3,6 +3,7 @@
 //! See the Core Audio Data Types Reference
 //! [here](https://developer.apple.com/library/mac/documentation/MusicAudio/Reference/CoreAudioDataTypesRef/#//apple_ref/doc/constant_group/Audio_Data_Format_Identifiers) for more info.
 
+use std::fmt;
 use std::os::raw::c_uint;
 
 use bitflags::bitflags;
@@ -170,65 +171,92 @@ pub enum AudioFormat {
     /// Adopted into MXF and MPEG-2 containers and SDTI transport streams with SMPTE specs
     /// 203M-2002 and 331M-2000.
     AES3, // = 1634038579,
+    /// Free Lossless Audio Codec, the flags give the bit depth of the source data.
+    ///
+    /// **Available** in OS X v10.13 and later.
+    FLAC(AppleLosslessFlags), // = 1718378851,
+    /// Opus codec.
+    ///
+    /// **Available** in OS X v10.13 and later.
+    Opus, // = 1869641075,
+    /// Enhanced AC-3, also known as E-AC-3 or Dolby Digital Plus.
+    ///
+    /// **Available** in OS X v10.11 and later.
+    EnhancedAC3, // = 1700998451,
+    /// Dolby AC-4.
+    AC4, // = 1633889588,
+    /// MPEG-D Unified Speech and Audio Coding.
+    MPEGD_USAC, // = 1970495843,
+    /// Apple Positional Audio Codec.
+    APAC, // = 1634754915,
+    /// A format without a variant, with its format ID and flags.
+    Unknown(FourCC, u32),
 }
 
 impl AudioFormat {
     /// Convert from the FFI C format and flags to a typesafe Rust enum representation.
+    ///
+    /// Missing flags are taken as 0. This never returns `None`, see
+    /// [`AudioFormat::from_id_and_flags`].
     pub fn from_format_and_flag(format: c_uint, flag: Option<u32>) -> Option<AudioFormat> {
-        match (format, flag) {
-            (1819304813, Some(i)) => Some(AudioFormat::LinearPCM(
-                LinearPcmFlags::from_bits_truncate(i),
-            )),
-            (1633889587, _) => Some(AudioFormat::AC3),
-            (1667326771, Some(i)) => {
-                Some(AudioFormat::F60958AC3(StandardFlags::from_bits_truncate(i)))
-            }
-            (1768775988, _) => Some(AudioFormat::AppleIMA4),
-            (1633772320, Some(i)) => Some(AudioFormat::MPEG4AAC(
-                Mpeg4ObjectId::from_u32(i).expect("Unknown Mpeg4ObjectId"),
-            )),
-            (1667591280, Some(i)) => Some(AudioFormat::MPEG4CELP(
-                Mpeg4ObjectId::from_u32(i).expect("Unknown Mpeg4ObjectId"),
-            )),
-            (1752594531, Some(i)) => Some(AudioFormat::MPEG4HVXC(
-                Mpeg4ObjectId::from_u32(i).expect("Unknown Mpeg4ObjectId"),
-            )),
-            (1953986161, Some(i)) => Some(AudioFormat::MPEG4TwinVQ(
-                Mpeg4ObjectId::from_u32(i).expect("Unknown Mpeg4ObjectId"),
-            )),
-            (1296122675, _) => Some(AudioFormat::MACE3),
-            (1296122678, _) => Some(AudioFormat::MACE6),
-            (1970037111, _) => Some(AudioFormat::ULaw),
-            (1634492791, _) => Some(AudioFormat::ALaw),
-            (1363430723, _) => Some(AudioFormat::QDesign),
-            (1363430706, _) => Some(AudioFormat::QDesign2),
-            (1365470320, _) => Some(AudioFormat::QUALCOMM),
-            (778924081, _) => Some(AudioFormat::MPEGLayer1),
-            (778924082, _) => Some(AudioFormat::MPEGLayer2),
-            (778924083, _) => Some(AudioFormat::MPEGLayer3),
-            (1953066341, Some(i)) => Some(AudioFormat::TimeCode(
-                AudioTimeStampFlags::from_bits_truncate(i),
-            )),
-            (1835623529, _) => Some(AudioFormat::MIDIStream),
-            (1634760307, _) => Some(AudioFormat::ParameterValueStream),
-            (1634492771, Some(i)) => Some(AudioFormat::AppleLossless(
-                AppleLosslessFlags::from_bits_truncate(i),
-            )),
-            (1633772392, _) => Some(AudioFormat::MPEG4AAC_HE),
-            (1633772396, _) => Some(AudioFormat::MPEG4AAC_LD),
-            (1633772389, _) => Some(AudioFormat::MPEG4AAC_ELD),
-            (1633772390, _) => Some(AudioFormat::MPEG4AAC_ELD_SBR),
-            (1633772391, _) => Some(AudioFormat::MPEG4AAC_ELD_V2),
-            (1633772400, _) => Some(AudioFormat::MPEG4AAC_HE_V2),
-            (1633772403, _) => Some(AudioFormat::MPEG4AAC_Spatial),
-            (1935764850, _) => Some(AudioFormat::AMR),
-            (1935767394, _) => Some(AudioFormat::AMR_WB),
-            (1096107074, _) => Some(AudioFormat::Audible),
-            (1768710755, _) => Some(AudioFormat::iLBC),
-            (1836253201, _) => Some(AudioFormat::DVIIntelIMA),
-            (1836253233, _) => Some(AudioFormat::MicrosoftGSM),
-            (1634038579, _) => Some(AudioFormat::AES3),
-            _ => None,
+        Some(AudioFormat::from_id_and_flags(format, flag.unwrap_or(0)))
+    }
+
+    /// Convert from a format ID and its flags.
+    ///
+    /// Formats without a variant, and MPEG-4 formats with an unknown object type, are
+    /// [`AudioFormat::Unknown`].
+    pub fn from_id_and_flags(format: c_uint, flags: u32) -> AudioFormat {
+        let mpeg4 = |variant: fn(Mpeg4ObjectId) -> AudioFormat| match Mpeg4ObjectId::from_u32(flags)
+        {
+            Some(id) => variant(id),
+            None => AudioFormat::Unknown(FourCC(format), flags),
+        };
+
+        match format {
+            1819304813 => AudioFormat::LinearPCM(LinearPcmFlags::from_bits_truncate(flags)),
+            1633889587 => AudioFormat::AC3,
+            1667326771 => AudioFormat::F60958AC3(StandardFlags::from_bits_truncate(flags)),
+            1768775988 => AudioFormat::AppleIMA4,
+            1633772320 => mpeg4(AudioFormat::MPEG4AAC),
+            1667591280 => mpeg4(AudioFormat::MPEG4CELP),
+            1752594531 => mpeg4(AudioFormat::MPEG4HVXC),
+            1953986161 => mpeg4(AudioFormat::MPEG4TwinVQ),
+            1296122675 => AudioFormat::MACE3,
+            1296122678 => AudioFormat::MACE6,
+            1970037111 => AudioFormat::ULaw,
+            1634492791 => AudioFormat::ALaw,
+            1363430723 => AudioFormat::QDesign,
+            1363430706 => AudioFormat::QDesign2,
+            1365470320 => AudioFormat::QUALCOMM,
+            778924081 => AudioFormat::MPEGLayer1,
+            778924082 => AudioFormat::MPEGLayer2,
+            778924083 => AudioFormat::MPEGLayer3,
+            1953066341 => AudioFormat::TimeCode(AudioTimeStampFlags::from_bits_truncate(flags)),
+            1835623529 => AudioFormat::MIDIStream,
+            1634760307 => AudioFormat::ParameterValueStream,
+            1634492771 => AudioFormat::AppleLossless(AppleLosslessFlags::from_bits_truncate(flags)),
+            1633772392 => AudioFormat::MPEG4AAC_HE,
+            1633772396 => AudioFormat::MPEG4AAC_LD,
+            1633772389 => AudioFormat::MPEG4AAC_ELD,
+            1633772390 => AudioFormat::MPEG4AAC_ELD_SBR,
+            1633772391 => AudioFormat::MPEG4AAC_ELD_V2,
+            1633772400 => AudioFormat::MPEG4AAC_HE_V2,
+            1633772403 => AudioFormat::MPEG4AAC_Spatial,
+            1935764850 => AudioFormat::AMR,
+            1935767394 => AudioFormat::AMR_WB,
+            1096107074 => AudioFormat::Audible,
+            1768710755 => AudioFormat::iLBC,
+            1836253201 => AudioFormat::DVIIntelIMA,
+            1836253233 => AudioFormat::MicrosoftGSM,
+            1634038579 => AudioFormat::AES3,
+            1718378851 => AudioFormat::FLAC(AppleLosslessFlags::from_bits_truncate(flags)),
+            1869641075 => AudioFormat::Opus,
+            1700998451 => AudioFormat::EnhancedAC3,
+            1633889588 => AudioFormat::AC4,
+            1970495843 => AudioFormat::MPEGD_USAC,
+            1634754915 => AudioFormat::APAC,
+            _ => AudioFormat::Unknown(FourCC(format), flags),
         }
     }
 
@@ -271,10 +299,61 @@ impl AudioFormat {
             AudioFormat::DVIIntelIMA => (1836253201, None),
             AudioFormat::MicrosoftGSM => (1836253233, None),
             AudioFormat::AES3 => (1634038579, None),
+            AudioFormat::FLAC(flag) => (1718378851, Some(flag.bits())),
+            AudioFormat::Opus => (1869641075, None),
+            AudioFormat::EnhancedAC3 => (1700998451, None),
+            AudioFormat::AC4 => (1633889588, None),
+            AudioFormat::MPEGD_USAC => (1970495843, None),
+            AudioFormat::APAC => (1634754915, None),
+            AudioFormat::Unknown(format, flag) => (format.0, Some(flag)),
+        }
+    }
+}
+
+/// A four char code, like the format IDs of **AudioFormat**.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FourCC(pub u32);
+
+impl FourCC {
+    pub const fn new(code: &[u8; 4]) -> Self {
+        FourCC(u32::from_be_bytes(*code))
+    }
+
+    pub fn to_bytes(self) -> [u8; 4] {
+        self.0.to_be_bytes()
+    }
+}
+
+impl From<u32> for FourCC {
+    fn from(code: u32) -> Self {
+        FourCC(code)
+    }
+}
+
+impl From<FourCC> for u32 {
+    fn from(code: FourCC) -> Self {
+        code.0
+    }
+}
+
+/// The code as four chars if they are printable, like `'lpcm'`, in hex otherwise.
+impl fmt::Display for FourCC {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes = self.to_bytes();
+        if bytes.iter().all(|b| b.is_ascii_graphic() || *b == b' ') {
+            write!(f, "'{}'", String::from_utf8_lossy(&bytes))
+        } else {
+            write!(f, "{:#010x}", self.0)
         }
     }
 }
 
+impl fmt::Debug for FourCC {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "FourCC({self})")
+    }
+}
+
 bitflags! {
     /// Standard flags for use in the **F60958AC3** **AudioFormat** variant.
     ///
@@ -393,7 +472,7 @@ bitflags! {
 }
 
 bitflags! {
-    /// Flags set for Apple Lossless data.
+    /// Flags set for Apple Lossless and FLAC data.
     ///
     /// **Available** in OS X v10.3 and later.
     ///
@@ -492,3 +571,57 @@ bitflags! {
         const SMPTE_TIME_VALID = 16;
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn modern_formats() {
+        let flac = AudioFormat::from_id_and_flags(u32::from_be_bytes(*b"flac"), 3);
+        assert_eq!(
+            flac,
+            AudioFormat::FLAC(AppleLosslessFlags::BIT_24_SOURCE_DATA)
+        );
+
+        for (code, format) in [
+            (b"opus", AudioFormat::Opus),
+            (b"ec-3", AudioFormat::EnhancedAC3),
+            (b"ac-4", AudioFormat::AC4),
+            (b"usac", AudioFormat::MPEGD_USAC),
+            (b"apac", AudioFormat::APAC),
+        ] {
+            let code = FourCC::new(code);
+            assert_eq!(AudioFormat::from_id_and_flags(code.into(), 0), format);
+            assert_eq!(format.as_format_and_flag(), (code.0, None));
+        }
+    }
+
+    #[test]
+    fn unknown_formats() {
+        let code = FourCC::new(b"xyz1");
+        let format = AudioFormat::from_id_and_flags(code.into(), 7);
+        assert_eq!(format, AudioFormat::Unknown(code, 7));
+        assert_eq!(format.as_format_and_flag(), (code.0, Some(7)));
+
+        // An MPEG-4 object type without an Mpeg4ObjectId.
+        let aac = u32::from_be_bytes(*b"aac ");
+        assert_eq!(
+            AudioFormat::from_id_and_flags(aac, 42),
+            AudioFormat::Unknown(FourCC(aac), 42)
+        );
+        // Missing flags.
+        assert_eq!(
+            AudioFormat::from_format_and_flag(aac, None),
+            Some(AudioFormat::Unknown(FourCC(aac), 0))
+        );
+        assert_eq!(
+            AudioFormat::from_format_and_flag(1819304813, None),
+            Some(AudioFormat::LinearPCM(LinearPcmFlags::empty()))
+        );
+
+        assert_eq!(code.to_string(), "'xyz1'");
+        assert_eq!(FourCC(1).to_string(), "0x00000001");
+        assert_eq!(format!("{code:?}"), "FourCC('xyz1')");
+    }
+}
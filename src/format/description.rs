@@ -0,0 +1,139 @@
+use super::{AudioFormat, LinearPcmFlags, SampleFormat, StreamFormat};
+
+/// Description of a stream in any format, with the fields of an
+/// AudioStreamBasicDescription.
+///
+/// Unlike [`StreamFormat`] this can describe compressed formats.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FormatDescription {
+    pub sample_rate: f64,
+    pub format: AudioFormat,
+    /// 0 if packets vary in size.
+    pub bytes_per_packet: u32,
+    /// 0 if packets vary in the number of frames.
+    pub frames_per_packet: u32,
+    pub channels: u32,
+    /// 0 for compressed formats.
+    pub bits_per_channel: u32,
+}
+
+impl FormatDescription {
+    /// Whether packets need packet descriptions because they vary in size or length.
+    pub fn has_variable_packets(&self) -> bool {
+        self.bytes_per_packet == 0 || self.frames_per_packet == 0
+    }
+
+    /// The description as a [`StreamFormat`], for linear PCM in a sample format of the
+    /// crate.
+    pub fn stream_format(&self) -> Option<StreamFormat> {
+        let AudioFormat::LinearPCM(flags) = self.format else {
+            return None;
+        };
+
+        let sample_format =
+            SampleFormat::from_flags_and_bits_per_sample(flags, self.bits_per_channel)?;
+
+        // Checked, as the channel count may come straight from a file.
+        let bytes_per_sample = sample_format.size_in_bytes() as u32;
+        let bytes_per_packet = if flags.contains(LinearPcmFlags::IS_NON_INTERLEAVED) {
+            bytes_per_sample
+        } else {
+            bytes_per_sample.checked_mul(self.channels)?
+        };
+        if self.frames_per_packet != 1 || bytes_per_packet != self.bytes_per_packet {
+            return None;
+        }
+
+        Some(StreamFormat::new(
+            self.sample_rate,
+            sample_format,
+            flags,
+            self.channels as usize,
+        ))
+    }
+
+    pub(crate) fn as_sys_asbd(&self) -> sys::AudioStreamBasicDescription {
+        let (format, flags) = self.format.as_format_and_flag();
+
+        // Only linear PCM has frames of a fixed size.
+        let bytes_per_frame = match self.format {
+            AudioFormat::LinearPCM(_) if self.frames_per_packet > 0 => {
+                self.bytes_per_packet / self.frames_per_packet
+            }
+            _ => 0,
+        };
+
+        sys::AudioStreamBasicDescription {
+            mSampleRate: self.sample_rate,
+            mFormatID: format,
+            mFormatFlags: flags.unwrap_or(0),
+            mBytesPerPacket: self.bytes_per_packet,
+            mFramesPerPacket: self.frames_per_packet,
+            mBytesPerFrame: bytes_per_frame,
+            mChannelsPerFrame: self.channels,
+            mBitsPerChannel: self.bits_per_channel,
+            mReserved: 0,
+        }
+    }
+}
+
+impl From<&StreamFormat> for FormatDescription {
+    fn from(format: &StreamFormat) -> Self {
+        let asbd = format.as_sys_asbd();
+
+        FormatDescription {
+            sample_rate: asbd.mSampleRate,
+            format: AudioFormat::LinearPCM(format.flags()),
+            bytes_per_packet: asbd.mBytesPerPacket,
+            frames_per_packet: asbd.mFramesPerPacket,
+            channels: asbd.mChannelsPerFrame,
+            bits_per_channel: asbd.mBitsPerChannel,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::format::LinearPcmFlags;
+
+    use super::*;
+
+    #[test]
+    fn stream_format_round_trip() {
+        let format = StreamFormat::new(
+            44_100.0,
+            SampleFormat::I24,
+            LinearPcmFlags::IS_SIGNED_INTEGER | LinearPcmFlags::IS_BIG_ENDIAN,
+            2,
+        );
+        let description = FormatDescription::from(&format);
+        assert_eq!(description.bytes_per_packet, 6);
+        assert!(!description.has_variable_packets());
+
+        let asbd = description.as_sys_asbd();
+        let expected = format.as_sys_asbd();
+        assert_eq!(asbd.mBytesPerFrame, expected.mBytesPerFrame);
+        assert_eq!(asbd.mFormatFlags, expected.mFormatFlags);
+
+        let back = description.stream_format().unwrap();
+        assert_eq!(back.sample_format(), SampleFormat::I24);
+        assert!(back.flags().contains(LinearPcmFlags::IS_BIG_ENDIAN));
+
+        let overflowing = FormatDescription {
+            channels: u32::MAX,
+            ..description
+        };
+        assert!(overflowing.stream_format().is_none());
+
+        let aac = FormatDescription {
+            format: AudioFormat::MPEG4AAC_HE,
+            bytes_per_packet: 0,
+            frames_per_packet: 2048,
+            bits_per_channel: 0,
+            ..description
+        };
+        assert!(aac.has_variable_packets());
+        assert!(aac.stream_format().is_none());
+        assert_eq!(aac.as_sys_asbd().mBytesPerFrame, 0);
+    }
+}
@@ -0,0 +1,257 @@
+//! What formats are like, their names and how their packets are laid out.
+
+use super::AudioFormat;
+
+/// What a format is like, whatever the stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FormatInfo {
+    /// Human readable name, like `Apple Lossless`.
+    pub name: &'static str,
+    /// Typical frames in a packet, `None` if it depends on the stream.
+    pub frames_per_packet: Option<u32>,
+    /// Every packet of a stream has the same size, so packets need no descriptions.
+    pub constant_bytes_per_packet: bool,
+    /// Streams can't be decoded without a magic cookie.
+    pub magic_cookie_required: bool,
+    /// Data is coded into packets rather than stored as samples.
+    pub compressed: bool,
+    /// Decoding gives back exactly what was encoded.
+    pub lossless: bool,
+}
+
+impl FormatInfo {
+    const fn new(name: &'static str, frames_per_packet: Option<u32>) -> Self {
+        FormatInfo {
+            name,
+            frames_per_packet,
+            constant_bytes_per_packet: false,
+            magic_cookie_required: false,
+            compressed: true,
+            lossless: false,
+        }
+    }
+
+    const fn constant(mut self) -> Self {
+        self.constant_bytes_per_packet = true;
+        self
+    }
+
+    const fn cookie(mut self) -> Self {
+        self.magic_cookie_required = true;
+        self
+    }
+
+    const fn lossless(mut self) -> Self {
+        self.lossless = true;
+        self
+    }
+
+    /// Samples or data that isn't coded, like linear PCM.
+    const fn uncompressed(mut self) -> Self {
+        self.compressed = false;
+        self.lossless = true;
+        self
+    }
+}
+
+impl AudioFormat {
+    /// What the format is like. Unknown formats are taken as compressed and lossy.
+    pub fn info(&self) -> FormatInfo {
+        use FormatInfo as I;
+
+        match self {
+            AudioFormat::LinearPCM(_) => I::new("Linear PCM", Some(1)).constant().uncompressed(),
+            // Frames at 44.1 kHz alternate in size.
+            AudioFormat::AC3 => I::new("AC-3", Some(1536)),
+            AudioFormat::F60958AC3(_) => I::new("AC-3 over IEC 60958", Some(1536)).constant(),
+            AudioFormat::AppleIMA4 => I::new("IMA 4:1 ADPCM", Some(64)).constant(),
+            AudioFormat::MPEG4AAC(_) => I::new("AAC", Some(1024)).cookie(),
+            AudioFormat::MPEG4CELP(_) => I::new("MPEG-4 CELP", None).cookie(),
+            AudioFormat::MPEG4HVXC(_) => I::new("MPEG-4 HVXC", None).cookie(),
+            AudioFormat::MPEG4TwinVQ(_) => I::new("MPEG-4 TwinVQ", None).cookie(),
+            AudioFormat::MACE3 => I::new("MACE 3:1", Some(6)).constant(),
+            AudioFormat::MACE6 => I::new("MACE 6:1", Some(6)).constant(),
+            AudioFormat::ULaw => I::new("µ-law", Some(1)).constant(),
+            AudioFormat::ALaw => I::new("A-law", Some(1)).constant(),
+            AudioFormat::QDesign => I::new("QDesign Music", None).cookie(),
+            AudioFormat::QDesign2 => I::new("QDesign Music 2", None).cookie(),
+            AudioFormat::QUALCOMM => I::new("QUALCOMM PureVoice", Some(160)),
+            AudioFormat::MPEGLayer1 => I::new("MPEG Layer I", Some(384)),
+            AudioFormat::MPEGLayer2 => I::new("MPEG Layer II", Some(1152)),
+            AudioFormat::MPEGLayer3 => I::new("MPEG Layer III", Some(1152)),
+            AudioFormat::TimeCode(_) => I::new("Time code", None).constant().uncompressed(),
+            AudioFormat::MIDIStream => I::new("MIDI stream", None).uncompressed(),
+            AudioFormat::ParameterValueStream => I::new("Parameter value stream", Some(1))
+                .constant()
+                .uncompressed(),
+            AudioFormat::AppleLossless(_) => {
+                I::new("Apple Lossless", Some(4096)).cookie().lossless()
+            }
+            AudioFormat::MPEG4AAC_HE => I::new("HE-AAC", Some(2048)).cookie(),
+            AudioFormat::MPEG4AAC_LD => I::new("AAC LD", Some(512)).cookie(),
+            AudioFormat::MPEG4AAC_ELD => I::new("AAC ELD", Some(512)).cookie(),
+            AudioFormat::MPEG4AAC_ELD_SBR => I::new("AAC ELD with SBR", Some(1024)).cookie(),
+            AudioFormat::MPEG4AAC_ELD_V2 => I::new("AAC ELD v2", Some(512)).cookie(),
+            AudioFormat::MPEG4AAC_HE_V2 => I::new("HE-AAC v2", Some(2048)).cookie(),
+            AudioFormat::MPEG4AAC_Spatial => I::new("AAC with MPEG Surround", None).cookie(),
+            AudioFormat::AMR => I::new("AMR", Some(160)),
+            AudioFormat::AMR_WB => I::new("AMR-WB", Some(320)),
+            AudioFormat::Audible => I::new("Audible", None),
+            // 20 ms mode, 30 ms packets have 240 frames.
+            AudioFormat::iLBC => I::new("iLBC", Some(160)).constant(),
+            AudioFormat::DVIIntelIMA => I::new("IMA ADPCM", None).constant(),
+            AudioFormat::MicrosoftGSM => I::new("GSM 6.10", Some(320)).constant(),
+            AudioFormat::AES3 => I::new("AES3", Some(1)).constant().uncompressed(),
+            AudioFormat::FLAC(_) => I::new("FLAC", Some(4096)).cookie().lossless(),
+            AudioFormat::Opus => I::new("Opus", Some(960)).cookie(),
+            AudioFormat::EnhancedAC3 => I::new("E-AC-3", Some(1536)),
+            AudioFormat::AC4 => I::new("AC-4", None).cookie(),
+            AudioFormat::MPEGD_USAC => I::new("USAC", None).cookie(),
+            AudioFormat::APAC => I::new("APAC", None).cookie(),
+            AudioFormat::Unknown(..) => I::new("Unknown", None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::format::*;
+
+    /// The row of a format in the table of `every_format`, in the order of the variants,
+    /// so a new variant doesn't build until it has a row.
+    fn row(format: &AudioFormat) -> usize {
+        match format {
+            AudioFormat::LinearPCM(_) => 0,
+            AudioFormat::AC3 => 1,
+            AudioFormat::F60958AC3(_) => 2,
+            AudioFormat::AppleIMA4 => 3,
+            AudioFormat::MPEG4AAC(_) => 4,
+            AudioFormat::MPEG4CELP(_) => 5,
+            AudioFormat::MPEG4HVXC(_) => 6,
+            AudioFormat::MPEG4TwinVQ(_) => 7,
+            AudioFormat::MACE3 => 8,
+            AudioFormat::MACE6 => 9,
+            AudioFormat::ULaw => 10,
+            AudioFormat::ALaw => 11,
+            AudioFormat::QDesign => 12,
+            AudioFormat::QDesign2 => 13,
+            AudioFormat::QUALCOMM => 14,
+            AudioFormat::MPEGLayer1 => 15,
+            AudioFormat::MPEGLayer2 => 16,
+            AudioFormat::MPEGLayer3 => 17,
+            AudioFormat::TimeCode(_) => 18,
+            AudioFormat::MIDIStream => 19,
+            AudioFormat::ParameterValueStream => 20,
+            AudioFormat::AppleLossless(_) => 21,
+            AudioFormat::MPEG4AAC_HE => 22,
+            AudioFormat::MPEG4AAC_LD => 23,
+            AudioFormat::MPEG4AAC_ELD => 24,
+            AudioFormat::MPEG4AAC_ELD_SBR => 25,
+            AudioFormat::MPEG4AAC_ELD_V2 => 26,
+            AudioFormat::MPEG4AAC_HE_V2 => 27,
+            AudioFormat::MPEG4AAC_Spatial => 28,
+            AudioFormat::AMR => 29,
+            AudioFormat::AMR_WB => 30,
+            AudioFormat::Audible => 31,
+            AudioFormat::iLBC => 32,
+            AudioFormat::DVIIntelIMA => 33,
+            AudioFormat::MicrosoftGSM => 34,
+            AudioFormat::AES3 => 35,
+            AudioFormat::FLAC(_) => 36,
+            AudioFormat::Opus => 37,
+            AudioFormat::EnhancedAC3 => 38,
+            AudioFormat::AC4 => 39,
+            AudioFormat::MPEGD_USAC => 40,
+            AudioFormat::APAC => 41,
+            AudioFormat::Unknown(..) => 42,
+        }
+    }
+
+    #[test]
+    fn every_format() {
+        let aac = Mpeg4ObjectId::AAC_LC;
+        #[rustfmt::skip]
+        let table = [
+            (AudioFormat::LinearPCM(LinearPcmFlags::IS_FLOAT | LinearPcmFlags::IS_PACKED), b"lpcm", Some(9)),
+            (AudioFormat::AC3, b"ac-3", None),
+            (AudioFormat::F60958AC3(StandardFlags::IS_BIG_ENDIAN), b"cac3", Some(2)),
+            (AudioFormat::AppleIMA4, b"ima4", None),
+            (AudioFormat::MPEG4AAC(aac), b"aac ", Some(2)),
+            (AudioFormat::MPEG4CELP(Mpeg4ObjectId::CELP), b"celp", Some(8)),
+            (AudioFormat::MPEG4HVXC(Mpeg4ObjectId::HVXC), b"hvxc", Some(9)),
+            (AudioFormat::MPEG4TwinVQ(Mpeg4ObjectId::TwinVQ), b"twvq", Some(7)),
+            (AudioFormat::MACE3, b"MAC3", None),
+            (AudioFormat::MACE6, b"MAC6", None),
+            (AudioFormat::ULaw, b"ulaw", None),
+            (AudioFormat::ALaw, b"alaw", None),
+            (AudioFormat::QDesign, b"QDMC", None),
+            (AudioFormat::QDesign2, b"QDM2", None),
+            (AudioFormat::QUALCOMM, b"Qclp", None),
+            (AudioFormat::MPEGLayer1, b".mp1", None),
+            (AudioFormat::MPEGLayer2, b".mp2", None),
+            (AudioFormat::MPEGLayer3, b".mp3", None),
+            (AudioFormat::TimeCode(AudioTimeStampFlags::SAMPLE_TIME_VALID), b"time", Some(1)),
+            (AudioFormat::MIDIStream, b"midi", None),
+            (AudioFormat::ParameterValueStream, b"apvs", None),
+            (AudioFormat::AppleLossless(AppleLosslessFlags::BIT_16_SOURCE_DATA), b"alac", Some(1)),
+            (AudioFormat::MPEG4AAC_HE, b"aach", None),
+            (AudioFormat::MPEG4AAC_LD, b"aacl", None),
+            (AudioFormat::MPEG4AAC_ELD, b"aace", None),
+            (AudioFormat::MPEG4AAC_ELD_SBR, b"aacf", None),
+            (AudioFormat::MPEG4AAC_ELD_V2, b"aacg", None),
+            (AudioFormat::MPEG4AAC_HE_V2, b"aacp", None),
+            (AudioFormat::MPEG4AAC_Spatial, b"aacs", None),
+            (AudioFormat::AMR, b"samr", None),
+            (AudioFormat::AMR_WB, b"sawb", None),
+            (AudioFormat::Audible, b"AUDB", None),
+            (AudioFormat::iLBC, b"ilbc", None),
+            (AudioFormat::DVIIntelIMA, b"ms\x00\x11", None),
+            (AudioFormat::MicrosoftGSM, b"ms\x00\x31", None),
+            (AudioFormat::AES3, b"aes3", None),
+            (AudioFormat::FLAC(AppleLosslessFlags::BIT_24_SOURCE_DATA), b"flac", Some(3)),
+            (AudioFormat::Opus, b"opus", None),
+            (AudioFormat::EnhancedAC3, b"ec-3", None),
+            (AudioFormat::AC4, b"ac-4", None),
+            (AudioFormat::MPEGD_USAC, b"usac", None),
+            (AudioFormat::APAC, b"apac", None),
+            (AudioFormat::Unknown(FourCC::new(b"xyzw"), 5), b"xyzw", Some(5)),
+        ];
+
+        // Unknown comes last, so every row is in the table.
+        assert_eq!(
+            row(&AudioFormat::Unknown(FourCC::new(b"    "), 0)),
+            table.len() - 1
+        );
+
+        let mut names = std::collections::HashSet::new();
+        for (i, (format, code, flag)) in table.into_iter().enumerate() {
+            assert_eq!(row(&format), i, "{format:?}");
+            let (id, flags) = format.as_format_and_flag();
+            assert_eq!((FourCC(id), flags), (FourCC::new(code), flag), "{format:?}");
+            assert_eq!(
+                AudioFormat::from_format_and_flag(id, flags),
+                Some(format),
+                "{format:?}"
+            );
+
+            let info = format.info();
+            assert!(names.insert(info.name), "{format:?}");
+            assert!(info.compressed || info.lossless, "{format:?}");
+            assert!(!(info.magic_cookie_required && info.constant_bytes_per_packet));
+        }
+
+        let info = AudioFormat::AppleLossless(AppleLosslessFlags::BIT_16_SOURCE_DATA).info();
+        assert_eq!(info.name, "Apple Lossless");
+        assert_eq!(info.frames_per_packet, Some(4096));
+        assert!(info.compressed && info.lossless && info.magic_cookie_required);
+        assert!(!info.constant_bytes_per_packet);
+
+        let info = AudioFormat::AppleIMA4.info();
+        assert!(info.constant_bytes_per_packet && !info.lossless);
+        assert!(
+            !AudioFormat::LinearPCM(LinearPcmFlags::empty())
+                .info()
+                .compressed
+        );
+    }
+}
@@ -0,0 +1,193 @@
+/// Speaker position of a channel, with the values of `AudioChannelLabel`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChannelLabel {
+    Unknown,
+    Unused,
+    Left,
+    Right,
+    Center,
+    LfeScreen,
+    LeftSurround,
+    RightSurround,
+    LeftCenter,
+    RightCenter,
+    CenterSurround,
+    LeftSurroundDirect,
+    RightSurroundDirect,
+    TopCenterSurround,
+    VerticalHeightLeft,
+    VerticalHeightCenter,
+    VerticalHeightRight,
+    TopBackLeft,
+    TopBackCenter,
+    TopBackRight,
+    Mono,
+    /// Discrete channel without a position, numbered from 0.
+    Discrete(u16),
+    /// Any other `AudioChannelLabel`.
+    Other(u32),
+}
+
+/// Labels in the order of the bits of a channel bitmap, which is also the order of the
+/// WAVE_FORMAT_EXTENSIBLE channel mask.
+const BITMAP_LABELS: [ChannelLabel; 18] = [
+    ChannelLabel::Left,
+    ChannelLabel::Right,
+    ChannelLabel::Center,
+    ChannelLabel::LfeScreen,
+    ChannelLabel::LeftSurround,
+    ChannelLabel::RightSurround,
+    ChannelLabel::LeftCenter,
+    ChannelLabel::RightCenter,
+    ChannelLabel::CenterSurround,
+    ChannelLabel::LeftSurroundDirect,
+    ChannelLabel::RightSurroundDirect,
+    ChannelLabel::TopCenterSurround,
+    ChannelLabel::VerticalHeightLeft,
+    ChannelLabel::VerticalHeightCenter,
+    ChannelLabel::VerticalHeightRight,
+    ChannelLabel::TopBackLeft,
+    ChannelLabel::TopBackCenter,
+    ChannelLabel::TopBackRight,
+];
+
+impl ChannelLabel {
+    pub fn from_u32(label: u32) -> Self {
+        match label {
+            0xFFFF_FFFF => ChannelLabel::Unknown,
+            0 => ChannelLabel::Unused,
+            1..=18 => BITMAP_LABELS[label as usize - 1],
+            42 => ChannelLabel::Mono,
+            0x1_0000..=0x1_FFFF => ChannelLabel::Discrete(label as u16),
+            _ => ChannelLabel::Other(label),
+        }
+    }
+
+    pub fn as_u32(&self) -> u32 {
+        match *self {
+            ChannelLabel::Unknown => 0xFFFF_FFFF,
+            ChannelLabel::Unused => 0,
+            ChannelLabel::Mono => 42,
+            ChannelLabel::Discrete(n) => 0x1_0000 | n as u32,
+            ChannelLabel::Other(label) => label,
+            label => BITMAP_LABELS.iter().position(|&l| l == label).unwrap() as u32 + 1,
+        }
+    }
+
+    /// Bit of the label in a channel bitmap, if it has one.
+    pub fn bitmap_bit(&self) -> Option<u32> {
+        BITMAP_LABELS.iter().position(|l| l == self).map(|i| 1 << i)
+    }
+}
+
+/// Speaker positions of the channels of a stream, in channel order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelLayout {
+    labels: Vec<ChannelLabel>,
+}
+
+impl ChannelLayout {
+    pub fn new(labels: Vec<ChannelLabel>) -> Self {
+        ChannelLayout { labels }
+    }
+
+    /// Layout from a channel bitmap. The channels take the positions of the set bits from
+    /// the lowest up. Channels beyond the set bits are [`ChannelLabel::Unknown`].
+    pub fn from_bitmap(bitmap: u32, channels: usize) -> Self {
+        let mut labels: Vec<ChannelLabel> = BITMAP_LABELS
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| bitmap & (1 << i) != 0)
+            .map(|(_, &label)| label)
+            .take(channels)
+            .collect();
+
+        labels.resize(channels, ChannelLabel::Unknown);
+
+        ChannelLayout { labels }
+    }
+
+    /// The usual layout for a number of channels: mono, stereo, quad, 5.1 and 7.1 have
+    /// positions, other counts are discrete channels.
+    pub fn default_for(channels: usize) -> Self {
+        match channels {
+            1 => ChannelLayout::new(vec![ChannelLabel::Mono]),
+            2 => ChannelLayout::from_bitmap(0x3, 2),
+            4 => ChannelLayout::from_bitmap(0x33, 4),
+            6 => ChannelLayout::from_bitmap(0x3F, 6),
+            8 => ChannelLayout::from_bitmap(0x63F, 8),
+            n => ChannelLayout::new((0..n as u16).map(ChannelLabel::Discrete).collect()),
+        }
+    }
+
+    pub fn labels(&self) -> &[ChannelLabel] {
+        &self.labels
+    }
+
+    pub fn channels(&self) -> usize {
+        self.labels.len()
+    }
+
+    /// The layout as a channel bitmap.
+    ///
+    /// Only possible if every channel has a bitmap position and the positions are in
+    /// bitmap order. [`ChannelLabel::Mono`] maps to the center.
+    pub fn to_bitmap(&self) -> Option<u32> {
+        let mut bitmap = 0;
+
+        for label in &self.labels {
+            let label = match label {
+                ChannelLabel::Mono => ChannelLabel::Center,
+                label => *label,
+            };
+            let bit = label.bitmap_bit()?;
+
+            // Channels have to be in bit order.
+            if bit <= bitmap {
+                return None;
+            }
+            bitmap |= bit;
+        }
+
+        Some(bitmap)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn label_values_round_trip() {
+        for value in [0, 1, 4, 18, 42, 0x1_0003, 100, 0xFFFF_FFFF] {
+            assert_eq!(ChannelLabel::from_u32(value).as_u32(), value);
+        }
+        assert_eq!(ChannelLabel::from_u32(6), ChannelLabel::RightSurround);
+    }
+
+    #[test]
+    fn bitmaps() {
+        let surround = ChannelLayout::default_for(6);
+        assert_eq!(
+            surround.labels(),
+            [
+                ChannelLabel::Left,
+                ChannelLabel::Right,
+                ChannelLabel::Center,
+                ChannelLabel::LfeScreen,
+                ChannelLabel::LeftSurround,
+                ChannelLabel::RightSurround,
+            ]
+        );
+        assert_eq!(surround.to_bitmap(), Some(0x3F));
+
+        assert_eq!(ChannelLayout::default_for(1).to_bitmap(), Some(0x4));
+        assert_eq!(ChannelLayout::default_for(3).to_bitmap(), None);
+
+        let swapped = ChannelLayout::new(vec![ChannelLabel::Right, ChannelLabel::Left]);
+        assert_eq!(swapped.to_bitmap(), None);
+
+        let short = ChannelLayout::from_bitmap(0x1, 2);
+        assert_eq!(short.labels(), [ChannelLabel::Left, ChannelLabel::Unknown]);
+    }
+}
@@ -1,8 +1,20 @@
 mod audio;
 pub use audio::*;
 
+mod description;
+pub use description::FormatDescription;
+
+mod info;
+pub use info::FormatInfo;
+
+mod layout;
+pub use layout::{ChannelLabel, ChannelLayout};
+
+mod packet;
+pub use packet::{PacketBuffer, PacketDescription, PacketTableInfo, Packets};
+
 mod sample;
-pub use sample::{Sample, SampleFormat};
+pub use sample::{Sample, SampleConvert, SampleFormat};
 
 mod stream;
 pub use stream::StreamFormat;
@@ -0,0 +1,216 @@
+use std::slice;
+
+use crate::error::AudioFileError;
+use crate::CAError;
+
+/// Location of a packet in a buffer of compressed audio, the fields of an
+/// AudioStreamPacketDescription.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PacketDescription {
+    /// Offset of the packet from the start of the buffer.
+    pub start_offset: u64,
+    /// Frames in the packet, 0 if the format has a constant number of frames per packet.
+    pub variable_frames: u32,
+    pub data_byte_size: u32,
+}
+
+impl From<&PacketDescription> for sys::AudioStreamPacketDescription {
+    fn from(packet: &PacketDescription) -> Self {
+        sys::AudioStreamPacketDescription {
+            mStartOffset: packet.start_offset as i64,
+            mVariableFramesInPacket: packet.variable_frames,
+            mDataByteSize: packet.data_byte_size,
+        }
+    }
+}
+
+/// Packets of compressed audio: their bytes and where each one is in them.
+///
+/// Every description lies within the bytes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PacketBuffer {
+    data: Vec<u8>,
+    descriptions: Vec<PacketDescription>,
+}
+
+impl PacketBuffer {
+    pub fn new() -> Self {
+        PacketBuffer::default()
+    }
+
+    /// Packets described by `descriptions`, relative to the start of `data`.
+    ///
+    /// Fails with [`AudioFileError::InvalidPacketOffset`] for a packet not in `data`.
+    pub fn from_parts(
+        data: Vec<u8>,
+        descriptions: Vec<PacketDescription>,
+    ) -> Result<Self, CAError> {
+        let len = data.len() as u64;
+        let in_data = |p: &PacketDescription| {
+            p.start_offset
+                .checked_add(p.data_byte_size as u64)
+                .is_some_and(|end| end <= len)
+        };
+        if !descriptions.iter().all(in_data) {
+            return Err(AudioFileError::InvalidPacketOffset.into());
+        }
+
+        Ok(PacketBuffer { data, descriptions })
+    }
+
+    /// Append a packet. `variable_frames` is 0 for formats with a constant number of
+    /// frames per packet.
+    pub fn push(&mut self, packet: &[u8], variable_frames: u32) {
+        self.descriptions.push(PacketDescription {
+            start_offset: self.data.len() as u64,
+            variable_frames,
+            data_byte_size: packet.len() as u32,
+        });
+        self.data.extend_from_slice(packet);
+    }
+
+    /// Number of packets.
+    pub fn len(&self) -> usize {
+        self.descriptions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.descriptions.is_empty()
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn descriptions(&self) -> &[PacketDescription] {
+        &self.descriptions
+    }
+
+    /// The bytes of a packet.
+    pub fn get(&self, index: usize) -> Option<&[u8]> {
+        self.descriptions
+            .get(index)
+            .map(|p| packet_data(&self.data, p))
+    }
+
+    /// The packets in order, with their descriptions.
+    pub fn iter(&self) -> Packets<'_> {
+        Packets {
+            data: &self.data,
+            descriptions: self.descriptions.iter(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.data.clear();
+        self.descriptions.clear();
+    }
+
+    pub fn into_parts(self) -> (Vec<u8>, Vec<PacketDescription>) {
+        (self.data, self.descriptions)
+    }
+
+    /// For readers that append packets with offsets relative to the start of the data.
+    pub(crate) fn parts_mut(&mut self) -> (&mut Vec<u8>, &mut Vec<PacketDescription>) {
+        (&mut self.data, &mut self.descriptions)
+    }
+}
+
+fn packet_data<'a>(data: &'a [u8], packet: &PacketDescription) -> &'a [u8] {
+    let start = packet.start_offset as usize;
+    &data[start..start + packet.data_byte_size as usize]
+}
+
+/// Iterator over the packets of a [`PacketBuffer`].
+#[derive(Debug, Clone)]
+pub struct Packets<'a> {
+    data: &'a [u8],
+    descriptions: slice::Iter<'a, PacketDescription>,
+}
+
+impl<'a> Iterator for Packets<'a> {
+    type Item = (&'a PacketDescription, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let packet = self.descriptions.next()?;
+        Some((packet, packet_data(self.data, packet)))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.descriptions.size_hint()
+    }
+}
+
+impl ExactSizeIterator for Packets<'_> {}
+
+impl<'a> IntoIterator for &'a PacketBuffer {
+    type Item = (&'a PacketDescription, &'a [u8]);
+    type IntoIter = Packets<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Frames of a compressed stream that are not audio: the encoder delay at the start and
+/// the padding of the last packet.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PacketTableInfo {
+    pub valid_frames: i64,
+    pub priming_frames: i32,
+    pub remainder_frames: i32,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn packet_buffer() {
+        let mut buffer = PacketBuffer::new();
+        buffer.push(&[1, 2, 3], 0);
+        buffer.push(&[], 0);
+        buffer.push(&[4, 5], 7);
+
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer.data(), [1, 2, 3, 4, 5]);
+        assert_eq!(buffer.get(2), Some(&[4, 5][..]));
+        assert_eq!(buffer.get(3), None);
+
+        let packets: Vec<_> = buffer
+            .iter()
+            .map(|(p, data)| (p.variable_frames, data))
+            .collect();
+        assert_eq!(packets, [(0, &[1, 2, 3][..]), (0, &[]), (7, &[4, 5])]);
+        assert_eq!(buffer.iter().len(), 3);
+
+        let (data, descriptions) = buffer.clone().into_parts();
+        assert_eq!(descriptions[2].start_offset, 3);
+        assert_eq!(
+            PacketBuffer::from_parts(data, descriptions).unwrap(),
+            buffer
+        );
+
+        let packet = PacketDescription {
+            start_offset: 4,
+            variable_frames: 0,
+            data_byte_size: 2,
+        };
+        assert!(matches!(
+            PacketBuffer::from_parts(vec![0; 5], vec![packet]),
+            Err(CAError::AudioFileError(AudioFileError::InvalidPacketOffset))
+        ));
+        let packet = PacketDescription {
+            start_offset: u64::MAX,
+            ..packet
+        };
+        assert!(PacketBuffer::from_parts(vec![0; 5], vec![packet]).is_err());
+
+        let sys = sys::AudioStreamPacketDescription::from(&buffer.descriptions()[2]);
+        assert_eq!(sys.mStartOffset, 3);
+        assert_eq!((sys.mVariableFramesInPacket, sys.mDataByteSize), (7, 2));
+
+        buffer.clear();
+        assert!(buffer.is_empty());
+    }
+}
@@ -88,6 +88,23 @@ pub trait Sample: Default + Clone + Debug + 'static {
     fn sample_format() -> SampleFormat;
 }
 
+/// Conversions between the sample types of the crate, used to generate, analyze and
+/// encode samples.
+pub trait SampleConvert: Sample {
+    /// Convert from a float in `[-1.0, 1.0]`. Values outside are clipped for integer
+    /// samples.
+    fn from_f32(value: f32) -> Self;
+
+    /// Convert to a float in `[-1.0, 1.0]`.
+    fn to_f32(&self) -> f32;
+
+    /// Convert from a full scale 32 bit integer. Narrower integers are truncated.
+    fn from_i32(value: i32) -> Self;
+
+    /// Convert to a full scale 32 bit integer. Floats are clipped.
+    fn to_i32(&self) -> i32;
+}
+
 /// Simplified implementation of the `Sample` trait for sample types.
 /// This is only implemented for the sample types that map directly to a numeric type.
 macro_rules! impl_sample {
@@ -98,8 +115,82 @@ macro_rules! impl_sample {
                     SampleFormat::$format
                 }
             }
+
+            impl SampleConvert for $T {
+                fn from_f32(value: f32) -> Self {
+                    // Full scale is 2^(bits - 1), so that conversions round trip exactly.
+                    let scaled = (value as f64 * -($T::MIN as f64)).round();
+                    scaled.clamp($T::MIN as f64, $T::MAX as f64) as $T
+                }
+
+                fn to_f32(&self) -> f32 {
+                    (*self as f64 / -($T::MIN as f64)) as f32
+                }
+
+                fn from_i32(value: i32) -> Self {
+                    (value >> (32 - $T::BITS)) as $T
+                }
+
+                fn to_i32(&self) -> i32 {
+                    (*self as i32) << (32 - $T::BITS)
+                }
+            }
         )*
     }
 }
 
-impl_sample!(f32 F32, i32 I32, i16 I16, i8 I8);
+impl_sample!(i32 I32, i16 I16, i8 I8);
+
+impl Sample for f32 {
+    fn sample_format() -> SampleFormat {
+        SampleFormat::F32
+    }
+}
+
+impl SampleConvert for f32 {
+    fn from_f32(value: f32) -> Self {
+        value
+    }
+
+    fn to_f32(&self) -> f32 {
+        *self
+    }
+
+    fn from_i32(value: i32) -> Self {
+        value as f32 / 2_147_483_648.0
+    }
+
+    fn to_i32(&self) -> i32 {
+        i32::from_f32(*self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn f32_conversions() {
+        assert_eq!(i16::from_f32(1.0), i16::MAX);
+        assert_eq!(i16::from_f32(-1.0), i16::MIN);
+        assert_eq!(i16::from_f32(2.0), i16::MAX);
+        assert_eq!(i8::from_f32(0.5), 64);
+        assert_eq!(i32::from_f32(-0.5), -(1 << 30));
+        assert_eq!(f32::from_f32(1.5), 1.5);
+
+        for v in [i16::MIN, -1, 0, 1, i16::MAX] {
+            assert_eq!(i16::from_f32(v.to_f32()), v);
+        }
+        assert_eq!(i16::MIN.to_f32(), -1.0);
+    }
+
+    #[test]
+    fn i32_conversions() {
+        assert_eq!(i16::from_i32(0x1234_5678), 0x1234);
+        assert_eq!(0x12_i8.to_i32(), 0x1200_0000);
+        assert_eq!(i32::from_i32(-5), -5);
+        assert_eq!(f32::from_i32(i32::MIN), -1.0);
+        assert_eq!(0.5_f32.to_i32(), 1 << 30);
+        assert_eq!(2.0_f32.to_i32(), i32::MAX);
+    }
+}
@@ -7,6 +7,7 @@ use crate::CAError;
 use super::SampleFormat;
 
 /// Wrapper around an AudioStreamBasicDescription.
+#[derive(Clone)]
 pub struct StreamFormat {
     asbd: sys::AudioStreamBasicDescription,
 }
@@ -0,0 +1,294 @@
+//! Test signal generators.
+//!
+//! A [`Generator`] keeps its phase between calls, so filling buffers one after the other
+//! produces one continuous signal. Every channel gets the same signal.
+
+use std::f64::consts::PI;
+use std::time::Duration;
+
+use crate::format::{SampleConvert, StreamFormat};
+use crate::queue::AudioQueueBuffer;
+use crate::rng::Rng;
+use crate::unit::AudioBufferList;
+
+/// Shape of the generated signal.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Waveform {
+    /// Sine with the frequency in Hz.
+    Sine(f64),
+    /// Square with the frequency in Hz.
+    Square(f64),
+    /// Rising sawtooth with the frequency in Hz.
+    Saw(f64),
+    /// Uniform white noise.
+    WhiteNoise,
+    /// Noise falling off at 3 dB per octave.
+    PinkNoise,
+    /// Sine sweeping exponentially from `start` to `end` Hz, then staying at `end`.
+    LogSweep {
+        start: f64,
+        end: f64,
+        duration: Duration,
+    },
+    /// A single full scale sample, repeated every `period` frames if given.
+    Impulse { period: Option<u64> },
+    /// Constant full scale.
+    Dc,
+    /// All zeros.
+    Silence,
+}
+
+/// Generates a [`Waveform`] for a stream format.
+#[derive(Debug, Clone)]
+pub struct Generator {
+    waveform: Waveform,
+    sample_rate: f64,
+    channels: usize,
+    amplitude: f32,
+    /// Phase in cycles, in `[0, 1)`.
+    phase: f64,
+    /// Frames generated so far.
+    position: u64,
+    rng: Rng,
+    pink: [f64; 7],
+}
+
+impl Generator {
+    /// Creates a generator at full scale. Noise is seeded with 0.
+    pub fn new(waveform: Waveform, format: &StreamFormat) -> Self {
+        Generator {
+            waveform,
+            sample_rate: format.sample_rate(),
+            channels: format.channels(),
+            amplitude: 1.0,
+            phase: 0.0,
+            position: 0,
+            rng: Rng::new(0),
+            pink: [0.0; 7],
+        }
+    }
+
+    /// Scale the signal, 1.0 is full scale.
+    pub fn set_amplitude(&mut self, amplitude: f32) {
+        self.amplitude = amplitude;
+    }
+
+    /// Restart the noise from a seed.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+        self.pink = [0.0; 7];
+    }
+
+    /// Frames generated so far.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// The next value, before scaling by the amplitude.
+    fn next_value(&mut self) -> f64 {
+        let value = match self.waveform {
+            Waveform::Sine(frequency) => self.oscillate(frequency, |p| (2.0 * PI * p).sin()),
+            Waveform::Square(frequency) => {
+                self.oscillate(frequency, |p| if p < 0.5 { 1.0 } else { -1.0 })
+            }
+            Waveform::Saw(frequency) => self.oscillate(frequency, |p| 2.0 * p - 1.0),
+            Waveform::WhiteNoise => self.white(),
+            Waveform::PinkNoise => self.pink(),
+            Waveform::LogSweep {
+                start,
+                end,
+                duration,
+            } => {
+                let t = self.position as f64 / self.sample_rate;
+                let progress = (t / duration.as_secs_f64()).min(1.0);
+                let frequency = start * (end / start).powf(progress);
+                self.oscillate(frequency, |p| (2.0 * PI * p).sin())
+            }
+            Waveform::Impulse { period } => {
+                let at = match period {
+                    Some(period) => self.position % period == 0,
+                    None => self.position == 0,
+                };
+                if at {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Waveform::Dc => 1.0,
+            Waveform::Silence => 0.0,
+        };
+
+        self.position += 1;
+        value
+    }
+
+    /// Shape the current phase and advance it.
+    fn oscillate(&mut self, frequency: f64, shape: impl Fn(f64) -> f64) -> f64 {
+        let value = shape(self.phase);
+        self.phase = (self.phase + frequency / self.sample_rate).rem_euclid(1.0);
+        value
+    }
+
+    fn white(&mut self) -> f64 {
+        self.rng.next_f64() * 2.0 - 1.0
+    }
+
+    /// Paul Kellet's refined pink noise filter.
+    fn pink(&mut self) -> f64 {
+        let white = self.white();
+        let b = &mut self.pink;
+
+        b[0] = 0.99886 * b[0] + white * 0.0555179;
+        b[1] = 0.99332 * b[1] + white * 0.0750759;
+        b[2] = 0.96900 * b[2] + white * 0.1538520;
+        b[3] = 0.86650 * b[3] + white * 0.3104856;
+        b[4] = 0.55000 * b[4] + white * 0.5329522;
+        b[5] = -0.7616 * b[5] - white * 0.0168980;
+
+        let pink = b[0] + b[1] + b[2] + b[3] + b[4] + b[5] + b[6] + white * 0.5362;
+        b[6] = white * 0.115926;
+
+        // The filter has a gain of about 9 at its peak.
+        (pink * 0.11).clamp(-1.0, 1.0)
+    }
+
+    /// Fill interleaved samples.
+    pub fn fill<S: SampleConvert>(&mut self, samples: &mut [S]) {
+        for frame in samples.chunks_mut(self.channels) {
+            let value = S::from_f32(self.next_value() as f32 * self.amplitude);
+            frame.fill(value);
+        }
+    }
+
+    /// Fill all frames of the buffers, which can be interleaved or not.
+    pub fn fill_buffer_list<S: SampleConvert>(&mut self, list: &mut AudioBufferList<S>) {
+        let frames = list.first().map(|b| b.frames()).unwrap_or(0);
+
+        for frame in 0..frames {
+            let value = S::from_f32(self.next_value() as f32 * self.amplitude);
+
+            for buffer in list.iter_mut() {
+                let channels = buffer.channels();
+                buffer.samples_mut()[frame * channels..(frame + 1) * channels].fill(value.clone());
+            }
+        }
+    }
+
+    /// Fill an interleaved queue buffer up to its current size.
+    pub fn fill_queue_buffer<S: SampleConvert>(&mut self, buffer: &mut AudioQueueBuffer<S>) {
+        self.fill(buffer);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::format::{LinearPcmFlags, SampleFormat};
+
+    use super::*;
+
+    fn mono() -> StreamFormat {
+        StreamFormat::new(48_000.0, SampleFormat::F32, LinearPcmFlags::IS_FLOAT, 1)
+    }
+
+    #[test]
+    fn phase_continuity() {
+        let mut whole = Generator::new(Waveform::Sine(440.0), &mono());
+        let mut parts = whole.clone();
+
+        let mut expected = vec![0.0_f32; 1000];
+        whole.fill(&mut expected);
+
+        let mut actual = vec![0.0_f32; 1000];
+        let (a, b) = actual.split_at_mut(333);
+        parts.fill(a);
+        parts.fill(b);
+
+        assert_eq!(actual, expected);
+        assert_eq!(parts.position(), 1000);
+    }
+
+    #[test]
+    fn shapes() {
+        let format = StreamFormat::new(8.0, SampleFormat::F32, LinearPcmFlags::IS_FLOAT, 1);
+
+        let run = |waveform| {
+            let mut out = vec![0.0_f32; 8];
+            Generator::new(waveform, &format).fill(&mut out);
+            out
+        };
+
+        let sine = run(Waveform::Sine(2.0));
+        assert!(sine[0].abs() < 1e-6 && (sine[1] - 1.0).abs() < 1e-6);
+        assert!(sine[2].abs() < 1e-6 && (sine[3] + 1.0).abs() < 1e-6);
+
+        assert_eq!(
+            run(Waveform::Square(2.0)),
+            [1., 1., -1., -1., 1., 1., -1., -1.]
+        );
+        assert_eq!(
+            run(Waveform::Saw(2.0)),
+            [-1., -0.5, 0., 0.5, -1., -0.5, 0., 0.5]
+        );
+        assert_eq!(
+            run(Waveform::Impulse { period: Some(3) }),
+            [1., 0., 0., 1., 0., 0., 1., 0.]
+        );
+        assert_eq!(
+            run(Waveform::Impulse { period: None }),
+            [1., 0., 0., 0., 0., 0., 0., 0.]
+        );
+        let sweep = run(Waveform::LogSweep {
+            start: 2.0,
+            end: 2.0,
+            duration: Duration::from_secs(1),
+        });
+        assert_eq!(sweep, sine);
+
+        assert_eq!(run(Waveform::Dc), [1.0; 8]);
+        assert_eq!(run(Waveform::Silence), [0.0; 8]);
+    }
+
+    #[test]
+    fn seeded_noise() {
+        let mut a = Generator::new(Waveform::PinkNoise, &mono());
+        let mut b = Generator::new(Waveform::PinkNoise, &mono());
+        a.set_seed(3);
+        b.set_seed(3);
+
+        let mut x = vec![0_i16; 4096];
+        let mut y = vec![0_i16; 4096];
+        a.fill(&mut x);
+        b.fill(&mut y);
+        assert_eq!(x, y);
+
+        b.set_seed(4);
+        b.fill(&mut y);
+        assert_ne!(x, y);
+
+        let mut white = Generator::new(Waveform::WhiteNoise, &mono());
+        white.set_amplitude(0.5);
+        let mut z = vec![0.0_f32; 4096];
+        white.fill(&mut z);
+        assert!(z.iter().all(|v| v.abs() <= 0.5));
+        assert!(z.iter().any(|v| v.abs() > 0.4));
+    }
+
+    #[test]
+    fn fills_non_interleaved_lists() {
+        let format = StreamFormat::new(
+            8.0,
+            SampleFormat::I16,
+            LinearPcmFlags::IS_SIGNED_INTEGER | LinearPcmFlags::IS_NON_INTERLEAVED,
+            2,
+        );
+
+        let mut list = AudioBufferList::<i16>::new(2, 1, 4);
+        let mut generator = Generator::new(Waveform::Saw(2.0), &format);
+        generator.fill_buffer_list(&mut list);
+
+        for buffer in list.iter() {
+            assert_eq!(buffer.samples(), [i16::MIN, -16384, 0, 16384]);
+        }
+    }
+}
@@ -0,0 +1,324 @@
+//! Golden file regression tests.
+//!
+//! [`check_golden`] compares rendered audio against a reference WAV file. When the
+//! `CAUDIO_UPDATE_GOLDEN` environment variable is set, the reference is (re)written
+//! instead, e.g. `CAUDIO_UPDATE_GOLDEN=1 cargo test`.
+
+use std::fmt;
+use std::io::{Read, Seek};
+use std::path::Path;
+
+use crate::analysis::Spectrum;
+use crate::file::wav::{WavReader, WavWriter};
+use crate::format::{SampleConvert, StreamFormat};
+use crate::CAError;
+
+/// Environment variable that turns on update mode.
+pub const UPDATE_ENV: &str = "CAUDIO_UPDATE_GOLDEN";
+
+/// Bins more than 60 dB below the strongest bin are left out of the spectral distance,
+/// otherwise the noise floor dominates it.
+const SPECTRAL_FLOOR: f64 = 1e-6;
+
+/// How far audio may deviate from the reference.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tolerance {
+    /// Largest allowed difference of a single sample, on the `[-1.0, 1.0]` scale.
+    pub max_abs_error: f32,
+    /// Smallest allowed level of the difference below the reference, in dB.
+    pub min_null_depth_db: Option<f64>,
+    /// Largest allowed RMS difference of the spectra, in dB.
+    pub max_spectral_distance_db: Option<f64>,
+}
+
+impl Tolerance {
+    /// Bit exact.
+    pub fn exact() -> Self {
+        Tolerance {
+            max_abs_error: 0.0,
+            min_null_depth_db: None,
+            max_spectral_distance_db: None,
+        }
+    }
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Tolerance::exact()
+    }
+}
+
+/// Differences between audio and its reference.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub channels: usize,
+    pub frames: usize,
+    pub reference_frames: usize,
+    pub max_abs_error: f32,
+    /// Frame and channel of the largest difference.
+    pub max_error_at: (usize, usize),
+    /// Level of the difference below the reference in dB. Infinite for identical audio.
+    pub null_depth_db: f64,
+    /// RMS difference of the spectra in dB, averaged over the channels.
+    pub spectral_distance_db: f64,
+}
+
+impl Comparison {
+    /// Compare interleaved audio with a reference.
+    pub fn new(reference: &[f32], actual: &[f32], channels: usize, sample_rate: f64) -> Self {
+        let len = reference.len().min(actual.len());
+
+        let mut max_abs_error = 0.0;
+        let mut max_error_at = (0, 0);
+        let mut diff_power = 0.0;
+        let mut reference_power = 0.0;
+
+        for (i, (&r, &a)) in reference[..len].iter().zip(&actual[..len]).enumerate() {
+            let error = (a - r).abs();
+            if error > max_abs_error {
+                max_abs_error = error;
+                max_error_at = (i / channels, i % channels);
+            }
+            diff_power += (error as f64).powi(2);
+            reference_power += (r as f64).powi(2);
+        }
+
+        let null_depth_db = 10.0 * (reference_power / diff_power).log10();
+
+        let spectral_distance_db = (0..channels)
+            .map(|c| {
+                let reference = crate::analysis::channel(&reference[..len], channels, c).unwrap();
+                let actual = crate::analysis::channel(&actual[..len], channels, c).unwrap();
+                spectral_distance(
+                    &Spectrum::new(&reference, sample_rate),
+                    &Spectrum::new(&actual, sample_rate),
+                )
+            })
+            .sum::<f64>()
+            / channels as f64;
+
+        Comparison {
+            channels,
+            frames: actual.len() / channels,
+            reference_frames: reference.len() / channels,
+            max_abs_error,
+            max_error_at,
+            null_depth_db,
+            spectral_distance_db,
+        }
+    }
+
+    /// Whether the differences are within the tolerance.
+    pub fn within(&self, tolerance: &Tolerance) -> bool {
+        self.frames == self.reference_frames
+            && self.max_abs_error <= tolerance.max_abs_error
+            && tolerance
+                .min_null_depth_db
+                .map_or(true, |min| self.null_depth_db >= min)
+            && tolerance
+                .max_spectral_distance_db
+                .map_or(true, |max| self.spectral_distance_db <= max)
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "frames: {} (reference {})",
+            self.frames, self.reference_frames
+        )?;
+        writeln!(
+            f,
+            "max abs error: {:e} at frame {} channel {}",
+            self.max_abs_error, self.max_error_at.0, self.max_error_at.1
+        )?;
+        writeln!(f, "null depth: {:.1} dB", self.null_depth_db)?;
+        write!(f, "spectral distance: {:.3} dB", self.spectral_distance_db)
+    }
+}
+
+/// Log spectral distance over the bins that are above the floor in either spectrum.
+fn spectral_distance(a: &Spectrum, b: &Spectrum) -> f64 {
+    let max = a
+        .power()
+        .iter()
+        .chain(b.power())
+        .cloned()
+        .fold(0.0, f64::max);
+    let floor = max * SPECTRAL_FLOOR;
+
+    let (sum, count) = a
+        .power()
+        .iter()
+        .zip(b.power())
+        .filter(|(&a, &b)| a > floor || b > floor)
+        .map(|(&a, &b)| (10.0 * (a.max(floor) / b.max(floor)).log10()).powi(2))
+        .fold((0.0, 0), |(sum, count), d| (sum + d, count + 1));
+
+    if count == 0 {
+        return 0.0;
+    }
+    (sum / count as f64).sqrt()
+}
+
+/// Compare interleaved audio with the reference WAV file at the path.
+///
+/// Fails with a report of the differences if they are not within the tolerance, or if
+/// the reference is missing or in a different format. In update mode the reference is
+/// written and the comparison is against itself.
+pub fn check_golden<S: SampleConvert>(
+    path: impl AsRef<Path>,
+    format: &StreamFormat,
+    samples: &[S],
+    tolerance: &Tolerance,
+) -> Result<Comparison, CAError> {
+    let update = std::env::var_os(UPDATE_ENV).is_some_and(|v| !v.is_empty() && v != "0");
+    check(path.as_ref(), format, samples, tolerance, update)
+}
+
+fn check<S: SampleConvert>(
+    path: &Path,
+    format: &StreamFormat,
+    samples: &[S],
+    tolerance: &Tolerance,
+    update: bool,
+) -> Result<Comparison, CAError> {
+    if update {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(CAError::from_io_error)?;
+        }
+        let mut writer = WavWriter::create(path, format)?;
+        writer.write_samples(samples)?;
+        writer.finalize()?;
+    }
+
+    let mut reader = WavReader::open(path).map_err(|e| {
+        CAError::Other(format!(
+            "can't read golden file {}: {}, set {}=1 to create it",
+            path.display(),
+            e,
+            UPDATE_ENV
+        ))
+    })?;
+
+    let reference = read_reference(&mut reader, format)?
+        .ok_or_else(|| CAError::Other(format!("{} is not a valid golden file", path.display())))?;
+
+    let actual: Vec<f32> = samples.iter().map(|s| s.to_f32()).collect();
+    let comparison = Comparison::new(&reference, &actual, format.channels(), format.sample_rate());
+
+    if !comparison.within(tolerance) {
+        return Err(CAError::Other(format!(
+            "audio differs from golden file {}\n{}",
+            path.display(),
+            comparison
+        )));
+    }
+
+    Ok(comparison)
+}
+
+/// Read the samples of a reference, which must match the format.
+fn read_reference<R: Read + Seek>(
+    reader: &mut WavReader<R>,
+    format: &StreamFormat,
+) -> Result<Option<Vec<f32>>, CAError> {
+    let reference = reader.format();
+    if reference.channels() != format.channels()
+        || reference.sample_rate() != format.sample_rate().round()
+        || reference.sample_format() != format.sample_format()
+    {
+        return Ok(None);
+    }
+
+    let mut samples = vec![0.0; reader.frames() as usize * format.channels()];
+    let frames = reader.read_samples(&mut samples)?;
+    samples.truncate(frames * format.channels());
+
+    Ok(Some(samples))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::format::{LinearPcmFlags, SampleFormat};
+    use crate::generator::{Generator, Waveform};
+
+    use super::*;
+
+    fn render(format: &StreamFormat) -> Vec<i16> {
+        let mut generator = Generator::new(Waveform::Sine(1_000.0), format);
+        generator.set_amplitude(0.5);
+        let mut out = vec![0_i16; 4096 * format.channels()];
+        generator.fill(&mut out);
+        out
+    }
+
+    #[test]
+    fn update_then_compare() {
+        let dir = std::env::temp_dir().join(format!("caudio-golden-{}", std::process::id()));
+        let path = dir.join("sine.wav");
+
+        let format = StreamFormat::new(
+            48_000.0,
+            SampleFormat::I16,
+            LinearPcmFlags::IS_SIGNED_INTEGER,
+            2,
+        );
+        let samples = render(&format);
+
+        let missing = check(&path, &format, &samples, &Tolerance::exact(), false);
+        assert!(missing.unwrap_err().to_string().contains(UPDATE_ENV));
+
+        check(&path, &format, &samples, &Tolerance::exact(), true).unwrap();
+        let same = check(&path, &format, &samples, &Tolerance::exact(), false).unwrap();
+        assert_eq!(same.max_abs_error, 0.0);
+        assert_eq!(same.null_depth_db, f64::INFINITY);
+        assert_eq!(same.spectral_distance_db, 0.0);
+
+        // One LSB off everywhere.
+        let off: Vec<i16> = samples.iter().map(|s| s + 1).collect();
+        let report = check(&path, &format, &off, &Tolerance::exact(), false)
+            .unwrap_err()
+            .to_string();
+        assert!(report.contains("max abs error"), "{report}");
+
+        let loose = Tolerance {
+            max_abs_error: 2.0 / 32768.0,
+            min_null_depth_db: Some(60.0),
+            max_spectral_distance_db: Some(1.0),
+        };
+        let comparison = check(&path, &format, &off, &loose, false).unwrap();
+        assert!(comparison.null_depth_db > 60.0);
+
+        // Too short.
+        assert!(check(&path, &format, &samples[..100], &loose, false).is_err());
+
+        // Different format.
+        let mono = StreamFormat::new(
+            48_000.0,
+            SampleFormat::I16,
+            LinearPcmFlags::IS_SIGNED_INTEGER,
+            1,
+        );
+        assert!(check(&path, &mono, &samples, &loose, false).is_err());
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn spectral_distance_of_gain() {
+        let format = StreamFormat::new(48_000.0, SampleFormat::F32, LinearPcmFlags::IS_FLOAT, 1);
+        let mut generator = Generator::new(Waveform::WhiteNoise, &format);
+        let mut reference = vec![0.0_f32; 4096];
+        generator.fill(&mut reference);
+
+        // 1 dB quieter.
+        let gain = 10_f32.powf(-1.0 / 20.0);
+        let quieter: Vec<f32> = reference.iter().map(|s| s * gain).collect();
+
+        let comparison = Comparison::new(&reference, &quieter, 1, 48_000.0);
+        assert!((comparison.spectral_distance_db - 1.0).abs() < 1e-3);
+        assert!((comparison.null_depth_db - 19.3).abs() < 0.1);
+    }
+}
@@ -1,12 +1,37 @@
 pub mod error;
 pub use error::CAError;
 
+pub mod analysis;
+
+mod bits;
+
+pub mod codec;
+
+pub mod device;
+
+pub mod file;
+
 pub mod format;
 
+pub mod generator;
+
+pub mod golden;
+
+pub mod panic;
+
 pub mod queue;
 
+pub mod record;
+
+pub mod ring;
+
+mod rng;
+
 pub mod unit;
 
+#[cfg(feature = "async")]
+mod waker;
+
 macro_rules! try_os_status {
     ($expr:expr) => {
         CAError::from_os_status($expr)?
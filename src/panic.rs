@@ -0,0 +1,162 @@
+//! Panic handling for the callbacks CoreAudio calls into.
+//!
+//! Unwinding out of an `extern "C"` function is undefined behaviour, so every callback
+//! trampoline catches panics and handles them according to a [`PanicPolicy`].
+
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc};
+
+use sys::OSStatus;
+
+/// What to do when a user callback panics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PanicPolicy {
+    /// Output silence. Render callbacks also set
+    /// [`ActionFlags::OUTPUT_IS_SILENCE`][crate::unit::ActionFlags::OUTPUT_IS_SILENCE].
+    #[default]
+    Silence,
+    /// Return the status to CoreAudio.
+    ///
+    /// Only render callbacks return a status, for other callbacks this is the same as
+    /// [`PanicPolicy::Silence`].
+    Error(OSStatus),
+    /// Abort the process.
+    Abort,
+}
+
+impl PanicPolicy {
+    fn to_bits(self) -> u64 {
+        match self {
+            PanicPolicy::Silence => 0,
+            PanicPolicy::Abort => 1,
+            PanicPolicy::Error(status) => 2 << 32 | status as u32 as u64,
+        }
+    }
+
+    fn from_bits(bits: u64) -> Self {
+        match bits >> 32 {
+            2 => PanicPolicy::Error(bits as u32 as OSStatus),
+            _ if bits == 1 => PanicPolicy::Abort,
+            _ => PanicPolicy::Silence,
+        }
+    }
+}
+
+/// Panics caught in the callbacks of an audio unit or queue.
+///
+/// The policy can be changed at any time, also while the callbacks are running.
+pub struct Panics {
+    policy: Arc<AtomicU64>,
+    rx: mpsc::Receiver<String>,
+}
+
+/// The callback side of [`Panics`].
+#[derive(Clone)]
+pub(crate) struct PanicHandler {
+    policy: Arc<AtomicU64>,
+    tx: mpsc::Sender<String>,
+}
+
+impl Panics {
+    pub(crate) fn new() -> (Panics, PanicHandler) {
+        let policy = Arc::new(AtomicU64::new(PanicPolicy::default().to_bits()));
+        let (tx, rx) = mpsc::channel();
+
+        let handler = PanicHandler {
+            policy: policy.clone(),
+            tx,
+        };
+
+        (Panics { policy, rx }, handler)
+    }
+
+    /// Current policy.
+    pub fn policy(&self) -> PanicPolicy {
+        PanicPolicy::from_bits(self.policy.load(Ordering::Relaxed))
+    }
+
+    /// Set the policy for panics from now on.
+    pub fn set_policy(&self, policy: PanicPolicy) {
+        self.policy.store(policy.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Poll for the message of a caught panic.
+    pub fn try_recv(&self) -> Option<String> {
+        self.rx.try_recv().ok()
+    }
+}
+
+impl PanicHandler {
+    /// Run the closure, catching any panic.
+    ///
+    /// On panic the message is reported and the policy returned as error. This never
+    /// returns for [`PanicPolicy::Abort`].
+    pub fn catch<R>(&self, f: impl FnOnce() -> R) -> Result<R, PanicPolicy> {
+        match panic::catch_unwind(AssertUnwindSafe(f)) {
+            Ok(r) => Ok(r),
+            Err(payload) => {
+                let policy = PanicPolicy::from_bits(self.policy.load(Ordering::Relaxed));
+
+                if policy == PanicPolicy::Abort {
+                    std::process::abort();
+                }
+
+                // The app might not be listening, that's fine.
+                self.tx.send(payload_message(payload)).ok();
+
+                Err(policy)
+            }
+        }
+    }
+}
+
+fn payload_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Ok(s) = payload.downcast::<String>() {
+        *s
+    } else {
+        "Box<dyn Any>".to_string()
+    }
+}
+
+impl std::fmt::Debug for Panics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Panics")
+            .field("policy", &self.policy())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn policy_bits_round_trip() {
+        for policy in [
+            PanicPolicy::Silence,
+            PanicPolicy::Abort,
+            PanicPolicy::Error(0),
+            PanicPolicy::Error(-10863),
+            PanicPolicy::Error(1718449215),
+        ] {
+            assert_eq!(PanicPolicy::from_bits(policy.to_bits()), policy);
+        }
+    }
+
+    #[test]
+    fn catch_reports_message() {
+        let (panics, handler) = Panics::new();
+        panics.set_policy(PanicPolicy::Error(-50));
+
+        assert_eq!(handler.catch(|| 42), Ok(42));
+        assert_eq!(panics.try_recv(), None);
+
+        let r = handler.catch(|| panic!("boom {}", 1));
+        assert_eq!(r, Err(PanicPolicy::Error(-50)));
+        assert_eq!(panics.try_recv().as_deref(), Some("boom 1"));
+    }
+}
@@ -0,0 +1,1116 @@
+//! AudioQueue for hardware input/output.
+
+use std::cell::Cell;
+use std::ffi::c_void;
+use std::iter::Peekable;
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
+
+use crate::error::AudioCodecError;
+use crate::format::{FormatDescription, PacketBuffer, Packets, Sample, StreamFormat};
+use crate::panic::{PanicHandler, Panics};
+use crate::{try_os_status, CAError};
+
+#[cfg(feature = "async")]
+mod stream;
+#[cfg(feature = "async")]
+pub use stream::{InputStream, NextBuffer};
+
+/// Output queue of samples, or of packets of a compressed format for `u8`, see
+/// [`AudioQueueOutput::with_description`].
+pub struct AudioQueueOutput<S> {
+    queue_ref: sys::AudioQueueRef,
+    buffers: Vec<AudioQueueBuffer<S>>,
+    free: Arc<FreeList>,
+    running: bool,
+    panics: Panics,
+    wrapper_ptr: *mut OutputCallbackWrapper,
+}
+
+type OutputCallbackFn = dyn FnMut(sys::AudioQueueBufferRef);
+
+struct OutputCallbackWrapper {
+    callback: Box<OutputCallbackFn>,
+    panic: PanicHandler,
+}
+
+/// Callback run by the queue when it is done with a buffer.
+///
+/// This runs on the realtime thread and must not allocate or lock.
+fn output_proc_fn(free: Arc<FreeList>) -> impl FnMut(sys::AudioQueueBufferRef) {
+    move |buffer_ref: sys::AudioQueueBufferRef| {
+        let idx = unsafe { (*buffer_ref).mUserData as usize };
+        free.push(idx);
+    }
+}
+
+/// Fixed capacity set of free buffer indexes.
+///
+/// Allocated up front, after which pushing and popping is lock-free.
+struct FreeList {
+    words: Box<[AtomicU64]>,
+    waiter: Thread,
+    #[cfg(feature = "async")]
+    waker: crate::waker::AtomicWaker,
+}
+
+impl FreeList {
+    fn new(capacity: usize, waiter: Thread) -> Self {
+        let words = (0..capacity.div_ceil(64))
+            .map(|_| AtomicU64::new(0))
+            .collect();
+        FreeList {
+            words,
+            waiter,
+            #[cfg(feature = "async")]
+            waker: crate::waker::AtomicWaker::new(),
+        }
+    }
+
+    /// Mark the index as free and wake the waiting thread (or task).
+    fn push(&self, idx: usize) {
+        self.words[idx / 64].fetch_or(1 << (idx % 64), Ordering::Release);
+        self.waiter.unpark();
+        #[cfg(feature = "async")]
+        self.waker.wake();
+    }
+
+    /// Take any free index.
+    fn pop(&self) -> Option<usize> {
+        for (w, word) in self.words.iter().enumerate() {
+            let mut current = word.load(Ordering::Acquire);
+            while current != 0 {
+                let bit = current.trailing_zeros() as usize;
+                let next = current & !(1 << bit);
+                match word.compare_exchange_weak(current, next, Ordering::AcqRel, Ordering::Acquire)
+                {
+                    Ok(_) => return Some(w * 64 + bit),
+                    Err(v) => current = v,
+                }
+            }
+        }
+        None
+    }
+
+    /// Take a free index, waiting until the deadline (or forever) for one to be pushed.
+    fn pop_until(&self, deadline: Option<Instant>) -> Option<usize> {
+        loop {
+            if let Some(idx) = self.pop() {
+                return Some(idx);
+            }
+            match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return None;
+                    }
+                    thread::park_timeout(deadline - now);
+                }
+                None => thread::park(),
+            }
+        }
+    }
+}
+
+impl<S: Sample> AudioQueueOutput<S> {
+    pub fn new(
+        format: &StreamFormat,
+        buffer_count: usize,
+        buffer_size: usize,
+    ) -> Result<Self, CAError> {
+        assert_eq!(S::sample_format(), format.sample_format());
+
+        Self::create(format.as_sys_asbd(), buffer_count, buffer_size, 0)
+    }
+}
+
+impl AudioQueueOutput<u8> {
+    /// Creates a queue playing packets of any format, enqueued with
+    /// [`enqueue_packets`](Self::enqueue_packets).
+    ///
+    /// Buffers hold `buffer_size` bytes and up to `max_packets` packet descriptions,
+    /// which can be 0 for formats with packets of constant size. Formats with variable
+    /// packets and no descriptions are an [`AudioCodecError::UnsupportedFormat`].
+    pub fn with_description(
+        description: &FormatDescription,
+        buffer_count: usize,
+        buffer_size: usize,
+        max_packets: usize,
+    ) -> Result<Self, CAError> {
+        if description.has_variable_packets() && max_packets == 0 {
+            return Err(AudioCodecError::UnsupportedFormat.into());
+        }
+        Self::create(
+            &description.as_sys_asbd(),
+            buffer_count,
+            buffer_size,
+            max_packets,
+        )
+    }
+
+    /// Set the magic cookie of the stream, needed before playing formats like AAC or
+    /// Apple Lossless.
+    pub fn set_magic_cookie(&mut self, cookie: &[u8]) -> Result<(), CAError> {
+        unsafe {
+            try_os_status!(sys::AudioQueueSetProperty(
+                self.queue_ref,
+                sys::kAudioQueueProperty_MagicCookie,
+                cookie.as_ptr() as *const c_void,
+                cookie.len() as u32,
+            ))
+        };
+        Ok(())
+    }
+
+    /// Enqueue all packets, waiting for free buffers and filling each with as many
+    /// packets as fit.
+    ///
+    /// Errors with [`AudioCodecError::NotEnoughBufferSpace`] for a packet larger than a
+    /// buffer, after enqueueing the packets before it.
+    pub fn enqueue_packets(&mut self, packets: &PacketBuffer) -> Result<(), CAError> {
+        let mut packets = packets.iter().peekable();
+
+        while packets.peek().is_some() {
+            let index = self.wait_for_buffer(None)?;
+            let mut buffer = self.borrow_buffer(index);
+            // The buffer is released again if it can't be filled.
+            buffer.fill_packets(&mut packets)?;
+            buffer.enqueue()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<S> AudioQueueOutput<S> {
+    fn create(
+        asbd: &sys::AudioStreamBasicDescription,
+        buffer_count: usize,
+        buffer_size: usize,
+        max_packets: usize,
+    ) -> Result<Self, CAError> {
+        let mut queue_ref: sys::AudioQueueRef = std::ptr::null_mut();
+
+        // AudioQueueOutput is not Send, which means the constructing thread is the
+        // one that will be waiting for free buffers.
+        let free = Arc::new(FreeList::new(buffer_count, thread::current()));
+
+        let (panics, panic) = Panics::new();
+
+        let wrapper = Box::new(OutputCallbackWrapper {
+            callback: Box::new(output_proc_fn(free.clone())),
+            panic,
+        });
+
+        let wrapper_ptr = Box::into_raw(wrapper);
+
+        unsafe {
+            try_os_status!(sys::AudioQueueNewOutput(
+                asbd,
+                Some(output_proc),
+                wrapper_ptr as *mut c_void,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                0,
+                &mut queue_ref,
+            ));
+        }
+
+        let mut instance = Self {
+            queue_ref,
+            buffers: Vec::with_capacity(buffer_count),
+            free,
+            running: false,
+            panics,
+            wrapper_ptr,
+        };
+
+        for idx in 0..buffer_count {
+            let buffer = AudioQueueBuffer::new(instance.queue_ref, idx, buffer_size, max_packets)?;
+            instance.buffers.push(buffer);
+            instance.free.push(idx);
+        }
+
+        Ok(instance)
+    }
+
+    pub fn start(&mut self) -> Result<(), CAError> {
+        unsafe { try_os_status!(sys::AudioQueueStart(self.queue_ref, ptr::null_mut())) };
+        self.running = true;
+        Ok(())
+    }
+
+    pub fn stop(&mut self) -> Result<(), CAError> {
+        unsafe { try_os_status!(sys::AudioQueueStop(self.queue_ref, 1)) };
+        self.running = false;
+        Ok(())
+    }
+
+    /// Panics caught in the queue callback.
+    pub fn panics(&self) -> &Panics {
+        &self.panics
+    }
+
+    /// Wait for a free buffer.
+    ///
+    /// Errors with [`CAError::QueueNotRunning`] if there are no free buffers and the queue
+    /// isn't started, since no buffer would ever be released.
+    pub fn request_buffer(&mut self) -> Result<BorrowedAudioQueueBuffer<'_, S>, CAError> {
+        let index = self.wait_for_buffer(None)?;
+        Ok(self.borrow_buffer(index))
+    }
+
+    /// Get a free buffer if there is one, without blocking.
+    pub fn try_request_buffer(&mut self) -> Option<BorrowedAudioQueueBuffer<'_, S>> {
+        let index = self.free.pop()?;
+        Some(self.borrow_buffer(index))
+    }
+
+    /// Wait at most `timeout` for a free buffer.
+    ///
+    /// Errors with [`CAError::Timeout`] if no buffer was released in time.
+    pub fn request_buffer_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<BorrowedAudioQueueBuffer<'_, S>, CAError> {
+        let index = self.wait_for_buffer(Some(Instant::now() + timeout))?;
+        Ok(self.borrow_buffer(index))
+    }
+
+    fn wait_for_buffer(&self, deadline: Option<Instant>) -> Result<usize, CAError> {
+        if let Some(index) = self.free.pop() {
+            return Ok(index);
+        }
+
+        if !self.running {
+            return Err(CAError::QueueNotRunning);
+        }
+
+        // Woken up by the output callback when a buffer is released.
+        self.free.pop_until(deadline).ok_or(CAError::Timeout)
+    }
+
+    fn borrow_buffer(&mut self, index: usize) -> BorrowedAudioQueueBuffer<'_, S> {
+        BorrowedAudioQueueBuffer {
+            output: self,
+            index,
+            was_enqueued: false,
+        }
+    }
+
+    fn enqueue(&mut self, index: usize) -> Result<(), CAError> {
+        let buf = &self.buffers[index];
+        unsafe {
+            try_os_status!(sys::AudioQueueEnqueueBuffer(
+                self.queue_ref,
+                buf.buffer_ref,
+                0,
+                ptr::null_mut()
+            ))
+        };
+        Ok(())
+    }
+}
+
+impl<S> Drop for AudioQueueOutput<S> {
+    fn drop(&mut self) {
+        let _ = self.stop();
+
+        // By dropping the owned buffers we are freeing them.
+        self.buffers.clear();
+
+        unsafe {
+            sys::AudioQueueDispose(self.queue_ref, 1);
+        }
+
+        let _ = unsafe { Box::from_raw(self.wrapper_ptr) };
+    }
+}
+
+pub struct AudioQueueInput<S: Sample> {
+    queue_ref: sys::AudioQueueRef,
+    shared: Arc<InputShared>,
+    panics: Panics,
+    _ph: PhantomData<S>,
+}
+
+/// Callback receiving recorded audio from an [`AudioQueueInput`].
+///
+/// The buffer is given back to the queue when the callback returns, unless it is
+/// kept using [`InputBuffer::hold`].
+pub trait InputCallback<S> {
+    fn audio_input(&mut self, start_time: sys::AudioTimeStamp, buffer: &InputBuffer<S>);
+}
+
+impl<S, T: FnMut(sys::AudioTimeStamp, &InputBuffer<S>)> InputCallback<S> for T {
+    fn audio_input(&mut self, start_time: sys::AudioTimeStamp, buffer: &InputBuffer<S>) {
+        (self)(start_time, buffer)
+    }
+}
+
+/// State shared between an [`AudioQueueInput`] and buffers held outside the callback.
+///
+/// The queue, its buffers and the callback are disposed of once the [`AudioQueueInput`]
+/// and every held buffer are dropped.
+#[derive(Default)]
+pub(crate) struct InputShared {
+    disposed: AtomicBool,
+    in_flight: AtomicUsize,
+    /// The [`AudioQueueInput`] while it isn't dropped, and every held buffer.
+    users: AtomicUsize,
+    queue: OnceLock<OwnedQueue>,
+    /// Memory of detached buffers, see [`InputShared::detached`].
+    _memory: Option<Box<dyn Send + Sync>>,
+}
+
+/// What is disposed of together with an input queue.
+struct OwnedQueue {
+    queue_ref: sys::AudioQueueRef,
+    wrapper_ptr: *mut InputCallbackWrapper,
+}
+
+// Only used by the last user of the queue, from whichever thread that is.
+unsafe impl Send for OwnedQueue {}
+unsafe impl Sync for OwnedQueue {}
+
+impl InputShared {
+    /// State for buffers that don't belong to a queue. Releasing them does nothing.
+    ///
+    /// The memory of the buffers, if any, is kept until neither the callback nor any
+    /// held buffer uses them.
+    pub(crate) fn detached(memory: Option<Box<dyn Send + Sync>>) -> Arc<Self> {
+        let shared = InputShared {
+            _memory: memory,
+            ..InputShared::default()
+        };
+        shared.disposed.store(true, Ordering::SeqCst);
+        Arc::new(shared)
+    }
+
+    /// Give the buffer back to the queue to record into, unless the queue is disposed.
+    fn enqueue(&self, queue_ref: sys::AudioQueueRef, buffer_ref: sys::AudioQueueBufferRef) {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        if !self.disposed.load(Ordering::SeqCst) {
+            unsafe { sys::AudioQueueEnqueueBuffer(queue_ref, buffer_ref, 0, ptr::null()) };
+        }
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Stop buffers from being enqueued and wait for any ongoing enqueue.
+    fn dispose(&self) {
+        self.disposed.store(true, Ordering::SeqCst);
+        while self.in_flight.load(Ordering::SeqCst) > 0 {
+            thread::yield_now();
+        }
+    }
+
+    fn acquire(&self) {
+        self.users.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Drop a user, disposing of the queue if it was the last one.
+    fn release(&self) {
+        if self.users.fetch_sub(1, Ordering::SeqCst) != 1 {
+            return;
+        }
+
+        if let Some(queue) = self.queue.get() {
+            unsafe {
+                // Also frees the buffers.
+                sys::AudioQueueDispose(queue.queue_ref, 1);
+                drop(Box::from_raw(queue.wrapper_ptr));
+            }
+        }
+    }
+}
+
+type InputCallbackFn =
+    dyn FnMut(sys::AudioQueueRef, sys::AudioQueueBufferRef, *const sys::AudioTimeStamp);
+
+struct InputCallbackWrapper {
+    callback: Box<InputCallbackFn>,
+    panic: PanicHandler,
+}
+
+impl<S: Sample> AudioQueueInput<S> {
+    /// Creates a new input queue.
+    ///
+    /// The queue records into `buffer_count` buffers of `buffer_size` samples each.
+    pub fn new(
+        format: &StreamFormat,
+        buffer_count: usize,
+        buffer_size: usize,
+        mut callback: impl InputCallback<S> + 'static,
+    ) -> Result<Self, CAError> {
+        assert_eq!(S::sample_format(), format.sample_format());
+
+        let mut queue_ref: sys::AudioQueueRef = ptr::null_mut();
+
+        let shared = Arc::new(InputShared {
+            users: AtomicUsize::new(1),
+            ..InputShared::default()
+        });
+
+        // This closure gets around the problem of having a generic S in the InputCallback.
+        let input_proc_fn = {
+            let shared = shared.clone();
+            move |queue_ref: sys::AudioQueueRef,
+                  buffer_ref: sys::AudioQueueBufferRef,
+                  start_time: *const sys::AudioTimeStamp| {
+                // The buffer is enqueued again when dropped, also if the callback panics.
+                let buffer = InputBuffer::borrowed(queue_ref, buffer_ref, shared.clone());
+                callback.audio_input(unsafe { *start_time }, &buffer);
+            }
+        };
+
+        let (panics, panic) = Panics::new();
+
+        let wrapper = Box::new(InputCallbackWrapper {
+            callback: Box::new(input_proc_fn),
+            panic,
+        });
+
+        let wrapper_ptr = Box::into_raw(wrapper);
+
+        unsafe {
+            try_os_status!(sys::AudioQueueNewInput(
+                format.as_sys_asbd(),
+                Some(input_proc),
+                wrapper_ptr as *mut c_void,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                0,
+                &mut queue_ref,
+            ));
+        }
+
+        let owned = OwnedQueue {
+            queue_ref,
+            wrapper_ptr,
+        };
+        let _ = shared.queue.set(owned);
+
+        let instance = Self {
+            queue_ref,
+            shared,
+            panics,
+            _ph: PhantomData,
+        };
+
+        for idx in 0..buffer_count {
+            // Freed when the queue is disposed of, which held buffers can delay.
+            let buffer_ref = AudioQueueBuffer::<S>::new(queue_ref, idx, buffer_size, 0)?.into_raw();
+            unsafe {
+                try_os_status!(sys::AudioQueueEnqueueBuffer(
+                    queue_ref,
+                    buffer_ref,
+                    0,
+                    ptr::null()
+                ))
+            };
+        }
+
+        Ok(instance)
+    }
+
+    /// Panics caught in the input callback.
+    ///
+    /// Since the input callback has no way of reporting errors, [`PanicPolicy::Error`]
+    /// is treated as [`PanicPolicy::Silence`], i.e. the recorded buffer is lost.
+    ///
+    /// [`PanicPolicy::Error`]: crate::panic::PanicPolicy::Error
+    /// [`PanicPolicy::Silence`]: crate::panic::PanicPolicy::Silence
+    pub fn panics(&self) -> &Panics {
+        &self.panics
+    }
+
+    pub fn start(&mut self) -> Result<(), CAError> {
+        unsafe { try_os_status!(sys::AudioQueueStart(self.queue_ref, ptr::null_mut())) };
+
+        Ok(())
+    }
+
+    pub fn stop(&mut self) -> Result<(), CAError> {
+        unsafe { try_os_status!(sys::AudioQueueStop(self.queue_ref, 1)) };
+        Ok(())
+    }
+}
+
+impl<S: Sample> Drop for AudioQueueInput<S> {
+    fn drop(&mut self) {
+        let _ = self.stop();
+
+        // Held buffers can't be enqueued after this.
+        self.shared.dispose();
+
+        self.shared.release();
+    }
+}
+
+/// Buffer with recorded audio, handed to the [`InputCallback`].
+pub struct InputBuffer<S> {
+    buffer: AudioQueueBuffer<S>,
+    shared: Arc<InputShared>,
+    held: Cell<bool>,
+}
+
+impl<S> InputBuffer<S> {
+    pub(crate) fn borrowed(
+        queue_ref: sys::AudioQueueRef,
+        buffer_ref: sys::AudioQueueBufferRef,
+        shared: Arc<InputShared>,
+    ) -> Self {
+        InputBuffer {
+            buffer: AudioQueueBuffer::borrowed(queue_ref, buffer_ref),
+            shared,
+            held: Cell::new(false),
+        }
+    }
+
+    /// Keep the buffer after the callback returns.
+    ///
+    /// The buffer goes back to the queue when the returned [`HeldInputBuffer`] is
+    /// dropped. While held, the queue has one buffer less to record into.
+    ///
+    /// Panics if the buffer is already held.
+    pub fn hold(&self) -> HeldInputBuffer<S> {
+        assert!(!self.held.replace(true), "input buffer already held");
+        self.shared.acquire();
+        HeldInputBuffer {
+            buffer: AudioQueueBuffer::borrowed(self.buffer.queue_ref, self.buffer.buffer_ref),
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<S> Drop for InputBuffer<S> {
+    fn drop(&mut self) {
+        if !self.held.get() {
+            self.shared
+                .enqueue(self.buffer.queue_ref, self.buffer.buffer_ref);
+        }
+    }
+}
+
+impl<S> Deref for InputBuffer<S> {
+    type Target = AudioQueueBuffer<S>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.buffer
+    }
+}
+
+/// Recorded audio kept past the [`InputCallback`], see [`InputBuffer::hold`].
+///
+/// Can outlive the [`AudioQueueInput`], which is then only disposed of, and its
+/// callback dropped, once the last held buffer is dropped.
+pub struct HeldInputBuffer<S> {
+    buffer: AudioQueueBuffer<S>,
+    shared: Arc<InputShared>,
+}
+
+// Enqueueing a buffer can be done from any thread.
+unsafe impl<S: Send> Send for HeldInputBuffer<S> {}
+
+impl<S> Drop for HeldInputBuffer<S> {
+    fn drop(&mut self) {
+        self.shared
+            .enqueue(self.buffer.queue_ref, self.buffer.buffer_ref);
+        self.shared.release();
+    }
+}
+
+impl<S> Deref for HeldInputBuffer<S> {
+    type Target = [S];
+
+    fn deref(&self) -> &Self::Target {
+        &self.buffer
+    }
+}
+
+pub struct BorrowedAudioQueueBuffer<'a, S> {
+    output: &'a mut AudioQueueOutput<S>,
+    index: usize,
+    was_enqueued: bool,
+}
+
+impl<'a, S> BorrowedAudioQueueBuffer<'a, S> {
+    pub fn enqueue(mut self) -> Result<(), CAError> {
+        self.was_enqueued = true;
+        self.output.enqueue(self.index)?;
+        Ok(())
+    }
+}
+
+impl<'a, S> Drop for BorrowedAudioQueueBuffer<'a, S> {
+    fn drop(&mut self) {
+        if !self.was_enqueued {
+            // Release straight away if buffer wasn't enqueued.
+            self.output.free.push(self.index);
+        }
+    }
+}
+
+impl<'a, S> Deref for BorrowedAudioQueueBuffer<'a, S> {
+    type Target = AudioQueueBuffer<S>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.output.buffers[self.index]
+    }
+}
+
+impl<'a, S> DerefMut for BorrowedAudioQueueBuffer<'a, S> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.output.buffers[self.index]
+    }
+}
+
+pub struct AudioQueueBuffer<S> {
+    queue_ref: sys::AudioQueueRef,
+    buffer_ref: sys::AudioQueueBufferRef,
+    free_on_drop: bool,
+    _ph: PhantomData<S>,
+}
+
+impl<S> AudioQueueBuffer<S> {
+    /// Allocate a buffer of `len` samples, with room for `max_packets` packet
+    /// descriptions if not 0.
+    fn new(
+        queue_ref: sys::AudioQueueRef,
+        idx: usize,
+        len: usize,
+        max_packets: usize,
+    ) -> Result<AudioQueueBuffer<S>, CAError> {
+        let size = len * mem::size_of::<S>();
+        let mut buffer_ref: sys::AudioQueueBufferRef = ptr::null_mut();
+
+        unsafe {
+            if max_packets > 0 {
+                try_os_status!(sys::AudioQueueAllocateBufferWithPacketDescriptions(
+                    queue_ref,
+                    size as u32,
+                    max_packets as u32,
+                    &mut buffer_ref
+                ));
+            } else {
+                try_os_status!(sys::AudioQueueAllocateBuffer(
+                    queue_ref,
+                    size as u32,
+                    &mut buffer_ref
+                ));
+            }
+
+            // this is just an index so we know which buffer is which.
+            (*buffer_ref).mUserData = idx as *mut c_void;
+        }
+
+        Ok(AudioQueueBuffer {
+            queue_ref,
+            buffer_ref,
+            free_on_drop: true,
+            _ph: PhantomData,
+        })
+    }
+
+    /// The buffer, no longer freed on drop.
+    fn into_raw(mut self) -> sys::AudioQueueBufferRef {
+        self.free_on_drop = false;
+        self.buffer_ref
+    }
+
+    fn borrowed(queue_ref: sys::AudioQueueRef, buffer_ref: sys::AudioQueueBufferRef) -> Self {
+        AudioQueueBuffer {
+            queue_ref,
+            buffer_ref,
+            free_on_drop: false,
+            _ph: PhantomData,
+        }
+    }
+
+    pub fn resize(&mut self, len: usize) {
+        let max_bytes = unsafe { (*self.buffer_ref).mAudioDataBytesCapacity } as usize;
+        let max = max_bytes / mem::size_of::<S>();
+        let clamped = len.clamp(0, max);
+        let byte_size = clamped * mem::size_of::<S>();
+        unsafe { (*self.buffer_ref).mAudioDataByteSize = byte_size as u32 };
+    }
+}
+
+impl AudioQueueBuffer<u8> {
+    /// Copy as many of `packets` as fit into the buffer, with their descriptions.
+    ///
+    /// Errors with [`AudioCodecError::NotEnoughBufferSpace`] if not even the first fits.
+    fn fill_packets(&mut self, packets: &mut Peekable<Packets<'_>>) -> Result<(), CAError> {
+        let raw = unsafe { &mut *self.buffer_ref };
+        let capacity = raw.mAudioDataBytesCapacity as usize;
+        // Formats with packets of constant size need no descriptions.
+        let max_packets = match raw.mPacketDescriptions.is_null() {
+            true => usize::MAX,
+            false => raw.mPacketDescriptionCapacity as usize,
+        };
+
+        let mut size = 0;
+        let mut count = 0;
+        while count < max_packets {
+            let Some(&(packet, data)) = packets.peek() else {
+                break;
+            };
+            if size + data.len() > capacity {
+                break;
+            }
+            packets.next();
+
+            unsafe {
+                let dest = (raw.mAudioData as *mut u8).add(size);
+                ptr::copy_nonoverlapping(data.as_ptr(), dest, data.len());
+                if !raw.mPacketDescriptions.is_null() {
+                    *raw.mPacketDescriptions.add(count) = sys::AudioStreamPacketDescription {
+                        mStartOffset: size as i64,
+                        ..packet.into()
+                    };
+                }
+            }
+
+            size += data.len();
+            count += 1;
+        }
+
+        if count == 0 {
+            return Err(AudioCodecError::NotEnoughBufferSpace.into());
+        }
+
+        raw.mAudioDataByteSize = size as u32;
+        if !raw.mPacketDescriptions.is_null() {
+            raw.mPacketDescriptionCount = count as u32;
+        }
+
+        Ok(())
+    }
+}
+
+impl<S> Drop for AudioQueueBuffer<S> {
+    fn drop(&mut self) {
+        if !self.free_on_drop {
+            return;
+        }
+
+        unsafe {
+            // ignore errors
+            sys::AudioQueueFreeBuffer(self.queue_ref, self.buffer_ref);
+        }
+    }
+}
+
+impl<S> Deref for AudioQueueBuffer<S> {
+    type Target = [S];
+
+    fn deref(&self) -> &Self::Target {
+        let len = unsafe { (*self.buffer_ref).mAudioDataByteSize } as usize / mem::size_of::<S>();
+        unsafe { std::slice::from_raw_parts((*self.buffer_ref).mAudioData as *mut S, len) }
+    }
+}
+
+impl<S> DerefMut for AudioQueueBuffer<S> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        let len = unsafe { (*self.buffer_ref).mAudioDataByteSize } as usize / mem::size_of::<S>();
+        unsafe { std::slice::from_raw_parts_mut((*self.buffer_ref).mAudioData as *mut S, len) }
+    }
+}
+
+unsafe extern "C" fn output_proc(
+    user_data: *mut c_void,
+    _queue_ref: sys::AudioQueueRef,
+    buffer_ref: sys::AudioQueueBufferRef,
+) {
+    let wrapper = &mut *(user_data as *mut OutputCallbackWrapper);
+    // Nothing to do on panic except reporting it. Abort never returns.
+    let _ = wrapper.panic.catch(|| (wrapper.callback)(buffer_ref));
+}
+
+/// The work of the output callback for a queue of `buffer_count` buffers, for the
+/// `output_callback` benchmark.
+///
+/// Each call of the returned closure releases every buffer through the callback and
+/// takes them all again.
+#[cfg(any(test, feature = "bench-internals"))]
+#[doc(hidden)]
+pub fn output_callback_round(buffer_count: usize) -> impl FnMut() {
+    let free = Arc::new(FreeList::new(buffer_count, thread::current()));
+
+    let mut wrapper = OutputCallbackWrapper {
+        callback: Box::new(output_proc_fn(free.clone())),
+        panic: Panics::new().1,
+    };
+
+    let mut buffers: Vec<sys::AudioQueueBuffer> = (0..buffer_count)
+        .map(|idx| sys::AudioQueueBuffer {
+            mAudioDataBytesCapacity: 0,
+            mAudioData: ptr::null_mut(),
+            mAudioDataByteSize: 0,
+            mUserData: idx as *mut c_void,
+            mPacketDescriptionCapacity: 0,
+            mPacketDescriptions: ptr::null_mut(),
+            mPacketDescriptionCount: 0,
+        })
+        .collect();
+
+    move || {
+        for buffer in buffers.iter_mut() {
+            unsafe {
+                output_proc(
+                    &mut wrapper as *mut _ as *mut c_void,
+                    ptr::null_mut(),
+                    buffer,
+                )
+            };
+        }
+
+        let mut taken = 0;
+        while free.pop().is_some() {
+            taken += 1;
+        }
+        assert_eq!(taken, buffer_count);
+    }
+}
+
+unsafe extern "C" fn input_proc(
+    user_data: *mut c_void,
+    queue_ref: sys::AudioQueueRef,
+    buffer_ref: sys::AudioQueueBufferRef,
+    start_time: *const sys::AudioTimeStamp,
+    _: u32,
+    _: *const sys::AudioStreamPacketDescription,
+) {
+    let wrapper = &mut *(user_data as *mut InputCallbackWrapper);
+    // The InputBuffer re-enqueues the buffer when dropped, even while unwinding. Abort
+    // never returns.
+    let _ = wrapper
+        .panic
+        .catch(|| (wrapper.callback)(queue_ref, buffer_ref, start_time));
+}
+
+#[cfg(test)]
+mod test {
+    use crate::format::{LinearPcmFlags, SampleFormat};
+    use crate::generator::{Generator, Waveform};
+
+    use super::*;
+
+    #[test]
+    fn free_list_push_pop() {
+        let free = FreeList::new(130, thread::current());
+        assert_eq!(free.pop(), None);
+
+        for idx in [0, 64, 129] {
+            free.push(idx);
+        }
+
+        let mut popped = vec![free.pop(), free.pop(), free.pop()];
+        popped.sort();
+        assert_eq!(popped, [Some(0), Some(64), Some(129)]);
+        assert_eq!(free.pop(), None);
+    }
+
+    #[test]
+    fn free_list_pop_until() {
+        let free = Arc::new(FreeList::new(1, thread::current()));
+
+        let deadline = Instant::now() + Duration::from_millis(10);
+        assert_eq!(free.pop_until(Some(deadline)), None);
+        assert!(Instant::now() >= deadline);
+
+        let releaser = {
+            let free = free.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(10));
+                free.push(0);
+            })
+        };
+
+        assert_eq!(free.pop_until(None), Some(0));
+        releaser.join().unwrap();
+    }
+
+    #[test]
+    fn output_callback_releases_buffers() {
+        let mut round = output_callback_round(100);
+        for _ in 0..3 {
+            round();
+        }
+    }
+
+    #[test]
+    fn held_input_buffer_outlives_callback() {
+        let mut data = [1.0_f32, 2.0, 3.0, 4.0];
+        let mut raw = sys::AudioQueueBuffer {
+            mAudioDataBytesCapacity: mem::size_of_val(&data) as u32,
+            mAudioData: data.as_mut_ptr() as *mut c_void,
+            mAudioDataByteSize: mem::size_of_val(&data) as u32,
+            mUserData: ptr::null_mut(),
+            mPacketDescriptionCapacity: 0,
+            mPacketDescriptions: ptr::null_mut(),
+            mPacketDescriptionCount: 0,
+        };
+
+        let held = {
+            let buffer = InputBuffer::<f32>::borrowed(
+                ptr::null_mut(),
+                &mut raw,
+                InputShared::detached(None),
+            );
+            buffer.hold()
+        };
+
+        let sum = thread::spawn(move || held.iter().sum::<f32>())
+            .join()
+            .unwrap();
+        assert_eq!(sum, 10.0);
+    }
+
+    #[test]
+    fn held_input_buffer_keeps_queue() {
+        let mut data = [1.0_f32; 4];
+        let mut raw = sys::AudioQueueBuffer {
+            mAudioDataBytesCapacity: mem::size_of_val(&data) as u32,
+            mAudioData: data.as_mut_ptr() as *mut c_void,
+            mAudioDataByteSize: mem::size_of_val(&data) as u32,
+            mUserData: ptr::null_mut(),
+            mPacketDescriptionCapacity: 0,
+            mPacketDescriptions: ptr::null_mut(),
+            mPacketDescriptionCount: 0,
+        };
+
+        // As owned by a queue, which isn't created so there is nothing to dispose of.
+        let shared = Arc::new(InputShared {
+            users: AtomicUsize::new(1),
+            ..InputShared::default()
+        });
+        let held = InputBuffer::<f32>::borrowed(ptr::null_mut(), &mut raw, shared.clone()).hold();
+        assert_eq!(shared.users.load(Ordering::SeqCst), 2);
+
+        // What dropping the queue does.
+        shared.dispose();
+        shared.release();
+        assert_eq!(shared.users.load(Ordering::SeqCst), 1);
+        assert_eq!(held.iter().sum::<f32>(), 4.0);
+
+        drop(held);
+        assert_eq!(shared.users.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "already held")]
+    fn input_buffer_hold_twice() {
+        let mut raw = sys::AudioQueueBuffer {
+            mAudioDataBytesCapacity: 0,
+            mAudioData: ptr::null_mut(),
+            mAudioDataByteSize: 0,
+            mUserData: ptr::null_mut(),
+            mPacketDescriptionCapacity: 0,
+            mPacketDescriptions: ptr::null_mut(),
+            mPacketDescriptionCount: 0,
+        };
+        let buffer =
+            InputBuffer::<f32>::borrowed(ptr::null_mut(), &mut raw, InputShared::detached(None));
+        let _a = buffer.hold();
+        let _b = buffer.hold();
+    }
+
+    #[test]
+    fn fill_packets() {
+        let mut buffer = PacketBuffer::new();
+        for (len, frames) in [(3, 10), (4, 20), (2, 30), (9, 40)] {
+            buffer.push(&vec![len as u8; len], frames);
+        }
+
+        let mut data = [0_u8; 8];
+        let mut descriptions = [sys::AudioStreamPacketDescription {
+            mStartOffset: 0,
+            mVariableFramesInPacket: 0,
+            mDataByteSize: 0,
+        }; 2];
+        let mut raw = sys::AudioQueueBuffer {
+            mAudioDataBytesCapacity: data.len() as u32,
+            mAudioData: data.as_mut_ptr() as *mut c_void,
+            mAudioDataByteSize: 0,
+            mUserData: ptr::null_mut(),
+            mPacketDescriptionCapacity: descriptions.len() as u32,
+            mPacketDescriptions: descriptions.as_mut_ptr(),
+            mPacketDescriptionCount: 0,
+        };
+        let mut queue_buffer = AudioQueueBuffer::<u8>::borrowed(ptr::null_mut(), &mut raw);
+
+        // Limited by the packet descriptions.
+        let mut packets = buffer.iter().peekable();
+        queue_buffer.fill_packets(&mut packets).unwrap();
+        assert_eq!(&*queue_buffer, [3, 3, 3, 4, 4, 4, 4]);
+
+        // Limited by the bytes, the last packet is larger than the buffer.
+        queue_buffer.fill_packets(&mut packets).unwrap();
+        assert_eq!(&*queue_buffer, [2, 2]);
+        assert!(matches!(
+            queue_buffer.fill_packets(&mut packets),
+            Err(CAError::AudioCodecError(
+                AudioCodecError::NotEnoughBufferSpace
+            ))
+        ));
+        assert_eq!(packets.len(), 1);
+        drop(queue_buffer);
+
+        assert_eq!(raw.mPacketDescriptionCount, 1);
+        assert_eq!(descriptions[0].mStartOffset, 0);
+        assert_eq!(descriptions[0].mVariableFramesInPacket, 30);
+        assert_eq!(descriptions[0].mDataByteSize, 2);
+    }
+
+    #[test]
+    fn variable_packets_need_descriptions() {
+        let aac = crate::codec::aac::AudioSpecificConfig::parse(&[0x12, 0x10]).unwrap();
+        assert!(matches!(
+            AudioQueueOutput::with_description(&aac.description(), 3, 4096, 0),
+            Err(CAError::AudioCodecError(AudioCodecError::UnsupportedFormat))
+        ));
+    }
+
+    #[test]
+    fn test_queue_input() {
+        let mut q = AudioQueueInput::<f32>::new(
+            &StreamFormat::new(44_100.0, SampleFormat::F32, LinearPcmFlags::IS_FLOAT, 2),
+            3,
+            2048,
+            move |start_time: sys::AudioTimeStamp, _buffer: &InputBuffer<f32>| {
+                println!("{:?}", start_time);
+            },
+        )
+        .unwrap();
+
+        q.start().unwrap();
+
+        // std::thread::sleep(std::time::Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_queue_output() {
+        let format = StreamFormat::new(48_000.0, SampleFormat::F32, LinearPcmFlags::IS_FLOAT, 1);
+        let mut q = AudioQueueOutput::<f32>::new(&format, 10, 64).unwrap();
+
+        q.start().unwrap();
+
+        let mut generator = Generator::new(Waveform::Sine(440.0), &format);
+        generator.set_amplitude(0.1);
+
+        for _ in 0..300 {
+            let mut buf = q.request_buffer().unwrap();
+            buf.resize(128);
+            generator.fill_queue_buffer(&mut buf);
+
+            buf.enqueue().unwrap();
+        }
+    }
+}
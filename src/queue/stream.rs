@@ -0,0 +1,282 @@
+//! Futures based interface for the audio queues.
+//!
+//! This is runtime agnostic. The futures are woken straight from the queue callbacks.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use crate::format::{Sample, StreamFormat};
+use crate::ring::{Consumer, RingBuffer};
+use crate::waker::AtomicWaker;
+use crate::CAError;
+
+use super::{AudioQueueInput, AudioQueueOutput, BorrowedAudioQueueBuffer, InputBuffer};
+
+impl<S: Sample> AudioQueueOutput<S> {
+    /// Wait for a free buffer.
+    ///
+    /// Async version of [`AudioQueueOutput::request_buffer`]. Like the queue itself,
+    /// the future is not `Send` and must be driven by a local executor.
+    pub fn next_buffer(&mut self) -> NextBuffer<'_, S> {
+        NextBuffer { output: Some(self) }
+    }
+}
+
+/// Future returned by [`AudioQueueOutput::next_buffer`].
+///
+/// It borrows the queue, so it is not `Send` either: poll it on the thread owning the
+/// queue, with a local executor like `futures::executor::block_on` or a tokio
+/// `LocalSet`.
+pub struct NextBuffer<'a, S: Sample> {
+    output: Option<&'a mut AudioQueueOutput<S>>,
+}
+
+impl<'a, S: Sample> Future for NextBuffer<'a, S> {
+    type Output = Result<BorrowedAudioQueueBuffer<'a, S>, CAError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let output = this
+            .output
+            .take()
+            .expect("NextBuffer polled after completion");
+
+        if let Some(index) = output.free.pop() {
+            return Poll::Ready(Ok(output.borrow_buffer(index)));
+        }
+
+        if !output.running {
+            return Poll::Ready(Err(CAError::QueueNotRunning));
+        }
+
+        output.free.waker.register(cx.waker());
+
+        // A buffer might have been released before we registered.
+        if let Some(index) = output.free.pop() {
+            return Poll::Ready(Ok(output.borrow_buffer(index)));
+        }
+
+        this.output = Some(output);
+        Poll::Pending
+    }
+}
+
+impl<S: Sample + Send> AudioQueueInput<S> {
+    /// Creates a new input queue that delivers the recorded audio as a [`Stream`].
+    ///
+    /// The queue is set up like [`AudioQueueInput::new`]. The callback hands over the
+    /// audio through a [`RingBuffer`] holding `capacity` frames. Audio recorded while
+    /// the ring buffer is full is dropped.
+    ///
+    /// [`Stream`]: futures_core::Stream
+    pub fn new_stream(
+        format: &StreamFormat,
+        buffer_count: usize,
+        buffer_size: usize,
+        capacity: usize,
+    ) -> Result<(Self, InputStream<S>), CAError> {
+        let (callback, stream) = input_stream(format.channels(), capacity);
+        let queue = AudioQueueInput::new(format, buffer_count, buffer_size, callback)?;
+        Ok((queue, stream))
+    }
+}
+
+struct StreamShared {
+    waker: AtomicWaker,
+    closed: AtomicBool,
+}
+
+/// Marks the stream as closed when the input callback is dropped together with the queue.
+struct CloseOnDrop(Arc<StreamShared>);
+
+impl Drop for CloseOnDrop {
+    fn drop(&mut self) {
+        self.0.closed.store(true, Ordering::Release);
+        self.0.waker.wake();
+    }
+}
+
+fn input_stream<S: Sample>(
+    channels: usize,
+    capacity: usize,
+) -> (
+    impl FnMut(sys::AudioTimeStamp, &InputBuffer<S>) + 'static,
+    InputStream<S>,
+) {
+    let (mut producer, consumer) = RingBuffer::new(channels, capacity).split();
+
+    let shared = Arc::new(StreamShared {
+        waker: AtomicWaker::new(),
+        closed: AtomicBool::new(false),
+    });
+
+    let close = CloseOnDrop(shared.clone());
+
+    let callback = move |_: sys::AudioTimeStamp, buffer: &InputBuffer<S>| {
+        producer.write_from_queue_buffer(buffer);
+        close.0.waker.wake();
+    };
+
+    (callback, InputStream { consumer, shared })
+}
+
+/// Stream of recorded audio from an [`AudioQueueInput`].
+///
+/// Each item is a chunk of interleaved samples holding everything recorded since the
+/// previous item. The stream ends when the queue is dropped.
+pub struct InputStream<S: Sample> {
+    consumer: Consumer<S>,
+    shared: Arc<StreamShared>,
+}
+
+impl<S: Sample> InputStream<S> {
+    /// Poll for the next chunk of samples.
+    pub fn poll_chunk(&mut self, cx: &mut Context<'_>) -> Poll<Option<Vec<S>>> {
+        // Read closed before draining so that audio written just before closing isn't lost.
+        let closed = self.shared.closed.load(Ordering::Acquire);
+
+        if let Some(chunk) = self.take_chunk() {
+            return Poll::Ready(Some(chunk));
+        }
+
+        if closed {
+            return Poll::Ready(None);
+        }
+
+        self.shared.waker.register(cx.waker());
+
+        if let Some(chunk) = self.take_chunk() {
+            return Poll::Ready(Some(chunk));
+        }
+
+        if self.shared.closed.load(Ordering::Acquire) {
+            return Poll::Ready(None);
+        }
+
+        Poll::Pending
+    }
+
+    fn take_chunk(&mut self) -> Option<Vec<S>> {
+        let frames = self.consumer.available_frames();
+        if frames == 0 {
+            return None;
+        }
+
+        let mut chunk = vec![S::default(); frames * self.consumer.channels()];
+        self.consumer.pop_frames(&mut chunk);
+
+        Some(chunk)
+    }
+}
+
+impl<S: Sample> futures_core::Stream for InputStream<S> {
+    type Item = Vec<S>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_chunk(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::c_void;
+    use std::ptr;
+    use std::sync::atomic::AtomicUsize;
+    use std::task::{Wake, Waker};
+    use std::thread;
+
+    use crate::panic::Panics;
+    use crate::queue::{output_proc, output_proc_fn, FreeList, InputShared, OutputCallbackWrapper};
+
+    use super::*;
+
+    #[derive(Default)]
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn fake_buffer(data: &mut [f32]) -> sys::AudioQueueBuffer {
+        sys::AudioQueueBuffer {
+            mAudioDataBytesCapacity: std::mem::size_of_val(data) as u32,
+            mAudioData: data.as_mut_ptr() as *mut c_void,
+            mAudioDataByteSize: std::mem::size_of_val(data) as u32,
+            mUserData: ptr::null_mut(),
+            mPacketDescriptionCapacity: 0,
+            mPacketDescriptions: ptr::null_mut(),
+            mPacketDescriptionCount: 0,
+        }
+    }
+
+    #[test]
+    fn input_stream_wakes_and_closes() {
+        let (mut callback, mut stream) = input_stream::<f32>(2, 64);
+
+        let wake_count = Arc::new(CountingWaker::default());
+        let waker = Waker::from(wake_count.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        assert_eq!(stream.poll_chunk(&mut cx), Poll::Pending);
+
+        let mut data = [0.1, 0.2, 0.3, 0.4];
+        let mut raw = fake_buffer(&mut data);
+        let buffer = InputBuffer::borrowed(ptr::null_mut(), &mut raw, InputShared::detached(None));
+        callback(sys::AudioTimeStamp::default(), &buffer);
+
+        assert_eq!(wake_count.0.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            stream.poll_chunk(&mut cx),
+            Poll::Ready(Some(vec![0.1, 0.2, 0.3, 0.4]))
+        );
+        assert_eq!(stream.poll_chunk(&mut cx), Poll::Pending);
+
+        // Dropping the callback is what happens when the queue is disposed.
+        drop(callback);
+
+        assert_eq!(wake_count.0.load(Ordering::SeqCst), 2);
+        assert_eq!(stream.poll_chunk(&mut cx), Poll::Ready(None));
+    }
+
+    #[test]
+    fn next_buffer_wakes_on_release() {
+        let free = Arc::new(FreeList::new(1, thread::current()));
+        let (panics, panic) = Panics::new();
+        let wrapper = Box::new(OutputCallbackWrapper {
+            callback: Box::new(output_proc_fn(free.clone())),
+            panic,
+        });
+        let mut output = AudioQueueOutput::<f32> {
+            queue_ref: ptr::null_mut(),
+            buffers: Vec::new(),
+            free,
+            running: true,
+            panics,
+            wrapper_ptr: Box::into_raw(wrapper),
+        };
+        let wrapper_ptr = output.wrapper_ptr;
+
+        let wake_count = Arc::new(CountingWaker::default());
+        let waker = Waker::from(wake_count.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        let mut next = output.next_buffer();
+        assert!(Pin::new(&mut next).poll(&mut cx).is_pending());
+        assert_eq!(wake_count.0.load(Ordering::SeqCst), 0);
+
+        // The queue is done with buffer 0.
+        let mut raw = fake_buffer(&mut []);
+        unsafe { output_proc(wrapper_ptr as *mut c_void, ptr::null_mut(), &mut raw) };
+
+        assert_eq!(wake_count.0.load(Ordering::SeqCst), 1);
+        match Pin::new(&mut next).poll(&mut cx) {
+            Poll::Ready(Ok(buffer)) => assert_eq!(buffer.index, 0),
+            _ => panic!("no buffer after the release"),
+        };
+    }
+}
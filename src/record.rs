@@ -0,0 +1,700 @@
+//! Recording audio to files.
+//!
+//! A [`Recorder`] owns a writer thread that drains a [`RingBuffer`] into WAV or CAF
+//! files. The audio is pushed into the ring buffer through a [`RecorderInput`], usually
+//! from an [`AudioQueueInput`] callback, see [`Recorder::from_input`].
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle, Thread};
+use std::time::Duration;
+
+use crate::error::AudioFormatError;
+use crate::file::caf::{self, CafWriter};
+use crate::file::wav::{self, WavWriter};
+use crate::format::{LinearPcmFlags, SampleConvert, StreamFormat};
+use crate::queue::{AudioQueueInput, InputBuffer};
+use crate::ring::{Consumer, Producer, RingBuffer};
+use crate::CAError;
+
+/// How long the writer thread sleeps when there is nothing to write.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Container formats that can be recorded to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    /// RIFF/WAVE.
+    Wav,
+    /// Core Audio Format.
+    Caf,
+}
+
+impl FileType {
+    /// File name extension, without the dot.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            FileType::Wav => "wav",
+            FileType::Caf => "caf",
+        }
+    }
+}
+
+/// When to start a new file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rotation {
+    /// Write everything to a single file.
+    Never,
+    /// Start a new file after this much audio.
+    Duration(Duration),
+    /// Start a new file before a file grows larger than this many bytes, including the
+    /// header.
+    Bytes(u64),
+}
+
+/// Configuration of a [`Recorder`].
+#[derive(Debug, Clone)]
+pub struct RecorderConfig {
+    /// Directory the files are written to.
+    pub directory: PathBuf,
+    /// Files are named `{prefix}-{index:04}.{extension}`, with the index counting from 0,
+    /// like `recording-0000.wav`.
+    pub prefix: String,
+    pub file_type: FileType,
+    pub rotation: Rotation,
+    /// Stop recording after this much audio.
+    pub max_duration: Option<Duration>,
+    /// Size of the ring buffer between the input and the writer thread, in frames.
+    pub capacity: usize,
+}
+
+impl RecorderConfig {
+    /// Record to a single WAV file in the directory, with one second of buffering at
+    /// 48kHz.
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        RecorderConfig {
+            directory: directory.into(),
+            prefix: "recording".to_string(),
+            file_type: FileType::Wav,
+            rotation: Rotation::Never,
+            max_duration: None,
+            capacity: 48_000,
+        }
+    }
+
+    fn path(&self, index: usize) -> PathBuf {
+        self.directory.join(format!(
+            "{}-{:04}.{}",
+            self.prefix,
+            index,
+            self.file_type.extension()
+        ))
+    }
+}
+
+/// State of a [`Recorder`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecorderState {
+    /// Incoming audio is written to disk.
+    Recording,
+    /// Incoming audio is dropped, the current file stays open.
+    Paused,
+    /// The max duration has been reached, incoming audio is dropped.
+    Finished,
+    /// The writer thread has been asked to stop.
+    Stopped,
+    /// Writing failed and the writer thread exited, incoming audio is dropped.
+    /// [`Recorder::stop`] returns the error.
+    Failed,
+}
+
+impl RecorderState {
+    fn from_u8(v: u8) -> Self {
+        match v {
+            0 => RecorderState::Recording,
+            1 => RecorderState::Paused,
+            2 => RecorderState::Finished,
+            3 => RecorderState::Stopped,
+            _ => RecorderState::Failed,
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            RecorderState::Recording => 0,
+            RecorderState::Paused => 1,
+            RecorderState::Finished => 2,
+            RecorderState::Stopped => 3,
+            RecorderState::Failed => 4,
+        }
+    }
+}
+
+struct RecorderShared {
+    state: AtomicU8,
+    frames_written: AtomicU64,
+    frames_dropped: AtomicU64,
+    writer: Thread,
+}
+
+impl RecorderShared {
+    fn state(&self) -> RecorderState {
+        RecorderState::from_u8(self.state.load(Ordering::Acquire))
+    }
+
+    /// Change the state, unless the recorder already finished, stopped or failed.
+    fn transition(&self, to: RecorderState) {
+        self.state
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |current| {
+                match RecorderState::from_u8(current) {
+                    RecorderState::Recording | RecorderState::Paused => Some(to.to_u8()),
+                    _ => None,
+                }
+            })
+            .ok();
+        self.writer.unpark();
+    }
+}
+
+/// Records audio to one or more files on a writer thread.
+///
+/// The recorder starts out paused. Dropping it stops the writer thread and finalizes
+/// the current file, use [`Recorder::stop`] to find out if that succeeded.
+pub struct Recorder {
+    shared: Arc<RecorderShared>,
+    thread: Option<JoinHandle<Result<Vec<PathBuf>, CAError>>>,
+}
+
+/// The audio side of a [`Recorder`].
+///
+/// Pushing never blocks or allocates, it's safe to call from an audio callback.
+pub struct RecorderInput<S: SampleConvert> {
+    producer: Producer<S>,
+    shared: Arc<RecorderShared>,
+}
+
+impl Recorder {
+    /// Creates a recorder writing audio in the format, and the input to push the audio
+    /// into.
+    ///
+    /// The format must be interleaved, non-interleaved formats are rejected with
+    /// [`AudioFormatError::UnsupportedDataFormat`].
+    pub fn new<S: SampleConvert + Send>(
+        format: &StreamFormat,
+        config: RecorderConfig,
+    ) -> Result<(Recorder, RecorderInput<S>), CAError> {
+        if S::sample_format() != format.sample_format() {
+            return Err(CAError::Other(
+                "sample type does not match the stream format".into(),
+            ));
+        }
+        if format.flags().contains(LinearPcmFlags::IS_NON_INTERLEAVED) {
+            return Err(AudioFormatError::UnsupportedDataFormat.into());
+        }
+
+        std::fs::create_dir_all(&config.directory).map_err(CAError::from_io_error)?;
+
+        let (producer, consumer) = RingBuffer::new(format.channels(), config.capacity).split();
+
+        let format = format.clone();
+        let (shared_tx, shared_rx) = std::sync::mpsc::channel::<Arc<RecorderShared>>();
+
+        let thread = thread::Builder::new()
+            .name("caudio-recorder".to_string())
+            .spawn(move || {
+                // The shared state needs the handle of this thread, wait for it.
+                let shared = shared_rx.recv().expect("recorder dropped while starting");
+                let mut writer = SegmentedWriter::new(format, config, consumer, &shared);
+                let result = writer.run();
+                if result.is_err() {
+                    shared
+                        .state
+                        .store(RecorderState::Failed.to_u8(), Ordering::Release);
+                }
+                result
+            })
+            .map_err(CAError::from_io_error)?;
+
+        let shared = Arc::new(RecorderShared {
+            state: AtomicU8::new(RecorderState::Paused.to_u8()),
+            frames_written: AtomicU64::new(0),
+            frames_dropped: AtomicU64::new(0),
+            writer: thread.thread().clone(),
+        });
+
+        shared_tx
+            .send(shared.clone())
+            .expect("recorder thread exited while starting");
+
+        let recorder = Recorder {
+            shared: shared.clone(),
+            thread: Some(thread),
+        };
+
+        Ok((recorder, RecorderInput { producer, shared }))
+    }
+
+    /// Creates a new input queue that records into a recorder.
+    ///
+    /// The queue is set up like [`AudioQueueInput::new`]. Both the queue and the
+    /// recorder need to be started for audio to be written.
+    pub fn from_input<S: SampleConvert + Send>(
+        format: &StreamFormat,
+        buffer_count: usize,
+        buffer_size: usize,
+        config: RecorderConfig,
+    ) -> Result<(AudioQueueInput<S>, Recorder), CAError> {
+        let (recorder, mut input) = Recorder::new(format, config)?;
+
+        let queue = AudioQueueInput::new(
+            format,
+            buffer_count,
+            buffer_size,
+            move |_: sys::AudioTimeStamp, buffer: &InputBuffer<S>| {
+                input.push(buffer);
+            },
+        )?;
+
+        Ok((queue, recorder))
+    }
+
+    pub fn state(&self) -> RecorderState {
+        self.shared.state()
+    }
+
+    /// Start or resume writing incoming audio.
+    pub fn start(&self) {
+        self.shared.transition(RecorderState::Recording);
+    }
+
+    /// Drop incoming audio until started again.
+    pub fn pause(&self) {
+        self.shared.transition(RecorderState::Paused);
+    }
+
+    /// Number of frames written to disk so far, across all files.
+    pub fn frames_written(&self) -> u64 {
+        self.shared.frames_written.load(Ordering::Relaxed)
+    }
+
+    /// Number of frames dropped because the writer thread couldn't keep up.
+    pub fn frames_dropped(&self) -> u64 {
+        self.shared.frames_dropped.load(Ordering::Relaxed)
+    }
+
+    /// Write the remaining buffered audio, finalize the current file and stop the writer
+    /// thread.
+    ///
+    /// Returns the paths of all files written.
+    pub fn stop(mut self) -> Result<Vec<PathBuf>, CAError> {
+        self.join()
+    }
+
+    fn join(&mut self) -> Result<Vec<PathBuf>, CAError> {
+        self.shared
+            .state
+            .store(RecorderState::Stopped.to_u8(), Ordering::Release);
+
+        let thread = self.thread.take().expect("recorder already stopped");
+        thread.thread().unpark();
+
+        match thread.join() {
+            Ok(result) => result,
+            Err(_) => Err(CAError::Other("recorder thread panicked".into())),
+        }
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        if self.thread.is_some() {
+            self.join().ok();
+        }
+    }
+}
+
+impl<S: SampleConvert> RecorderInput<S> {
+    /// Push interleaved samples.
+    ///
+    /// The samples are dropped unless the recorder is recording.
+    pub fn push(&mut self, samples: &[S]) {
+        if self.shared.state() != RecorderState::Recording {
+            return;
+        }
+
+        let frames = samples.len() / self.producer.channels();
+        let pushed = self.producer.push_frames(samples);
+
+        if pushed < frames {
+            self.shared
+                .frames_dropped
+                .fetch_add((frames - pushed) as u64, Ordering::Relaxed);
+        }
+
+        self.shared.writer.unpark();
+    }
+}
+
+enum FileWriter {
+    Wav(WavWriter<BufWriter<File>>),
+    Caf(CafWriter<BufWriter<File>>),
+}
+
+impl FileWriter {
+    fn create(path: &Path, file_type: FileType, format: &StreamFormat) -> Result<Self, CAError> {
+        Ok(match file_type {
+            FileType::Wav => FileWriter::Wav(WavWriter::create(path, format)?),
+            FileType::Caf => FileWriter::Caf(CafWriter::create(path, format)?),
+        })
+    }
+
+    fn write_samples<S: SampleConvert>(&mut self, samples: &[S]) -> Result<(), CAError> {
+        match self {
+            FileWriter::Wav(w) => w.write_samples(samples),
+            FileWriter::Caf(w) => w.write_samples(samples),
+        }
+    }
+
+    fn finalize(self) -> Result<(), CAError> {
+        match self {
+            FileWriter::Wav(w) => w.finalize().map(drop),
+            FileWriter::Caf(w) => w.finalize().map(drop),
+        }
+    }
+}
+
+/// The writer thread.
+struct SegmentedWriter<'a, S: SampleConvert> {
+    format: StreamFormat,
+    config: RecorderConfig,
+    consumer: Consumer<S>,
+    shared: &'a RecorderShared,
+    scratch: Vec<S>,
+    current: Option<FileWriter>,
+    /// Frames written to the current file.
+    segment_frames: u64,
+    /// Frames allowed per file.
+    segment_limit: u64,
+    /// Frames allowed in total.
+    total_limit: u64,
+    paths: Vec<PathBuf>,
+}
+
+impl<'a, S: SampleConvert> SegmentedWriter<'a, S> {
+    fn new(
+        format: StreamFormat,
+        config: RecorderConfig,
+        consumer: Consumer<S>,
+        shared: &'a RecorderShared,
+    ) -> Self {
+        let sample_rate = format.sample_rate();
+        let frame_bytes = (format.sample_format().size_in_bytes() * format.channels()) as u64;
+
+        let header_size = match config.file_type {
+            FileType::Wav => wav::header_size(&format),
+            FileType::Caf => caf::HEADER_SIZE,
+        };
+
+        let segment_limit = match config.rotation {
+            Rotation::Never => u64::MAX,
+            Rotation::Duration(d) => duration_to_frames(d, sample_rate),
+            Rotation::Bytes(bytes) => bytes.saturating_sub(header_size) / frame_bytes,
+        }
+        .max(1);
+
+        let total_limit = config
+            .max_duration
+            .map(|d| duration_to_frames(d, sample_rate))
+            .unwrap_or(u64::MAX);
+
+        let scratch = vec![S::default(); consumer.capacity() * consumer.channels()];
+
+        SegmentedWriter {
+            format,
+            config,
+            consumer,
+            shared,
+            scratch,
+            current: None,
+            segment_frames: 0,
+            segment_limit,
+            total_limit,
+            paths: Vec::new(),
+        }
+    }
+
+    fn run(&mut self) -> Result<Vec<PathBuf>, CAError> {
+        let result = self.write_until_stopped();
+
+        // Finalize even after an error, to leave a readable file behind.
+        let finalized = match self.current.take() {
+            Some(file) => file.finalize(),
+            None => Ok(()),
+        };
+
+        result.and(finalized)?;
+
+        Ok(std::mem::take(&mut self.paths))
+    }
+
+    fn write_until_stopped(&mut self) -> Result<(), CAError> {
+        loop {
+            // Read the state before draining so that audio pushed before stopping is
+            // written.
+            let stopped = self.shared.state() == RecorderState::Stopped;
+
+            let written = self.drain()?;
+
+            if stopped {
+                return Ok(());
+            }
+
+            if written == 0 {
+                thread::park_timeout(POLL_INTERVAL);
+            }
+        }
+    }
+
+    /// Write everything in the ring buffer, returns the number of frames popped.
+    fn drain(&mut self) -> Result<usize, CAError> {
+        let channels = self.consumer.channels();
+        let frames = self.consumer.pop_frames(&mut self.scratch);
+
+        let mut offset = 0;
+
+        while offset < frames {
+            let total = self.shared.frames_written.load(Ordering::Relaxed);
+
+            if total >= self.total_limit {
+                // Drop the rest.
+                break;
+            }
+
+            if self.segment_frames >= self.segment_limit {
+                if let Some(file) = self.current.take() {
+                    file.finalize()?;
+                }
+            }
+
+            if self.current.is_none() {
+                let path = self.config.path(self.paths.len());
+                self.current = Some(FileWriter::create(
+                    &path,
+                    self.config.file_type,
+                    &self.format,
+                )?);
+                self.paths.push(path);
+                self.segment_frames = 0;
+            }
+
+            let n = ((frames - offset) as u64)
+                .min(self.segment_limit - self.segment_frames)
+                .min(self.total_limit - total) as usize;
+
+            let samples = &self.scratch[offset * channels..(offset + n) * channels];
+            self.current.as_mut().unwrap().write_samples(samples)?;
+
+            offset += n;
+            self.segment_frames += n as u64;
+            self.shared
+                .frames_written
+                .fetch_add(n as u64, Ordering::Relaxed);
+
+            if total + n as u64 >= self.total_limit {
+                self.shared.transition(RecorderState::Finished);
+            }
+        }
+
+        Ok(frames)
+    }
+}
+
+fn duration_to_frames(duration: Duration, sample_rate: f64) -> u64 {
+    (duration.as_secs_f64() * sample_rate).round() as u64
+}
+
+impl std::fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Recorder")
+            .field("state", &self.state())
+            .field("frames_written", &self.frames_written())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::file::wav::WavReader;
+    use crate::format::SampleFormat;
+
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("caudio-{}-{}", name, std::process::id()));
+        std::fs::remove_dir_all(&dir).ok();
+        dir
+    }
+
+    fn format() -> StreamFormat {
+        StreamFormat::new(
+            1_000.0,
+            SampleFormat::I16,
+            LinearPcmFlags::IS_SIGNED_INTEGER,
+            2,
+        )
+    }
+
+    /// Push `frames` frames of a ramp as a synthetic input source, in small chunks like
+    /// a queue would.
+    fn push_ramp(input: &mut RecorderInput<i16>, start: i16, frames: usize) {
+        let samples: Vec<i16> = (0..frames as i16)
+            .flat_map(|i| [start + i, -(start + i)])
+            .collect();
+
+        for chunk in samples.chunks(20) {
+            input.push(chunk);
+        }
+    }
+
+    fn wait_for_frames(recorder: &Recorder, frames: u64) {
+        while recorder.frames_written() < frames {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn wav_data(path: &Path) -> Vec<i16> {
+        let mut reader = WavReader::open(path).unwrap();
+        let mut samples = vec![0_i16; reader.frames() as usize * reader.format().channels()];
+        reader.read_samples(&mut samples).unwrap();
+        samples
+    }
+
+    #[test]
+    fn record_pause_and_stop() {
+        let dir = temp_dir("record");
+        let (recorder, mut input) = Recorder::new(&format(), RecorderConfig::new(&dir)).unwrap();
+
+        // Starts paused.
+        push_ramp(&mut input, 1000, 10);
+
+        recorder.start();
+        push_ramp(&mut input, 0, 100);
+        wait_for_frames(&recorder, 100);
+
+        recorder.pause();
+        push_ramp(&mut input, 1000, 10);
+
+        recorder.start();
+        push_ramp(&mut input, 100, 50);
+
+        let paths = recorder.stop().unwrap();
+        assert_eq!(paths, vec![dir.join("recording-0000.wav")]);
+
+        let data = wav_data(&paths[0]);
+        let expected: Vec<i16> = (0..150).flat_map(|i| [i, -i]).collect();
+        assert_eq!(data, expected);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn non_interleaved() {
+        let format = StreamFormat::new(
+            1_000.0,
+            SampleFormat::I16,
+            LinearPcmFlags::IS_SIGNED_INTEGER | LinearPcmFlags::IS_NON_INTERLEAVED,
+            2,
+        );
+        let dir = temp_dir("non-interleaved");
+        assert!(matches!(
+            Recorder::new::<i16>(&format, RecorderConfig::new(&dir)),
+            Err(CAError::AudioFormatError(
+                AudioFormatError::UnsupportedDataFormat
+            ))
+        ));
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn writer_failure() {
+        let dir = temp_dir("failure");
+        let (recorder, mut input) = Recorder::new(&format(), RecorderConfig::new(&dir)).unwrap();
+
+        // Creating the first file fails.
+        std::fs::remove_dir_all(&dir).unwrap();
+        recorder.start();
+        push_ramp(&mut input, 0, 10);
+
+        while recorder.state() != RecorderState::Failed {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        // Stays failed.
+        recorder.start();
+        assert_eq!(recorder.state(), RecorderState::Failed);
+        assert!(recorder.stop().is_err());
+    }
+
+    #[test]
+    fn rotate_and_cap() {
+        let dir = temp_dir("rotate");
+        let config = RecorderConfig {
+            rotation: Rotation::Duration(Duration::from_millis(40)),
+            max_duration: Some(Duration::from_millis(100)),
+            ..RecorderConfig::new(&dir)
+        };
+
+        let (recorder, mut input) = Recorder::new(&format(), config).unwrap();
+        recorder.start();
+
+        push_ramp(&mut input, 0, 130);
+
+        while recorder.state() != RecorderState::Finished {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        let paths = recorder.stop().unwrap();
+        assert_eq!(paths.len(), 3);
+
+        let lengths: Vec<usize> = paths.iter().map(|p| wav_data(p).len() / 2).collect();
+        assert_eq!(lengths, vec![40, 40, 20]);
+
+        assert_eq!(wav_data(&paths[1])[0], 40);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn rotate_by_bytes_caf() {
+        let dir = temp_dir("bytes");
+        let config = RecorderConfig {
+            file_type: FileType::Caf,
+            rotation: Rotation::Bytes(caf::HEADER_SIZE + 4 * 25),
+            ..RecorderConfig::new(&dir)
+        };
+
+        let (recorder, mut input) = Recorder::new(&format(), config).unwrap();
+        recorder.start();
+        push_ramp(&mut input, 0, 60);
+
+        let paths = recorder.stop().unwrap();
+
+        let sizes: Vec<u64> = paths
+            .iter()
+            .map(|p| std::fs::metadata(p).unwrap().len())
+            .collect();
+
+        assert_eq!(
+            sizes,
+            vec![
+                caf::HEADER_SIZE + 4 * 25,
+                caf::HEADER_SIZE + 4 * 25,
+                caf::HEADER_SIZE + 4 * 10
+            ]
+        );
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,479 @@
+//! Lock-free single producer/single consumer ring buffer.
+//!
+//! Used to move audio frames between a realtime callback (such as an
+//! [`InputCallback`][crate::queue::InputCallback] or a
+//! [`RenderCallback`][crate::unit::RenderCallback]) and a normal application thread.
+//! Both sides are wait-free: no locks, no allocations and no retry loops.
+
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::format::Sample;
+use crate::queue::AudioQueueBuffer;
+use crate::unit::AudioBufferList;
+
+/// Ring buffer holding whole multi-channel frames.
+///
+/// The buffer itself is only used to create the [`Producer`] and [`Consumer`] halves.
+pub struct RingBuffer<S: Sample> {
+    shared: Arc<Shared<S>>,
+}
+
+struct Shared<S> {
+    data: Box<[UnsafeCell<S>]>,
+    channels: usize,
+    /// Capacity in frames.
+    capacity: usize,
+    /// Slots minus one. There is a power of two of slots, so that positions wrapping
+    /// around `usize` still map to consecutive slots.
+    mask: usize,
+    /// Read position in frames. Only ever increases (wrapping).
+    head: AtomicUsize,
+    /// Write position in frames. Only ever increases (wrapping).
+    tail: AtomicUsize,
+    overruns: AtomicUsize,
+    underruns: AtomicUsize,
+}
+
+// The producer only writes slots between tail and head + capacity, the consumer only
+// reads slots between head and tail. The atomics hand over ownership of the slots.
+unsafe impl<S: Send> Sync for Shared<S> {}
+
+/// Writing half of a [`RingBuffer`].
+pub struct Producer<S: Sample> {
+    shared: Arc<Shared<S>>,
+}
+
+/// Reading half of a [`RingBuffer`].
+pub struct Consumer<S: Sample> {
+    shared: Arc<Shared<S>>,
+}
+
+unsafe impl<S: Sample + Send> Send for Producer<S> {}
+unsafe impl<S: Sample + Send> Send for Consumer<S> {}
+
+impl<S: Sample> RingBuffer<S> {
+    /// Creates a new ring buffer.
+    ///
+    /// * `channels` is the number of interleaved channels per frame.
+    ///
+    /// * `frames` is the capacity in frames.
+    pub fn new(channels: usize, frames: usize) -> Self {
+        assert!(channels >= 1);
+        assert!(frames >= 1);
+
+        let slots = frames
+            .checked_next_power_of_two()
+            .expect("ring buffer capacity overflow");
+        let data = (0..channels * slots)
+            .map(|_| UnsafeCell::new(S::default()))
+            .collect();
+
+        RingBuffer {
+            shared: Arc::new(Shared {
+                data,
+                channels,
+                capacity: frames,
+                mask: slots - 1,
+                head: AtomicUsize::new(0),
+                tail: AtomicUsize::new(0),
+                overruns: AtomicUsize::new(0),
+                underruns: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// Split the buffer into its producer and consumer halves.
+    pub fn split(self) -> (Producer<S>, Consumer<S>) {
+        let producer = Producer {
+            shared: self.shared.clone(),
+        };
+        let consumer = Consumer {
+            shared: self.shared,
+        };
+        (producer, consumer)
+    }
+}
+
+impl<S> Shared<S> {
+    fn len(&self, head: usize, tail: usize) -> usize {
+        tail.wrapping_sub(head)
+    }
+
+    /// Pointer to the first sample of the frame at position `pos`.
+    fn slot(&self, pos: usize, channel: usize) -> *mut S {
+        let idx = (pos & self.mask) * self.channels + channel;
+        self.data[idx].get()
+    }
+}
+
+impl<S: Sample> Producer<S> {
+    /// Number of interleaved channels per frame.
+    pub fn channels(&self) -> usize {
+        self.shared.channels
+    }
+
+    /// Capacity in frames.
+    pub fn capacity(&self) -> usize {
+        self.shared.capacity
+    }
+
+    /// Number of frames that can be written without overrun.
+    pub fn free_frames(&self) -> usize {
+        let s = &self.shared;
+        let head = s.head.load(Ordering::Acquire);
+        let tail = s.tail.load(Ordering::Relaxed);
+        s.capacity - s.len(head, tail)
+    }
+
+    /// Total number of frames dropped because the buffer was full.
+    pub fn overruns(&self) -> usize {
+        self.shared.overruns.load(Ordering::Relaxed)
+    }
+
+    /// Write interleaved frames.
+    ///
+    /// Returns the number of frames written. Frames that don't fit are dropped and
+    /// counted as overruns.
+    pub fn push_frames(&mut self, samples: &[S]) -> usize {
+        let channels = self.shared.channels;
+        debug_assert_eq!(samples.len() % channels, 0);
+
+        let frames = samples.len() / channels;
+        self.write_with(frames, |frame, channel| {
+            &samples[frame * channels + channel]
+        })
+    }
+
+    /// Write all frames of an [`AudioBufferList`].
+    ///
+    /// Works for both interleaved and non-interleaved lists, as long as the total
+    /// number of channels across the buffers matches the ring buffer.
+    ///
+    /// Returns the number of frames written.
+    pub fn write_from_buffer_list(&mut self, list: &AudioBufferList<S>) -> usize {
+        let buffers = list.buffers();
+        let total_channels: usize = buffers.iter().map(|b| b.channels()).sum();
+        assert_eq!(
+            total_channels, self.shared.channels,
+            "channel count mismatch"
+        );
+
+        let frames = buffers.iter().map(|b| b.frames()).min().unwrap_or(0);
+
+        self.write_with(frames, |frame, channel| {
+            let (buffer, channel) = locate(buffers.iter().map(|b| b.channels()), channel);
+            let buf = &buffers[buffer];
+            &buf.samples()[frame * buf.channels() + channel]
+        })
+    }
+
+    /// Write the interleaved contents of an [`AudioQueueBuffer`].
+    ///
+    /// Returns the number of frames written.
+    pub fn write_from_queue_buffer(&mut self, buffer: &AudioQueueBuffer<S>) -> usize {
+        self.push_frames(buffer)
+    }
+
+    fn write_with<'a>(&mut self, frames: usize, sample: impl Fn(usize, usize) -> &'a S) -> usize {
+        let s = &self.shared;
+        let head = s.head.load(Ordering::Acquire);
+        let tail = s.tail.load(Ordering::Relaxed);
+
+        let free = s.capacity - s.len(head, tail);
+        let n = frames.min(free);
+
+        for frame in 0..n {
+            let pos = tail.wrapping_add(frame);
+            for channel in 0..s.channels {
+                // SAFETY: slots between tail and head + capacity belong to the producer.
+                unsafe { *s.slot(pos, channel) = sample(frame, channel).clone() };
+            }
+        }
+
+        s.tail.store(tail.wrapping_add(n), Ordering::Release);
+
+        if n < frames {
+            s.overruns.fetch_add(frames - n, Ordering::Relaxed);
+        }
+
+        n
+    }
+}
+
+impl<S: Sample> Consumer<S> {
+    /// Number of interleaved channels per frame.
+    pub fn channels(&self) -> usize {
+        self.shared.channels
+    }
+
+    /// Capacity in frames.
+    pub fn capacity(&self) -> usize {
+        self.shared.capacity
+    }
+
+    /// Number of frames available for reading.
+    pub fn available_frames(&self) -> usize {
+        let s = &self.shared;
+        let head = s.head.load(Ordering::Relaxed);
+        let tail = s.tail.load(Ordering::Acquire);
+        s.len(head, tail)
+    }
+
+    /// Total number of frames that were padded with silence because the buffer was empty.
+    pub fn underruns(&self) -> usize {
+        self.shared.underruns.load(Ordering::Relaxed)
+    }
+
+    /// Read interleaved frames into `out`.
+    ///
+    /// Returns the number of frames read, which might be less than fits in `out`.
+    pub fn pop_frames(&mut self, out: &mut [S]) -> usize {
+        let channels = self.shared.channels;
+        debug_assert_eq!(out.len() % channels, 0);
+
+        let frames = out.len() / channels;
+        self.read_with(frames, |frame, channel, v| {
+            out[frame * channels + channel] = v
+        })
+    }
+
+    /// Fill all frames of an [`AudioBufferList`].
+    ///
+    /// Frames that aren't available are filled with silence and counted as underruns.
+    ///
+    /// Returns the number of frames read from the ring buffer.
+    pub fn read_into_buffer_list(&mut self, list: &mut AudioBufferList<S>) -> usize {
+        let buffers = list.buffers_mut();
+        let total_channels: usize = buffers.iter().map(|b| b.channels()).sum();
+        assert_eq!(
+            total_channels, self.shared.channels,
+            "channel count mismatch"
+        );
+
+        let frames = buffers.iter().map(|b| b.frames()).min().unwrap_or(0);
+
+        let mut write = |frame: usize, channel: usize, v: S| {
+            let (buffer, channel) = locate(buffers.iter().map(|b| b.channels()), channel);
+            let buf = &mut buffers[buffer];
+            let buf_channels = buf.channels();
+            buf.samples_mut()[frame * buf_channels + channel] = v;
+        };
+
+        let n = self.read_with(frames, &mut write);
+        self.pad_with(n, frames, write);
+
+        n
+    }
+
+    /// Fill the whole [`AudioQueueBuffer`] with interleaved frames.
+    ///
+    /// Frames that aren't available are filled with silence and counted as underruns.
+    ///
+    /// Returns the number of frames read from the ring buffer.
+    pub fn read_into_queue_buffer(&mut self, buffer: &mut AudioQueueBuffer<S>) -> usize {
+        let channels = self.shared.channels;
+        let frames = buffer.len() / channels;
+
+        let mut write = |frame: usize, channel: usize, v: S| buffer[frame * channels + channel] = v;
+
+        let n = self.read_with(frames, &mut write);
+        self.pad_with(n, frames, write);
+
+        n
+    }
+
+    fn read_with(&mut self, frames: usize, mut write: impl FnMut(usize, usize, S)) -> usize {
+        let s = &self.shared;
+        let head = s.head.load(Ordering::Relaxed);
+        let tail = s.tail.load(Ordering::Acquire);
+
+        let n = frames.min(s.len(head, tail));
+
+        for frame in 0..n {
+            let pos = head.wrapping_add(frame);
+            for channel in 0..s.channels {
+                // SAFETY: slots between head and tail belong to the consumer.
+                let v = unsafe { (*s.slot(pos, channel)).clone() };
+                write(frame, channel, v);
+            }
+        }
+
+        s.head.store(head.wrapping_add(n), Ordering::Release);
+
+        n
+    }
+
+    fn pad_with(&mut self, from: usize, to: usize, mut write: impl FnMut(usize, usize, S)) {
+        if from == to {
+            return;
+        }
+
+        for frame in from..to {
+            for channel in 0..self.shared.channels {
+                write(frame, channel, S::default());
+            }
+        }
+
+        self.shared
+            .underruns
+            .fetch_add(to - from, Ordering::Relaxed);
+    }
+}
+
+/// Find which buffer holds the (total) channel, and the channel index in that buffer.
+fn locate(channels_per_buffer: impl Iterator<Item = usize>, mut channel: usize) -> (usize, usize) {
+    for (idx, channels) in channels_per_buffer.enumerate() {
+        if channel < channels {
+            return (idx, channel);
+        }
+        channel -= channels;
+    }
+    panic!("channel out of range");
+}
+
+impl<S: Sample> fmt::Debug for Producer<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Producer")
+            .field("channels", &self.channels())
+            .field("capacity", &self.capacity())
+            .field("free_frames", &self.free_frames())
+            .field("overruns", &self.overruns())
+            .finish()
+    }
+}
+
+impl<S: Sample> fmt::Debug for Consumer<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Consumer")
+            .field("channels", &self.channels())
+            .field("capacity", &self.capacity())
+            .field("available_frames", &self.available_frames())
+            .field("underruns", &self.underruns())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn push_pop_interleaved() {
+        let (mut p, mut c) = RingBuffer::<i16>::new(2, 4).split();
+
+        assert_eq!(p.push_frames(&[1, 2, 3, 4, 5, 6]), 3);
+        assert_eq!(c.available_frames(), 3);
+
+        let mut out = [0; 4];
+        assert_eq!(c.pop_frames(&mut out), 2);
+        assert_eq!(out, [1, 2, 3, 4]);
+        assert_eq!(c.available_frames(), 1);
+    }
+
+    #[test]
+    fn overrun_is_counted() {
+        let (mut p, _c) = RingBuffer::<f32>::new(1, 4).split();
+
+        assert_eq!(p.push_frames(&[0.0; 6]), 4);
+        assert_eq!(p.overruns(), 2);
+        assert_eq!(p.free_frames(), 0);
+    }
+
+    #[test]
+    fn underrun_pads_with_silence() {
+        let (mut p, mut c) = RingBuffer::<f32>::new(1, 8).split();
+        p.push_frames(&[1.0, 2.0]);
+
+        let mut list = AudioBufferList::<f32>::new(1, 1, 4);
+        list[0].copy_from_slice(&[9.0; 4]);
+
+        assert_eq!(c.read_into_buffer_list(&mut list), 2);
+        assert_eq!(&list[0][..], &[1.0, 2.0, 0.0, 0.0]);
+        assert_eq!(c.underruns(), 2);
+    }
+
+    #[test]
+    fn non_interleaved_round_trip() {
+        let (mut p, mut c) = RingBuffer::<f32>::new(2, 16).split();
+
+        let mut input = AudioBufferList::<f32>::new(2, 1, 4);
+        input[0].copy_from_slice(&[1.0, 2.0, 3.0, 4.0]);
+        input[1].copy_from_slice(&[-1.0, -2.0, -3.0, -4.0]);
+        assert_eq!(p.write_from_buffer_list(&input), 4);
+
+        // Read back interleaved.
+        let mut output = AudioBufferList::<f32>::new(1, 2, 4);
+        assert_eq!(c.read_into_buffer_list(&mut output), 4);
+        assert_eq!(
+            &output[0][..],
+            &[1.0, -1.0, 2.0, -2.0, 3.0, -3.0, 4.0, -4.0]
+        );
+    }
+
+    #[test]
+    fn positions_wrap() {
+        let (mut p, mut c) = RingBuffer::<i16>::new(1, 3).split();
+        p.shared.head.store(usize::MAX - 1, Ordering::Relaxed);
+        p.shared.tail.store(usize::MAX - 1, Ordering::Relaxed);
+
+        let mut out = [0; 3];
+        for start in [0, 3, 6] {
+            assert_eq!(p.push_frames(&[start, start + 1, start + 2]), 3);
+            assert_eq!(c.pop_frames(&mut out), 3);
+            assert_eq!(out, [start, start + 1, start + 2]);
+        }
+    }
+
+    #[test]
+    fn stress_threads() {
+        const TOTAL: i32 = 100_000;
+        let (mut p, mut c) = RingBuffer::<i32>::new(2, 61).split();
+
+        let writer = thread::spawn(move || {
+            let mut next = 0;
+            let mut chunk = Vec::new();
+            while next < TOTAL {
+                chunk.clear();
+                let n = (next % 17 + 1).min(TOTAL - next);
+                for i in next..next + n {
+                    chunk.push(i);
+                    chunk.push(-i);
+                }
+                let mut written = 0;
+                while written < n as usize {
+                    // Only push what fits so we don't count overruns.
+                    let free = p.free_frames().min(n as usize - written);
+                    if free == 0 {
+                        thread::yield_now();
+                        continue;
+                    }
+                    written += p.push_frames(&chunk[written * 2..(written + free) * 2]);
+                }
+                next += n;
+            }
+            p.overruns()
+        });
+
+        let mut expected = 0;
+        let mut out = [0; 2 * 23];
+        while expected < TOTAL {
+            let n = c.pop_frames(&mut out);
+            if n == 0 {
+                thread::yield_now();
+            }
+            for frame in out[..n * 2].chunks(2) {
+                assert_eq!(frame, [expected, -expected]);
+                expected += 1;
+            }
+        }
+
+        assert_eq!(writer.join().unwrap(), 0);
+        assert_eq!(c.available_frames(), 0);
+        assert_eq!(c.underruns(), 0);
+    }
+}
@@ -0,0 +1,58 @@
+//! Small deterministic random number generator.
+//!
+//! Used where reproducibility matters more than quality, like simulated jitter and noise.
+
+/// SplitMix64.
+#[derive(Debug, Clone)]
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `[0, 1)`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1_u64 << 53) as f64
+    }
+
+    /// Uniform in `[-max, max]`.
+    pub fn jitter(&mut self, max: usize) -> isize {
+        if max == 0 {
+            return 0;
+        }
+        (self.next_u64() % (2 * max as u64 + 1)) as isize - max as isize
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn deterministic_and_in_range() {
+        let mut a = Rng::new(7);
+        let mut b = Rng::new(7);
+
+        for _ in 0..1000 {
+            assert_eq!(a.next_u64(), b.next_u64());
+
+            let f = a.next_f64();
+            assert!((0.0..1.0).contains(&f));
+
+            let j = a.jitter(3);
+            assert!((-3..=3).contains(&j));
+
+            b.next_f64();
+            b.jitter(3);
+        }
+    }
+}
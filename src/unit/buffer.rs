@@ -179,6 +179,15 @@ impl<S: Sample> AudioBuffer<S> {
         self.len() / self.channels()
     }
 
+    /// Change the number of frames.
+    ///
+    /// # Safety
+    ///
+    /// The frames must fit in the memory the buffer points to.
+    pub(crate) unsafe fn set_frames(&mut self, frames: usize) {
+        self.data_byte_size = (frames * self.channels() * mem::size_of::<S>()) as u32;
+    }
+
     /// Samples as a slice.
     pub fn samples(&self) -> &[S] {
         unsafe {
@@ -60,6 +60,23 @@ bitflags! {
     }
 }
 
+bitflags! {
+    /// Which fields of an `AudioTimeStamp` are valid.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct TimeStampFlags: u32 {
+        /// The sample frame time is valid.
+        const SAMPLE_TIME_VALID = sys::kAudioTimeStampSampleTimeValid;
+        /// The host time is valid.
+        const HOST_TIME_VALID = sys::kAudioTimeStampHostTimeValid;
+        /// The rate scalar is valid.
+        const RATE_SCALAR_VALID = sys::kAudioTimeStampRateScalarValid;
+        /// The word clock time is valid.
+        const WORD_CLOCK_TIME_VALID = sys::kAudioTimeStampWordClockTimeValid;
+        /// The SMPTE time is valid.
+        const SMPTE_TIME_VALID = sys::kAudioTimeStampSMPTETimeValid;
+    }
+}
+
 impl fmt::Display for ActionFlags {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
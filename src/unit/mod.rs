@@ -13,9 +13,10 @@ mod buffer;
 pub use buffer::AudioBufferList;
 
 mod flags;
-pub use flags::ActionFlags;
+pub use flags::{ActionFlags, TimeStampFlags};
 
 use crate::format::{Sample, StreamFormat};
+use crate::panic::{PanicHandler, PanicPolicy, Panics};
 use crate::{try_os_status, CAError};
 
 pub struct AudioUnit<S: Sample> {
@@ -23,6 +24,8 @@ pub struct AudioUnit<S: Sample> {
     initialized: bool,
     started: bool,
     callback: Option<Box<RenderCallbackFnWrapper>>,
+    panics: Panics,
+    panic_handler: PanicHandler,
     _ph: PhantomData<S>,
 }
 
@@ -60,15 +63,24 @@ impl<S: Sample> AudioUnit<S> {
             try_os_status!(sys::AudioComponentInstanceNew(component, &mut unit,));
         }
 
+        let (panics, panic_handler) = Panics::new();
+
         Ok(AudioUnit {
             unit,
             initialized: false,
             started: false,
             callback: None,
+            panics,
+            panic_handler,
             _ph: PhantomData,
         })
     }
 
+    /// Panics caught in the render callback.
+    pub fn panics(&self) -> &Panics {
+        &self.panics
+    }
+
     pub fn initialize(&mut self) -> Result<(), CAError> {
         if self.initialized {
             return Ok(());
@@ -170,33 +182,13 @@ impl<S: Sample> AudioUnit<S> {
 
     pub fn set_render_callback(
         &mut self,
-        mut callback: impl RenderCallback<S> + 'static,
+        callback: impl RenderCallback<S> + 'static,
     ) -> Result<(), CAError> {
         assert!(self.callback.is_none(), "set render callback only once");
 
-        // This closure gets around the problem of having a generic S..
-        let input_proc_fn = move |io_action_flags: *mut sys::AudioUnitRenderActionFlags,
-                                  in_time_stamp: *const sys::AudioTimeStamp,
-                                  in_bus_number: sys::UInt32,
-                                  in_number_frames: sys::UInt32,
-                                  io_data: *mut sys::AudioBufferList|
-              -> sys::OSStatus {
-            let mut buffers = AudioBufferList::<S>::borrow(io_data);
-
-            unsafe {
-                callback.render(
-                    ActionFlags::from_bits_truncate(*io_action_flags),
-                    *in_time_stamp,
-                    in_bus_number,
-                    in_number_frames as usize,
-                    &mut buffers,
-                );
-            }
-            0
-        };
-
         let mut wrapper = Box::new(RenderCallbackFnWrapper {
-            callback: Box::new(input_proc_fn),
+            callback: Box::new(render_callback_fn(callback)),
+            panic: self.panic_handler.clone(),
         });
 
         let wrapper_ptr = &mut *wrapper as *mut RenderCallbackFnWrapper;
@@ -271,30 +263,41 @@ impl<S: Sample> Drop for AudioUnit<S> {
     }
 }
 
+/// Callback providing audio to an [`AudioUnit`].
+///
+/// The `flags` are passed back to the caller, which means the callback can set
+/// [`ActionFlags::OUTPUT_IS_SILENCE`]. An error is returned to the caller as its OSStatus,
+/// see [`CAError::to_os_status`].
 pub trait RenderCallback<S: Sample> {
     fn render(
         &mut self,
-        flags: ActionFlags,
+        flags: &mut ActionFlags,
         time: sys::AudioTimeStamp,
         bus: u32,
         frames: usize,
         buffers: &mut AudioBufferList<S>,
-    );
+    ) -> Result<(), CAError>;
 }
 
 impl<
         S: Sample,
-        T: for<'a> FnMut(ActionFlags, sys::AudioTimeStamp, u32, usize, &'a mut AudioBufferList<S>),
+        T: for<'a, 'b> FnMut(
+            &'a mut ActionFlags,
+            sys::AudioTimeStamp,
+            u32,
+            usize,
+            &'b mut AudioBufferList<S>,
+        ) -> Result<(), CAError>,
     > RenderCallback<S> for T
 {
     fn render(
         &mut self,
-        flags: ActionFlags,
+        flags: &mut ActionFlags,
         time: sys::AudioTimeStamp,
         bus: u32,
         frames: usize,
         buffers: &mut AudioBufferList<S>,
-    ) {
+    ) -> Result<(), CAError> {
         (self)(flags, time, bus, frames, buffers)
     }
 }
@@ -309,6 +312,45 @@ type RenderCallbackFn = dyn FnMut(
 
 struct RenderCallbackFnWrapper {
     callback: Box<RenderCallbackFn>,
+    panic: PanicHandler,
+}
+
+// This closure gets around the problem of having a generic S..
+fn render_callback_fn<S: Sample>(
+    mut callback: impl RenderCallback<S> + 'static,
+) -> impl FnMut(
+    *mut sys::AudioUnitRenderActionFlags,
+    *const sys::AudioTimeStamp,
+    sys::UInt32,
+    sys::UInt32,
+    *mut sys::AudioBufferList,
+) -> sys::OSStatus {
+    move |io_action_flags: *mut sys::AudioUnitRenderActionFlags,
+          in_time_stamp: *const sys::AudioTimeStamp,
+          in_bus_number: sys::UInt32,
+          in_number_frames: sys::UInt32,
+          io_data: *mut sys::AudioBufferList|
+          -> sys::OSStatus {
+        let mut buffers = AudioBufferList::<S>::borrow(io_data);
+
+        // Keep unknown bits so they are passed back untouched.
+        let mut flags = unsafe { ActionFlags::from_bits_retain(*io_action_flags) };
+
+        let result = callback.render(
+            &mut flags,
+            unsafe { *in_time_stamp },
+            in_bus_number,
+            in_number_frames as usize,
+            &mut buffers,
+        );
+
+        unsafe { *io_action_flags = flags.bits() };
+
+        match result {
+            Ok(()) => 0,
+            Err(e) => e.to_os_status(),
+        }
+    }
 }
 
 /// Callback procedure that will be called each time our audio_unit requests audio.
@@ -320,27 +362,164 @@ extern "C" fn input_proc(
     in_number_frames: sys::UInt32,
     io_data: *mut sys::AudioBufferList,
 ) -> sys::OSStatus {
-    let wrapper = in_ref_con as *mut RenderCallbackFnWrapper;
-    unsafe {
-        (*(*wrapper).callback)(
+    let wrapper = unsafe { &mut *(in_ref_con as *mut RenderCallbackFnWrapper) };
+
+    let result = wrapper.panic.catch(|| {
+        (wrapper.callback)(
             io_action_flags,
             in_time_stamp,
             in_bus_number,
             in_number_frames,
             io_data,
         )
+    });
+
+    match result {
+        Ok(status) => status,
+        Err(PanicPolicy::Error(status)) => status,
+        Err(_) => {
+            unsafe { output_silence(io_action_flags, io_data) };
+            0
+        }
+    }
+}
+
+/// Zero all buffers and flag the output as silent.
+unsafe fn output_silence(
+    io_action_flags: *mut sys::AudioUnitRenderActionFlags,
+    io_data: *mut sys::AudioBufferList,
+) {
+    if !io_action_flags.is_null() {
+        *io_action_flags |= ActionFlags::OUTPUT_IS_SILENCE.bits();
+    }
+
+    if io_data.is_null() {
+        return;
+    }
+
+    let len = (*io_data).mNumberBuffers as usize;
+    let ptr = &mut (*io_data).mBuffers as *mut _ as *mut sys::AudioBuffer;
+    for buffer in std::slice::from_raw_parts_mut(ptr, len) {
+        if !buffer.mData.is_null() {
+            ptr::write_bytes(buffer.mData as *mut u8, 0, buffer.mDataByteSize as usize);
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use std::f32::consts::PI;
-
+    use crate::error::AudioUnitError;
     use crate::format::{LinearPcmFlags, SampleFormat};
+    use crate::generator::{Generator, Waveform};
 
     use super::types::EffectType;
     use super::*;
 
+    fn invoke(
+        wrapper: &mut RenderCallbackFnWrapper,
+        flags: &mut sys::AudioUnitRenderActionFlags,
+        buffers: &mut AudioBufferList<f32>,
+    ) -> sys::OSStatus {
+        let time = sys::AudioTimeStamp::default();
+        input_proc(
+            wrapper as *mut _ as *mut c_void,
+            flags,
+            &time,
+            0,
+            buffers[0].frames() as u32,
+            buffers.as_sys_list(),
+        )
+    }
+
+    #[test]
+    fn render_callback_panic_outputs_silence() {
+        let (panics, panic) = Panics::new();
+
+        let mut wrapper = RenderCallbackFnWrapper {
+            callback: Box::new(render_callback_fn(
+                |_flags: &mut ActionFlags,
+                 _time,
+                 _bus,
+                 _frames,
+                 buffers: &mut AudioBufferList<f32>|
+                 -> Result<(), CAError> {
+                    buffers[0].fill(1.0);
+                    panic!("boom");
+                },
+            )),
+            panic,
+        };
+
+        let mut buffers = AudioBufferList::<f32>::new(1, 2, 4);
+        let mut flags = 0;
+
+        assert_eq!(invoke(&mut wrapper, &mut flags, &mut buffers), 0);
+        assert_eq!(&buffers[0][..], &[0.0; 8]);
+        assert_eq!(flags, ActionFlags::OUTPUT_IS_SILENCE.bits());
+        assert_eq!(panics.try_recv().as_deref(), Some("boom"));
+
+        panics.set_policy(PanicPolicy::Error(-10863));
+        let mut flags = 0;
+
+        assert_eq!(invoke(&mut wrapper, &mut flags, &mut buffers), -10863);
+        assert_eq!(flags, 0);
+        assert_eq!(panics.try_recv().as_deref(), Some("boom"));
+    }
+
+    #[test]
+    fn render_callback_sets_flags() {
+        let mut wrapper = RenderCallbackFnWrapper {
+            callback: Box::new(render_callback_fn(
+                |flags: &mut ActionFlags,
+                 _time,
+                 _bus,
+                 _frames,
+                 buffers: &mut AudioBufferList<f32>| {
+                    buffers[0].fill(0.0);
+                    flags.insert(ActionFlags::OUTPUT_IS_SILENCE);
+                    Ok(())
+                },
+            )),
+            panic: Panics::new().1,
+        };
+
+        let mut buffers = AudioBufferList::<f32>::new(1, 1, 4);
+        let mut flags = ActionFlags::PRE_RENDER.bits();
+
+        assert_eq!(invoke(&mut wrapper, &mut flags, &mut buffers), 0);
+        assert_eq!(
+            flags,
+            (ActionFlags::PRE_RENDER | ActionFlags::OUTPUT_IS_SILENCE).bits()
+        );
+    }
+
+    #[test]
+    fn render_callback_returns_error_status() {
+        let mut wrapper = RenderCallbackFnWrapper {
+            callback: Box::new(render_callback_fn(
+                |_flags: &mut ActionFlags,
+                 _time,
+                 _bus,
+                 frames,
+                 _buffers: &mut AudioBufferList<f32>| {
+                    if frames > 2 {
+                        return Err(AudioUnitError::TooManyFramesToProcess.into());
+                    }
+                    Ok(())
+                },
+            )),
+            panic: Panics::new().1,
+        };
+
+        let mut flags = 0;
+
+        let mut small = AudioBufferList::<f32>::new(1, 1, 2);
+        assert_eq!(invoke(&mut wrapper, &mut flags, &mut small), 0);
+
+        let mut large = AudioBufferList::<f32>::new(1, 1, 4);
+        assert_eq!(invoke(&mut wrapper, &mut flags, &mut large), -10874);
+    }
+
     #[test]
     fn instantiate_reverb() {
         let d = Description::first(EffectType::MatrixReverb).unwrap();
@@ -353,10 +532,6 @@ mod test {
         let d = Description::first(EffectType::Delay).unwrap();
         let mut u = AudioUnit::<f32>::new(d).unwrap();
 
-        let angular_frequency = 2.0 * PI * 440.0;
-        let sample_period = 1.0 / 48_000.0;
-        let mut i = 0;
-
         let format = StreamFormat::new(
             44100.0,
             SampleFormat::F32,
@@ -368,14 +543,16 @@ mod test {
         u.set_stream_format(&format, Scope::Input).unwrap();
         u.set_stream_format(&format, Scope::Output).unwrap();
 
+        let mut generator = Generator::new(Waveform::Sine(440.0), &format);
+
         u.set_render_callback(
-            move |_flags, _time, _bus, _frames, buffers: &mut AudioBufferList<f32>| {
-                for buf in &mut **buffers {
-                    for sample in &mut **buf {
-                        *sample = (angular_frequency * i as f32 * sample_period).sin();
-                        i += 1;
-                    }
-                }
+            move |_flags: &mut ActionFlags,
+                  _time,
+                  _bus,
+                  _frames,
+                  buffers: &mut AudioBufferList<f32>| {
+                generator.fill_buffer_list(buffers);
+                Ok(())
             },
         )
         .unwrap();